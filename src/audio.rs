@@ -0,0 +1,540 @@
+//! Sound cues for remote players joining, leaving and coming close, panned and attenuated by
+//! where their heads are relative to the listener. Sound output needs the `audio` feature.
+use anyhow::{bail, Context, Result};
+use cubehead::{Head, PlayerId};
+use nalgebra::Point3;
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_4;
+use std::sync::mpsc::Sender;
+
+/// Sources closer than this many meters are heard at full volume
+const REF_DISTANCE: f32 = 1.;
+
+/// Sources further than this many meters are silent
+const MAX_DISTANCE: f32 = 50.;
+
+/// Players coming closer than this many meters play the proximity cue...
+const PROXIMITY_DISTANCE: f32 = 1.5;
+
+/// ...and must move further than this away before it plays again
+const PROXIMITY_RESET_DISTANCE: f32 = 3.;
+
+/// Short sounds played at a player's head
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cue {
+    Join,
+    Leave,
+    Proximity,
+}
+
+/// Volume of each output channel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StereoGains {
+    pub left: f32,
+    pub right: f32,
+}
+
+/// Messages to the output thread
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+enum Command {
+    /// Gains of every player this frame
+    Gains(Vec<(PlayerId, StereoGains)>),
+    Play(PlayerId, Cue),
+}
+
+/// Plays cues for remote players on a thread of its own. Feed it the heads each frame with
+/// `update`; if there is no output device it stays silent.
+pub struct AudioEngine {
+    commands: Sender<Command>,
+    /// Players present last frame, and whether each was within proximity
+    known: HashMap<PlayerId, bool>,
+    /// Players present when the client connects don't play the join cue
+    first_update: bool,
+}
+
+impl AudioEngine {
+    /// Start the output thread. Fails if cubehead was built without the `audio` feature.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            commands: output::spawn()?,
+            known: HashMap::new(),
+            first_update: true,
+        })
+    }
+
+    /// Update where each source is heard from, and play cues for players who joined, left or
+    /// came close since the last call. Use the same smoothed heads as the renderer.
+    pub fn update(&mut self, listener: &Head, sources: &[(PlayerId, Head)]) {
+        let gains = sources
+            .iter()
+            .map(|(id, head)| (*id, stereo_gains(listener, head.pos)))
+            .collect();
+        // Sending only fails once the thread has given up on finding a device
+        let _ = self.commands.send(Command::Gains(gains));
+
+        let mut cues = vec![];
+        let mut present = HashMap::new();
+        for (id, head) in sources {
+            let distance = (head.pos - listener.pos).norm();
+            let close = match self.known.get(id) {
+                None => {
+                    if !self.first_update {
+                        cues.push((*id, Cue::Join));
+                    }
+                    distance < PROXIMITY_DISTANCE
+                }
+                Some(false) if distance < PROXIMITY_DISTANCE => {
+                    cues.push((*id, Cue::Proximity));
+                    true
+                }
+                Some(true) => distance < PROXIMITY_RESET_DISTANCE,
+                Some(false) => false,
+            };
+            present.insert(*id, close);
+        }
+        for id in self.known.keys().filter(|id| !present.contains_key(id)) {
+            cues.push((*id, Cue::Leave));
+        }
+        self.known = present;
+        self.first_update = false;
+
+        for (id, cue) in cues {
+            let _ = self.commands.send(Command::Play(id, cue));
+        }
+    }
+}
+
+/// Volume of a source at `distance` meters: full up to `REF_DISTANCE`, then falling off
+/// inversely with distance and fading to silence at `MAX_DISTANCE`
+pub fn attenuation(distance: f32) -> f32 {
+    let fade = (1. - distance / MAX_DISTANCE).clamp(0., 1.);
+    REF_DISTANCE / distance.max(REF_DISTANCE) * fade
+}
+
+/// Position of a source from -1 (fully left) to 1 (fully right) of the listener. Sources
+/// straight above, below or at the listener are centered.
+pub fn pan(listener: &Head, source: Point3<f32>) -> f32 {
+    let local = listener.orient.inverse() * (source - listener.pos);
+    let horizontal = local.x.hypot(local.z);
+    if horizontal < 1e-6 {
+        0.
+    } else {
+        (local.x / horizontal).clamp(-1., 1.)
+    }
+}
+
+/// Equal power stereo gains for a source heard by `listener`
+pub fn stereo_gains(listener: &Head, source: Point3<f32>) -> StereoGains {
+    let volume = attenuation((source - listener.pos).norm());
+    let angle = (pan(listener, source) + 1.) * FRAC_PI_4;
+    StereoGains {
+        left: angle.cos() * volume,
+        right: angle.sin() * volume,
+    }
+}
+
+/// Sound file for each cue, built into the binary
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+fn cue_wav(cue: Cue) -> &'static [u8] {
+    match cue {
+        Cue::Join => include_bytes!("sounds/join.wav"),
+        Cue::Leave => include_bytes!("sounds/leave.wav"),
+        Cue::Proximity => include_bytes!("sounds/proximity.wav"),
+    }
+}
+
+/// Mono samples from -1 to 1
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+struct Pcm {
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+/// Decode a WAV file of 16-bit mono PCM, which is all the cue sounds need
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+fn decode_wav(bytes: &[u8]) -> Result<Pcm> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        bail!("Not a WAV file");
+    }
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+    let mut sample_rate = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let len = u32_at(rest, 4) as usize;
+        let body = rest
+            .get(8..8 + len)
+            .context("WAV chunk runs past the end of the file")?;
+        match &rest[..4] {
+            b"fmt " => {
+                if body.len() < 16 {
+                    bail!("WAV format chunk is too short");
+                }
+                let (tag, channels, bits) = (u16_at(body, 0), u16_at(body, 2), u16_at(body, 14));
+                if (tag, channels, bits) != (1, 1, 16) {
+                    bail!(
+                        "Expected 16-bit mono PCM, found format {} with {} channels of {} bits",
+                        tag,
+                        channels,
+                        bits
+                    );
+                }
+                sample_rate = Some(u32_at(body, 4));
+            }
+            b"data" => {
+                let sample_rate = sample_rate.context("WAV data comes before its format")?;
+                let samples = body
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.)
+                    .collect();
+                return Ok(Pcm {
+                    sample_rate,
+                    samples,
+                });
+            }
+            _ => (),
+        }
+        // Chunks are padded to an even length
+        rest = &rest[(8 + len + len % 2).min(rest.len())..];
+    }
+    bail!("WAV file has no data")
+}
+
+#[cfg(feature = "audio")]
+mod output {
+    use super::{cue_wav, decode_wav, Command, Cue, StereoGains};
+    use anyhow::{Context, Result};
+    use cubehead::PlayerId;
+    use rodio::buffer::SamplesBuffer;
+    use rodio::Source;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Start the thread which owns the output stream
+    pub(super) fn spawn() -> Result<Sender<Command>> {
+        let (sender, receiver) = channel();
+        std::thread::Builder::new()
+            .name("audio".into())
+            .spawn(move || run(receiver))
+            .context("Failed to start the audio thread")?;
+        Ok(sender)
+    }
+
+    fn run(commands: Receiver<Command>) {
+        // The stream must stay alive for anything to play
+        let (_stream, handle) = match rodio::OutputStream::try_default() {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("No audio output, sound cues are off; {}", e);
+                return;
+            }
+        };
+
+        let mut players: HashMap<PlayerId, Arc<SharedGains>> = HashMap::new();
+        for command in commands {
+            match command {
+                Command::Gains(gains) => {
+                    for (id, gains) in gains {
+                        players.entry(id).or_default().set(gains);
+                    }
+                }
+                Command::Play(id, cue) => {
+                    // Players who left keep their last gains for the leave cue
+                    let gains = match cue {
+                        Cue::Leave => players.remove(&id),
+                        _ => players.get(&id).cloned(),
+                    };
+                    let sound = Panned::new(cue_sound(cue), gains.unwrap_or_default());
+                    if let Err(e) = handle.play_raw(sound) {
+                        eprintln!("Failed to play sound; {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The built-in sound of a cue
+    fn cue_sound(cue: Cue) -> SamplesBuffer<f32> {
+        let pcm = decode_wav(cue_wav(cue)).expect("Built-in cue sounds are 16-bit mono PCM");
+        SamplesBuffer::new(1, pcm.sample_rate, pcm.samples)
+    }
+
+    /// Stereo gains written by the audio thread and read by playing sounds
+    struct SharedGains {
+        left: AtomicU32,
+        right: AtomicU32,
+    }
+
+    impl Default for SharedGains {
+        fn default() -> Self {
+            Self {
+                left: AtomicU32::new(0.5f32.to_bits()),
+                right: AtomicU32::new(0.5f32.to_bits()),
+            }
+        }
+    }
+
+    impl SharedGains {
+        fn set(&self, gains: StereoGains) {
+            self.left.store(gains.left.to_bits(), Ordering::Relaxed);
+            self.right.store(gains.right.to_bits(), Ordering::Relaxed);
+        }
+
+        fn get(&self) -> StereoGains {
+            StereoGains {
+                left: f32::from_bits(self.left.load(Ordering::Relaxed)),
+                right: f32::from_bits(self.right.load(Ordering::Relaxed)),
+            }
+        }
+    }
+
+    /// A mono source played in stereo with gains which follow its player while it plays
+    struct Panned<S> {
+        input: S,
+        gains: Arc<SharedGains>,
+        /// Right channel of the current frame, once the left one is out
+        right: Option<f32>,
+    }
+
+    impl<S> Panned<S> {
+        fn new(input: S, gains: Arc<SharedGains>) -> Self {
+            Self {
+                input,
+                gains,
+                right: None,
+            }
+        }
+    }
+
+    impl<S: Source<Item = f32>> Iterator for Panned<S> {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            if let Some(right) = self.right.take() {
+                return Some(right);
+            }
+            let sample = self.input.next()?;
+            let gains = self.gains.get();
+            self.right = Some(sample * gains.right);
+            Some(sample * gains.left)
+        }
+    }
+
+    impl<S: Source<Item = f32>> Source for Panned<S> {
+        fn current_frame_len(&self) -> Option<usize> {
+            self.input.current_frame_len().map(|len| len * 2)
+        }
+
+        fn channels(&self) -> u16 {
+            2
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.input.sample_rate()
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            self.input.total_duration()
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+mod output {
+    use super::Command;
+    use anyhow::{bail, Result};
+    use std::sync::mpsc::Sender;
+
+    pub(super) fn spawn() -> Result<Sender<Command>> {
+        bail!("Cannot play sound; cubehead was built without the `audio` feature")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{UnitQuaternion, Vector3};
+    use std::f32::consts::FRAC_PI_2;
+    use std::sync::mpsc::{channel, Receiver};
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn volume_falls_off_with_distance() {
+        assert_eq!(attenuation(0.), 1. - 0. / MAX_DISTANCE);
+        assert_close(attenuation(REF_DISTANCE), 1. - REF_DISTANCE / MAX_DISTANCE);
+        assert_close(attenuation(10.), 0.1 * (1. - 10. / MAX_DISTANCE));
+        assert_eq!(attenuation(MAX_DISTANCE), 0.);
+        assert_eq!(attenuation(2. * MAX_DISTANCE), 0.);
+    }
+
+    #[test]
+    fn sources_pan_by_where_the_listener_faces() {
+        let listener = Head::default();
+        assert_close(pan(&listener, Point3::new(3., 0., 0.)), 1.);
+        assert_close(pan(&listener, Point3::new(-3., 1., 0.)), -1.);
+        assert_close(pan(&listener, Point3::new(0., 0., -3.)), 0.);
+        assert_close(pan(&listener, Point3::new(1., 0., -1.)), 0.5f32.sqrt());
+        // Straight overhead or right on top of us is centered
+        assert_eq!(pan(&listener, Point3::new(0., 5., 0.)), 0.);
+        assert_eq!(pan(&listener, Point3::origin()), 0.);
+
+        // Turning left puts what was ahead on the right
+        let turned = Head {
+            orient: UnitQuaternion::from_euler_angles(0., FRAC_PI_2, 0.),
+            ..Head::default()
+        };
+        assert_close(pan(&turned, Point3::new(0., 0., -3.)), 1.);
+    }
+
+    #[test]
+    fn gains_keep_equal_power() {
+        let listener = Head {
+            pos: Point3::new(1., 1., 1.),
+            ..Head::default()
+        };
+        for offset in [
+            Vector3::new(2., 0., 0.),
+            Vector3::new(-2., 0., -2.),
+            Vector3::new(0., 0., 5.),
+        ] {
+            let source = listener.pos + offset;
+            let gains = stereo_gains(&listener, source);
+            let power = gains.left * gains.left + gains.right * gains.right;
+            let volume = attenuation(offset.norm());
+            assert_close(power, volume * volume);
+        }
+
+        let ahead = stereo_gains(&listener, listener.pos - Vector3::z() * 2.);
+        assert_close(ahead.left, ahead.right);
+        let right = stereo_gains(&listener, listener.pos + Vector3::x() * 2.);
+        assert_close(right.left, 0.);
+    }
+
+    fn engine() -> (AudioEngine, Receiver<Command>) {
+        let (commands, receiver) = channel();
+        let engine = AudioEngine {
+            commands,
+            known: HashMap::new(),
+            first_update: true,
+        };
+        (engine, receiver)
+    }
+
+    fn cues(receiver: &Receiver<Command>) -> Vec<(PlayerId, Cue)> {
+        let mut cues: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|command| match command {
+                Command::Play(id, cue) => Some((id, cue)),
+                Command::Gains(_) => None,
+            })
+            .collect();
+        cues.sort_by_key(|&(id, _)| id);
+        cues
+    }
+
+    fn at(x: f32) -> Head {
+        Head {
+            pos: Point3::new(x, 0., 0.),
+            ..Head::default()
+        }
+    }
+
+    #[test]
+    fn cues_play_on_joining_leaving_and_coming_close() {
+        let (mut engine, receiver) = engine();
+        let listener = Head::default();
+
+        // Whoever is already there when we connect is quiet
+        engine.update(&listener, &[(1, at(10.))]);
+        assert_eq!(cues(&receiver), vec![]);
+
+        engine.update(&listener, &[(1, at(10.)), (2, at(10.))]);
+        assert_eq!(cues(&receiver), vec![(2, Cue::Join)]);
+
+        engine.update(&listener, &[(1, at(1.)), (2, at(10.))]);
+        assert_eq!(cues(&receiver), vec![(1, Cue::Proximity)]);
+
+        // Backing off a little doesn't rearm the proximity cue
+        engine.update(&listener, &[(1, at(2.)), (2, at(10.))]);
+        engine.update(&listener, &[(1, at(1.)), (2, at(10.))]);
+        assert_eq!(cues(&receiver), vec![]);
+
+        engine.update(&listener, &[(1, at(5.)), (2, at(10.))]);
+        engine.update(&listener, &[(1, at(1.))]);
+        assert_eq!(cues(&receiver), vec![(1, Cue::Proximity), (2, Cue::Leave)]);
+    }
+
+    #[test]
+    fn built_in_cue_sounds_decode() {
+        for cue in [Cue::Join, Cue::Leave, Cue::Proximity] {
+            let pcm = decode_wav(cue_wav(cue)).unwrap();
+            let seconds = pcm.samples.len() as f32 / pcm.sample_rate as f32;
+            assert!(seconds > 0.01 && seconds < 0.5, "{:?}", cue);
+            assert!(pcm.samples.iter().all(|s| (-1. ..1.).contains(s)));
+            assert!(pcm.samples.iter().any(|&s| s != 0.));
+        }
+    }
+
+    /// A WAV file with the given format fields and 16-bit samples
+    fn wav(tag: u16, channels: u16, bits: u16, samples: &[i16]) -> Vec<u8> {
+        let mut fmt: Vec<u8> = vec![];
+        fmt.extend(tag.to_le_bytes());
+        fmt.extend(channels.to_le_bytes());
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend((8000u32 * 2).to_le_bytes());
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(bits.to_le_bytes());
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+        let mut chunks: Vec<u8> = vec![];
+        for (id, body) in [
+            (b"LIST", &[7u8][..]),
+            (b"fmt ", &fmt[..]),
+            (b"data", &data[..]),
+        ] {
+            chunks.extend(id);
+            chunks.extend((body.len() as u32).to_le_bytes());
+            chunks.extend(body);
+            if body.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend((chunks.len() as u32 + 4).to_le_bytes());
+        file.extend(b"WAVE");
+        file.extend(chunks);
+        file
+    }
+
+    #[test]
+    fn wav_files_decode_to_samples() {
+        let pcm = decode_wav(&wav(1, 1, 16, &[0, 16384, -32768])).unwrap();
+        assert_eq!(pcm.sample_rate, 8000);
+        assert_eq!(pcm.samples, vec![0., 0.5, -1.]);
+    }
+
+    #[test]
+    fn other_wav_files_are_errors() {
+        let error = |bytes: &[u8]| decode_wav(bytes).err().unwrap().to_string();
+        assert_eq!(error(b"RIFF\0\0\0\0AVI "), "Not a WAV file");
+        assert_eq!(
+            error(&wav(1, 2, 16, &[])),
+            "Expected 16-bit mono PCM, found format 1 with 2 channels of 16 bits"
+        );
+        assert_eq!(
+            error(&wav(3, 1, 32, &[])),
+            "Expected 16-bit mono PCM, found format 3 with 1 channels of 32 bits"
+        );
+        let mut truncated = wav(1, 1, 16, &[1, 2, 3]);
+        truncated.truncate(truncated.len() - 1);
+        assert_eq!(error(&truncated), "WAV chunk runs past the end of the file");
+        assert_eq!(error(b"RIFF\0\0\0\0WAVE"), "WAV file has no data");
+    }
+}
@@ -2,23 +2,37 @@ use std::{
     io::{self, Write},
     net::{SocketAddr, TcpListener, TcpStream},
     sync::mpsc::{self, Receiver, Sender},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use anyhow::Result;
 
-use cubehead::{AsyncBufferedReceiver, Head, ReadState, ServerState, ClientState, serialize_msg};
+use cubehead::{
+    serialize_msg, write_hello, AppData, AsyncBufferedReceiver, ClientState, Heightfield, Player,
+    PlayerId, ReadState, ServerState, DEFAULT_PORT, GROUND_CLEARANCE, TERRAIN_EXTENT,
+    TERRAIN_RESOLUTION,
+};
 
 fn main() -> Result<()> {
     let mut args = std::env::args().skip(1);
-    let bind_addr = args.next().unwrap_or("0.0.0.0:5031".into());
+    let bind_addr = args.next().unwrap_or(format!("0.0.0.0:{}", DEFAULT_PORT));
     let bind_addr: SocketAddr = bind_addr.parse().expect("Failed to parse bind addr");
     println!("Running on {}", bind_addr);
 
+    // Keep heads above the generated terrain of this seed, if one is given
+    let terrain = args
+        .next()
+        .map(|seed| {
+            let seed: u32 = seed.parse().expect("Failed to parse terrain seed");
+            println!("Keeping heads above the terrain of seed {}", seed);
+            Heightfield::generate(TERRAIN_EXTENT, TERRAIN_RESOLUTION, seed)
+        })
+        .transpose()?;
+
     // Create a new thread for the connection listener
     let (conn_tx, conn_rx) = mpsc::channel();
     std::thread::spawn(move || connection_listener(bind_addr, conn_tx));
 
-    server(conn_rx)
+    server(conn_rx, terrain)
 }
 
 /// Thread which listens for new connections and sends them to the given MPSC channel
@@ -34,43 +48,81 @@ fn connection_listener(
 }
 
 struct Connection {
+    id: PlayerId,
     last_state: ClientState,
+    /// When `last_state` arrived
+    last_heard: Instant,
+    /// Most other players to send, as last told by the client; 0 means no limit
+    max_players: usize,
     stream: TcpStream,
     addr: SocketAddr,
     msg_buf: AsyncBufferedReceiver,
 }
 
-fn server(conn_rx: Receiver<(TcpStream, SocketAddr)>) -> Result<()> {
+fn server(conn_rx: Receiver<(TcpStream, SocketAddr)>, terrain: Option<Heightfield>) -> Result<()> {
     let mut conns: Vec<Connection> = vec![];
     let mut conns_tmp = vec![];
+    let mut next_id: PlayerId = 0;
 
     loop {
         // Check for new connections
-        for (stream, addr) in conn_rx.try_iter() {
+        for (mut stream, addr) in conn_rx.try_iter() {
+            // Tell the client it has reached a server speaking its protocol
+            if let Err(e) = write_hello(&mut stream) {
+                eprintln!("{} Failed to say hello; {}", addr, e);
+                continue;
+            }
             stream.set_nonblocking(true)?;
-            eprintln!("{} Connected", addr);
+            eprintln!("{} Connected as player {}", addr, next_id);
             conns.push(Connection {
+                id: next_id,
                 last_state: ClientState::default(),
+                last_heard: Instant::now(),
+                max_players: 0,
                 msg_buf: AsyncBufferedReceiver::new(),
                 stream,
                 addr,
             });
+            next_id += 1;
         }
 
         let mut any_update = false;
+        // Data to pass on to everyone but whoever sent it
+        let mut relayed: Vec<(PlayerId, AppData)> = vec![];
 
-        // Update head positions
+        // Update head positions. A client which sends something we can't read is dropped, rather
+        // than taking the server down for everyone.
         for mut conn in conns.drain(..) {
-            match conn.msg_buf.read(&mut conn.stream)? {
+            let read = match conn.msg_buf.read(&mut conn.stream) {
+                Ok(read) => read,
+                Err(e) => {
+                    eprintln!("{} Dropped; {}", conn.addr, e);
+                    continue;
+                }
+            };
+            match read {
                 ReadState::Disconnected => {
                     eprintln!("{} Disconnected", conn.addr);
                 }
-                ReadState::Complete(buf) => {
-                    let new_state: ClientState = bincode::deserialize(&buf).expect("Malformed message");
-                    conn.last_state = new_state;
-                    conns_tmp.push(conn);
-                    any_update = true;
-                }
+                ReadState::Complete(buf) => match bincode::deserialize::<ClientState>(&buf) {
+                    Ok(mut new_state) => {
+                        relayed.extend(new_state.app_data.drain(..).map(|data| (conn.id, data)));
+                        if let Some(terrain) = &terrain {
+                            new_state.head.pos =
+                                terrain.keep_above(new_state.head.pos, GROUND_CLEARANCE);
+                        }
+                        if let Some(max) = new_state.max_players {
+                            conn.max_players = max as usize;
+                        }
+                        conn.last_state = new_state;
+                        conn.last_heard = Instant::now();
+                        conns_tmp.push(conn);
+                        any_update = true;
+                    }
+                    Err(e) => {
+                        eprintln!("{} Dropped for a malformed message; {}", conn.addr, e);
+                    }
+                },
                 ReadState::Invalid | ReadState::Incomplete => {
                     conns_tmp.push(conn);
                 }
@@ -78,17 +130,52 @@ fn server(conn_rx: Receiver<(TcpStream, SocketAddr)>) -> Result<()> {
         }
 
         if any_update {
-            // Compile head position message
-            let heads: Vec<Head> = conns_tmp.iter().map(|c| c.last_state.head).collect();
-            // TODO: Exclude the user's own head! Lmao
-            let state = ServerState {
-                heads,
-            };
-
-            let mut msg = vec![];
-            serialize_msg(&state, &mut msg)?;
+            // Compile player states
+            let players: Vec<Player> = conns_tmp
+                .iter()
+                .map(|c| Player {
+                    id: c.id,
+                    name: c.last_state.name.clone(),
+                    head: c.last_state.head,
+                    hands: c.last_state.hands,
+                    since_update: u32::try_from(c.last_heard.elapsed().as_millis())
+                        .unwrap_or(u32::MAX),
+                })
+                .collect();
 
             for mut conn in conns_tmp.drain(..) {
+                // Each client receives everyone except themselves, nearest first if it can't
+                // draw them all
+                let mut others: Vec<Player> = players
+                    .iter()
+                    .filter(|p| p.id != conn.id)
+                    .cloned()
+                    .collect();
+
+                let max = conn.max_players;
+                if max > 0 && others.len() > max {
+                    let pos = conn.last_state.head.pos;
+                    others.sort_by(|a, b| {
+                        let da = (a.head.pos - pos).norm_squared();
+                        let db = (b.head.pos - pos).norm_squared();
+                        da.total_cmp(&db)
+                    });
+                    others.truncate(max);
+                }
+
+                let state = ServerState {
+                    players: others,
+                    echo: conn.last_state.ping,
+                    app_data: relayed
+                        .iter()
+                        .filter(|(id, _)| *id != conn.id)
+                        .cloned()
+                        .collect(),
+                };
+
+                let mut msg = vec![];
+                serialize_msg(&state, &mut msg)?;
+
                 match conn.stream.write_all(&msg) {
                     Ok(_) => conns.push(conn),
                     Err(e) => match e.kind() {
@@ -98,7 +185,7 @@ fn server(conn_rx: Receiver<(TcpStream, SocketAddr)>) -> Result<()> {
                         | io::ErrorKind::ConnectionAborted => {
                             eprintln!("{} Disconnected", conn.addr);
                         }
-                        _ => return Err(e.into()),
+                        _ => eprintln!("{} Dropped; {}", conn.addr, e),
                     },
                 }
             }
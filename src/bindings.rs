@@ -0,0 +1,369 @@
+//! Keyboard bindings of the desktop client and the VR mirror window, kept in the client config
+use glutin::event::VirtualKeyCode;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use winit_input_helper::WinitInputHelper;
+
+/// Keys which can be bound, by the names used in the config file
+const KEY_NAMES: &[VirtualKeyCode] = {
+    use VirtualKeyCode::*;
+    &[
+        A,
+        B,
+        C,
+        D,
+        E,
+        F,
+        G,
+        H,
+        I,
+        J,
+        K,
+        L,
+        M,
+        N,
+        O,
+        P,
+        Q,
+        R,
+        S,
+        T,
+        U,
+        V,
+        W,
+        X,
+        Y,
+        Z, //
+        Key0,
+        Key1,
+        Key2,
+        Key3,
+        Key4,
+        Key5,
+        Key6,
+        Key7,
+        Key8,
+        Key9, //
+        F1,
+        F2,
+        F3,
+        F4,
+        F5,
+        F6,
+        F7,
+        F8,
+        F9,
+        F10,
+        F11,
+        F12, //
+        Escape,
+        Tab,
+        Space,
+        Return,
+        Back,
+        Insert,
+        Delete,
+        Home,
+        End,
+        PageUp,
+        PageDown, //
+        Up,
+        Down,
+        Left,
+        Right, //
+        LShift,
+        RShift,
+        LControl,
+        RControl,
+        LAlt,
+        RAlt, //
+        Grave,
+        Minus,
+        Equals,
+        LBracket,
+        RBracket,
+        Backslash,
+        Semicolon,
+        Apostrophe,
+        Comma,
+        Period,
+        Slash, //
+        Numpad0,
+        Numpad1,
+        Numpad2,
+        Numpad3,
+        Numpad4,
+        Numpad5,
+        Numpad6,
+        Numpad7,
+        Numpad8,
+        Numpad9,
+        NumpadAdd,
+        NumpadSubtract,
+        NumpadMultiply,
+        NumpadDivide,
+        NumpadDecimal,
+        NumpadEnter,
+    ]
+};
+
+/// The keys bound to an action; any of them triggers it. Written in the config file as a key
+/// name, such as `"W"`, or a list of them, such as `["LShift", "RShift"]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keys(pub Vec<VirtualKeyCode>);
+
+/// Keys bound to each action, defaulting to a QWERTY layout. Unknown actions in the config are
+/// left out with a warning when it's loaded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub move_forward: Keys,
+    pub move_back: Keys,
+    pub strafe_left: Keys,
+    pub strafe_right: Keys,
+    pub ascend: Keys,
+    pub descend: Keys,
+    /// While walking
+    pub jump: Keys,
+    /// Between flying and walking
+    pub toggle_walk: Keys,
+    /// Held to fly faster...
+    pub speed_boost: Keys,
+    /// ...or slower
+    pub slow_down: Keys,
+    pub toggle_stats: Keys,
+    pub toggle_labels_through_walls: Keys,
+    /// Solid, wireframe and normals
+    pub cycle_debug_mode: Keys,
+    pub toggle_gamma: Keys,
+    pub toggle_terrain: Keys,
+    pub toggle_grid: Keys,
+    pub toggle_minimap: Keys,
+    pub toggle_settings: Keys,
+    pub toggle_third_person: Keys,
+    /// Between windowed and fullscreen
+    pub toggle_fullscreen: Keys,
+    /// Saves the window as a PNG
+    pub screenshot: Keys,
+    /// Held to zoom in, as is the right mouse button
+    pub zoom: Keys,
+    /// Orthographic view of the whole map from above
+    pub toggle_top_down: Keys,
+    /// Keep culling heads against the view as it is, to see the culling from elsewhere
+    pub freeze_culling: Keys,
+    /// Camera path recording and playback
+    pub add_keyframe: Keys,
+    pub clear_path: Keys,
+    pub save_path: Keys,
+    pub toggle_path_playback: Keys,
+    /// On the VR mirror window
+    pub recenter: Keys,
+    /// Floor height calibration, on the VR mirror window
+    pub raise_floor: Keys,
+    pub lower_floor: Keys,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+        let keys = |keys: &[VirtualKeyCode]| Keys(keys.to_vec());
+        Self {
+            move_forward: keys(&[W]),
+            move_back: keys(&[S]),
+            strafe_left: keys(&[A]),
+            strafe_right: keys(&[D]),
+            ascend: keys(&[E]),
+            descend: keys(&[Q]),
+            jump: keys(&[Space]),
+            toggle_walk: keys(&[G]),
+            speed_boost: keys(&[LShift, RShift]),
+            slow_down: keys(&[LAlt, RAlt]),
+            toggle_stats: keys(&[F1]),
+            toggle_labels_through_walls: keys(&[F2]),
+            cycle_debug_mode: keys(&[F3]),
+            toggle_gamma: keys(&[F4]),
+            toggle_terrain: keys(&[T]),
+            toggle_grid: keys(&[F6]),
+            toggle_minimap: keys(&[M]),
+            toggle_settings: keys(&[F10]),
+            toggle_third_person: keys(&[F5]),
+            toggle_fullscreen: keys(&[F11]),
+            screenshot: keys(&[F12]),
+            zoom: keys(&[Z]),
+            toggle_top_down: keys(&[F8]),
+            freeze_culling: keys(&[F7]),
+            add_keyframe: keys(&[K]),
+            clear_path: keys(&[Back]),
+            save_path: keys(&[F9]),
+            toggle_path_playback: keys(&[P]),
+            recenter: keys(&[R]),
+            raise_floor: keys(&[PageUp]),
+            lower_floor: keys(&[PageDown]),
+        }
+    }
+}
+
+impl Bindings {
+    /// Keys moving the camera of the right half in split screen, apart from the usual ones: the
+    /// arrow keys, Home and End to fly up and down, Right Control to go faster and Enter to jump
+    pub fn split_view() -> Self {
+        use VirtualKeyCode::*;
+        let keys = |keys: &[VirtualKeyCode]| Keys(keys.to_vec());
+        Self {
+            move_forward: keys(&[Up]),
+            move_back: keys(&[Down]),
+            strafe_left: keys(&[Left]),
+            strafe_right: keys(&[Right]),
+            ascend: keys(&[Home]),
+            descend: keys(&[End]),
+            jump: keys(&[Return]),
+            speed_boost: keys(&[RControl]),
+            slow_down: keys(&[]),
+            ..Self::default()
+        }
+    }
+}
+
+impl Keys {
+    /// Whether any of the keys is held down
+    pub fn held(&self, wih: &WinitInputHelper) -> bool {
+        self.0.iter().any(|&key| wih.key_held(key))
+    }
+
+    /// Whether any of the keys was pressed since the last update
+    pub fn pressed(&self, wih: &WinitInputHelper) -> bool {
+        self.0.iter().any(|&key| wih.key_pressed(key))
+    }
+
+    pub fn contains(&self, key: VirtualKeyCode) -> bool {
+        self.0.contains(&key)
+    }
+}
+
+/// Name of a key in the config file
+pub fn key_name(key: VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
+
+/// The key with a name, ignoring case
+pub fn parse_key(name: &str) -> Result<VirtualKeyCode, String> {
+    KEY_NAMES
+        .iter()
+        .copied()
+        .find(|&key| key_name(key).eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            format!(
+                "Unknown key `{}`; use a letter, Key0 to Key9, F1 to F12 or a name such as \
+                 Space, PageUp, LShift or Numpad0",
+                name
+            )
+        })
+}
+
+impl Serialize for Keys {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for &key in &self.0 {
+            seq.serialize_element(&key_name(key))?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> Visitor<'de> for KeysVisitor {
+            type Value = Keys;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a key name or a list of key names")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Keys, E> {
+                Ok(Keys(vec![parse_key(name).map_err(E::custom)?]))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Keys, A::Error> {
+                let mut keys = vec![];
+                while let Some(name) = seq.next_element::<String>()? {
+                    keys.push(parse_key(&name).map_err(de::Error::custom)?);
+                }
+                Ok(Keys(keys))
+            }
+        }
+
+        deserializer.deserialize_any(KeysVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names_parse_ignoring_case() {
+        assert_eq!(parse_key("W"), Ok(VirtualKeyCode::W));
+        assert_eq!(parse_key("pageup"), Ok(VirtualKeyCode::PageUp));
+        assert_eq!(parse_key("F12"), Ok(VirtualKeyCode::F12));
+        for &key in KEY_NAMES {
+            assert_eq!(parse_key(&key_name(key)), Ok(key));
+        }
+    }
+
+    #[test]
+    fn unknown_key_names_are_reported() {
+        let error = parse_key("Shift").unwrap_err();
+        assert!(error.starts_with("Unknown key `Shift`"), "{}", error);
+        assert!(parse_key("").is_err());
+
+        let error = toml::from_str::<Bindings>("ascend = [\"Space\", \"Spacebar\"]").unwrap_err();
+        assert!(
+            error.to_string().contains("Unknown key `Spacebar`"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn keys_are_a_name_or_a_list() {
+        let bindings: Bindings = toml::from_str(
+            "move_forward = \"z\"\nspeed_boost = [\"LShift\", \"Tab\"]\nascend = []",
+        )
+        .unwrap();
+        assert_eq!(bindings.move_forward, Keys(vec![VirtualKeyCode::Z]));
+        assert_eq!(
+            bindings.speed_boost,
+            Keys(vec![VirtualKeyCode::LShift, VirtualKeyCode::Tab])
+        );
+        assert_eq!(bindings.ascend, Keys(vec![]));
+        // Actions left out keep their defaults
+        assert_eq!(bindings.descend, Bindings::default().descend);
+    }
+
+    #[test]
+    fn bindings_round_trip_through_toml() {
+        for bindings in [Bindings::default(), Bindings::split_view()] {
+            let text = toml::to_string(&bindings).unwrap();
+            assert_eq!(toml::from_str::<Bindings>(&text).unwrap(), bindings);
+        }
+    }
+
+    #[test]
+    fn default_keys_are_bound_once() {
+        let table = toml::Value::try_from(Bindings::default()).unwrap();
+        let mut seen: Vec<(&str, &str)> = vec![];
+        for (action, keys) in table.as_table().unwrap() {
+            for key in keys.as_array().unwrap() {
+                let key = key.as_str().unwrap();
+                if let Some((other, _)) = seen.iter().find(|(_, k)| *k == key) {
+                    panic!("{} is bound to both {} and {}", key, other, action);
+                }
+                seen.push((action, key));
+            }
+        }
+    }
+}
@@ -1,12 +1,78 @@
+use crate::bindings::Bindings;
 use cubehead::Head;
 use glutin::{
     dpi::PhysicalPosition,
-    event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent},
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
 };
-use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3, Vector4};
+use nalgebra::{Matrix4, Point2, Point3, UnitQuaternion, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
 use std::f32::consts::FRAC_PI_2;
 use winit_input_helper::WinitInputHelper;
 
+/// Fly camera base speed to start with, in meters per second...
+pub const DEFAULT_FLY_SPEED: f32 = 3.;
+
+/// ...and the range it can be set within
+pub const FLY_SPEED_RANGE: (f32, f32) = (0.1, 200.);
+
+/// Base speed multiplier per notch of the scroll wheel
+const SCROLL_SPEED_FACTOR: f32 = 1.25;
+
+/// Speed multiplier while Shift is held...
+const FAST_MULTIPLIER: f32 = 4.;
+
+/// ...and while Alt is held
+const SLOW_MULTIPLIER: f32 = 0.25;
+
+/// Mouse look sensitivity to start with, in radians per pixel...
+pub const DEFAULT_LOOK_SENSITIVITY: f32 = 2e-3;
+
+/// ...and the range it can be set within
+pub const LOOK_SENSITIVITY_RANGE: (f32, f32) = (1e-4, 1e-2);
+
+/// Difference from the target velocity, in meters per second, below which the fly camera takes
+/// the target exactly, so that it comes to a full stop rather than creeping forever
+const STOP_SPEED: f32 = 1e-3;
+
+/// Distance of the third person camera behind the head to start with, in meters...
+pub const DEFAULT_ORBIT_DISTANCE: f32 = 2.5;
+
+/// ...and the range scrolling keeps it within
+const ORBIT_DISTANCE_RANGE: (f32, f32) = (0.5, 20.);
+
+/// Angle the third person camera looks down at the head from, in radians
+const ORBIT_ELEVATION: f32 = 0.35;
+
+/// Height the third person camera keeps above the ground, in meters
+const ORBIT_CLEARANCE: f32 = 0.2;
+
+/// Number of steps the third person camera backs off towards the head in to clear the ground
+const ORBIT_BACKOFF_STEPS: u32 = 16;
+
+/// Range of half the height of the top-down debug view, in meters
+const TOP_DOWN_EXTENT_RANGE: (f32, f32) = (1., 10_000.);
+
+/// Space the top-down debug view leaves around the map, above and below as well as at the sides,
+/// in meters, so that heads flying off the map stay in view
+const TOP_DOWN_MARGIN: f32 = 50.;
+
+/// Default distances to the near and far planes, in meters
+pub const DEFAULT_NEAR: f32 = 0.05;
+pub const DEFAULT_FAR: f32 = 1000.;
+
+/// Difference in field of view, in radians, below which zooming takes the target exactly
+const FOV_SNAP: f32 = 1e-4;
+
+/// Downward acceleration while walking, in meters per second squared
+pub const GRAVITY: f32 = 9.81;
+
+/// Upward speed of a jump, in meters per second, which rises about 0.8 m
+pub const JUMP_SPEED: f32 = 4.;
+
+/// Longest time step the fly camera moves by at once, in seconds, so that it doesn't leap forward
+/// after a hitch or a debugger pause
+const MAX_UPDATE_DT: f32 = 0.1;
+
 /// Camera controller and parameters
 #[derive(Default, Copy, Clone)]
 pub struct Camera {
@@ -82,9 +148,22 @@ impl Camera {
 /// Perspective projection parameters
 #[derive(Copy, Clone)]
 pub struct Perspective {
+    /// Vertical field of view, in radians
     pub fov: f32,
-    pub clip_near: f32,
-    pub clip_far: f32,
+    /// Field of view `fov` is easing towards, in radians
+    pub target_fov: f32,
+    pub projection: ProjectionConfig,
+}
+
+/// Clip planes and depth mapping shared by the desktop and VR projections
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProjectionConfig {
+    /// Distance to the near plane, in meters
+    pub near: f32,
+    /// Distance to the far plane, in meters. Unused with reversed depth, which has no far plane.
+    pub far: f32,
+    /// Map depth from 1 at the near plane to 0 at infinity, for `Engine::reverse_z`
+    pub reverse_z: bool,
 }
 
 /// Arcball camera parameters
@@ -106,8 +185,69 @@ pub struct ArcBallController {
 }
 
 impl Perspective {
+    /// Bring the field of view towards the target over `dt` seconds, returning whether it changed
+    /// and so whether the projection must be rebuilt
+    pub fn approach_fov(&mut self, dt: f32, time_constant: f32) -> bool {
+        let fov = approach_fov(self.fov, self.target_fov, dt, time_constant);
+        let changed = fov != self.fov;
+        self.fov = fov;
+        changed
+    }
+
     pub fn matrix(&self, width: f32, height: f32) -> Matrix4<f32> {
-        Matrix4::new_perspective(width / height, self.fov, self.clip_near, self.clip_far)
+        self.projection.symmetric(self.fov, width, height)
+    }
+}
+
+impl ProjectionConfig {
+    /// Projection of a frustum given the tangents of the angles from the view direction to each
+    /// side, negative to the left and below, as OpenXR describes each eye's field of view.
+    /// Without reversed depth, `near` and `far` map to -1 and 1 for OpenGL's default clip range.
+    pub fn frustum(
+        &self,
+        tan_left: f32,
+        tan_right: f32,
+        tan_down: f32,
+        tan_up: f32,
+    ) -> Matrix4<f32> {
+        let tan_width = tan_right - tan_left;
+        let tan_height = tan_up - tan_down;
+
+        let x_scale = 2. / tan_width;
+        let y_scale = 2. / tan_height;
+        let x_offset = (tan_right + tan_left) / tan_width;
+        let y_offset = (tan_up + tan_down) / tan_height;
+
+        if self.reverse_z {
+            return reversed_infinite_projection(x_scale, y_scale, x_offset, y_offset, self.near);
+        }
+
+        let (near, far) = (self.near, self.far);
+        let z_scale = -(far + near) / (far - near);
+        let z_offset = -2. * far * near / (far - near);
+        Matrix4::new(
+            x_scale, 0.0, x_offset, 0.0, //
+            0.0, y_scale, y_offset, 0.0, //
+            0.0, 0.0, z_scale, z_offset, //
+            0.0, 0.0, -1.0, 0.0, //
+        )
+    }
+
+    /// Normalized device depth of a point `distance` meters ahead
+    pub fn ndc_depth(&self, distance: f32) -> f32 {
+        let (near, far) = (self.near, self.far);
+        match self.reverse_z {
+            true => near / distance,
+            false => (far + near) / (far - near) - 2. * far * near / ((far - near) * distance),
+        }
+    }
+
+    /// Projection centered on the view direction, with a vertical field of view of `fov` radians
+    /// over a viewport of `width` by `height`
+    pub fn symmetric(&self, fov: f32, width: f32, height: f32) -> Matrix4<f32> {
+        let tan_up = (fov / 2.).tan();
+        let tan_right = tan_up * width / height;
+        self.frustum(-tan_right, tan_right, -tan_up, tan_up)
     }
 }
 
@@ -176,8 +316,18 @@ impl Default for Perspective {
     fn default() -> Self {
         Self {
             fov: 45.0f32.to_radians(),
-            clip_near: 0.0001,
-            clip_far: 20_000.0,
+            target_fov: 45.0f32.to_radians(),
+            projection: ProjectionConfig::default(),
+        }
+    }
+}
+
+impl Default for ProjectionConfig {
+    fn default() -> Self {
+        Self {
+            near: DEFAULT_NEAR,
+            far: DEFAULT_FAR,
+            reverse_z: false,
         }
     }
 }
@@ -193,58 +343,249 @@ impl Default for ArcBallController {
     }
 }
 
+/// Fly camera settings, kept in the client config and changed live from the settings panel
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// Base speed, in meters per second
+    pub fly_speed: f32,
+    /// Rotation per pixel of mouse movement, in radians
+    pub look_sensitivity: f32,
+    /// Moving the mouse up looks down
+    pub invert_y: bool,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            fly_speed: DEFAULT_FLY_SPEED,
+            look_sensitivity: DEFAULT_LOOK_SENSITIVITY,
+            invert_y: false,
+        }
+    }
+}
+
+impl CameraSettings {
+    /// The same settings within their ranges, as a hand-edited config may not be
+    pub fn clamped(self) -> Self {
+        let (min_speed, max_speed) = FLY_SPEED_RANGE;
+        let (min_sensitivity, max_sensitivity) = LOOK_SENSITIVITY_RANGE;
+        Self {
+            fly_speed: self.fly_speed.clamp(min_speed, max_speed),
+            look_sensitivity: self
+                .look_sensitivity
+                .clamp(min_sensitivity, max_sensitivity),
+            ..self
+        }
+    }
+
+    /// How mouse movement turns the fly camera
+    pub fn look(&self) -> LookConfig {
+        LookConfig {
+            sensitivity: self.look_sensitivity,
+            invert_y: self.invert_y,
+        }
+    }
+}
+
+/// How mouse movement turns the fly camera
+#[derive(Copy, Clone, Debug)]
+pub struct LookConfig {
+    /// Radians per pixel of mouse movement
+    pub sensitivity: f32,
+    /// Moving the mouse up looks down
+    pub invert_y: bool,
+}
+
+/// Height of the ground, for keeping the fly camera above it
+pub trait HeightQuery {
+    /// Height of the ground at (x, z), or None where there is no ground
+    fn ground_height(&self, x: f32, z: f32) -> Option<f32>;
+}
+
+/// Level ground at a fixed height, stretching forever
+pub struct FlatGround(pub f32);
+
+impl HeightQuery for FlatGround {
+    fn ground_height(&self, _x: f32, _z: f32) -> Option<f32> {
+        Some(self.0)
+    }
+}
+
+/// How quickly the fly camera speeds up and slows down. Each is the time in seconds to cover 63%
+/// of the difference to the velocity the keys ask for; zero changes velocity instantly.
+#[derive(Copy, Clone, Debug)]
+pub struct FlySmoothing {
+    pub accelerate: f32,
+    pub decelerate: f32,
+}
+
 pub struct FlyCam {
+    pub mode: MovementMode,
     yaw: f32,
     pitch: f32,
     pos: Point3<f32>,
+    /// Meters per second, before Shift or Alt
+    speed: f32,
+    /// Meters per second
+    velocity: Vector3<f32>,
+    /// Standing on the ground as of the last `collide`
+    grounded: bool,
+}
+
+/// How the fly camera moves
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MovementMode {
+    /// Freely in the direction of view
+    Fly,
+    /// Across the ground, falling under gravity and able to jump
+    Walk,
 }
 
 impl FlyCam {
     pub fn new(pos: Point3<f32>) -> Self {
         Self {
+            mode: MovementMode::Fly,
             yaw: 0.,
             pitch: 0.,
             pos,
+            speed: DEFAULT_FLY_SPEED,
+            velocity: Vector3::zeros(),
+            grounded: false,
+        }
+    }
+
+    /// Whether the camera stood on the ground as of the last `collide`
+    pub fn grounded(&self) -> bool {
+        self.grounded
+    }
+
+    /// Base speed in meters per second
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Set the base speed in meters per second, within `FLY_SPEED_RANGE`
+    pub fn set_speed(&mut self, speed: f32) {
+        let (min, max) = FLY_SPEED_RANGE;
+        self.speed = speed.clamp(min, max);
+    }
+
+    /// Scale the base speed by scrolling the wheel `notches` notches
+    pub fn scroll_speed(&mut self, notches: f32) {
+        if notches != 0. {
+            self.set_speed(self.speed * SCROLL_SPEED_FACTOR.powf(notches));
         }
     }
 
-    pub fn update(&mut self, wih: &WinitInputHelper, mut speed: f32, sensitivity: f32) {
+    /// Look and move over `dt` seconds since the last update. Movement depends only on time, never
+    /// on how often this is called. Holding Shift or Alt moves faster or slower. Looking is
+    /// direct, while movement eases in and out by `smoothing`. Walking moves along the ground in
+    /// the direction of view, whatever the pitch, and jumps instead of ascending.
+    pub fn update(
+        &mut self,
+        wih: &WinitInputHelper,
+        dt: f32,
+        look: LookConfig,
+        smoothing: FlySmoothing,
+        bindings: &Bindings,
+    ) {
+        let mut speed = self.speed;
+        if bindings.speed_boost.held(wih) {
+            speed *= FAST_MULTIPLIER;
+        }
+        if bindings.slow_down.held(wih) {
+            speed *= SLOW_MULTIPLIER;
+        }
+
         if wih.mouse_held(0) {
-            let (x_delta, y_delta) = wih.mouse_diff();
-            self.yaw += x_delta * sensitivity;
-            self.pitch = (self.pitch + y_delta * sensitivity).clamp(-FRAC_PI_2, FRAC_PI_2);
+            let (yaw, pitch) = look_angles(wih.mouse_diff(), look);
+            self.yaw += yaw;
+            self.pitch = (self.pitch + pitch).clamp(-FRAC_PI_2, FRAC_PI_2);
+        }
+
+        // The keys set the velocity to head for
+        let facing = match self.mode {
+            MovementMode::Fly => self.head().orient,
+            MovementMode::Walk => UnitQuaternion::from_euler_angles(0., self.yaw, 0.),
+        };
+        let tf_vect = |v| facing.transform_vector(&v) * speed;
+        let mut target = Vector3::zeros();
+
+        if bindings.move_forward.held(wih) {
+            target += tf_vect(-Vector3::z());
+        }
+
+        if bindings.move_back.held(wih) {
+            target += tf_vect(Vector3::z());
         }
 
-        let head = self.head();
-        let tf_vect = |v| head.orient.transform_vector(&v) * speed;
+        if bindings.strafe_left.held(wih) {
+            target += tf_vect(-Vector3::x());
+        }
 
-        if wih.key_held(VirtualKeyCode::W) {
-            self.pos += tf_vect(-Vector3::z());
+        if bindings.strafe_right.held(wih) {
+            target += tf_vect(Vector3::x());
         }
 
-        if wih.key_held(VirtualKeyCode::S) {
-            self.pos += tf_vect(Vector3::z());
+        let dt = dt.clamp(0., MAX_UPDATE_DT);
+        if self.mode == MovementMode::Walk {
+            self.walk(target, dt, smoothing, bindings.jump.pressed(wih));
+            return;
         }
 
-        if wih.key_held(VirtualKeyCode::A) {
-            self.pos += tf_vect(-Vector3::x());
+        if bindings.ascend.held(wih) {
+            //target += tf_vect(-Vector3::y());
+            target += Vector3::y() * speed;
         }
 
-        if wih.key_held(VirtualKeyCode::D) {
-            self.pos += tf_vect(Vector3::x());
+        if bindings.descend.held(wih) {
+            //target += tf_vect(Vector3::y());
+            target += -Vector3::y() * speed;
         }
 
-        if wih.key_held(VirtualKeyCode::E) {
-            //self.pos += tf_vect(-Vector3::y());
-            self.pos += Vector3::y() * speed;
+        self.glide(target, dt, smoothing);
+    }
+
+    /// Bring the horizontal velocity towards `target` over `dt` seconds while falling, jumping
+    /// first if `jump` and on the ground
+    pub fn walk(&mut self, target: Vector3<f32>, dt: f32, smoothing: FlySmoothing, jump: bool) {
+        let horizontal = Vector3::new(self.velocity.x, 0., self.velocity.z);
+        let horizontal = approach_velocity(horizontal, target, dt, smoothing);
+        if jump && self.grounded {
+            self.velocity.y = JUMP_SPEED;
+            self.grounded = false;
         }
 
-        if wih.key_held(VirtualKeyCode::Q) {
-            //self.pos += tf_vect(Vector3::y());
-            self.pos += -Vector3::y() * speed;
+        let (y, vertical) = fall(self.pos.y, self.velocity.y, dt);
+        self.velocity = Vector3::new(horizontal.x, vertical, horizontal.z);
+        self.pos += Vector3::new(horizontal.x, 0., horizontal.z) * dt;
+        self.pos.y = y;
+    }
+
+    /// Keep the camera at least `eye_height` above the ground, stopping any fall into it. Call
+    /// after all movement for the frame.
+    pub fn collide(&mut self, ground: &dyn HeightQuery, eye_height: f32) {
+        let floor = match ground.ground_height(self.pos.x, self.pos.z) {
+            Some(height) => height + eye_height,
+            None => {
+                self.grounded = false;
+                return;
+            }
+        };
+        self.grounded = self.pos.y <= floor;
+        if self.pos.y < floor {
+            self.pos.y = floor;
+            self.velocity.y = self.velocity.y.max(0.);
         }
     }
 
+    /// Bring the velocity towards `target` over `dt` seconds, then move by it
+    pub fn glide(&mut self, target: Vector3<f32>, dt: f32, smoothing: FlySmoothing) {
+        self.velocity = approach_velocity(self.velocity, target, dt, smoothing);
+        self.pos += self.velocity * dt;
+    }
+
     pub fn head(&self) -> Head {
         Head {
             pos: self.pos,
@@ -252,3 +593,816 @@ impl FlyCam {
         }
     }
 }
+
+/// Height and upward velocity after falling under `GRAVITY` for `dt` seconds from height `y` at
+/// upward velocity `vy`. Exact for any time step, so a jump peaks at `vy² / 2g` however often this
+/// is called.
+pub fn fall(y: f32, vy: f32, dt: f32) -> (f32, f32) {
+    (y + vy * dt - 0.5 * GRAVITY * dt * dt, vy - GRAVITY * dt)
+}
+
+/// Third person view of the desktop camera, looking down at the head from behind
+pub struct Orbit {
+    /// Meters from the head, when the ground isn't in the way
+    pub distance: f32,
+}
+
+impl Orbit {
+    pub fn new() -> Self {
+        Self {
+            distance: DEFAULT_ORBIT_DISTANCE,
+        }
+    }
+
+    /// Move closer or further by scrolling the wheel `notches` notches
+    pub fn zoom(&mut self, notches: f32) {
+        let (min, max) = ORBIT_DISTANCE_RANGE;
+        self.distance = (self.distance / SCROLL_SPEED_FACTOR.powf(notches)).clamp(min, max);
+    }
+
+    /// Where to view `head` from
+    pub fn view(&self, head: &Head, ground: &dyn HeightQuery) -> Head {
+        orbit_view(head, self.distance, ground)
+    }
+}
+
+/// Debug views of the desktop client, independent of the player's head
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugCamera {
+    /// Orthographic view straight down onto the map, with -Z up the screen
+    TopDown {
+        /// Point on the ground at the middle of the view, as X and Z
+        center: Point2<f32>,
+        /// Half the height of the view, in meters
+        extent: f32,
+    },
+}
+
+impl DebugCamera {
+    /// Top-down view of the whole map, given its bounding box
+    pub fn top_down(map_bounds: (Point3<f32>, Point3<f32>)) -> Self {
+        let (min, max) = map_bounds;
+        let (lo, hi) = TOP_DOWN_EXTENT_RANGE;
+        let extent = (max.x - min.x).max(max.z - min.z) / 2. + TOP_DOWN_MARGIN;
+        Self::TopDown {
+            center: Point2::new((min.x + max.x) / 2., (min.z + max.z) / 2.),
+            extent: extent.clamp(lo, hi),
+        }
+    }
+
+    /// Move the view along with a mouse drag of `delta` pixels, over a viewport `height` pixels
+    /// tall, so that the ground under the cursor stays there
+    pub fn pan(&mut self, delta: (f32, f32), height: f32) {
+        let Self::TopDown { center, extent } = self;
+        let meters_per_pixel = 2. * *extent / height;
+        center.x -= delta.0 * meters_per_pixel;
+        center.y -= delta.1 * meters_per_pixel;
+    }
+
+    /// Zoom in or out by scrolling the wheel `notches` notches
+    pub fn zoom(&mut self, notches: f32) {
+        let Self::TopDown { extent, .. } = self;
+        let (min, max) = TOP_DOWN_EXTENT_RANGE;
+        *extent = (*extent / SCROLL_SPEED_FACTOR.powf(notches)).clamp(min, max);
+    }
+
+    /// Projection and view matrices for a viewport of `width` by `height`, with depth covering
+    /// the map's bounding box
+    pub fn matrices(
+        &self,
+        map_bounds: (Point3<f32>, Point3<f32>),
+        width: f32,
+        height: f32,
+        reverse_z: bool,
+    ) -> (Matrix4<f32>, Matrix4<f32>) {
+        let Self::TopDown { center, extent } = *self;
+        let (min, max) = map_bounds;
+        let top = max.y + TOP_DOWN_MARGIN;
+        let depth = top - min.y + TOP_DOWN_MARGIN;
+        let proj = orthographic(extent * width / height, extent, 0., depth, reverse_z);
+
+        let eye = Point3::new(center.x, top, center.y);
+        let below = Point3::new(center.x, top - 1., center.y);
+        let view = Matrix4::look_at_rh(&eye, &below, &-Vector3::z());
+        (proj, view)
+    }
+}
+
+/// Orthographic projection of the box `half_width` to either side, `half_height` above and below
+/// and from `near` to `far` ahead. Depth goes from -1 at `near` to 1 at `far`, or with `reverse_z`
+/// from 1 to 0 for 0..1 clip control.
+pub fn orthographic(
+    half_width: f32,
+    half_height: f32,
+    near: f32,
+    far: f32,
+    reverse_z: bool,
+) -> Matrix4<f32> {
+    let (z_scale, z_offset) = match reverse_z {
+        true => (1. / (far - near), far / (far - near)),
+        false => (-2. / (far - near), -(far + near) / (far - near)),
+    };
+    Matrix4::new(
+        1. / half_width,
+        0.0,
+        0.0,
+        0.0, //
+        0.0,
+        1. / half_height,
+        0.0,
+        0.0, //
+        0.0,
+        0.0,
+        z_scale,
+        z_offset, //
+        0.0,
+        0.0,
+        0.0,
+        1.0, //
+    )
+}
+
+/// View from `distance` meters behind and above `head`, looking down at it. Where that would be
+/// within `ORBIT_CLEARANCE` of the ground, the view backs off towards the head until it is clear.
+pub fn orbit_view(head: &Head, distance: f32, ground: &dyn HeightQuery) -> Head {
+    // Pitching the view down swings the boom behind the head up
+    let orient =
+        head.orient * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -ORBIT_ELEVATION);
+    let back = orient * Vector3::z();
+    let clear = |distance: f32| {
+        let pos = head.pos + back * distance;
+        ground
+            .ground_height(pos.x, pos.z)
+            .is_none_or(|height| pos.y >= height + ORBIT_CLEARANCE)
+    };
+
+    let distance = (0..=ORBIT_BACKOFF_STEPS)
+        .map(|step| distance * (1. - step as f32 / ORBIT_BACKOFF_STEPS as f32))
+        .find(|&distance| clear(distance))
+        .unwrap_or(0.);
+
+    Head {
+        pos: head.pos + back * distance,
+        orient,
+    }
+}
+
+/// Velocity after chasing `target` for `dt` seconds, with the time constant to accelerate while
+/// the target is faster and to decelerate while it is slower. Independent of frame rate; two steps
+/// of dt land where one step of 2 * dt does.
+pub fn approach_velocity(
+    velocity: Vector3<f32>,
+    target: Vector3<f32>,
+    dt: f32,
+    smoothing: FlySmoothing,
+) -> Vector3<f32> {
+    let time_constant = match target.norm() >= velocity.norm() {
+        true => smoothing.accelerate,
+        false => smoothing.decelerate,
+    };
+    if time_constant <= 0. {
+        return target;
+    }
+
+    let next = velocity + (target - velocity) * (1. - (-dt / time_constant).exp());
+    match (target - next).norm() < STOP_SPEED {
+        true => target,
+        false => next,
+    }
+}
+
+/// Field of view after easing from `fov` towards `target` for `dt` seconds, covering 63% of the
+/// difference per `time_constant` seconds and taking the target once within `FOV_SNAP`. Zero
+/// time constants jump straight to the target.
+pub fn approach_fov(fov: f32, target: f32, dt: f32, time_constant: f32) -> f32 {
+    if time_constant <= 0. {
+        return target;
+    }
+    let next = fov + (target - fov) * (1. - (-dt / time_constant).exp());
+    match (target - next).abs() < FOV_SNAP {
+        true => target,
+        false => next,
+    }
+}
+
+/// Look sensitivity while zoomed to `fov` from `default_fov`, scaled down by the magnification so
+/// that the view moves the same distance on screen per pixel of mouse movement
+pub fn zoomed_sensitivity(sensitivity: f32, fov: f32, default_fov: f32) -> f32 {
+    sensitivity * (fov / 2.).tan() / (default_fov / 2.).tan()
+}
+
+/// Change in yaw and pitch, in radians, for a mouse movement in pixels
+pub fn look_angles((x_delta, y_delta): (f32, f32), look: LookConfig) -> (f32, f32) {
+    let y_delta = match look.invert_y {
+        true => -y_delta,
+        false => y_delta,
+    };
+    (x_delta * look.sensitivity, y_delta * look.sensitivity)
+}
+
+/// Perspective projection with an infinite far plane, mapping depth from 1 at `near` to 0 at
+/// infinity (for 0..1 clip control). `x_scale` and `y_scale` are the reciprocal tangents of the
+/// half field of view, and `x_offset` and `y_offset` shift the center for asymmetric frusta.
+pub fn reversed_infinite_projection(
+    x_scale: f32,
+    y_scale: f32,
+    x_offset: f32,
+    y_offset: f32,
+    near: f32,
+) -> Matrix4<f32> {
+    Matrix4::new(
+        x_scale, 0.0, x_offset, 0.0, //
+        0.0, y_scale, y_offset, 0.0, //
+        0.0, 0.0, 0.0, near, //
+        0.0, 0.0, -1.0, 0.0, //
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glutin::event::{DeviceId, Event, KeyboardInput, ModifiersState};
+    use glutin::window::WindowId;
+
+    /// Ground as high as `height` within `radius` of the origin, and none beyond
+    struct Island {
+        height: f32,
+        radius: f32,
+    }
+
+    impl HeightQuery for Island {
+        fn ground_height(&self, x: f32, z: f32) -> Option<f32> {
+            (x.hypot(z) <= self.radius).then_some(self.height)
+        }
+    }
+
+    #[test]
+    fn reversed_depth_falls_from_one_to_zero() {
+        let proj = reversed_infinite_projection(2., 4., 0., 0., 0.25);
+        let depth = |distance: f32| {
+            let clip = proj * Vector4::new(0., 0., -distance, 1.);
+            assert_eq!(clip.w, distance);
+            clip.z / clip.w
+        };
+        assert_eq!(depth(0.25), 1.);
+        assert_eq!(depth(0.5), 0.5);
+        assert_eq!(depth(1.), 0.25);
+
+        // Always in front of the far end, however far away, and still telling distant points apart
+        let mut last = depth(1.);
+        for distance in [10., 1e3, 1e6, 1e30] {
+            let next = depth(distance);
+            assert!(next > 0. && next < last, "{} m", distance);
+            last = next;
+        }
+        assert!(depth(1000.) > depth(1001.));
+
+        // The sides scale by the reciprocal half tangents, whatever the distance
+        let clip = proj * Vector4::new(3., 1.5, -6., 1.);
+        assert_eq!((clip.x / clip.w, clip.y / clip.w), (1., 1.));
+    }
+
+    /// Input holding down `key`
+    #[allow(deprecated)] // KeyboardInput::modifiers
+    fn holding(key: VirtualKeyCode) -> WinitInputHelper {
+        let mut wih = WinitInputHelper::new();
+        let event: Event<()> = Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event: WindowEvent::KeyboardInput {
+                device_id: unsafe { DeviceId::dummy() },
+                input: KeyboardInput {
+                    scancode: 0,
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(key),
+                    modifiers: ModifiersState::empty(),
+                },
+                is_synthetic: false,
+            },
+        };
+        wih.update(&event);
+        wih
+    }
+
+    const LOOK: LookConfig = LookConfig {
+        sensitivity: 0.,
+        invert_y: false,
+    };
+
+    const SMOOTHING: FlySmoothing = FlySmoothing {
+        accelerate: 0.5,
+        decelerate: 0.5,
+    };
+
+    const INSTANT: FlySmoothing = FlySmoothing {
+        accelerate: 0.,
+        decelerate: 0.,
+    };
+
+    /// Where the fly camera ends up after `steps` updates of `dt` seconds each
+    fn flown(wih: &WinitInputHelper, steps: u32, dt: f32) -> Point3<f32> {
+        let mut camera = FlyCam::new(Point3::origin());
+        for _ in 0..steps {
+            camera.update(wih, dt, LOOK, INSTANT, &Bindings::default());
+        }
+        camera.pos
+    }
+
+    #[test]
+    fn fly_steps_are_capped_and_frame_rate_independent() {
+        let right = holding(VirtualKeyCode::D);
+        let one = flown(&right, 1, 1. / 30.);
+        assert!((one.x - DEFAULT_FLY_SPEED / 30.).abs() < 1e-6);
+        assert!((flown(&right, 2, 1. / 60.) - one).norm() < 1e-6);
+
+        // A hitch moves no further than the longest step, and time never runs backwards
+        assert_eq!(flown(&right, 1, 5.), flown(&right, 1, MAX_UPDATE_DT));
+        assert_eq!(flown(&right, 1, -1.), Point3::origin());
+    }
+
+    #[test]
+    fn hitches_move_no_further_than_the_longest_step() {
+        let idle = WinitInputHelper::new();
+        let mut hitched = FlyCam::new(Point3::origin());
+        hitched.velocity = Vector3::new(2., 0., 0.);
+        hitched.update(&idle, 5., LOOK, SMOOTHING, &Bindings::default());
+
+        let mut stepped = FlyCam::new(Point3::origin());
+        stepped.velocity = Vector3::new(2., 0., 0.);
+        stepped.glide(Vector3::zeros(), MAX_UPDATE_DT, SMOOTHING);
+        assert_eq!(hitched.pos, stepped.pos);
+        assert_eq!(hitched.velocity, stepped.velocity);
+        assert!(hitched.pos.x > 0. && hitched.pos.x < 2. * MAX_UPDATE_DT);
+
+        // Time never runs backwards
+        let mut camera = FlyCam::new(Point3::origin());
+        camera.velocity = Vector3::new(2., 0., 0.);
+        camera.update(&idle, -1., LOOK, SMOOTHING, &Bindings::default());
+        assert_eq!(camera.pos, Point3::origin());
+        assert_eq!(camera.velocity, Vector3::new(2., 0., 0.));
+    }
+
+    #[test]
+    fn look_angles_scale_by_sensitivity() {
+        // Powers of two keep the products exact
+        let look = LookConfig {
+            sensitivity: 0.25,
+            invert_y: false,
+        };
+        assert_eq!(look_angles((0., 0.), look), (0., 0.));
+        assert_eq!(look_angles((10., -20.), look), (2.5, -5.));
+        let slower = LookConfig {
+            sensitivity: 0.125,
+            ..look
+        };
+        assert_eq!(look_angles((10., -20.), slower), (1.25, -2.5));
+    }
+
+    #[test]
+    fn inverting_y_flips_only_the_pitch() {
+        let look = LookConfig {
+            sensitivity: 0.25,
+            invert_y: true,
+        };
+        assert_eq!(look_angles((10., -20.), look), (2.5, 5.));
+        assert_eq!(look_angles((-10., 20.), look), (-2.5, -5.));
+    }
+
+    #[test]
+    fn velocity_eases_by_its_time_constants() {
+        let smoothing = FlySmoothing {
+            accelerate: 0.5,
+            decelerate: 2.,
+        };
+        // Speeding up covers 63% of the difference per time constant of accelerating...
+        let target = Vector3::new(1., 0., 0.);
+        let faster = approach_velocity(Vector3::zeros(), target, 0.5, smoothing);
+        assert!((faster.x - (1. - (-1f32).exp())).abs() < 1e-6);
+        // ...and slowing down per time constant of decelerating
+        let slower = approach_velocity(Vector3::new(2., 0., 0.), target, 2., smoothing);
+        assert!((slower.x - (1. + (-1f32).exp())).abs() < 1e-6);
+
+        let instant = FlySmoothing {
+            accelerate: 0.,
+            decelerate: 0.,
+        };
+        let velocity = Vector3::new(0., 5., 0.);
+        assert_eq!(approach_velocity(velocity, target, 0.01, instant), target);
+        assert_eq!(approach_velocity(target, velocity, 0.01, instant), velocity);
+    }
+
+    #[test]
+    fn velocity_is_independent_of_step_size() {
+        let target = Vector3::new(3., 0., -4.);
+        let once = approach_velocity(Vector3::zeros(), target, 0.2, SMOOTHING);
+        let half = approach_velocity(Vector3::zeros(), target, 0.1, SMOOTHING);
+        let twice = approach_velocity(half, target, 0.1, SMOOTHING);
+        assert!((once - twice).norm() < 1e-5);
+    }
+
+    #[test]
+    fn velocity_settles_on_the_target() {
+        let target = Vector3::new(0., 0., -3.);
+        let mut velocity = Vector3::new(1., 0., 0.);
+        let mut steps = 0;
+        while velocity != target {
+            let next = approach_velocity(velocity, target, 1. / 60., SMOOTHING);
+            // Always closer, never overshooting
+            assert!((target - next).norm() < (target - velocity).norm());
+            velocity = next;
+            steps += 1;
+            assert!(steps < 1000, "never settled; {:?}", velocity);
+        }
+        // Coming to a stop is the same
+        let stop = approach_velocity(
+            Vector3::new(STOP_SPEED / 2., 0., 0.),
+            Vector3::zeros(),
+            0.01,
+            SMOOTHING,
+        );
+        assert_eq!(stop, Vector3::zeros());
+    }
+
+    #[test]
+    fn flat_ground_is_level_everywhere() {
+        let ground = FlatGround(-2.5);
+        for (x, z) in [(0., 0.), (1e6, -3.), (-7., 1e-3)] {
+            assert_eq!(ground.ground_height(x, z), Some(-2.5));
+        }
+    }
+
+    #[test]
+    fn cameras_stop_at_eye_height_above_the_ground() {
+        let ground = FlatGround(1.);
+        let mut camera = FlyCam::new(Point3::new(3., 0.5, 4.));
+        camera.velocity = Vector3::new(1., -2., 0.);
+        camera.collide(&ground, 1.7);
+        assert_eq!(camera.pos, Point3::new(3., 2.7, 4.));
+        // The fall stops, but not the sliding along the ground
+        assert_eq!(camera.velocity, Vector3::new(1., 0., 0.));
+        assert!(camera.grounded());
+
+        // Rising out of the ground keeps going up
+        camera.pos.y = 2.;
+        camera.velocity.y = JUMP_SPEED;
+        camera.collide(&ground, 1.7);
+        assert_eq!((camera.pos.y, camera.velocity.y), (2.7, JUMP_SPEED));
+
+        // Above the ground is left alone
+        camera.pos.y = 5.;
+        camera.collide(&ground, 1.7);
+        assert_eq!(camera.pos.y, 5.);
+        assert!(!camera.grounded());
+    }
+
+    #[test]
+    fn cameras_fall_past_the_edge_of_the_ground() {
+        let island = Island {
+            height: 0.,
+            radius: 1.,
+        };
+        let mut camera = FlyCam::new(Point3::new(0., 0.5, 0.));
+        camera.collide(&island, 1.7);
+        assert_eq!(camera.pos.y, 1.7);
+        assert!(camera.grounded());
+
+        camera.pos = Point3::new(2., -10., 0.);
+        camera.velocity = Vector3::new(0., -5., 0.);
+        camera.collide(&island, 1.7);
+        assert_eq!(camera.pos, Point3::new(2., -10., 0.));
+        assert_eq!(camera.velocity.y, -5.);
+        assert!(!camera.grounded());
+    }
+
+    fn head_at(pos: [f32; 3]) -> Head {
+        Head {
+            pos: pos.into(),
+            orient: UnitQuaternion::identity(),
+        }
+    }
+
+    #[test]
+    fn orbit_view_is_behind_and_above_the_head() {
+        let head = head_at([0., 2., 0.]);
+        let view = orbit_view(&head, 3., &FlatGround(0.));
+        let offset = view.pos - head.pos;
+        assert!((offset.norm() - 3.).abs() < 1e-4);
+        // Forward is -Z, so behind is +Z
+        assert!(offset.z > 0. && offset.y > 0.);
+        assert!(offset.x.abs() < 1e-5);
+
+        // Looking back at the head
+        let forward = view.orient * -Vector3::z();
+        assert!((forward.dot(&-offset.normalize()) - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orbit_view_backs_off_from_the_ground() {
+        // Looking up, the boom swings down below the ground behind the head
+        let head = Head {
+            pos: Point3::new(0., 1., 0.),
+            orient: UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 1.2),
+        };
+        let ground = FlatGround(0.);
+        let view = orbit_view(&head, 10., &ground);
+        let distance = (view.pos - head.pos).norm();
+        assert!(distance < 10.);
+        assert!(view.pos.y >= ORBIT_CLEARANCE - 1e-4);
+
+        // No further than it needs to
+        let further = distance + 10. / ORBIT_BACKOFF_STEPS as f32;
+        let back = view.orient * Vector3::z();
+        assert!((head.pos + back * further).y < ORBIT_CLEARANCE);
+    }
+
+    #[test]
+    fn orbit_view_sits_on_the_head_when_nothing_is_clear() {
+        let head = head_at([0., 0., 0.]);
+        let view = orbit_view(&head, 5., &FlatGround(1.));
+        assert_eq!(view.pos, head.pos);
+    }
+
+    #[test]
+    fn orbit_view_ignores_missing_ground() {
+        let head = Head {
+            pos: Point3::new(0., 1., 0.),
+            orient: UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 1.2),
+        };
+        let island = Island {
+            height: 0.,
+            radius: 0.5,
+        };
+        let view = orbit_view(&head, 10., &island);
+        assert!(((view.pos - head.pos).norm() - 10.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fov_eases_then_snaps_to_the_target() {
+        let (wide, narrow) = (1.2, 0.4);
+        let fov = approach_fov(wide, narrow, 0.1, 0.1);
+        assert!((fov - (narrow + (wide - narrow) * (-1f32).exp())).abs() < 1e-6);
+        assert_eq!(approach_fov(wide, narrow, 0.1, 0.), narrow);
+        assert_eq!(
+            approach_fov(narrow + FOV_SNAP / 2., narrow, 1e-3, 0.1),
+            narrow
+        );
+        assert_eq!(approach_fov(narrow, narrow, 0.1, 0.1), narrow);
+
+        // Rebuilding the projection is only needed until the target is reached
+        let mut perspective = Perspective {
+            target_fov: narrow,
+            ..Perspective::default()
+        };
+        let mut frames = 0;
+        while perspective.approach_fov(1. / 60., 0.1) {
+            frames += 1;
+            assert!(frames < 1000);
+        }
+        assert_eq!(perspective.fov, narrow);
+        assert!(!perspective.approach_fov(1. / 60., 0.1));
+    }
+
+    #[test]
+    fn zoomed_sensitivity_follows_the_magnification() {
+        let default_fov = 90f32.to_radians();
+        assert!((zoomed_sensitivity(2e-3, default_fov, default_fov) - 2e-3).abs() < 1e-9);
+        // Half the tangent of the half angle is twice the magnification
+        let zoomed = 2. * 0.5f32.atan();
+        assert!((zoomed_sensitivity(2e-3, zoomed, default_fov) - 1e-3).abs() < 1e-9);
+        assert!(zoomed_sensitivity(2e-3, 120f32.to_radians(), default_fov) > 2e-3);
+    }
+
+    fn assert_matrix_close(a: &Matrix4<f32>, b: &Matrix4<f32>) {
+        assert!((a - b).abs().max() < 1e-5, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn symmetric_projections_match_nalgebra() {
+        let config = ProjectionConfig {
+            near: 0.1,
+            far: 100.,
+            reverse_z: false,
+        };
+        let fov = 70f32.to_radians();
+        let expected = nalgebra::Perspective3::new(16. / 9., fov, 0.1, 100.).to_homogeneous();
+        let proj = config.symmetric(fov, 1600., 900.);
+        assert_matrix_close(&proj, &expected);
+
+        // Near and far go to either end of the clip range
+        let depth = |distance: f32| {
+            let clip = proj * Vector4::new(0., 0., -distance, 1.);
+            clip.z / clip.w
+        };
+        assert!((depth(0.1) + 1.).abs() < 1e-5);
+        assert!((depth(100.) - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn perspective_depth_follows_the_mode() {
+        let mut perspective = Perspective {
+            projection: ProjectionConfig {
+                near: 0.1,
+                far: 100.,
+                reverse_z: false,
+            },
+            ..Perspective::default()
+        };
+        let depth = |perspective: &Perspective, distance: f32| {
+            let clip = perspective.matrix(800., 600.) * Vector4::new(0., 0., -distance, 1.);
+            clip.z / clip.w
+        };
+
+        // -1 to 1 between the clip planes, and beyond the far plane past it
+        assert!((depth(&perspective, 0.1) + 1.).abs() < 1e-5);
+        assert!((depth(&perspective, 100.) - 1.).abs() < 1e-4);
+        assert!(depth(&perspective, 200.) > 1.);
+        for distance in [0.5, 10., 50.] {
+            let expected = perspective.projection.ndc_depth(distance);
+            assert!((depth(&perspective, distance) - expected).abs() < 1e-5);
+        }
+
+        // 1 to 0 from the near plane to infinity, ignoring the far plane
+        perspective.projection.reverse_z = true;
+        assert!((depth(&perspective, 0.1) - 1.).abs() < 1e-6);
+        assert!((depth(&perspective, 100.) - 1e-3).abs() < 1e-6);
+        assert!(depth(&perspective, 1e6) > 0.);
+        for distance in [0.5, 10., 50.] {
+            let expected = perspective.projection.ndc_depth(distance);
+            assert!((depth(&perspective, distance) - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn frusta_follow_the_tangents_of_each_side() {
+        let config = ProjectionConfig {
+            near: 0.5,
+            far: 50.,
+            reverse_z: false,
+        };
+        // Twice as wide to the right as to the left, and only looking up
+        let proj = config.frustum(-1., 2., 0., 1.5);
+        let project = |x: f32, y: f32, distance: f32| {
+            let clip = proj * Vector4::new(x, y, -distance, 1.);
+            Point3::from(clip.xyz() / clip.w)
+        };
+        let left_bottom = project(-2., 0., 2.);
+        let right_top = project(4., 3., 2.);
+        assert!((left_bottom - Point3::new(-1., -1., config.ndc_depth(2.))).norm() < 1e-5);
+        assert!((right_top - Point3::new(1., 1., config.ndc_depth(2.))).norm() < 1e-5);
+        assert!((project(0., 0., 0.5).z + 1.).abs() < 1e-5);
+
+        // Reversed depth uses the same sides, with depth falling from the near plane
+        let reversed = ProjectionConfig {
+            reverse_z: true,
+            ..config
+        };
+        let proj = reversed.frustum(-1., 2., 0., 1.5);
+        let clip = proj * Vector4::new(4., 3., -2., 1.);
+        assert!((clip.x / clip.w - 1.).abs() < 1e-5 && (clip.y / clip.w - 1.).abs() < 1e-5);
+        assert!((clip.z / clip.w - reversed.ndc_depth(2.)).abs() < 1e-6);
+        assert!((reversed.ndc_depth(0.5) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn orthographic_maps_its_box_to_clip_space() {
+        let ndc = |proj: &Matrix4<f32>, point: [f32; 3]| {
+            let clip = proj * Vector4::new(point[0], point[1], point[2], 1.);
+            assert_eq!(clip.w, 1.);
+            clip.xyz()
+        };
+        let proj = orthographic(4., 2., 1., 11., false);
+        assert!((ndc(&proj, [-4., -2., -1.]) - Vector3::new(-1., -1., -1.)).norm() < 1e-6);
+        assert!((ndc(&proj, [4., 2., -11.]) - Vector3::new(1., 1., 1.)).norm() < 1e-6);
+        // Depth is linear, unlike perspective
+        assert!(ndc(&proj, [0., 0., -6.]).norm() < 1e-6);
+
+        let reversed = orthographic(4., 2., 1., 11., true);
+        assert!((ndc(&reversed, [4., -2., -1.]) - Vector3::new(1., -1., 1.)).norm() < 1e-6);
+        assert!(ndc(&reversed, [0., 0., -11.]).norm() < 1e-6);
+        assert!((ndc(&reversed, [0., 0., -6.]).z - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn top_down_views_see_the_whole_map() {
+        let bounds = (Point3::new(-10., -1., -30.), Point3::new(30., 5., 10.));
+        let camera = DebugCamera::top_down(bounds);
+        assert_eq!(
+            camera,
+            DebugCamera::TopDown {
+                center: Point2::new(10., -10.),
+                extent: 20. + TOP_DOWN_MARGIN,
+            }
+        );
+
+        // Every corner of the map lands within the square viewport, with -Z up the screen
+        for reverse_z in [false, true] {
+            let (proj, view) = camera.matrices(bounds, 100., 100., reverse_z);
+            let (min, max) = bounds;
+            for i in 0..8 {
+                let corner = Point3::new(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                );
+                let ndc = (proj * view).transform_point(&corner);
+                let lowest = if reverse_z { 0. } else { -1. };
+                assert!(ndc.x.abs() < 1. && ndc.y.abs() < 1., "{}", ndc);
+                assert!(ndc.z > lowest && ndc.z < 1., "{}", ndc);
+            }
+            let north = (proj * view).transform_point(&Point3::new(10., 0., -20.));
+            assert!(north.y > 0. && north.x.abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn falls_are_the_same_at_any_frame_rate() {
+        let idle = WinitInputHelper::new();
+        let fallen = |steps: u32| {
+            let mut camera = FlyCam::new(Point3::new(0., 10., 0.));
+            camera.mode = MovementMode::Walk;
+            for _ in 0..steps {
+                let dt = 0.5 / steps as f32;
+                camera.update(&idle, dt, LOOK, SMOOTHING, &Bindings::default());
+            }
+            camera.pos.y
+        };
+        let expected = 10. - 0.5 * GRAVITY * 0.25;
+        for steps in [5, 10, 50] {
+            assert!((fallen(steps) - expected).abs() < 1e-4, "{} steps", steps);
+        }
+
+        // Only the longest step of a hitch counts
+        let step = MAX_UPDATE_DT;
+        assert!((fallen(1) - (10. - 0.5 * GRAVITY * step * step)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn falling_follows_gravity() {
+        assert_eq!(fall(3., 0., 0.), (3., 0.));
+        let (y, vy) = fall(0., 0., 2.);
+        assert!((y + 2. * GRAVITY).abs() < 1e-5);
+        assert!((vy + 2. * GRAVITY).abs() < 1e-5);
+
+        // Thrown upwards, the peak is at v² / 2g however it's split up
+        let peak_time = JUMP_SPEED / GRAVITY;
+        let (peak, vy) = fall(0., JUMP_SPEED, peak_time);
+        assert!((peak - JUMP_SPEED * JUMP_SPEED / (2. * GRAVITY)).abs() < 1e-5);
+        assert!(vy.abs() < 1e-5);
+        let (half, vy) = fall(0., JUMP_SPEED, peak_time / 2.);
+        let (split, _) = fall(half, vy, peak_time / 2.);
+        assert!((split - peak).abs() < 1e-5);
+    }
+
+    #[test]
+    fn jumps_only_leave_the_ground() {
+        let ground = FlatGround(0.);
+        let mut camera = FlyCam::new(Point3::new(0., 1.7, 0.));
+        camera.mode = MovementMode::Walk;
+        camera.collide(&ground, 1.7);
+        assert!(camera.grounded());
+
+        // Standing still stays put
+        camera.walk(Vector3::zeros(), 0.01, SMOOTHING, false);
+        camera.collide(&ground, 1.7);
+        assert_eq!(camera.pos.y, 1.7);
+        assert!(camera.grounded());
+
+        camera.walk(Vector3::zeros(), 0.01, SMOOTHING, true);
+        camera.collide(&ground, 1.7);
+        assert!(camera.pos.y > 1.7);
+        assert!(!camera.grounded());
+        let rising = camera.velocity.y;
+        assert!(rising > 0. && rising < JUMP_SPEED);
+
+        // Jumping again in the air does nothing
+        camera.walk(Vector3::zeros(), 0.01, SMOOTHING, true);
+        assert!(camera.velocity.y < rising);
+    }
+
+    #[test]
+    fn jumps_land_where_they_started() {
+        let ground = FlatGround(0.);
+        let mut camera = FlyCam::new(Point3::new(0., 1.7, 0.));
+        camera.mode = MovementMode::Walk;
+        camera.collide(&ground, 1.7);
+
+        let dt = 1. / 64.;
+        camera.walk(Vector3::zeros(), dt, SMOOTHING, true);
+        camera.collide(&ground, 1.7);
+        let mut highest = camera.pos.y;
+        let mut steps = 1;
+        while !camera.grounded() {
+            camera.walk(Vector3::zeros(), dt, SMOOTHING, false);
+            camera.collide(&ground, 1.7);
+            highest = highest.max(camera.pos.y);
+            steps += 1;
+            assert!(steps < 1000, "never landed");
+        }
+        assert_eq!(camera.pos.y, 1.7);
+        assert_eq!(camera.velocity.y, 0.);
+        let peak = 1.7 + JUMP_SPEED * JUMP_SPEED / (2. * GRAVITY);
+        assert!(highest <= peak + 1e-4 && highest > peak - 0.01);
+        // In the air for about 2v / g
+        let airtime = steps as f32 * dt;
+        assert!((airtime - 2. * JUMP_SPEED / GRAVITY).abs() <= dt);
+    }
+}
@@ -0,0 +1,169 @@
+//! Camera paths for flythrough shots, recorded as keyframes of the desktop camera and played back
+//! along a smooth curve through them
+use anyhow::{bail, Context, Result};
+use cubehead::{sample_keyframes, Head, Keyframe};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Instant;
+
+/// Keyframes in order of time, saved as RON
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let camera_path: Self =
+            ron::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        if camera_path
+            .keyframes
+            .windows(2)
+            .any(|pair| pair[1].time < pair[0].time)
+        {
+            bail!("Keyframes of {} are out of order", path.display());
+        }
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Add a keyframe `time` seconds after the path began, which must be after the others
+    pub fn push(&mut self, time: f32, head: Head) {
+        let last = self.keyframes.last().map_or(0., |key| key.time);
+        self.keyframes.push(Keyframe {
+            time: time.max(last),
+            head,
+        });
+    }
+
+    /// Seconds from the first keyframe to the last
+    pub fn duration(&self) -> f32 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.,
+        }
+    }
+
+    /// The pose `time` seconds after the first keyframe
+    pub fn sample(&self, time: f32) -> Option<Head> {
+        let start = self.keyframes.first()?.time;
+        sample_keyframes(&self.keyframes, start + time)
+    }
+}
+
+/// Recording of a camera path, timed from its first keyframe
+#[derive(Default)]
+pub struct PathRecorder {
+    pub path: CameraPath,
+    start: Option<Instant>,
+}
+
+impl PathRecorder {
+    /// Drop a keyframe at `head` now
+    pub fn keyframe(&mut self, head: Head) {
+        let now = Instant::now();
+        let start = *self.start.get_or_insert(now);
+        self.path.push((now - start).as_secs_f32(), head);
+    }
+
+    /// Forget the keyframes, to start over
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Playback of a camera path from when it was started, at `speed` times the recorded speed
+pub struct PathPlayback {
+    path: CameraPath,
+    start: Instant,
+    speed: f32,
+}
+
+impl PathPlayback {
+    pub fn new(path: CameraPath, speed: f32) -> Self {
+        Self {
+            path,
+            start: Instant::now(),
+            speed,
+        }
+    }
+
+    /// The pose to view from now, or None once the path is over
+    pub fn head(&self) -> Option<Head> {
+        let time = self.start.elapsed().as_secs_f32() * self.speed;
+        match time <= self.path.duration() {
+            true => self.path.sample(time),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, UnitQuaternion};
+
+    fn head(x: f32, yaw: f32) -> Head {
+        Head {
+            pos: Point3::new(x, 1.5, 0.),
+            orient: UnitQuaternion::from_euler_angles(0., yaw, 0.),
+        }
+    }
+
+    #[test]
+    fn keyframes_stay_in_order() {
+        let mut path = CameraPath::default();
+        assert_eq!(path.duration(), 0.);
+        assert!(path.sample(0.).is_none());
+
+        path.push(2., head(0., 0.));
+        path.push(5., head(3., 0.));
+        // Earlier than the last keyframe, so it goes at the same time
+        path.push(4., head(6., 0.));
+        let times: Vec<_> = path.keyframes.iter().map(|key| key.time).collect();
+        assert_eq!(times, [2., 5., 5.]);
+        assert_eq!(path.duration(), 3.);
+
+        // Sampled from the first keyframe
+        let start = path.sample(0.).unwrap();
+        assert_eq!(start.pos, Point3::new(0., 1.5, 0.));
+        let halfway = path.sample(1.5).unwrap();
+        assert!(halfway.pos.x > 0. && halfway.pos.x < 3.);
+        assert_eq!(path.sample(3.).unwrap().pos, Point3::new(6., 1.5, 0.));
+    }
+
+    #[test]
+    fn paths_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("cubehead-path-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("path.ron");
+
+        let mut path = CameraPath::default();
+        path.push(0., head(0., 0.));
+        path.push(1.25, head(2., 0.5));
+        path.save(&file).unwrap();
+        let loaded = CameraPath::load(&file).unwrap();
+        assert_eq!(loaded.keyframes.len(), 2);
+        for (a, b) in loaded.keyframes.iter().zip(&path.keyframes) {
+            assert_eq!(a.time, b.time);
+            assert_eq!(a.head.pos, b.head.pos);
+            assert!(a.head.orient.angle_to(&b.head.orient) < 1e-6);
+        }
+
+        // Edited out of order by hand
+        path.keyframes.swap(0, 1);
+        path.save(&file).unwrap();
+        let error = CameraPath::load(&file).unwrap_err().to_string();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            error,
+            format!("Keyframes of {} are out of order", file.display())
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Comfort vignette in VR: darkening the edges of the view while the thumbsticks move or turn
+//! the player, which eases motion sickness
+/// Radius of the clear part of the view while still, in normalized device coordinates from the
+/// direction of view. Past the corners, so nothing is darkened.
+pub const OPEN_RADIUS: f32 = 1.5;
+
+/// Radius at full strength and full speed
+const CLOSED_RADIUS: f32 = 0.45;
+
+/// Artificial movement in meters per second, and turning in radians per second, below which the
+/// view stays open...
+const MOVE_THRESHOLD: f32 = 0.1;
+const TURN_THRESHOLD: f32 = 0.1;
+
+/// ...and at which it closes as far as it goes
+const FULL_MOVE_SPEED: f32 = 3.;
+const FULL_TURN_RATE: f32 = 1.6;
+
+/// Time constant of the radius following its target, in seconds
+const FOLLOW_TIME: f32 = 0.15;
+
+/// Radius to close the view to for artificial movement at `speed` meters per second and turning
+/// at `turn_rate` radians per second, with `strength` from 0, which never closes it, to 1
+pub fn target_radius(speed: f32, turn_rate: f32, strength: f32) -> f32 {
+    let ramp = |value: f32, threshold: f32, full: f32| {
+        ((value.abs() - threshold) / (full - threshold)).clamp(0., 1.)
+    };
+    let motion = ramp(speed, MOVE_THRESHOLD, FULL_MOVE_SPEED).max(ramp(
+        turn_rate,
+        TURN_THRESHOLD,
+        FULL_TURN_RATE,
+    ));
+    OPEN_RADIUS - (OPEN_RADIUS - CLOSED_RADIUS) * motion * strength.clamp(0., 1.)
+}
+
+/// Whether a vignette of `radius` darkens any of the view. The radius only approaches
+/// `OPEN_RADIUS` after closing, so it counts as open once within a hair of it.
+pub fn darkens(radius: f32) -> bool {
+    radius < OPEN_RADIUS - 1e-3
+}
+
+/// Radius of the vignette, following its target as a critically damped spring so that it never
+/// pops or overshoots
+pub struct Vignette {
+    radius: f32,
+    /// Rate of change of the radius, per second
+    rate: f32,
+}
+
+impl Vignette {
+    pub fn new() -> Self {
+        Self {
+            radius: OPEN_RADIUS,
+            rate: 0.,
+        }
+    }
+
+    /// Current radius, in normalized device coordinates
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Follow `target` for `dt` seconds. Exact for any time step.
+    pub fn update(&mut self, target: f32, dt: f32) {
+        let omega = 1. / FOLLOW_TIME;
+        let offset = self.radius - target;
+        let c = self.rate + omega * offset;
+        let decay = (-omega * dt).exp();
+        self.radius = target + (offset + c * dt) * decay;
+        self.rate = (self.rate - omega * c * dt) * decay;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn still_or_slow_leaves_the_view_open() {
+        assert_eq!(target_radius(0., 0., 1.), OPEN_RADIUS);
+        assert_eq!(
+            target_radius(MOVE_THRESHOLD, -TURN_THRESHOLD, 1.),
+            OPEN_RADIUS
+        );
+    }
+
+    #[test]
+    fn radius_closes_with_speed() {
+        assert_close(target_radius(FULL_MOVE_SPEED, 0., 1.), CLOSED_RADIUS);
+        assert_close(target_radius(100., 0., 1.), CLOSED_RADIUS);
+        assert_close(target_radius(0., -FULL_TURN_RATE, 1.), CLOSED_RADIUS);
+
+        let radii: Vec<f32> = (0..=30)
+            .map(|i| target_radius(i as f32 * 0.1, 0., 1.))
+            .collect();
+        assert!(radii.windows(2).all(|pair| pair[1] <= pair[0]));
+
+        // The faster of moving and turning wins
+        let moving = target_radius(1., 0., 1.);
+        assert_eq!(target_radius(1., 0.2, 1.), moving);
+        assert!(target_radius(1., 1.5, 1.) < moving);
+    }
+
+    #[test]
+    fn strength_scales_the_closing() {
+        assert_eq!(target_radius(FULL_MOVE_SPEED, 0., 0.), OPEN_RADIUS);
+        let half = target_radius(FULL_MOVE_SPEED, 0., 0.5);
+        assert_close(half, (OPEN_RADIUS + CLOSED_RADIUS) / 2.);
+        assert_close(target_radius(FULL_MOVE_SPEED, 0., 2.), CLOSED_RADIUS);
+    }
+
+    #[test]
+    fn vignette_follows_without_overshooting() {
+        let mut vignette = Vignette::new();
+        assert!(!darkens(vignette.radius()));
+        let mut last = vignette.radius();
+        for _ in 0..300 {
+            vignette.update(CLOSED_RADIUS, 1. / 90.);
+            assert!(vignette.radius() <= last && vignette.radius() >= CLOSED_RADIUS);
+            last = vignette.radius();
+        }
+        assert!(darkens(vignette.radius()));
+        assert!((vignette.radius() - CLOSED_RADIUS).abs() < 1e-3);
+    }
+
+    #[test]
+    fn vignette_is_independent_of_frame_rate() {
+        let (mut once, mut twice) = (Vignette::new(), Vignette::new());
+        once.update(CLOSED_RADIUS, 0.1);
+        twice.update(CLOSED_RADIUS, 0.05);
+        twice.update(CLOSED_RADIUS, 0.05);
+        assert_close(once.radius(), twice.radius());
+    }
+}
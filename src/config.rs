@@ -0,0 +1,466 @@
+//! Client options kept between runs, in `cubehead/client.toml` under the user's config directory
+//! unless `--config` says otherwise. Options on the command line take precedence over the file,
+//! which takes precedence over the defaults.
+use crate::bindings::Bindings;
+use crate::camera::{CameraSettings, DEFAULT_FAR, DEFAULT_NEAR};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Largest number of edits between an unknown key and a known one for it to be suggested
+const MAX_SUGGESTION_EDITS: usize = 3;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// Name shown above your head to other players, instead of your user name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Raises the VR play space by this many meters, to correct the runtime's floor height
+    pub height_offset: f32,
+    /// Desktop fly camera speed and mouse look, at the top level of the file
+    #[serde(flatten)]
+    pub camera: CameraSettings,
+    /// Desktop vertical field of view, in degrees
+    pub fov: f32,
+    /// Desktop vertical field of view while zoomed in, in degrees
+    pub zoom_fov: f32,
+    /// Time constant of zooming in and out, in seconds; zero zooms instantly
+    pub zoom_time: f32,
+    /// Distance to the near clip plane, in meters
+    pub near: f32,
+    /// Distance to the far clip plane, in meters
+    pub far: f32,
+    /// Whether the desktop window is fullscreen, as it last was
+    pub fullscreen: FullscreenMode,
+    /// Show the statistics overlay on startup
+    pub stats: bool,
+    /// Draw debug lines for the world bounds, the origin and where each head is facing
+    pub debug_lines: bool,
+    /// Draw a reference grid on the ground
+    pub grid: bool,
+    /// Cast shadows from the sun onto the map
+    pub shadows: bool,
+    /// Draw a fading trail behind each head
+    pub trails: bool,
+    /// Keys for each action, under `[bindings]`
+    pub bindings: Bindings,
+}
+
+/// Options given on the command line, each taking precedence over the config file where given.
+/// Switches can only be turned on from the command line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overrides {
+    pub name: Option<String>,
+    pub height_offset: Option<f32>,
+    pub fly_speed: Option<f32>,
+    pub look_sensitivity: Option<f32>,
+    pub invert_y: bool,
+    pub fov: Option<f32>,
+    pub near: Option<f32>,
+    pub far: Option<f32>,
+    pub fullscreen: Option<FullscreenMode>,
+    pub stats: bool,
+    pub debug_lines: bool,
+    pub grid: bool,
+    pub shadows: bool,
+    pub trails: bool,
+}
+
+/// The config file a client started with. Changes made at runtime are saved into it without the
+/// options from the command line, which only last the one run.
+#[derive(Clone, Debug)]
+pub struct ConfigFile {
+    /// None if the platform has no config directory and no file was given
+    path: Option<PathBuf>,
+    /// What is in the file
+    saved: ClientConfig,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            height_offset: 0.,
+            camera: CameraSettings::default(),
+            fov: 45.,
+            zoom_fov: 25.,
+            zoom_time: 0.08,
+            near: DEFAULT_NEAR,
+            far: DEFAULT_FAR,
+            fullscreen: FullscreenMode::Windowed,
+            stats: false,
+            debug_lines: false,
+            grid: false,
+            shadows: false,
+            trails: false,
+            bindings: Bindings::default(),
+        }
+    }
+}
+
+/// How the desktop window covers the screen
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FullscreenMode {
+    Windowed,
+    /// A window covering the whole monitor, which other windows can still go on top of
+    Borderless,
+    /// Taking over the monitor at its largest video mode, where the platform supports it
+    Exclusive,
+}
+
+impl ClientConfig {
+    /// Where the config file lives, if the platform has a config directory
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("cubehead").join("client.toml"))
+    }
+
+    /// Read a config file, or the defaults if there isn't one. Keys the config doesn't have are
+    /// ignored with a warning.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let (config, unknown) =
+            Self::parse(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        for (key, suggestion) in unknown {
+            match suggestion {
+                Some(suggestion) => eprintln!(
+                    "Ignoring unknown key {} in {}; did you mean {}?",
+                    key,
+                    path.display(),
+                    suggestion
+                ),
+                None => eprintln!("Ignoring unknown key {} in {}", key, path.display()),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parse the text of a config file, leaving out unknown keys. Returns them too, as in
+    /// `unknown_keys`.
+    fn parse(text: &str) -> Result<(Self, Vec<(String, Option<String>)>)> {
+        let mut value: toml::Value = toml::from_str(text)?;
+
+        // Every key appears in the defaults once the optional ones are filled in
+        let template = Self {
+            name: Some(String::new()),
+            ..Self::default()
+        };
+        let known = toml::Value::try_from(template)?;
+        let mut unknown = vec![];
+        unknown_keys(&mut value, &known, "", &mut unknown);
+
+        Ok((value.try_into()?, unknown))
+    }
+
+    /// Write a config file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The settings in effect, from the command line over this config over the defaults
+    pub fn merged(&self, overrides: &Overrides) -> Self {
+        let camera = CameraSettings {
+            fly_speed: overrides.fly_speed.unwrap_or(self.camera.fly_speed),
+            look_sensitivity: overrides
+                .look_sensitivity
+                .unwrap_or(self.camera.look_sensitivity),
+            invert_y: self.camera.invert_y || overrides.invert_y,
+        };
+        Self {
+            name: overrides.name.clone().or_else(|| self.name.clone()),
+            height_offset: overrides.height_offset.unwrap_or(self.height_offset),
+            camera: camera.clamped(),
+            fov: overrides.fov.unwrap_or(self.fov),
+            near: overrides.near.unwrap_or(self.near),
+            far: overrides.far.unwrap_or(self.far),
+            fullscreen: overrides.fullscreen.unwrap_or(self.fullscreen),
+            stats: self.stats || overrides.stats,
+            debug_lines: self.debug_lines || overrides.debug_lines,
+            grid: self.grid || overrides.grid,
+            shadows: self.shadows || overrides.shadows,
+            trails: self.trails || overrides.trails,
+            ..self.clone()
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Read the config file at `path`. A file given explicitly must be readable, as must one
+    /// which is about to be overwritten; otherwise a broken file is reported and the defaults
+    /// used.
+    pub fn load(path: Option<PathBuf>, required: bool) -> Result<Self> {
+        let saved = match &path {
+            Some(path) if required => ClientConfig::load(path)?,
+            Some(path) => ClientConfig::load(path).unwrap_or_else(|e| {
+                eprintln!("{:#}", e);
+                ClientConfig::default()
+            }),
+            None => ClientConfig::default(),
+        };
+        Ok(Self { path, saved })
+    }
+
+    /// The settings in effect, as in `ClientConfig::merged`
+    pub fn merged(&self, overrides: &Overrides) -> ClientConfig {
+        self.saved.merged(overrides)
+    }
+
+    /// Make `change` to the file and write it. The change should already be in effect.
+    pub fn save(&mut self, change: impl FnOnce(&mut ClientConfig)) -> Result<()> {
+        change(&mut self.saved);
+        self.write(&self.saved)
+    }
+
+    /// Replace the file with `config`, such as the settings in effect for `--write-config`
+    pub fn write(&self, config: &ClientConfig) -> Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .context("No config directory on this platform; pass --config")?;
+        config.save(path)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+/// Remove the keys in `value` which aren't in `known`, collecting their dotted paths, each with
+/// the closest key at the same level of `known` if there is one within `MAX_SUGGESTION_EDITS`
+fn unknown_keys(
+    value: &mut toml::Value,
+    known: &toml::Value,
+    prefix: &str,
+    unknown: &mut Vec<(String, Option<String>)>,
+) {
+    let (table, known) = match (value.as_table_mut(), known.as_table()) {
+        (Some(table), Some(known)) => (table, known),
+        _ => return,
+    };
+    let mut removed = vec![];
+    for (key, child) in table.iter_mut() {
+        let path = format!("{}{}", prefix, key);
+        match known.get(key) {
+            Some(known_child) => unknown_keys(child, known_child, &format!("{}.", path), unknown),
+            None => {
+                removed.push(key.clone());
+                let suggestion = known
+                    .keys()
+                    .map(|candidate| (edit_distance(key, candidate), candidate))
+                    .filter(|&(edits, _)| edits <= MAX_SUGGESTION_EDITS && edits < key.len())
+                    .min()
+                    .map(|(_, candidate)| format!("{}{}", prefix, candidate));
+                unknown.push((path, suggestion));
+            }
+        }
+    }
+    for key in removed {
+        table.remove(&key);
+    }
+}
+
+/// Fewest single character insertions, deletions and substitutions turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_beats_file_beats_defaults() {
+        let defaults = ClientConfig::default();
+        assert_eq!(defaults.merged(&Overrides::default()), defaults);
+
+        let (file, _) = ClientConfig::parse("fov = 60.0\nnear = 0.5\nshadows = true\n").unwrap();
+        let merged = file.merged(&Overrides::default());
+        assert_eq!(merged.fov, 60.);
+        assert_eq!(merged.near, 0.5);
+        assert_eq!(merged.far, defaults.far);
+        assert!(merged.shadows);
+
+        let overrides = Overrides {
+            fov: Some(90.),
+            far: Some(50.),
+            name: Some("cli".into()),
+            ..Overrides::default()
+        };
+        let merged = file.merged(&overrides);
+        assert_eq!(merged.fov, 90.);
+        assert_eq!(merged.near, 0.5);
+        assert_eq!(merged.far, 50.);
+        assert_eq!(merged.name.as_deref(), Some("cli"));
+        // A switch left off on the command line doesn't turn off one set in the file
+        assert!(merged.shadows);
+    }
+
+    #[test]
+    fn height_offset_comes_from_the_file_unless_given() {
+        let (file, unknown) = ClientConfig::parse("height_offset = -0.03\n").unwrap();
+        assert!(unknown.is_empty());
+        assert_eq!(file.height_offset, -0.03);
+        assert_eq!(file.merged(&Overrides::default()).height_offset, -0.03);
+
+        let overrides = Overrides {
+            height_offset: Some(0.1),
+            ..Overrides::default()
+        };
+        assert_eq!(file.merged(&overrides).height_offset, 0.1);
+        // Without the key the play space is where the runtime puts it
+        let (file, _) = ClientConfig::parse("").unwrap();
+        assert_eq!(file.height_offset, 0.);
+    }
+
+    #[test]
+    fn merged_camera_settings_are_clamped() {
+        let overrides = Overrides {
+            fly_speed: Some(1e9),
+            invert_y: true,
+            ..Overrides::default()
+        };
+        let merged = ClientConfig::default().merged(&overrides);
+        assert_eq!(merged.camera.fly_speed, crate::camera::FLY_SPEED_RANGE.1);
+        assert!(merged.camera.invert_y);
+    }
+
+    #[test]
+    fn command_line_camera_settings_beat_the_file() {
+        let (file, _) =
+            ClientConfig::parse("fly_speed = 4.0\nlook_sensitivity = 0.002\ninvert_y = true\n")
+                .unwrap();
+        let merged = file.merged(&Overrides::default());
+        assert_eq!(merged.camera.fly_speed, 4.);
+        assert_eq!(merged.camera.look_sensitivity, 0.002);
+        assert!(merged.camera.invert_y);
+
+        let overrides = Overrides {
+            fly_speed: Some(8.),
+            look_sensitivity: Some(0.005),
+            ..Overrides::default()
+        };
+        let merged = file.merged(&overrides);
+        assert_eq!(merged.camera.fly_speed, 8.);
+        assert_eq!(merged.camera.look_sensitivity, 0.005);
+        // Leaving the switch off on the command line keeps the file's inversion
+        assert!(merged.camera.invert_y);
+    }
+
+    #[test]
+    fn out_of_range_file_camera_settings_are_clamped() {
+        let (file, _) = ClientConfig::parse("fly_speed = 0.0\nlook_sensitivity = 1.0\n").unwrap();
+        let merged = file.merged(&Overrides::default());
+        assert_eq!(merged.camera.fly_speed, crate::camera::FLY_SPEED_RANGE.0);
+        assert_eq!(
+            merged.camera.look_sensitivity,
+            crate::camera::LOOK_SENSITIVITY_RANGE.1
+        );
+
+        let overrides = Overrides {
+            look_sensitivity: Some(0.),
+            ..Overrides::default()
+        };
+        let merged = file.merged(&overrides);
+        assert_eq!(
+            merged.camera.look_sensitivity,
+            crate::camera::LOOK_SENSITIVITY_RANGE.0
+        );
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let mut config = ClientConfig {
+            name: Some("tester".into()),
+            height_offset: -0.25,
+            fov: 70.,
+            zoom_time: 0.5,
+            fullscreen: FullscreenMode::Exclusive,
+            grid: true,
+            ..ClientConfig::default()
+        };
+        config.camera.invert_y = true;
+        config.bindings.jump = crate::bindings::Keys(vec![]);
+
+        let text = toml::to_string(&config).unwrap();
+        let (parsed, unknown) = ClientConfig::parse(&text).unwrap();
+        assert_eq!(parsed, config);
+        assert!(unknown.is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_are_left_out_with_suggestions() {
+        let text =
+            "fly_sped = 3.0\nqwertyuiop = 1\n\n[bindings]\nmove_forwrd = \"Z\"\njump = \"J\"\n";
+        let (config, unknown) = ClientConfig::parse(text).unwrap();
+        assert_eq!(
+            unknown,
+            vec![
+                (
+                    "bindings.move_forwrd".to_string(),
+                    Some("bindings.move_forward".to_string())
+                ),
+                ("fly_sped".to_string(), Some("fly_speed".to_string())),
+                ("qwertyuiop".to_string(), None),
+            ]
+        );
+        // The rest of the file still applies
+        assert_eq!(
+            config.bindings.jump,
+            crate::bindings::Keys(vec![glutin::event::VirtualKeyCode::J])
+        );
+        assert_eq!(config.camera, CameraSettings::default());
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("fov", "fov"), 0);
+        assert_eq!(edit_distance("fov", ""), 3);
+        assert_eq!(edit_distance("far", "fat"), 1);
+        assert_eq!(edit_distance("nera", "near"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn saving_leaves_out_the_command_line() {
+        let dir = std::env::temp_dir().join(format!("cubehead-config-{}", std::process::id()));
+        let path = dir.join("client.toml");
+        let _ = std::fs::remove_file(&path);
+
+        // A missing file is the defaults
+        let mut file = ConfigFile::load(Some(path.clone()), true).unwrap();
+        let overrides = Overrides {
+            fov: Some(80.),
+            ..Overrides::default()
+        };
+        assert_eq!(file.merged(&overrides).fov, 80.);
+
+        file.save(|file| file.height_offset = 0.5).unwrap();
+        let saved = ClientConfig::load(&path).unwrap();
+        assert_eq!(saved.height_offset, 0.5);
+        assert_eq!(saved.fov, ClientConfig::default().fov);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+//! Accumulation of debug lines, drawn by the `Engine` after the opaque passes
+use crate::shapes::aabb_lines;
+use bytemuck::{Pod, Zeroable};
+use nalgebra::{Matrix4, Point3, Vector3};
+
+/// Vertex of a debug line
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LineVertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 3],
+}
+
+// Allow LineVertex to be cast to bytes using bytemuck
+unsafe impl Zeroable for LineVertex {}
+unsafe impl Pod for LineVertex {}
+
+/// Line segments between each pair of consecutive points, in the colors of the points. Built by
+/// the line generators in `shapes`.
+#[derive(Clone, Debug, Default)]
+pub struct LineMesh {
+    pub points: Vec<(Point3<f32>, [f32; 3])>,
+}
+
+impl LineMesh {
+    /// Add a segment from `a` to `b`
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) {
+        self.points.extend([(a, color), (b, color)]);
+    }
+}
+
+/// Lines added since the last `clear`. While disabled, adding lines does nothing.
+#[derive(Default)]
+pub struct DebugLines {
+    pub enabled: bool,
+    /// Draw on top of all other geometry
+    pub xray: bool,
+    vertices: Vec<LineVertex>,
+    /// Whether `vertices` changed since `take_dirty` was last called
+    dirty: bool,
+}
+
+impl DebugLines {
+    /// Remove all lines; call once per frame before adding new ones
+    pub fn clear(&mut self) {
+        if !self.vertices.is_empty() {
+            self.vertices.clear();
+            self.dirty = true;
+        }
+    }
+
+    /// Line segment from `a` to `b`
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) {
+        if !self.enabled {
+            return;
+        }
+
+        self.vertices.extend([
+            LineVertex {
+                pos: [a.x, a.y, a.z],
+                color,
+            },
+            LineVertex {
+                pos: [b.x, b.y, b.z],
+                color,
+            },
+        ]);
+        self.dirty = true;
+    }
+
+    /// All segments of `mesh`
+    pub fn mesh(&mut self, mesh: &LineMesh) {
+        if !self.enabled {
+            return;
+        }
+
+        let points = mesh.points.chunks_exact(2).flatten();
+        self.vertices.extend(points.map(|&(pos, color)| LineVertex {
+            pos: [pos.x, pos.y, pos.z],
+            color,
+        }));
+        self.dirty = true;
+    }
+
+    /// X, Y and Z axes of a transform as red, green and blue lines of the given length
+    pub fn axes(&mut self, transform: &Matrix4<f32>, size: f32) {
+        let origin = transform.transform_point(&Point3::origin());
+        for (axis, color) in [
+            (Vector3::x(), [1., 0., 0.]),
+            (Vector3::y(), [0., 1., 0.]),
+            (Vector3::z(), [0., 0., 1.]),
+        ] {
+            let end = transform.transform_point(&Point3::from(axis * size));
+            self.line(origin, end, color);
+        }
+    }
+
+    /// Edges of an axis-aligned box
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 3]) {
+        self.mesh(&aabb_lines(min, max, color));
+    }
+
+    pub fn vertices(&self) -> &[LineVertex] {
+        &self.vertices
+    }
+
+    /// Whether the lines changed since the last call, resetting the flag
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> DebugLines {
+        DebugLines {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn disabled_lines_cost_nothing() {
+        let mut lines = DebugLines::default();
+        lines.line(Point3::origin(), Point3::new(1., 0., 0.), [1.; 3]);
+        lines.axes(&Matrix4::identity(), 1.);
+        lines.aabb(Point3::origin(), Point3::new(1., 1., 1.), [1.; 3]);
+        assert!(lines.vertices().is_empty());
+        assert!(!lines.take_dirty());
+    }
+
+    #[test]
+    fn axes_follow_the_transform() {
+        let mut lines = enabled();
+        let transform = Matrix4::new_translation(&Vector3::new(1., 2., 3.))
+            * Matrix4::from_euler_angles(0., std::f32::consts::FRAC_PI_2, 0.);
+        lines.axes(&transform, 2.);
+
+        let vertices = lines.vertices();
+        assert_eq!(vertices.len(), 6);
+        let expected = [
+            // X turns to -Z, Y stays up, and Z turns to +X
+            ([1., 2., 1.], [1., 0., 0.]),
+            ([1., 4., 3.], [0., 1., 0.]),
+            ([3., 2., 3.], [0., 0., 1.]),
+        ];
+        for (segment, (end, color)) in vertices.chunks_exact(2).zip(expected) {
+            assert_eq!(segment[0].pos, [1., 2., 3.]);
+            let distance = Vector3::from(segment[1].pos) - Vector3::from(end);
+            assert!(distance.norm() < 1e-6, "{:?}", segment[1].pos);
+            assert_eq!(segment[0].color, color);
+            assert_eq!(segment[1].color, color);
+        }
+    }
+
+    #[test]
+    fn aabbs_have_twelve_axis_aligned_edges() {
+        let mut lines = enabled();
+        let (min, max) = (Point3::new(-1., 0., 2.), Point3::new(1., 3., 4.));
+        lines.aabb(min, max, [0., 1., 1.]);
+
+        let vertices = lines.vertices();
+        assert_eq!(vertices.len(), 24);
+        for segment in vertices.chunks_exact(2) {
+            let (a, b) = (Point3::from(segment[0].pos), Point3::from(segment[1].pos));
+            // Both ends are corners, differing along exactly one axis by the size of the box
+            for p in [a, b] {
+                for axis in 0..3 {
+                    assert!(p[axis] == min[axis] || p[axis] == max[axis]);
+                }
+            }
+            let changed: Vec<usize> = (0..3).filter(|&axis| a[axis] != b[axis]).collect();
+            assert_eq!(changed.len(), 1);
+            assert_eq!(segment[0].color, [0., 1., 1.]);
+        }
+    }
+
+    #[test]
+    fn clearing_marks_the_lines_dirty_once() {
+        let mut lines = enabled();
+        lines.clear();
+        assert!(!lines.take_dirty());
+
+        lines.line(Point3::origin(), Point3::new(0., 1., 0.), [1.; 3]);
+        assert!(lines.take_dirty());
+        assert!(!lines.take_dirty());
+
+        lines.clear();
+        assert!(lines.vertices().is_empty());
+        assert!(lines.take_dirty());
+        lines.clear();
+        assert!(!lines.take_dirty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Dynamic resolution in VR: rendering into less of each swapchain image while frames run over
+//! budget, and growing back once there is time to spare
+use std::collections::VecDeque;
+
+/// Smallest fraction of each side of the swapchain images rendered...
+pub const MIN_SCALE: f32 = 0.6;
+
+/// ...and the largest
+pub const MAX_SCALE: f32 = 1.;
+
+/// Scale change when frames run over budget...
+const STEP_DOWN: f32 = 0.1;
+
+/// ...and when there is time to spare
+const STEP_UP: f32 = 0.05;
+
+/// Frames costing more than this fraction of the budget count as over budget...
+const HIGH_WATER: f32 = 0.9;
+
+/// ...and all frames must cost less than this fraction to scale up. Scaling up by `STEP_UP`
+/// costs at most 17% more pixels, which keeps a frame under `LOW_WATER` from going over
+/// `HIGH_WATER`, so the scale doesn't flip back and forth.
+const LOW_WATER: f32 = 0.7;
+
+/// Recent frames looked at for scaling down...
+const DOWN_WINDOW: usize = 8;
+
+/// ...and how many of them must be over budget. More than one, as GPU timings lag a frame or two
+/// behind, and those frames were still rendered at the previous scale.
+const DOWN_COUNT: usize = 3;
+
+/// Frames which must all be comfortably under budget to scale up, about a second at 90 Hz
+const UP_WINDOW: usize = 90;
+
+/// The scale to render at next, given the current scale and the cost in milliseconds of each
+/// frame rendered at it, oldest first, against a budget of `budget` milliseconds per frame.
+/// Scales down by `STEP_DOWN` as soon as a few recent frames are over budget, and up by `STEP_UP`
+/// only after `UP_WINDOW` frames well under it, always within `MIN_SCALE` to `MAX_SCALE`.
+pub fn next_scale(scale: f32, frame_ms: &[f32], budget: f32) -> f32 {
+    let recent = &frame_ms[frame_ms.len().saturating_sub(DOWN_WINDOW)..];
+    let over = recent
+        .iter()
+        .filter(|&&ms| ms > budget * HIGH_WATER)
+        .count();
+    let next = if over >= DOWN_COUNT {
+        scale - STEP_DOWN
+    } else if frame_ms.len() >= UP_WINDOW
+        && frame_ms[frame_ms.len() - UP_WINDOW..]
+            .iter()
+            .all(|&ms| ms < budget * LOW_WATER)
+    {
+        scale + STEP_UP
+    } else {
+        return scale;
+    };
+
+    // Stay on multiples of the smaller step, however many steps were taken
+    ((next / STEP_UP).round() * STEP_UP).clamp(MIN_SCALE, MAX_SCALE)
+}
+
+/// Keeps the frame costs `next_scale` decides on, starting over whenever the scale changes
+pub struct DynamicResolution {
+    scale: f32,
+    /// Costs of the latest frames in milliseconds, oldest first
+    frame_ms: VecDeque<f32>,
+}
+
+impl DynamicResolution {
+    /// Start at full resolution
+    pub fn new() -> Self {
+        Self {
+            scale: MAX_SCALE,
+            frame_ms: VecDeque::with_capacity(UP_WINDOW),
+        }
+    }
+
+    /// Fraction of each side of the swapchain images to render
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Record the cost of a frame against the budget, both in milliseconds
+    pub fn frame(&mut self, ms: f32, budget: f32) {
+        if self.frame_ms.len() == UP_WINDOW {
+            self.frame_ms.pop_front();
+        }
+        self.frame_ms.push_back(ms);
+
+        let next = next_scale(self.scale, self.frame_ms.make_contiguous(), budget);
+        if next != self.scale {
+            self.scale = next;
+            self.frame_ms.clear();
+        }
+    }
+}
+
+/// Part of a swapchain image side of `size` pixels rendered at `scale`
+pub fn scaled_extent(size: u32, scale: f32) -> i32 {
+    ((size as f32 * scale).round() as i32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: f32 = 10.;
+
+    fn assert_scale(scale: f32, expected: f32) {
+        assert!((scale - expected).abs() < 1e-5, "{} != {}", scale, expected);
+    }
+
+    #[test]
+    fn a_few_slow_frames_scale_down() {
+        let frames = [5., 9.5, 5., 9.5, 5.];
+        assert_eq!(next_scale(1., &frames, BUDGET), 1.);
+        let frames = [5., 9.5, 5., 9.5, 9.5];
+        assert_scale(next_scale(1., &frames, BUDGET), 0.9);
+
+        // Only the latest frames count
+        let mut frames = vec![9.5; 3];
+        frames.extend([5.; DOWN_WINDOW]);
+        assert_eq!(next_scale(1., &frames, BUDGET), 1.);
+    }
+
+    #[test]
+    fn a_long_run_of_fast_frames_scales_up() {
+        let frames = [5.; UP_WINDOW];
+        assert_scale(next_scale(0.8, &frames, BUDGET), 0.85);
+        assert_eq!(next_scale(0.8, &frames[1..], BUDGET), 0.8);
+
+        // Frames in between neither scale up nor down
+        let mut frames = [5.; UP_WINDOW];
+        frames[0] = 8.;
+        assert_eq!(next_scale(0.8, &frames, BUDGET), 0.8);
+    }
+
+    #[test]
+    fn scales_stay_in_range_on_the_step_grid() {
+        let slow = [20.; DOWN_WINDOW];
+        assert_eq!(next_scale(MIN_SCALE, &slow, BUDGET), MIN_SCALE);
+        assert_eq!(next_scale(0.62, &slow, BUDGET), MIN_SCALE);
+        let fast = [1.; UP_WINDOW];
+        assert_eq!(next_scale(MAX_SCALE, &fast, BUDGET), MAX_SCALE);
+        assert_scale(next_scale(0.71, &fast, BUDGET), 0.75);
+    }
+
+    #[test]
+    fn changing_scale_starts_the_frame_count_over() {
+        let mut resolution = DynamicResolution::new();
+        assert_eq!(resolution.scale(), MAX_SCALE);
+        for _ in 0..DOWN_COUNT {
+            resolution.frame(20., BUDGET);
+        }
+        assert_scale(resolution.scale(), 0.9);
+
+        // The slow frames at the old scale don't count against the new one
+        resolution.frame(20., BUDGET);
+        assert_scale(resolution.scale(), 0.9);
+        for _ in 0..UP_WINDOW - 1 {
+            resolution.frame(1., BUDGET);
+        }
+        assert_scale(resolution.scale(), 0.9);
+        resolution.frame(1., BUDGET);
+        assert_scale(resolution.scale(), 0.95);
+    }
+
+    #[test]
+    fn scaled_extents_keep_a_pixel() {
+        assert_eq!(scaled_extent(2000, 1.), 2000);
+        assert_eq!(scaled_extent(2000, 0.6), 1200);
+        assert_eq!(scaled_extent(1, 0.6), 1);
+        assert_eq!(scaled_extent(0, 1.), 1);
+    }
+}
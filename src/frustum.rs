@@ -0,0 +1,116 @@
+//! View frusta, for leaving out heads which are out of view and drawing the culling camera
+use nalgebra::{Matrix4, Point3, Vector4};
+
+/// Edges of a frustum, as pairs of indices into `frustum_corners`
+pub const FRUSTUM_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (0, 2),
+    (1, 3), //
+    (4, 5),
+    (6, 7),
+    (4, 6),
+    (5, 7), //
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// What heads are culled from, which is normally the camera rendered from
+#[derive(Copy, Clone, Debug)]
+pub struct CullCamera {
+    /// Head distances are measured from here
+    pub eye: Point3<f32>,
+    /// Projection times view. Heads outside the sides of its frustum are left out; None culls by
+    /// distance only.
+    pub view_proj: Option<Matrix4<f32>>,
+}
+
+/// Left, right, bottom and top planes of the frustum of `view_proj`, as (a, b, c, d) such that
+/// ax + by + cz + d is the distance of a point inside. These don't depend on the depth mapping.
+pub fn side_planes(view_proj: &Matrix4<f32>) -> [Vector4<f32>; 4] {
+    let row = |i: usize| view_proj.row(i).transpose();
+    let (x, y, w) = (row(0), row(1), row(3));
+    [w + x, w - x, w + y, w - y].map(|plane| plane / plane.xyz().norm())
+}
+
+/// Whether a sphere is at least partly inside all of `planes`
+pub fn sphere_inside(planes: &[Vector4<f32>], center: Point3<f32>, radius: f32) -> bool {
+    planes
+        .iter()
+        .all(|plane| plane.xyz().dot(&center.coords) + plane.w >= -radius)
+}
+
+/// Corners of a frustum in world space, given the inverse of its projection times view, between
+/// normalized device depths `near` and `far`: bottom left, bottom right, top left and top right
+/// at `near`, then the same at `far`
+pub fn frustum_corners(inv_view_proj: &Matrix4<f32>, near: f32, far: f32) -> [Point3<f32>; 8] {
+    [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+        let x = if i & 1 == 0 { -1. } else { 1. };
+        let y = if i & 2 == 0 { -1. } else { 1. };
+        let z = if i & 4 == 0 { near } else { far };
+        inv_view_proj.transform_point(&Point3::new(x, y, z))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Perspective3, Vector3};
+
+    fn assert_near(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).norm() < 1e-3, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn corners_come_from_the_inverse_view_proj() {
+        // 90 degrees both ways, looking down -Z from (1, 2, 3)
+        let proj = Perspective3::new(1., std::f32::consts::FRAC_PI_2, 1., 10.).to_homogeneous();
+        let eye = Point3::new(1., 2., 3.);
+        let view = Matrix4::new_translation(&-eye.coords);
+        let inv = (proj * view).try_inverse().unwrap();
+
+        let corners = frustum_corners(&inv, -1., 1.);
+        let expected = [
+            [-1., -1., -1.],
+            [1., -1., -1.],
+            [-1., 1., -1.],
+            [1., 1., -1.],
+            [-10., -10., -10.],
+            [10., -10., -10.],
+            [-10., 10., -10.],
+            [10., 10., -10.],
+        ];
+        for (corner, offset) in corners.iter().zip(expected) {
+            assert_near(*corner, eye + Vector3::from(offset));
+        }
+
+        // Depth in between lands in between
+        let mid = frustum_corners(&inv, 0., 0.);
+        assert!(mid[0].z < eye.z - 1. && mid[0].z > eye.z - 10.);
+    }
+
+    #[test]
+    fn edges_join_neighbouring_corners() {
+        let mut seen = vec![];
+        for (a, b) in FRUSTUM_EDGES {
+            // Corners differ in exactly one of x, y and depth
+            assert_eq!((a ^ b).count_ones(), 1);
+            assert!(!seen.contains(&(a, b)));
+            seen.push((a, b));
+        }
+    }
+
+    #[test]
+    fn spheres_are_culled_by_the_sides() {
+        let proj = Perspective3::new(1., std::f32::consts::FRAC_PI_2, 1., 10.).to_homogeneous();
+        let planes = side_planes(&proj);
+        assert!(sphere_inside(&planes, Point3::new(0., 0., -5.), 0.1));
+        assert!(!sphere_inside(&planes, Point3::new(7., 0., -5.), 1.));
+        // Poking into view from the side
+        assert!(sphere_inside(&planes, Point3::new(5.5, 0., -5.), 1.));
+        // Behind the camera
+        assert!(!sphere_inside(&planes, Point3::new(0., 0., 5.), 1.));
+    }
+}
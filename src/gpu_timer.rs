@@ -0,0 +1,380 @@
+//! GPU time spent on each render pass, measured with timer queries. Results are read back a frame
+//! late, so that the CPU never waits for the GPU to finish.
+use glow::HasContext;
+use std::time::{Duration, Instant};
+
+/// Minimum time between warnings about passes over budget
+const WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A render pass timed on the GPU
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GpuPass {
+    Shadows,
+    Map,
+    Heads,
+    Translucent,
+    Post,
+}
+
+impl GpuPass {
+    pub const ALL: [GpuPass; 5] = [
+        GpuPass::Shadows,
+        GpuPass::Map,
+        GpuPass::Heads,
+        GpuPass::Translucent,
+        GpuPass::Post,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GpuPass::Shadows => "shadows",
+            GpuPass::Map => "map",
+            GpuPass::Heads => "heads",
+            GpuPass::Translucent => "translucent",
+            GpuPass::Post => "post",
+        }
+    }
+}
+
+/// Milliseconds of GPU time spent on each pass in a frame, summed over all views
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GpuTimings {
+    ms: [f32; GpuPass::ALL.len()],
+}
+
+impl GpuTimings {
+    pub fn get(&self, pass: GpuPass) -> f32 {
+        self.ms[pass as usize]
+    }
+
+    pub fn total(&self) -> f32 {
+        self.ms.iter().sum()
+    }
+
+    /// Add the `ns` nanoseconds a query measured to its pass
+    fn add(&mut self, pass: GpuPass, ns: u32) {
+        self.ms[pass as usize] += ns as f32 / 1e6;
+    }
+
+    /// Each pass taking longer than `budget` milliseconds, with its time
+    fn over_budget(&self, budget: f32) -> Vec<String> {
+        GpuPass::ALL
+            .iter()
+            .filter(|pass| self.get(**pass) > budget)
+            .map(|pass| format!("{} {:.2} ms", pass.name(), self.get(*pass)))
+            .collect()
+    }
+}
+
+/// The timer query calls `GpuTimer` makes, so that it can be driven without a GL context
+pub trait TimerQueries {
+    type Query: Copy;
+
+    /// A new query, or None if one couldn't be created
+    fn create(&self) -> Option<Self::Query>;
+    /// Start timing with `query`
+    fn begin(&self, query: Self::Query);
+    /// Stop the running query
+    fn end(&self);
+    /// Whether the result of `query` can be read without waiting
+    fn available(&self, query: Self::Query) -> bool;
+    /// Nanoseconds `query` measured
+    fn result(&self, query: Self::Query) -> u32;
+}
+
+impl TimerQueries for gl::Context {
+    type Query = gl::NativeQuery;
+
+    fn create(&self) -> Option<Self::Query> {
+        unsafe { self.create_query() }.ok()
+    }
+
+    fn begin(&self, query: Self::Query) {
+        unsafe { self.begin_query(gl::TIME_ELAPSED, query) }
+    }
+
+    fn end(&self) {
+        unsafe { self.end_query(gl::TIME_ELAPSED) }
+    }
+
+    fn available(&self, query: Self::Query) -> bool {
+        unsafe { self.get_query_parameter_u32(query, gl::QUERY_RESULT_AVAILABLE) != 0 }
+    }
+
+    fn result(&self, query: Self::Query) -> u32 {
+        unsafe { self.get_query_parameter_u32(query, gl::QUERY_RESULT) }
+    }
+}
+
+/// Pool of timer queries, double-buffered by frame
+pub struct GpuTimer<Q: TimerQueries = gl::Context> {
+    /// Queries issued in the frame being recorded, and in the one before it, which are read at
+    /// the end of this frame
+    frames: [Vec<(GpuPass, Q::Query)>; 2],
+    /// Index into `frames` of the frame being recorded
+    current: usize,
+    /// Queries whose results have been read, ready to be reused
+    free: Vec<Q::Query>,
+    /// Whether a query is running; GL only allows one timer query at a time
+    active: bool,
+    last: Option<GpuTimings>,
+    /// Passes taking longer than this many milliseconds are logged
+    budget: Option<f32>,
+    last_warning: Option<Instant>,
+}
+
+impl GpuTimer {
+    /// A timer, if the context supports timer queries
+    pub fn new(gl: &gl::Context) -> Option<Self> {
+        let version = gl.version();
+        let supported = !version.is_embedded
+            && ((version.major, version.minor) >= (3, 3)
+                || gl.supported_extensions().contains("GL_ARB_timer_query"));
+
+        supported.then(Self::with_queries)
+    }
+}
+
+impl<Q: TimerQueries> GpuTimer<Q> {
+    /// A timer using queries of type `Q`, which must be supported
+    fn with_queries() -> Self {
+        Self {
+            frames: [vec![], vec![]],
+            current: 0,
+            free: vec![],
+            active: false,
+            last: None,
+            budget: None,
+            last_warning: None,
+        }
+    }
+
+    /// Set the time in milliseconds a single pass may take before a warning is logged
+    pub fn set_budget(&mut self, budget: Option<f32>) {
+        self.budget = budget;
+    }
+
+    /// Start timing a pass, until `end`
+    pub fn begin(&mut self, queries: &Q, pass: GpuPass) {
+        debug_assert!(!self.active, "Timer queries can't be nested");
+        let query = match self.free.pop().or_else(|| queries.create()) {
+            Some(query) => query,
+            None => return,
+        };
+
+        queries.begin(query);
+        self.frames[self.current].push((pass, query));
+        self.active = true;
+    }
+
+    /// Stop timing the current pass
+    pub fn end(&mut self, queries: &Q) {
+        if self.active {
+            queries.end();
+            self.active = false;
+        }
+    }
+
+    /// Read back the previous frame's timings and start recording a new frame. Call once per
+    /// frame, after every view has been drawn.
+    pub fn end_frame(&mut self, queries: &Q) {
+        self.end(queries);
+
+        let previous = 1 - self.current;
+        let issued = std::mem::take(&mut self.frames[previous]);
+
+        // Results are usually ready a frame later. If any aren't, skip the frame rather than
+        // waiting for them.
+        let ready = issued.iter().all(|(_, query)| queries.available(*query));
+        if ready && !issued.is_empty() {
+            let mut timings = GpuTimings::default();
+            for (pass, query) in &issued {
+                timings.add(*pass, queries.result(*query));
+            }
+            self.warn_over_budget(&timings);
+            self.last = Some(timings);
+        }
+
+        // Reissuing a query discards any result still pending
+        self.free.extend(issued.into_iter().map(|(_, query)| query));
+        self.current = previous;
+    }
+
+    /// The most recent timings read back, if any
+    pub fn timings(&self) -> Option<&GpuTimings> {
+        self.last.as_ref()
+    }
+
+    fn warn_over_budget(&mut self, timings: &GpuTimings) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        if self
+            .last_warning
+            .is_some_and(|t| t.elapsed() < WARNING_INTERVAL)
+        {
+            return;
+        }
+
+        let over = timings.over_budget(budget);
+        if !over.is_empty() {
+            eprintln!(
+                "GPU passes over the {:.2} ms budget: {}",
+                budget,
+                over.join(", ")
+            );
+            self.last_warning = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    /// Queries numbered in order of creation, whose results arrive when a test says so
+    #[derive(Default)]
+    struct MockQueries {
+        created: Cell<u32>,
+        running: Cell<Option<u32>>,
+        /// Nanoseconds measured by each query whose result is available
+        results: RefCell<HashMap<u32, u32>>,
+    }
+
+    impl MockQueries {
+        /// Make the result of `query` available
+        fn finish(&self, query: u32, ns: u32) {
+            self.results.borrow_mut().insert(query, ns);
+        }
+    }
+
+    impl TimerQueries for MockQueries {
+        type Query = u32;
+
+        fn create(&self) -> Option<u32> {
+            let query = self.created.get();
+            self.created.set(query + 1);
+            Some(query)
+        }
+
+        fn begin(&self, query: u32) {
+            assert_eq!(self.running.replace(Some(query)), None);
+            // Reissuing a query discards its old result
+            self.results.borrow_mut().remove(&query);
+        }
+
+        fn end(&self) {
+            assert!(self.running.take().is_some());
+        }
+
+        fn available(&self, query: u32) -> bool {
+            self.results.borrow().contains_key(&query)
+        }
+
+        fn result(&self, query: u32) -> u32 {
+            // Reading a result which isn't available would stall until the GPU catches up
+            *self
+                .results
+                .borrow()
+                .get(&query)
+                .expect("Waited for a result")
+        }
+    }
+
+    /// Record a frame timing each of `passes`
+    fn frame(timer: &mut GpuTimer<MockQueries>, queries: &MockQueries, passes: &[GpuPass]) {
+        for &pass in passes {
+            timer.begin(queries, pass);
+            timer.end(queries);
+        }
+        timer.end_frame(queries);
+    }
+
+    #[test]
+    fn results_are_read_a_frame_late() {
+        let queries = MockQueries::default();
+        let mut timer = GpuTimer::with_queries();
+
+        frame(&mut timer, &queries, &[GpuPass::Map, GpuPass::Heads]);
+        queries.finish(0, 2_000_000);
+        queries.finish(1, 500_000);
+        assert!(timer.timings().is_none());
+
+        frame(&mut timer, &queries, &[GpuPass::Map]);
+        let timings = timer.timings().unwrap();
+        assert!((timings.get(GpuPass::Map) - 2.).abs() < 1e-6);
+        assert!((timings.get(GpuPass::Heads) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn frames_which_are_not_ready_are_skipped() {
+        let queries = MockQueries::default();
+        let mut timer = GpuTimer::with_queries();
+
+        frame(&mut timer, &queries, &[GpuPass::Map, GpuPass::Post]);
+        // Only one of the frame's two results has arrived by the time it's read
+        queries.finish(0, 1_000_000);
+        frame(&mut timer, &queries, &[GpuPass::Map, GpuPass::Post]);
+        assert!(timer.timings().is_none());
+
+        queries.finish(2, 3_000_000);
+        queries.finish(3, 1_000_000);
+        frame(&mut timer, &queries, &[GpuPass::Map, GpuPass::Post]);
+        assert!((timer.timings().unwrap().total() - 4.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn queries_are_recycled() {
+        let queries = MockQueries::default();
+        let mut timer = GpuTimer::with_queries();
+
+        for i in 0..10 {
+            frame(&mut timer, &queries, &[GpuPass::Shadows, GpuPass::Map]);
+            // Every other frame's results are late
+            if i % 2 == 0 {
+                for query in 0..queries.created.get() {
+                    queries.finish(query, 1_000);
+                }
+            }
+        }
+        // Two frames of two passes are in flight at once
+        assert_eq!(queries.created.get(), 4);
+        assert!(timer.timings().is_some());
+    }
+
+    #[test]
+    fn passes_index_their_own_timings() {
+        for (i, pass) in GpuPass::ALL.into_iter().enumerate() {
+            assert_eq!(pass as usize, i);
+        }
+    }
+
+    #[test]
+    fn timings_sum_over_views() {
+        let mut timings = GpuTimings::default();
+        // Both eyes of a VR frame
+        timings.add(GpuPass::Map, 1_500_000);
+        timings.add(GpuPass::Map, 500_000);
+        timings.add(GpuPass::Post, 250_000);
+        assert!((timings.get(GpuPass::Map) - 2.).abs() < 1e-6);
+        assert!((timings.get(GpuPass::Post) - 0.25).abs() < 1e-6);
+        assert_eq!(timings.get(GpuPass::Heads), 0.);
+        assert!((timings.total() - 2.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn passes_over_budget_are_listed_in_order() {
+        let mut timings = GpuTimings::default();
+        timings.add(GpuPass::Post, 3_000_000);
+        timings.add(GpuPass::Shadows, 2_500_000);
+        timings.add(GpuPass::Map, 1_000_000);
+        assert_eq!(
+            timings.over_budget(2.),
+            vec!["shadows 2.50 ms".to_string(), "post 3.00 ms".to_string()]
+        );
+        assert!(timings.over_budget(3.).is_empty());
+    }
+}
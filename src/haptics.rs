@@ -0,0 +1,164 @@
+//! Controller buzzes when another player's head comes close
+use cubehead::{Hands, Head};
+use nalgebra::Point3;
+use std::time::{Duration, Instant};
+
+/// Length of each buzz
+pub const PULSE_DURATION: Duration = Duration::from_millis(80);
+
+/// Shortest time between buzzes of the same hand
+const COOLDOWN: Duration = Duration::from_millis(1500);
+
+/// Amplitude of a buzz at the edge of the threshold...
+const MIN_AMPLITUDE: f32 = 0.15;
+
+/// ...and with a head right on top of us
+const MAX_AMPLITUDE: f32 = 0.6;
+
+/// Decides when each hand buzzes. A hand buzzes once when a remote head comes within the
+/// threshold of it or of our head, and again only after every head has left, with at most one
+/// buzz per `COOLDOWN`.
+pub struct ProximityHaptics {
+    threshold: f32,
+    /// Whether each hand has been clear of heads since its last buzz
+    armed: [bool; 2],
+    last_pulse: [Option<Instant>; 2],
+}
+
+impl ProximityHaptics {
+    /// Buzz for heads within `threshold` meters
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            armed: [true; 2],
+            last_pulse: [None; 2],
+        }
+    }
+
+    /// Check our head and hands against the (smoothed) remote heads, returning the amplitude to
+    /// buzz each hand with, if any
+    pub fn update(
+        &mut self,
+        now: Instant,
+        head: &Head,
+        hands: &Hands,
+        remote: &[Head],
+    ) -> [Option<f32>; 2] {
+        let remote: Vec<Point3<f32>> = remote.iter().map(|head| head.pos).collect();
+        let distances = [0, 1].map(|i| {
+            let ours = std::iter::once(head.pos).chain(hands[i].map(|hand| hand.pos));
+            nearest_distance(ours, &remote)
+        });
+        self.step(now, distances)
+    }
+
+    /// Advance with the distance from each hand (or our head) to the nearest remote head
+    pub fn step(&mut self, now: Instant, distances: [Option<f32>; 2]) -> [Option<f32>; 2] {
+        let mut pulses = [None; 2];
+        for i in 0..2 {
+            let inside = match distances[i] {
+                Some(distance) if distance < self.threshold => distance,
+                _ => {
+                    self.armed[i] = true;
+                    continue;
+                }
+            };
+            let cooled = self.last_pulse[i].is_none_or(|last| now - last >= COOLDOWN);
+            if self.armed[i] && cooled {
+                pulses[i] = Some(amplitude(inside, self.threshold));
+                self.armed[i] = false;
+                self.last_pulse[i] = Some(now);
+            }
+        }
+        pulses
+    }
+}
+
+/// Buzz amplitude for a head `distance` meters away, rising from `MIN_AMPLITUDE` at the
+/// threshold to `MAX_AMPLITUDE` at zero
+pub fn amplitude(distance: f32, threshold: f32) -> f32 {
+    let closeness = (1. - distance / threshold).clamp(0., 1.);
+    MIN_AMPLITUDE + (MAX_AMPLITUDE - MIN_AMPLITUDE) * closeness
+}
+
+/// Shortest distance between any of `ours` and any of `theirs`
+fn nearest_distance(
+    ours: impl Iterator<Item = Point3<f32>>,
+    theirs: &[Point3<f32>],
+) -> Option<f32> {
+    ours.flat_map(|a| theirs.iter().map(move |b| (b - a).norm()))
+        .reduce(f32::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32) -> Head {
+        Head {
+            pos: Point3::new(x, 0., 0.),
+            ..Head::default()
+        }
+    }
+
+    #[test]
+    fn amplitude_rises_as_heads_come_closer() {
+        assert_eq!(amplitude(1., 1.), MIN_AMPLITUDE);
+        assert_eq!(amplitude(0., 1.), MAX_AMPLITUDE);
+        let halfway = amplitude(0.5, 1.);
+        assert!((halfway - (MIN_AMPLITUDE + MAX_AMPLITUDE) / 2.).abs() < 1e-6);
+        assert!(amplitude(0.25, 1.) > halfway);
+        // Never outside the range
+        assert_eq!(amplitude(3., 1.), MIN_AMPLITUDE);
+    }
+
+    #[test]
+    fn hands_buzz_once_per_approach() {
+        let start = Instant::now();
+        let mut haptics = ProximityHaptics::new(1.);
+        let pulses = haptics.step(start, [Some(0.5), Some(2.)]);
+        assert!(pulses[0].is_some());
+        assert_eq!(pulses[1], None);
+
+        // Staying close doesn't buzz again, however long it lasts
+        let later = start + COOLDOWN * 2;
+        assert_eq!(haptics.step(later, [Some(0.5), None]), [None, None]);
+
+        // Leaving and coming back does
+        haptics.step(later, [Some(1.), None]);
+        let pulses = haptics.step(later, [Some(0.5), None]);
+        assert!(pulses[0].is_some());
+    }
+
+    #[test]
+    fn buzzes_wait_for_the_cooldown() {
+        let start = Instant::now();
+        let mut haptics = ProximityHaptics::new(1.);
+        assert!(haptics.step(start, [Some(0.5), None])[0].is_some());
+        haptics.step(start, [None, None]);
+
+        let soon = start + COOLDOWN / 2;
+        assert_eq!(haptics.step(soon, [Some(0.5), None]), [None, None]);
+        // Still armed, so it buzzes once cooled down
+        let cooled = start + COOLDOWN;
+        assert!(haptics.step(cooled, [Some(0.5), None])[0].is_some());
+    }
+
+    #[test]
+    fn the_nearest_of_our_head_and_hand_counts() {
+        let mut haptics = ProximityHaptics::new(1.);
+        let hands = [Some(at(2.)), None];
+        let pulses = haptics.update(Instant::now(), &at(0.), &hands, &[at(2.5), at(-3.)]);
+        let expected = amplitude(0.5, 1.);
+        assert!((pulses[0].unwrap() - expected).abs() < 1e-6);
+        // The other hand is untracked, so only our head is near enough to count, and it isn't
+        assert_eq!(pulses[1], None);
+
+        // Nobody else around
+        let mut haptics = ProximityHaptics::new(1.);
+        assert_eq!(
+            haptics.update(Instant::now(), &at(0.), &hands, &[]),
+            [None, None]
+        );
+    }
+}
@@ -1,17 +1,159 @@
-use nalgebra::{Matrix4, Point3, UnitQuaternion};
+use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Port the server listens on, and clients connect to, unless told otherwise
+pub const DEFAULT_PORT: u16 = 5031;
+
+/// Longest a client waits for each address of the server to accept its connection
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Start of the hello the server sends on every connection, telling clients they have reached a
+/// cubehead server
+pub const PROTOCOL_MAGIC: [u8; 8] = *b"cubehead";
+
+/// Version of the messages sent each way, following `PROTOCOL_MAGIC` in the hello. Bump this
+/// whenever `ClientState` or `ServerState` change.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Longest a client waits for the server's hello once connected
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest message body either side accepts, far above any real state, so that a peer speaking
+/// some other protocol can't make us allocate gigabytes
+pub const MAX_MESSAGE_SIZE: u32 = 16 << 20;
+
+/// Identifies a connected player
+pub type PlayerId = u32;
 
 /// Client data sent to server
-#[derive(Copy, Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct ClientState {
     pub head: Head,
+    /// Poses of the left and right hands, if tracked
+    pub hands: Hands,
+    /// Data for the server to relay to everyone else, cleared once sent
+    pub app_data: Vec<AppData>,
+    /// Display name
+    pub name: String,
+    /// Client clock in milliseconds, echoed back by the server to measure latency
+    pub ping: u32,
+    /// Most other players the client can draw. The server sends only this many, nearest first;
+    /// 0 means no limit. `Client::send_state` only sends it when it changes, leaving None in
+    /// between, and the server keeps the last one it got.
+    pub max_players: Option<u32>,
 }
 
 /// Server data sent to client
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct ServerState {
-    pub heads: Vec<Head>,
+    /// Every connected player except the recipient
+    pub players: Vec<Player>,
+    /// The `ping` of the recipient's latest message, or 0 if none has arrived yet
+    pub echo: u32,
+    /// Data other players have relayed since the last message, with who relayed it
+    pub app_data: Vec<(PlayerId, AppData)>,
+}
+
+/// Data passed between clients through the server, which relays it without looking inside. The
+/// channel names what it holds, so that clients can skip channels they don't know.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AppData {
+    pub channel: String,
+    pub data: Vec<u8>,
+}
+
+/// Relay channel of laser pointers, holding `Pointers` encoded with bincode
+pub const POINTER_CHANNEL: &str = "pointers";
+
+/// A player, as seen by other clients
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct Player {
+    pub id: PlayerId,
+    pub name: String,
+    pub head: Head,
+    pub hands: Hands,
+    /// Milliseconds between the server last hearing from this player and sending this
+    pub since_update: u32,
+}
+
+/// Poses of the left and right hand controllers (in that order), or None where not tracked.
+/// Hand poses use the same conventions as heads, with the controller pointing along negative Z.
+pub type Hands = [Option<Head>; 2];
+
+/// A laser pointer, from the controller to where it hit something or gave up
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Pointer {
+    pub from: Point3<f32>,
+    pub to: Point3<f32>,
+    /// Whether `to` is on the map or a head, rather than at the end of the beam's range
+    pub hit: bool,
+}
+
+/// Laser pointers of the left and right hands (in that order), or None where not pointing
+pub type Pointers = [Option<Pointer>; 2];
+
+/// Relay message showing `pointers` to other players, until the next one or `RemotePointers`
+/// expires them
+pub fn pointer_app_data(pointers: &Pointers) -> AppData {
+    AppData {
+        channel: POINTER_CHANNEL.into(),
+        data: bincode::serialize(pointers).expect("Pointers always serialize"),
+    }
+}
+
+/// Laser pointers other players have relayed to us. Players refresh them while pointing, so
+/// those which stop arriving are dropped after `expiry`, as are those of players who leave.
+pub struct RemotePointers {
+    pub expiry: Duration,
+    /// Latest pointers of each player and when they arrived
+    pointers: HashMap<PlayerId, (Pointers, Instant)>,
+}
+
+impl RemotePointers {
+    pub fn new(expiry: Duration) -> Self {
+        Self {
+            expiry,
+            pointers: HashMap::new(),
+        }
+    }
+
+    /// Take in the pointers among relayed `app_data`, ignoring any which can't be decoded, and
+    /// forget those of players other than `players` or which have expired
+    pub fn update(&mut self, players: &[Player], app_data: &[(PlayerId, AppData)], now: Instant) {
+        for (id, app_data) in app_data {
+            if app_data.channel != POINTER_CHANNEL {
+                continue;
+            }
+            match bincode::deserialize::<Pointers>(&app_data.data) {
+                Ok(pointers) => {
+                    self.pointers.insert(*id, (pointers, now));
+                }
+                Err(e) => eprintln!("Ignoring malformed pointers from player {}; {}", id, e),
+            }
+        }
+
+        let expiry = self.expiry;
+        self.pointers.retain(|id, (_, received)| {
+            now.saturating_duration_since(*received) <= expiry
+                && players.iter().any(|player| player.id == *id)
+        });
+    }
+
+    /// Pointers a player is showing
+    pub fn get(&self, id: PlayerId) -> Pointers {
+        self.pointers
+            .get(&id)
+            .map_or([None; 2], |(pointers, _)| *pointers)
+    }
+
+    /// Forget all pointers
+    pub fn clear(&mut self) {
+        self.pointers.clear();
+    }
 }
 
 /// The position and orientation of a user's head
@@ -29,6 +171,257 @@ impl Head {
         // TODO: Make this cheaper?
         Matrix4::new_translation(&self.pos.coords) * self.orient.to_homogeneous()
     }
+
+    /// Treating this as a transform, the pose `local` given relative to it
+    pub fn transform(&self, local: &Head) -> Head {
+        Head {
+            pos: self.pos + self.orient * local.pos.coords,
+            orient: self.orient * local.orient,
+        }
+    }
+}
+
+/// Smooths out jitter in the head and hand poses of other players before they are drawn. Each
+/// smoothed pose chases the latest one received from the network, covering a fixed fraction of
+/// the remaining distance per unit of time.
+pub struct HeadSmoother {
+    /// Time in seconds to cover 63% of the distance to the target. Zero disables smoothing.
+    pub time_constant: f32,
+    /// Poses further than this from their target (in meters) jump straight to it
+    pub snap_distance: f32,
+    heads: HashMap<PlayerId, (Head, Hands)>,
+}
+
+impl HeadSmoother {
+    pub fn new(time_constant: f32, snap_distance: f32) -> Self {
+        Self {
+            time_constant,
+            snap_distance,
+            heads: HashMap::new(),
+        }
+    }
+
+    /// Advance the smoothed heads and hands by `dt` seconds towards those of `players`, and
+    /// replace the latter with the former. Players which have left are forgotten, and hands which
+    /// were untracked start where they are.
+    pub fn apply(&mut self, players: &mut [Player], dt: f32) {
+        let mut heads = HashMap::with_capacity(players.len());
+
+        for player in players {
+            if let Some((prev_head, prev_hands)) = self.heads.get(&player.id) {
+                player.head = self.step(*prev_head, player.head, dt);
+                for (hand, prev) in player.hands.iter_mut().zip(prev_hands) {
+                    if let (Some(hand), Some(prev)) = (hand, prev) {
+                        *hand = self.step(*prev, *hand, dt);
+                    }
+                }
+            }
+            heads.insert(player.id, (player.head, player.hands));
+        }
+
+        self.heads = heads;
+    }
+
+    /// Move `head` towards `target` by `dt` seconds
+    fn step(&self, head: Head, target: Head, dt: f32) -> Head {
+        if self.time_constant <= 0. || (target.pos - head.pos).norm() > self.snap_distance {
+            return target;
+        }
+
+        // Independent of frame rate; two steps of dt land where one step of 2 * dt does
+        let t = 1. - (-dt / self.time_constant).exp();
+
+        Head {
+            pos: head.pos + (target.pos - head.pos) * t,
+            orient: head
+                .orient
+                .try_slerp(&target.orient, t, 1e-6)
+                .unwrap_or(target.orient),
+        }
+    }
+}
+
+/// Recent positions of each player's head, sampled at a fixed rate, for drawing motion trails.
+/// Each trail holds at most `length / interval` samples.
+pub struct HeadTrails {
+    /// Seconds between samples
+    pub interval: f32,
+    /// Samples older than this many seconds are dropped
+    pub length: f32,
+    /// Seconds since the last sample was taken
+    since_sample: f32,
+    /// Positions and their ages in seconds, oldest first
+    trails: HashMap<PlayerId, VecDeque<(Point3<f32>, f32)>>,
+}
+
+impl HeadTrails {
+    pub fn new(interval: f32, length: f32) -> Self {
+        Self {
+            interval,
+            length,
+            since_sample: 0.,
+            trails: HashMap::new(),
+        }
+    }
+
+    /// Age the trails by `dt` seconds, and add the heads of `players` if a sample is due.
+    /// Players which have left are forgotten.
+    pub fn update(&mut self, players: &[Player], dt: f32) {
+        self.since_sample += dt;
+        let sample = self.since_sample >= self.interval;
+        if sample {
+            // Don't try to catch up after a long frame
+            self.since_sample = (self.since_sample - self.interval).min(self.interval);
+        }
+
+        let capacity = (self.length / self.interval).ceil().max(1.) as usize;
+        let mut trails = HashMap::with_capacity(players.len());
+
+        for player in players {
+            let mut trail = self.trails.remove(&player.id).unwrap_or_default();
+            for (_, age) in &mut trail {
+                *age += dt;
+            }
+            while trail.front().is_some_and(|(_, age)| *age > self.length) {
+                trail.pop_front();
+            }
+            if sample || trail.is_empty() {
+                trail.push_back((player.head.pos, 0.));
+            }
+            while trail.len() > capacity {
+                trail.pop_front();
+            }
+            trails.insert(player.id, trail);
+        }
+
+        self.trails = trails;
+    }
+
+    /// Every sample with its opacity, from 1 when new to 0 when about to be dropped
+    pub fn samples(&self) -> impl Iterator<Item = (Point3<f32>, f32)> + '_ {
+        self.trails.values().flatten().map(|(pos, age)| {
+            let alpha = 1. - age / self.length;
+            (*pos, alpha.clamp(0., 1.))
+        })
+    }
+
+    /// Forget all trails
+    pub fn clear(&mut self) {
+        self.trails.clear();
+        self.since_sample = 0.;
+    }
+}
+
+/// A head pose at a moment in time, for camera paths
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds since the path began
+    pub time: f32,
+    pub head: Head,
+}
+
+/// The pose at `time` along a smooth curve through `keyframes`, which must be in order of time.
+/// Positions follow a Catmull-Rom spline, with tangents taken from the neighbouring keyframes and
+/// their times so that speed carries smoothly through each keyframe, and orientations follow a
+/// squad chain through each keyframe's orientation. Times before the first keyframe or after the
+/// last hold it; None for no keyframes.
+pub fn sample_keyframes(keyframes: &[Keyframe], time: f32) -> Option<Head> {
+    let (first, last) = (keyframes.first()?, keyframes.last()?);
+    if keyframes.len() == 1 || time <= first.time {
+        return Some(first.head);
+    }
+    if time >= last.time {
+        return Some(last.head);
+    }
+
+    // The segment from keyframe i to i + 1 holding `time`
+    let i = keyframes.partition_point(|key| key.time <= time) - 1;
+    let (a, b) = (&keyframes[i], &keyframes[i + 1]);
+    let duration = b.time - a.time;
+    if duration <= 0. {
+        return Some(b.head);
+    }
+    let t = (time - a.time) / duration;
+
+    let pos = hermite(
+        a.head.pos,
+        b.head.pos,
+        keyframe_velocity(keyframes, i) * duration,
+        keyframe_velocity(keyframes, i + 1) * duration,
+        t,
+    );
+    let orient = squad(
+        a.head.orient,
+        b.head.orient,
+        squad_control(keyframes, i),
+        squad_control(keyframes, i + 1),
+        t,
+    );
+    Some(Head { pos, orient })
+}
+
+/// Velocity through keyframe `i`, averaging the segments to either side. The ends use their one
+/// segment, which makes a path of two keyframes a straight line at constant speed.
+fn keyframe_velocity(keyframes: &[Keyframe], i: usize) -> Vector3<f32> {
+    let segment = |j: usize| {
+        let (a, b) = (&keyframes[j], &keyframes[j + 1]);
+        let duration = b.time - a.time;
+        match duration > 0. {
+            true => (b.head.pos - a.head.pos) / duration,
+            false => Vector3::zeros(),
+        }
+    };
+    match (i.checked_sub(1), i + 1 < keyframes.len()) {
+        (Some(before), true) => (segment(before) + segment(i)) / 2.,
+        (Some(before), false) => segment(before),
+        (None, _) => segment(i),
+    }
+}
+
+/// Cubic Hermite curve from `a` to `b` with tangents `ta` and `tb`, at `t` from 0 to 1
+pub fn hermite(
+    a: Point3<f32>,
+    b: Point3<f32>,
+    ta: Vector3<f32>,
+    tb: Vector3<f32>,
+    t: f32,
+) -> Point3<f32> {
+    let (t2, t3) = (t * t, t * t * t);
+    let h00 = 2. * t3 - 3. * t2 + 1.;
+    let h10 = t3 - 2. * t2 + t;
+    let h01 = -2. * t3 + 3. * t2;
+    let h11 = t3 - t2;
+    Point3::from(a.coords * h00 + ta * h10 + b.coords * h01 + tb * h11)
+}
+
+/// Inner control orientation of keyframe `i` for `squad`, which rounds off the turn through it.
+/// The ends are their own control, so that a path of two keyframes slerps.
+fn squad_control(keyframes: &[Keyframe], i: usize) -> UnitQuaternion<f32> {
+    let q = keyframes[i].head.orient;
+    match (i.checked_sub(1), keyframes.get(i + 1)) {
+        (Some(before), Some(after)) => {
+            let to_before = (q.inverse() * keyframes[before].head.orient).scaled_axis();
+            let to_after = (q.inverse() * after.head.orient).scaled_axis();
+            q * UnitQuaternion::from_scaled_axis(-(to_before + to_after) / 4.)
+        }
+        _ => q,
+    }
+}
+
+/// Spherical quadrangle interpolation from `a` to `b` with inner controls `sa` and `sb`, at `t`
+/// from 0 to 1
+pub fn squad(
+    a: UnitQuaternion<f32>,
+    b: UnitQuaternion<f32>,
+    sa: UnitQuaternion<f32>,
+    sb: UnitQuaternion<f32>,
+    t: f32,
+) -> UnitQuaternion<f32> {
+    // Nearly equal orientations have no well-defined arc between them, but either will do
+    let slerp = |from: UnitQuaternion<f32>, to: UnitQuaternion<f32>, t: f32| {
+        from.try_slerp(&to, t, 1e-6).unwrap_or(to)
+    };
+    slerp(slerp(a, b, t), slerp(sa, sb, t), 2. * t * (1. - t))
 }
 
 /// Facilitates reading a little-endian length header, and then a message body over a reliable,
@@ -70,6 +463,12 @@ impl AsyncBufferedReceiver {
                     } else if n_bytes == 4 {
                         // Set a new buffer size
                         let msg_size = u32::from_le_bytes(buf);
+                        if msg_size > MAX_MESSAGE_SIZE {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("Message of {} bytes is too large", msg_size),
+                            ));
+                        }
                         self.buf = vec![0; msg_size as usize];
                         self.buf_pos = 0;
                     } else {
@@ -119,3 +518,1019 @@ pub fn serialize_msg<W: Write, T: Serialize>(obj: &T, mut w: W) -> anyhow::Resul
     w.write_all(&header)?;
     Ok(bincode::serialize_into(w, obj)?)
 }
+
+/// Greet a client which just connected, as the server
+pub fn write_hello<W: Write>(mut w: W) -> io::Result<()> {
+    w.write_all(&PROTOCOL_MAGIC)?;
+    w.write_all(&PROTOCOL_VERSION.to_le_bytes())
+}
+
+/// Read the server's hello, failing unless it's a cubehead server speaking our version
+pub fn read_hello<R: Read>(mut r: R) -> anyhow::Result<()> {
+    let mut hello = [0; PROTOCOL_MAGIC.len() + 4];
+    if let Err(e) = r.read_exact(&mut hello) {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => anyhow::bail!(
+                "The server didn't say hello within {} s; is it a cubehead server?",
+                HELLO_TIMEOUT.as_secs()
+            ),
+            io::ErrorKind::UnexpectedEof => {
+                anyhow::bail!("The server hung up without saying hello")
+            }
+            _ => return Err(e.into()),
+        }
+    }
+    let (magic, version) = hello.split_at(PROTOCOL_MAGIC.len());
+    if magic != PROTOCOL_MAGIC {
+        anyhow::bail!("Not a cubehead server");
+    }
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    if version != PROTOCOL_VERSION {
+        anyhow::bail!(
+            "The server speaks protocol version {}, but this client speaks version {}",
+            version,
+            PROTOCOL_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Network counters reported by the client
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NetStats {
+    /// Latest round trip time measured through the server
+    pub rtt: Option<Duration>,
+    /// Total bytes sent, including message headers
+    pub bytes_sent: u64,
+    /// Total bytes received, including message headers
+    pub bytes_received: u64,
+}
+
+/// Split a server address into its host and port, which is `DEFAULT_PORT` if it's left out. The
+/// host may be a name, an IPv4 address or an IPv6 address, which needs brackets when followed by
+/// a port, as in [::1]:5031.
+pub fn split_server_addr(addr: &str) -> anyhow::Result<(&str, u16)> {
+    let addr = addr.trim();
+    let (host, port) = match addr.strip_prefix('[') {
+        Some(rest) => {
+            let (host, after) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow::format_err!("Missing ] in \"{}\"", addr))?;
+            match (after, after.strip_prefix(':')) {
+                ("", _) => (host, None),
+                (_, Some(port)) => (host, Some(port)),
+                (_, None) => anyhow::bail!("Expected a port after ] in \"{}\"", addr),
+            }
+        }
+        None => match addr.rsplit_once(':') {
+            // Colons in the host make it an IPv6 address without a port
+            Some((host, port)) if !host.contains(':') => (host, Some(port)),
+            _ => (addr, None),
+        },
+    };
+
+    if host.is_empty() {
+        anyhow::bail!("No host in \"{}\"", addr);
+    }
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| anyhow::format_err!("Invalid port \"{}\" in \"{}\"", port, addr))?,
+        None => DEFAULT_PORT,
+    };
+    Ok((host, port))
+}
+
+/// Look up the addresses of a server given as in `split_server_addr`, in the order to try them
+pub fn resolve_server_addr(addr: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    let (host, port) = split_server_addr(addr)?;
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| {
+            anyhow::format_err!(
+                "Couldn't find the server \"{}\"; check the name and your connection ({})",
+                host,
+                e
+            )
+        })?
+        .collect();
+    if addrs.is_empty() {
+        anyhow::bail!("The server \"{}\" has no addresses", host);
+    }
+    Ok(addrs)
+}
+
+/// Open a connection to the first of the server's addresses to accept within `CONNECT_TIMEOUT`,
+/// explaining why each of the others failed
+fn dial(addrs: &[SocketAddr]) -> anyhow::Result<TcpStream> {
+    let mut failures = vec![];
+    let mut connected = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                connected = Some(stream);
+                break;
+            }
+            Err(e) => {
+                let why = match e.kind() {
+                    io::ErrorKind::ConnectionRefused => {
+                        "refused the connection; is the server running?".into()
+                    }
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                        format!("didn't answer within {} s", CONNECT_TIMEOUT.as_secs())
+                    }
+                    _ => e.to_string(),
+                };
+                failures.push(format!("{} {}", addr, why));
+            }
+        }
+    }
+    connected.ok_or_else(|| {
+        anyhow::format_err!("Failed to connect to the server: {}", failures.join(", "))
+    })
+}
+
+/// Connection to the server, sending our state and keeping the latest state of everyone else
+pub struct Client {
+    tcp_stream: TcpStream,
+    msg_buf: AsyncBufferedReceiver,
+    latest_state: ServerState,
+    /// Relayed data received since it was last taken
+    app_data: Vec<(PlayerId, AppData)>,
+    /// Reference point for ping timestamps
+    start: Instant,
+    send_buf: Vec<u8>,
+    stats: NetStats,
+    /// `max_players` last sent on this connection
+    max_players_sent: Option<u32>,
+}
+
+impl Client {
+    /// Connect to the first of the server's addresses to accept within `CONNECT_TIMEOUT`
+    pub fn new(addrs: &[SocketAddr]) -> anyhow::Result<Self> {
+        let mut tcp_stream = dial(addrs)?;
+        tcp_stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+        read_hello(&mut tcp_stream)?;
+        tcp_stream.set_read_timeout(None)?;
+        tcp_stream.set_nonblocking(true)?;
+        let msg_buf = AsyncBufferedReceiver::new();
+
+        Ok(Self {
+            tcp_stream,
+            latest_state: ServerState::default(),
+            app_data: vec![],
+            msg_buf,
+            start: Instant::now(),
+            send_buf: vec![],
+            stats: NetStats::default(),
+            max_players_sent: None,
+        })
+    }
+
+    /// Send our own head position, stamping it with the current time. The data to relay is
+    /// cleared, as it is only sent once, and `max_players` is left out unless it changed.
+    pub fn send_state(&mut self, state: &mut ClientState) -> anyhow::Result<()> {
+        state.ping = self.timestamp();
+
+        let max_players = state.max_players;
+        if max_players == self.max_players_sent {
+            state.max_players = None;
+        }
+        self.send_buf.clear();
+        let result = serialize_msg(state, &mut self.send_buf);
+        state.max_players = max_players;
+        result?;
+        state.app_data.clear();
+        self.tcp_stream.write_all(&self.send_buf)?;
+        self.stats.bytes_sent += self.send_buf.len() as u64;
+        self.max_players_sent = max_players.or(self.max_players_sent);
+
+        Ok(())
+    }
+
+    /// Network counters and the latest round trip time
+    pub fn stats(&self) -> NetStats {
+        self.stats
+    }
+
+    /// Milliseconds since the client started, never zero
+    fn timestamp(&self) -> u32 {
+        (self.start.elapsed().as_millis() as u32).max(1)
+    }
+
+    /// Get latest head positions
+    pub fn update_heads(&mut self) -> anyhow::Result<&ServerState> {
+        self.poll()?;
+
+        Ok(&self.latest_state)
+    }
+
+    /// Latest state received from the server, without polling
+    pub fn state(&self) -> &ServerState {
+        &self.latest_state
+    }
+
+    /// Data relayed by other players since this was last called, oldest first. It piles up until
+    /// taken.
+    pub fn take_app_data(&mut self) -> Vec<(PlayerId, AppData)> {
+        std::mem::take(&mut self.app_data)
+    }
+
+    /// Receive head positions of all players. Only the latest state is kept, but the relayed
+    /// data of every message is.
+    fn poll(&mut self) -> anyhow::Result<()> {
+        let mut received = false;
+        loop {
+            match self.msg_buf.read(&mut self.tcp_stream)? {
+                ReadState::Complete(msg) => {
+                    // Account for the length header too
+                    self.stats.bytes_received += msg.len() as u64 + 4;
+                    self.latest_state = bincode::deserialize(&msg)?;
+                    self.app_data.append(&mut self.latest_state.app_data);
+                    received = true;
+                }
+                ReadState::Disconnected => anyhow::bail!("The server hung up"),
+                ReadState::Incomplete | ReadState::Invalid => break,
+            }
+        }
+
+        if received && self.latest_state.echo != 0 {
+            let rtt = self.timestamp().wrapping_sub(self.latest_state.echo);
+            self.stats.rtt = Some(Duration::from_millis(rtt.into()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Connection to the server, or none when playing offline, in which case nothing is sent and
+/// there is never anyone else
+pub enum Connection {
+    Online(Client),
+    Offline(ServerState),
+}
+
+impl Connection {
+    /// Connect to the first of the server's addresses to accept, as in `Client::new`
+    pub fn online(addrs: &[SocketAddr]) -> anyhow::Result<Self> {
+        Client::new(addrs).map(Self::Online)
+    }
+
+    pub fn offline() -> Self {
+        Self::Offline(ServerState::default())
+    }
+
+    /// Send our own head position to the server, if connected. The data to relay is cleared
+    /// either way.
+    pub fn send_state(&mut self, state: &mut ClientState) -> anyhow::Result<()> {
+        match self {
+            Self::Online(client) => client.send_state(state),
+            Self::Offline(_) => {
+                state.app_data.clear();
+                Ok(())
+            }
+        }
+    }
+
+    /// Network counters, which stay at zero offline
+    pub fn stats(&self) -> NetStats {
+        match self {
+            Self::Online(client) => client.stats(),
+            Self::Offline(_) => NetStats::default(),
+        }
+    }
+
+    /// Get latest head positions, of which there are none offline
+    pub fn update_heads(&mut self) -> anyhow::Result<&ServerState> {
+        match self {
+            Self::Online(client) => client.update_heads(),
+            Self::Offline(state) => Ok(state),
+        }
+    }
+
+    /// Latest state received from the server, without polling
+    pub fn state(&self) -> &ServerState {
+        match self {
+            Self::Online(client) => client.state(),
+            Self::Offline(state) => state,
+        }
+    }
+
+    /// Data relayed by other players since this was last called, which is none offline
+    pub fn take_app_data(&mut self) -> Vec<(PlayerId, AppData)> {
+        match self {
+            Self::Online(client) => client.take_app_data(),
+            Self::Offline(_) => vec![],
+        }
+    }
+}
+
+/// Half the width of the generated terrain, in meters...
+pub const TERRAIN_EXTENT: f32 = 30.;
+
+/// ...and quads along each side of it
+pub const TERRAIN_RESOLUTION: u32 = 120;
+
+/// Least height above the ground the server keeps heads at when given a terrain seed, about half
+/// a head
+pub const GROUND_CLEARANCE: f32 = 0.25;
+
+/// Octaves of noise layered into terrain, each twice the frequency and half the height of the
+/// one before
+const TERRAIN_OCTAVES: u32 = 5;
+
+/// Wavelength of the broadest hills, in meters...
+const TERRAIN_WAVELENGTH: f32 = 24.;
+
+/// ...and the most they rise or sink from the middle, in meters
+pub const TERRAIN_AMPLITUDE: f32 = 3.;
+
+/// Terrain heights sampled on a square grid, which clients stand on and the server keeps heads
+/// above
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    /// Half the width; the grid spans [-extent, extent] on X and Z
+    extent: f32,
+    /// Quads along each side
+    resolution: u32,
+    /// Row by row along Z, each running along X
+    heights: Vec<f32>,
+}
+
+impl Heightfield {
+    /// Hills spanning [-extent, extent] on X and Z with `resolution` quads along each side, shaped
+    /// by layers of value noise from `seed`. The extent must be above zero, and the resolution at
+    /// least 2.
+    pub fn generate(extent: f32, resolution: u32, seed: u32) -> anyhow::Result<Self> {
+        if !(extent.is_finite() && extent > 0.) {
+            anyhow::bail!("Terrain extent must be above zero, not {}", extent);
+        }
+        if resolution < 2 {
+            anyhow::bail!("Terrain resolution must be at least 2, not {}", resolution);
+        }
+
+        let n = resolution + 1;
+        let step = 2. * extent / resolution as f32;
+        let heights = (0..n)
+            .flat_map(|j| (0..n).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let x = -extent + i as f32 * step;
+                let z = -extent + j as f32 * step;
+                let noise = fractal_noise(x / TERRAIN_WAVELENGTH, z / TERRAIN_WAVELENGTH, seed);
+                noise * TERRAIN_AMPLITUDE
+            })
+            .collect();
+        Ok(Self {
+            extent,
+            resolution,
+            heights,
+        })
+    }
+
+    /// Height at (x, z), interpolated bilinearly between the grid points around it, or None
+    /// outside the grid
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let to_grid = |v: f32| (v + self.extent) / (2. * self.extent) * self.resolution as f32;
+        let (gx, gz) = (to_grid(x), to_grid(z));
+        let n = self.resolution as f32;
+        if !(0.0..=n).contains(&gx) || !(0.0..=n).contains(&gz) {
+            return None;
+        }
+
+        // The far edges interpolate within the last quad
+        let i = (gx.floor() as u32).min(self.resolution - 1);
+        let j = (gz.floor() as u32).min(self.resolution - 1);
+        let (tx, tz) = (gx - i as f32, gz - j as f32);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let near = lerp(self.height(i, j), self.height(i + 1, j), tx);
+        let far = lerp(self.height(i, j + 1), self.height(i + 1, j + 1), tx);
+        Some(lerp(near, far, tz))
+    }
+
+    /// `pos`, raised to `clearance` above the ground if it's any lower
+    pub fn keep_above(&self, pos: Point3<f32>, clearance: f32) -> Point3<f32> {
+        match self.height_at(pos.x, pos.z) {
+            Some(ground) => Point3::new(pos.x, pos.y.max(ground + clearance), pos.z),
+            None => pos,
+        }
+    }
+
+    /// Height of grid point `i` along X and `j` along Z
+    pub fn height(&self, i: u32, j: u32) -> f32 {
+        self.heights[(j * (self.resolution + 1) + i) as usize]
+    }
+
+    /// Position of grid point `i` along X and `j` along Z
+    pub fn point(&self, i: u32, j: u32) -> [f32; 3] {
+        let step = 2. * self.extent / self.resolution as f32;
+        let x = -self.extent + i as f32 * step;
+        let z = -self.extent + j as f32 * step;
+        [x, self.height(i, j), z]
+    }
+}
+
+/// Layered value noise at (x, z), roughly from -1 to 1
+fn fractal_noise(x: f32, z: f32, seed: u32) -> f32 {
+    let mut sum = 0.;
+    let mut total = 0.;
+    let (mut frequency, mut amplitude) = (1., 1.);
+    for octave in 0..TERRAIN_OCTAVES {
+        let seed = seed.wrapping_add(octave.wrapping_mul(0x9e37_79b9));
+        sum += value_noise(x * frequency, z * frequency, seed) * amplitude;
+        total += amplitude;
+        frequency *= 2.;
+        amplitude /= 2.;
+    }
+    sum / total
+}
+
+/// Smoothly interpolated random values from -1 to 1 at each whole (x, z)
+fn value_noise(x: f32, z: f32, seed: u32) -> f32 {
+    let (x0, z0) = (x.floor(), z.floor());
+    let (tx, tz) = (x - x0, z - z0);
+    let (ix, iz) = (x0 as i32, z0 as i32);
+    let corner = |dx: i32, dz: i32| lattice_value(ix.wrapping_add(dx), iz.wrapping_add(dz), seed);
+
+    let smooth = |t: f32| t * t * (3. - 2. * t);
+    let (sx, sz) = (smooth(tx), smooth(tz));
+    let near = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * sx;
+    let far = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * sx;
+    near + (far - near) * sz
+}
+
+/// Random value from -1 to 1 for a lattice point, the same every time for the same seed
+fn lattice_value(x: i32, z: i32, seed: u32) -> f32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x85eb_ca6b) ^ (z as u32).wrapping_mul(0xc2b2_ae35);
+    // Finalizer of MurmurHash3, mixing every bit into every other
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+    use std::net::TcpListener;
+
+    /// Smooth player `id` towards `head` for `dt` seconds, returning where it is drawn
+    fn smoothed(smoother: &mut HeadSmoother, id: PlayerId, head: Head, dt: f32) -> Head {
+        let mut players = [Player {
+            id,
+            head,
+            ..Player::default()
+        }];
+        smoother.apply(&mut players, dt);
+        players[0].head
+    }
+
+    #[test]
+    fn transforms_place_local_poses_in_the_parent() {
+        let parent = Head {
+            pos: Point3::new(1., 2., 0.),
+            orient: UnitQuaternion::from_axis_angle(
+                &Vector3::y_axis(),
+                std::f32::consts::FRAC_PI_2,
+            ),
+        };
+        let local = Head {
+            pos: Point3::new(0., 0., -1.),
+            orient: UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.5),
+        };
+
+        // A meter ahead of a parent facing -X
+        let world = parent.transform(&local);
+        assert!((world.pos - Point3::new(0., 2., 0.)).norm() < 1e-6);
+        assert!(world.orient.angle_to(&(parent.orient * local.orient)) < 1e-6);
+        // Which is what the matrices do
+        let matrix = parent.matrix() * local.matrix();
+        assert!((world.matrix() - matrix).norm() < 1e-5);
+
+        // The identity changes nothing
+        let same = Head::default().transform(&local);
+        assert_eq!(same.pos, local.pos);
+        assert!(same.orient.angle_to(&local.orient) < 1e-6);
+    }
+
+    #[test]
+    fn smoothing_chases_the_target() {
+        let mut smoother = HeadSmoother::new(1., 2.);
+        let origin = Head::default();
+        // New players start where they are
+        assert_eq!(smoothed(&mut smoother, 1, origin, 1.).pos, origin.pos);
+
+        let target = Head {
+            pos: Point3::new(1., 0., 0.),
+            orient: UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 1.),
+        };
+        let head = smoothed(&mut smoother, 1, target, 1.);
+        let covered = 1. - (-1f32).exp();
+        assert!((head.pos.x - covered).abs() < 1e-5);
+        assert!((head.orient.angle() - covered).abs() < 1e-4);
+    }
+
+    #[test]
+    fn smoothing_is_independent_of_frame_rate() {
+        let target = Head {
+            pos: Point3::new(1., 0.5, 0.),
+            ..Head::default()
+        };
+        let (mut once, mut twice) = (HeadSmoother::new(0.3, 2.), HeadSmoother::new(0.3, 2.));
+        smoothed(&mut once, 1, Head::default(), 0.);
+        smoothed(&mut twice, 1, Head::default(), 0.);
+
+        let once = smoothed(&mut once, 1, target, 0.2);
+        smoothed(&mut twice, 1, target, 0.1);
+        let twice = smoothed(&mut twice, 1, target, 0.1);
+        assert!((once.pos - twice.pos).norm() < 1e-5);
+    }
+
+    #[test]
+    fn heads_snap_when_far_or_smoothing_is_off() {
+        let far = Head {
+            pos: Point3::new(3., 0., 0.),
+            ..Head::default()
+        };
+        let mut smoother = HeadSmoother::new(1., 2.);
+        smoothed(&mut smoother, 1, Head::default(), 0.);
+        assert_eq!(smoothed(&mut smoother, 1, far, 0.01).pos, far.pos);
+
+        let near = Head {
+            pos: Point3::new(1., 0., 0.),
+            ..Head::default()
+        };
+        let mut smoother = HeadSmoother::new(0., 2.);
+        smoothed(&mut smoother, 1, Head::default(), 0.);
+        assert_eq!(smoothed(&mut smoother, 1, near, 0.01).pos, near.pos);
+    }
+
+    #[test]
+    fn hands_are_smoothed_while_tracked() {
+        let hand = |x| Head {
+            pos: Point3::new(x, 0., 0.),
+            ..Head::default()
+        };
+        let mut smoother = HeadSmoother::new(1., 2.);
+        let mut players = [Player {
+            id: 1,
+            hands: [Some(hand(0.)), None],
+            ..Player::default()
+        }];
+        smoother.apply(&mut players, 0.);
+
+        // The tracked hand chases its target, and the newly tracked one starts there
+        players[0].hands = [Some(hand(1.)), Some(hand(1.))];
+        smoother.apply(&mut players, 1.);
+        let covered = 1. - (-1f32).exp();
+        let [left, right] = players[0].hands.map(|hand| hand.unwrap().pos.x);
+        assert!((left - covered).abs() < 1e-5);
+        assert_eq!(right, 1.);
+
+        // Losing tracking forgets the hand
+        players[0].hands = [None, Some(hand(1.))];
+        smoother.apply(&mut players, 1.);
+        players[0].hands[0] = Some(hand(1.));
+        smoother.apply(&mut players, 1.);
+        assert_eq!(players[0].hands[0].unwrap().pos.x, 1.);
+    }
+
+    #[test]
+    fn players_who_left_start_afresh() {
+        let near = Head {
+            pos: Point3::new(1., 0., 0.),
+            ..Head::default()
+        };
+        let mut smoother = HeadSmoother::new(1., 2.);
+        smoothed(&mut smoother, 1, Head::default(), 0.);
+        // Player 1 is missing from this update
+        smoothed(&mut smoother, 2, Head::default(), 0.);
+        assert_eq!(smoothed(&mut smoother, 1, near, 0.01).pos, near.pos);
+    }
+
+    fn player_at(id: PlayerId, x: f32) -> Player {
+        Player {
+            id,
+            head: Head {
+                pos: Point3::new(x, 0., 0.),
+                ..Head::default()
+            },
+            ..Player::default()
+        }
+    }
+
+    #[test]
+    fn trails_sample_at_the_interval() {
+        let mut trails = HeadTrails::new(0.25, 1.);
+        // The first update starts the trail, and then it grows every other update
+        let mut counts = vec![];
+        for step in 0..20 {
+            trails.update(&[player_at(1, step as f32)], 0.125);
+            counts.push(trails.samples().count());
+        }
+        assert_eq!(counts[..6], [1, 2, 2, 3, 3, 4]);
+        // Capped at length / interval
+        assert!(counts.iter().all(|&count| count <= 4));
+        assert_eq!(counts[19], 4);
+    }
+
+    #[test]
+    fn trails_fade_with_age() {
+        let mut trails = HeadTrails::new(0.25, 1.);
+        trails.update(&[player_at(1, 0.)], 0.);
+        assert_eq!(
+            trails.samples().collect::<Vec<_>>(),
+            [(Point3::origin(), 1.)]
+        );
+        trails.update(&[player_at(1, 0.)], 0.125);
+        assert_eq!(trails.samples().next().unwrap().1, 0.875);
+
+        // Samples older than the length are dropped, leaving only the new one
+        trails.update(&[player_at(1, 5.)], 2.);
+        let samples: Vec<_> = trails.samples().collect();
+        assert_eq!(samples, [(Point3::new(5., 0., 0.), 1.)]);
+    }
+
+    #[test]
+    fn trails_of_players_who_left_are_forgotten() {
+        let mut trails = HeadTrails::new(0.25, 1.);
+        trails.update(&[player_at(1, 1.), player_at(2, 2.)], 0.);
+        assert_eq!(trails.samples().count(), 2);
+        trails.update(&[player_at(2, 2.)], 0.);
+        let xs: Vec<f32> = trails.samples().map(|(pos, _)| pos.x).collect();
+        assert_eq!(xs, [2.]);
+
+        trails.clear();
+        assert_eq!(trails.samples().count(), 0);
+    }
+
+    #[test]
+    fn remote_pointers_expire_and_leave_with_their_players() {
+        let player = |id| Player {
+            id,
+            ..Player::default()
+        };
+        let players = [player(1), player(2)];
+        let pointers = [Some(Pointer::default()), None];
+        let start = Instant::now();
+        let mut remote = RemotePointers::new(Duration::from_millis(500));
+
+        let unknown = AppData {
+            channel: "dice".into(),
+            data: vec![6],
+        };
+        let malformed = AppData {
+            channel: POINTER_CHANNEL.into(),
+            data: vec![],
+        };
+        let app_data = [
+            (1, pointer_app_data(&pointers)),
+            (2, unknown),
+            (2, malformed),
+        ];
+        remote.update(&players, &app_data, start);
+        assert_eq!(remote.get(1), pointers);
+        assert_eq!(remote.get(2), [None; 2]);
+
+        // Refreshing keeps them
+        let later = start + Duration::from_millis(400);
+        remote.update(&players, &app_data[..1], later);
+        remote.update(&players, &[], later + Duration::from_millis(400));
+        assert_eq!(remote.get(1), pointers);
+
+        // Until they stop arriving...
+        remote.update(&players, &[], later + Duration::from_millis(600));
+        assert_eq!(remote.get(1), [None; 2]);
+
+        // ...or their player leaves
+        remote.update(&players, &app_data[..1], later);
+        remote.update(&players[1..], &[], later);
+        assert_eq!(remote.get(1), [None; 2]);
+    }
+
+    fn key(time: f32, pos: [f32; 3], yaw: f32) -> Keyframe {
+        Keyframe {
+            time,
+            head: Head {
+                pos: pos.into(),
+                orient: UnitQuaternion::from_euler_angles(0., yaw, 0.),
+            },
+        }
+    }
+
+    fn assert_head_close(a: &Head, b: &Head) {
+        assert!((a.pos - b.pos).norm() < 1e-5, "{} is not {}", a.pos, b.pos);
+        assert!(
+            a.orient.angle_to(&b.orient) < 1e-3,
+            "{} is not {}",
+            a.orient,
+            b.orient
+        );
+    }
+
+    #[test]
+    fn keyframes_hold_their_ends() {
+        assert!(sample_keyframes(&[], 0.).is_none());
+        let keys = [key(1., [0., 0., 0.], 0.), key(3., [2., 0., 0.], 1.)];
+        for time in [-5., 0., 1.] {
+            assert_head_close(&sample_keyframes(&keys, time).unwrap(), &keys[0].head);
+        }
+        for time in [3., 3.5, f32::INFINITY] {
+            assert_head_close(&sample_keyframes(&keys, time).unwrap(), &keys[1].head);
+        }
+        let one = [keys[1]];
+        assert_head_close(&sample_keyframes(&one, 0.).unwrap(), &keys[1].head);
+    }
+
+    #[test]
+    fn two_keyframes_move_straight_at_constant_speed() {
+        let keys = [key(0., [0., 1., 0.], 0.), key(4., [4., 1., -8.], 1.)];
+        for i in 0..=8 {
+            let t = i as f32 / 8.;
+            let head = sample_keyframes(&keys, t * 4.).unwrap();
+            let expected = Head {
+                pos: Point3::new(4. * t, 1., -8. * t),
+                orient: UnitQuaternion::from_euler_angles(0., t, 0.),
+            };
+            assert_head_close(&head, &expected);
+        }
+    }
+
+    #[test]
+    fn paths_pass_through_every_keyframe() {
+        let keys = [
+            key(0., [0., 0., 0.], 0.),
+            key(1., [1., 0., 0.], 0.5),
+            key(3., [1., 2., -1.], -0.5),
+            key(3.5, [0., 2., -2.], 1.),
+        ];
+        for key in &keys {
+            assert_head_close(&sample_keyframes(&keys, key.time).unwrap(), &key.head);
+            // Without jumps either side
+            for side in [-1e-3, 1e-3] {
+                let near = sample_keyframes(&keys, key.time + side).unwrap();
+                assert!((near.pos - key.head.pos).norm() < 1e-2);
+            }
+        }
+    }
+
+    #[test]
+    fn hermite_and_squad_start_and_end_on_their_ends() {
+        let (a, b) = (Point3::new(1., 2., 3.), Point3::new(-1., 0., 5.));
+        let (ta, tb) = (Vector3::new(0., 5., 0.), Vector3::new(3., 0., 0.));
+        assert!((hermite(a, b, ta, tb, 0.) - a).norm() < 1e-6);
+        assert!((hermite(a, b, ta, tb, 1.) - b).norm() < 1e-6);
+        // Leaving along the first tangent and arriving along the second
+        let dt = 1e-3;
+        let start = (hermite(a, b, ta, tb, dt) - a) / dt;
+        let end = (b - hermite(a, b, ta, tb, 1. - dt)) / dt;
+        assert!((start - ta).norm() < 0.05 && (end - tb).norm() < 0.05);
+
+        let turn = |yaw: f32| UnitQuaternion::from_euler_angles(0., yaw, 0.);
+        let (qa, qb) = (turn(0.), turn(1.));
+        let (sa, sb) = (turn(0.3), turn(0.6));
+        assert!(squad(qa, qb, sa, sb, 0.).angle_to(&qa) < 1e-5);
+        assert!(squad(qa, qb, sa, sb, 1.).angle_to(&qb) < 1e-5);
+        // With the ends as their own controls, squad is slerp
+        let slerp = qa.slerp(&qb, 0.25);
+        assert!(squad(qa, qb, qa, qb, 0.25).angle_to(&slerp) < 1e-5);
+        // Equal ends don't break the slerps
+        assert!(squad(qa, qa, qa, qa, 0.5).angle_to(&qa) < 1e-5);
+    }
+
+    #[test]
+    fn heightfields_are_the_same_for_the_same_seed() {
+        let field = Heightfield::generate(10., 8, 7).unwrap();
+        assert_eq!(
+            field.heights,
+            Heightfield::generate(10., 8, 7).unwrap().heights
+        );
+        assert_ne!(
+            field.heights,
+            Heightfield::generate(10., 8, 8).unwrap().heights
+        );
+        assert_eq!(field.heights.len(), 81);
+        assert!(field.heights.iter().all(|h| h.abs() <= TERRAIN_AMPLITUDE));
+    }
+
+    #[test]
+    fn heightfields_need_two_quads_a_side() {
+        for resolution in [0, 1] {
+            let error = Heightfield::generate(10., resolution, 7).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Terrain resolution must be at least 2, not {}", resolution)
+            );
+        }
+        assert!(Heightfield::generate(0., 8, 7).is_err());
+        assert!(Heightfield::generate(f32::NAN, 8, 7).is_err());
+        let field = Heightfield::generate(10., 2, 7).unwrap();
+        assert!(field.height_at(10., 10.).is_some());
+    }
+
+    #[test]
+    fn height_at_interpolates_the_grid() {
+        let field = Heightfield::generate(10., 8, 7).unwrap();
+        for j in 0..=8 {
+            for i in 0..=8 {
+                let [x, y, z] = field.point(i, j);
+                assert!((field.height_at(x, z).unwrap() - y).abs() < 1e-4);
+            }
+        }
+
+        // Halfway between two grid points, along X and then along Z
+        let ([x0, y0, z0], [x1, y1, _]) = (field.point(3, 2), field.point(4, 2));
+        let mid = field.height_at((x0 + x1) / 2., z0).unwrap();
+        assert!((mid - (y0 + y1) / 2.).abs() < 1e-4);
+        let [_, y2, z2] = field.point(3, 3);
+        let mid = field.height_at(x0, (z0 + z2) / 2.).unwrap();
+        assert!((mid - (y0 + y2) / 2.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn height_at_covers_the_edges_and_nothing_beyond() {
+        let field = Heightfield::generate(10., 8, 7).unwrap();
+        let corner = field.point(8, 8);
+        assert!((field.height_at(10., 10.).unwrap() - corner[1]).abs() < 1e-4);
+        assert!(field.height_at(-10., -10.).is_some());
+        assert_eq!(field.height_at(10.01, 0.), None);
+        assert_eq!(field.height_at(0., -10.01), None);
+        assert_eq!(field.height_at(f32::NAN, 0.), None);
+    }
+
+    #[test]
+    fn heads_are_kept_above_the_ground() {
+        let field = Heightfield::generate(10., 8, 7).unwrap();
+        let ground = field.height_at(1., 2.).unwrap();
+        let below = Point3::new(1., ground - 5., 2.);
+        assert_eq!(
+            field.keep_above(below, 0.25),
+            Point3::new(1., ground + 0.25, 2.)
+        );
+        let above = Point3::new(1., ground + 1., 2.);
+        assert_eq!(field.keep_above(above, 0.25), above);
+        let beyond = Point3::new(50., -100., 0.);
+        assert_eq!(field.keep_above(beyond, 0.25), beyond);
+    }
+
+    /// Read one message, blocking
+    fn read_msg<T: serde::de::DeserializeOwned>(stream: &mut TcpStream) -> T {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).unwrap();
+        let mut body = vec![0; u32::from_le_bytes(header) as usize];
+        stream.read_exact(&mut body).unwrap();
+        bincode::deserialize(&body).unwrap()
+    }
+
+    /// Accept one client on a thread, greeting it as the server does
+    fn serve_one(listener: TcpListener) -> std::thread::JoinHandle<TcpStream> {
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            write_hello(&mut stream).unwrap();
+            stream
+        })
+    }
+
+    /// Poll the client until it has heard of `count` players, giving up after a second
+    fn wait_for_players(client: &mut Client, count: usize) {
+        for _ in 0..100 {
+            if client.update_heads().unwrap().players.len() == count {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Never heard of {} players", count);
+    }
+
+    #[test]
+    fn relayed_data_is_sent_once_and_kept_from_every_message() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let peer = serve_one(server);
+        let mut client = Client::new(&[server_addr]).unwrap();
+        let mut peer = peer.join().unwrap();
+
+        let pointers = [None, Some(Pointer::default())];
+        let mut sent = ClientState {
+            app_data: vec![pointer_app_data(&pointers)],
+            ..ClientState::default()
+        };
+        client.send_state(&mut sent).unwrap();
+        assert!(sent.app_data.is_empty());
+        let received: ClientState = read_msg(&mut peer);
+        assert_eq!(received.app_data, [pointer_app_data(&pointers)]);
+
+        // Two messages arrive before the client looks, and only the second has the players
+        for (id, players) in [(3, vec![]), (4, vec![Player::default()])] {
+            let state = ServerState {
+                players,
+                app_data: vec![(id, pointer_app_data(&pointers))],
+                ..ServerState::default()
+            };
+            serialize_msg(&state, &mut peer).unwrap();
+        }
+        wait_for_players(&mut client, 1);
+        let ids: Vec<PlayerId> = client.take_app_data().iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [3, 4]);
+        assert!(client.take_app_data().is_empty());
+    }
+
+    #[test]
+    fn max_players_is_only_sent_when_it_changes() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let peer = serve_one(server);
+        let mut client = Client::new(&[server_addr]).unwrap();
+        let mut peer = peer.join().unwrap();
+
+        // The client keeps what it asked for, but the server only hears about changes
+        let mut sent = ClientState::default();
+        let mut send = |max_players| {
+            sent.max_players = max_players;
+            client.send_state(&mut sent).unwrap();
+            assert_eq!(sent.max_players, max_players);
+            read_msg::<ClientState>(&mut peer).max_players
+        };
+        assert_eq!(send(Some(10)), Some(10));
+        assert_eq!(send(Some(10)), None);
+        assert_eq!(send(Some(20)), Some(20));
+        assert_eq!(send(Some(20)), None);
+        assert_eq!(send(None), None);
+        assert_eq!(send(Some(20)), None);
+    }
+
+    #[test]
+    fn server_addresses_split_into_host_and_port() {
+        let split = |addr| split_server_addr(addr).unwrap();
+        assert_eq!(split("example.com"), ("example.com", DEFAULT_PORT));
+        assert_eq!(split(" example.com:1234\n"), ("example.com", 1234));
+        assert_eq!(split("127.0.0.1:80"), ("127.0.0.1", 80));
+        assert_eq!(split("[::1]:1234"), ("::1", 1234));
+        assert_eq!(split("[::1]"), ("::1", DEFAULT_PORT));
+        assert_eq!(split("fe80::1"), ("fe80::1", DEFAULT_PORT));
+    }
+
+    #[test]
+    fn bad_server_addresses_are_rejected() {
+        for addr in [
+            "example.com:http",
+            "example.com:99999",
+            "example.com:",
+            ":1234",
+            "",
+            "[]:1234",
+            "[::1",
+            "[::1]1234",
+        ] {
+            assert!(split_server_addr(addr).is_err(), "{:?}", addr);
+        }
+    }
+
+    #[test]
+    fn hello_checks_the_version() {
+        let mut hello = vec![];
+        write_hello(&mut hello).unwrap();
+        read_hello(&hello[..]).unwrap();
+
+        let mut old = PROTOCOL_MAGIC.to_vec();
+        old.extend((PROTOCOL_VERSION + 1).to_le_bytes());
+        assert!(read_hello(&old[..])
+            .unwrap_err()
+            .to_string()
+            .contains("protocol version"));
+        assert!(read_hello(&b"cube"[..]).is_err());
+    }
+
+    #[test]
+    fn clients_only_talk_to_cubehead_servers() {
+        let web = TcpListener::bind("127.0.0.1:0").unwrap();
+        let web_addr = web.local_addr().unwrap();
+        let web_peer = std::thread::spawn(move || {
+            let (mut stream, _) = web.accept().unwrap();
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .unwrap();
+            stream
+        });
+        let error = Client::new(&[web_addr]).err().unwrap();
+        assert_eq!(error.to_string(), "Not a cubehead server");
+        drop(web_peer.join().unwrap());
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        let mut receiver = AsyncBufferedReceiver::new();
+        let error = receiver.read(&b"HTTP/1.1 200 OK"[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut msg = vec![];
+        serialize_msg(&ServerState::default(), &mut msg).unwrap();
+        let mut receiver = AsyncBufferedReceiver::new();
+        assert!(matches!(
+            receiver.read(&msg[..]).unwrap(),
+            ReadState::Complete(_)
+        ));
+    }
+}
@@ -0,0 +1,300 @@
+//! Moving and turning around the world with the thumbsticks in VR, beyond the bounds of the room,
+//! and recentering the view
+use crate::vr_input::VrInput;
+use cubehead::Head;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+
+/// Thumbstick deflection ignored as drift
+const DEAD_ZONE: f32 = 0.15;
+
+/// Deflection which triggers a snap turn...
+const SNAP_TRIGGER: f32 = 0.7;
+
+/// ...and which the stick must return within before the next one
+const SNAP_RESET: f32 = 0.3;
+
+/// Speed multiplier while the left thumbstick is pressed in
+const SPRINT_MULTIPLIER: f32 = 2.;
+
+/// How the right thumbstick turns
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TurnMode {
+    /// Turn this many degrees per flick
+    Snap(f32),
+    /// Turn continuously at up to this many degrees per second
+    Smooth(f32),
+}
+
+/// Offset of the play space within the world, moved by the left thumbstick, turned by the right
+/// one and reset by recentering. Poses from OpenXR are in the play space (the stage) and must go
+/// through `world_from_stage` before being drawn or sent to the server. This is the one place
+/// the floor offset, height calibration, movement and turning are applied.
+pub struct Locomotion {
+    /// Meters per second at full deflection
+    speed: f32,
+    turn: TurnMode,
+    world_from_stage: Head,
+    /// Whether the right thumbstick has returned to center since the last snap turn
+    snap_ready: bool,
+    /// Meters per second moved and radians per second turned by the thumbsticks in the last
+    /// update. Snap turns don't count, as they don't show motion.
+    motion: (f32, f32),
+}
+
+impl Locomotion {
+    /// Start with the stage origin `floor_offset` meters above the world origin
+    pub fn new(speed: f32, turn: TurnMode, floor_offset: f32) -> Self {
+        Self {
+            speed,
+            turn,
+            world_from_stage: Head {
+                pos: Point3::new(0., floor_offset, 0.),
+                ..Head::default()
+            },
+            snap_ready: true,
+            motion: (0., 0.),
+        }
+    }
+
+    /// Where the play space is in the world
+    pub fn world_from_stage(&self) -> Head {
+        self.world_from_stage
+    }
+
+    /// Speed of artificial movement and turning in the last update, in meters and radians per
+    /// second
+    pub fn motion(&self) -> (f32, f32) {
+        self.motion
+    }
+
+    /// Move and turn by the thumbsticks over `dt` seconds, given our head in the stage
+    pub fn update(&mut self, input: &VrInput, head: &Head, dt: f32) {
+        let head = self.world_from_stage.transform(head);
+        let [left, right] = &input.hands;
+
+        // Move along the ground the way we are facing
+        let [x, y] = dead_zone(left.thumbstick);
+        let heading = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), yaw(&head.orient));
+        let speed = match left.thumbstick_click {
+            true => self.speed * SPRINT_MULTIPLIER,
+            false => self.speed,
+        };
+        let velocity = heading * (Vector3::x() * x - Vector3::z() * y) * speed;
+        self.world_from_stage.pos += velocity * dt;
+
+        // Turn around our head, so that we don't swing across the room. Pushing right turns
+        // clockwise seen from above, which is negative about +Y.
+        let x = right.thumbstick[0];
+        let mut turn_rate = 0.;
+        let yaw = match self.turn {
+            TurnMode::Snap(degrees) => {
+                if x.abs() < SNAP_RESET {
+                    self.snap_ready = true;
+                }
+                if self.snap_ready && x.abs() > SNAP_TRIGGER {
+                    self.snap_ready = false;
+                    -x.signum() * degrees.to_radians()
+                } else {
+                    0.
+                }
+            }
+            TurnMode::Smooth(degrees_per_sec) => {
+                turn_rate = -dead_zone([x, 0.])[0] * degrees_per_sec.to_radians();
+                turn_rate * dt
+            }
+        };
+        self.motion = (velocity.norm(), turn_rate.abs());
+        if yaw != 0. {
+            self.turn_about(head.pos, yaw);
+        }
+    }
+
+    /// Turn the play space so that our head, given in the stage, faces world -Z without moving.
+    /// Only yaw changes; pitch and roll stay with the headset.
+    pub fn recenter(&mut self, head: &Head) {
+        let head = self.world_from_stage.transform(head);
+        self.turn_about(head.pos, -yaw(&head.orient));
+    }
+
+    /// Raise the play space by `height` meters, to correct the floor
+    pub fn raise(&mut self, height: f32) {
+        self.world_from_stage.pos.y += height;
+    }
+
+    /// Follow the runtime moving the stage, given the new origin within the old stage, so that the
+    /// world stays put
+    pub fn rebase(&mut self, old_from_new: &Head) {
+        self.world_from_stage = self.world_from_stage.transform(old_from_new);
+    }
+
+    /// Rotate the play space by `yaw` radians about a vertical axis through `center`
+    fn turn_about(&mut self, center: Point3<f32>, yaw: f32) {
+        let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), yaw);
+        self.world_from_stage = Head {
+            pos: center + rotation * (self.world_from_stage.pos - center),
+            orient: rotation * self.world_from_stage.orient,
+        };
+    }
+}
+
+/// Heading of an orientation in radians about +Y, zero facing -Z. Looking straight up or down,
+/// the heading comes from the top of the head instead, so it is always a number.
+pub fn yaw(orient: &UnitQuaternion<f32>) -> f32 {
+    let forward = orient * -Vector3::z();
+    let up = orient * Vector3::y();
+    // Tilt the forward vector back to the horizon by the top of the head
+    let heading = forward - up * forward.y;
+    (-heading.x).atan2(-heading.z)
+}
+
+/// Zero a thumbstick within the dead zone, rescaling the rest to start from zero
+fn dead_zone(stick: [f32; 2]) -> [f32; 2] {
+    let [x, y] = stick;
+    let length = x.hypot(y);
+    if length < DEAD_ZONE {
+        return [0.; 2];
+    }
+    let scale = ((length - DEAD_ZONE) / (1. - DEAD_ZONE)).min(1.) / length;
+    [x * scale, y * scale]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4};
+
+    fn looking(pitch: f32, heading: f32) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_euler_angles(pitch, heading, 0.)
+    }
+
+    /// Input with the left thumbstick at `left`, pressed in if `sprint`, and the right one at
+    /// `right`
+    fn sticks(left: [f32; 2], sprint: bool, right: [f32; 2]) -> VrInput {
+        let mut input = VrInput::default();
+        input.hands[0].thumbstick = left;
+        input.hands[0].thumbstick_click = sprint;
+        input.hands[1].thumbstick = right;
+        input
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    /// How far the play space has turned
+    fn turned(locomotion: &Locomotion) -> f32 {
+        yaw(&locomotion.world_from_stage().orient)
+    }
+
+    #[test]
+    fn dead_zone_ignores_drift_and_rescales_the_rest() {
+        assert_eq!(dead_zone([0.1, 0.1]), [0.; 2]);
+        assert_eq!(dead_zone([0., -DEAD_ZONE * 0.9]), [0.; 2]);
+        assert_eq!(dead_zone([1., 0.]), [1., 0.]);
+        assert_eq!(dead_zone([0., -1.]), [0., -1.]);
+        // Halfway between the dead zone and the edge is half speed
+        let [x, y] = dead_zone([(1. + DEAD_ZONE) / 2., 0.]);
+        assert!(close(x, 0.5) && y == 0.);
+        // Corners of a square stick are no faster than its edges, and keep their direction
+        let [x, y] = dead_zone([1., 1.]);
+        assert!(close(x, FRAC_1_SQRT_2) && close(y, FRAC_1_SQRT_2));
+    }
+
+    #[test]
+    fn snap_turns_wait_for_the_stick_to_return() {
+        let mut locomotion = Locomotion::new(1., TurnMode::Snap(45.), 0.);
+        let head = Head {
+            pos: Point3::new(1., 1.7, -2.),
+            orient: looking(0., 0.3),
+        };
+
+        // Pushing right turns clockwise about our head
+        locomotion.update(&sticks([0.; 2], false, [0.8, 0.]), &head, 0.1);
+        assert!(close(turned(&locomotion), -FRAC_PI_4));
+        let world = locomotion.world_from_stage().transform(&head);
+        assert!((world.pos - head.pos).norm() < 1e-5);
+        // Snap turns don't show motion
+        assert_eq!(locomotion.motion(), (0., 0.));
+
+        // Holding the stick or easing off partway doesn't turn again
+        locomotion.update(&sticks([0.; 2], false, [1., 0.]), &head, 0.1);
+        locomotion.update(&sticks([0.; 2], false, [0.5, 0.]), &head, 0.1);
+        locomotion.update(&sticks([0.; 2], false, [0.8, 0.]), &head, 0.1);
+        assert!(close(turned(&locomotion), -FRAC_PI_4));
+
+        // Until it returns near the center
+        locomotion.update(&sticks([0.; 2], false, [0.2, 0.]), &head, 0.1);
+        locomotion.update(&sticks([0.; 2], false, [-0.8, 0.]), &head, 0.1);
+        assert!(close(turned(&locomotion), 0.));
+    }
+
+    #[test]
+    fn smooth_turns_follow_the_stick() {
+        let mut locomotion = Locomotion::new(1., TurnMode::Smooth(90.), 0.);
+        let head = Head::default();
+        locomotion.update(&sticks([0.; 2], false, [1., 0.]), &head, 0.5);
+        assert!(close(turned(&locomotion), -FRAC_PI_4));
+        assert!(close(locomotion.motion().1, FRAC_PI_2));
+
+        // Drift within the dead zone doesn't turn
+        locomotion.update(&sticks([0.; 2], false, [-0.1, 0.]), &head, 0.5);
+        assert!(close(turned(&locomotion), -FRAC_PI_4));
+        assert_eq!(locomotion.motion(), (0., 0.));
+    }
+
+    #[test]
+    fn sprinting_moves_faster_the_way_we_face() {
+        let mut locomotion = Locomotion::new(2., TurnMode::Snap(45.), 0.);
+        // Looking down doesn't slow us down or move us into the ground
+        let head = Head {
+            orient: looking(-0.6, FRAC_PI_2),
+            ..Head::default()
+        };
+        locomotion.update(&sticks([0., 1.], false, [0.; 2]), &head, 1.);
+        let pos = locomotion.world_from_stage().pos;
+        assert!(close(pos.x, -2.) && close(pos.y, 0.) && close(pos.z, 0.));
+        assert!(close(locomotion.motion().0, 2.));
+
+        locomotion.update(&sticks([0., 1.], true, [0.; 2]), &head, 1.);
+        let pos = locomotion.world_from_stage().pos;
+        assert!(close(pos.x, -6.) && close(pos.y, 0.) && close(pos.z, 0.));
+        assert!(close(locomotion.motion().0, 4.));
+    }
+
+    #[test]
+    fn yaw_is_the_heading_whatever_the_pitch() {
+        assert_eq!(yaw(&UnitQuaternion::identity()), 0.);
+        for pitch in [0., 0.5, -1.2, FRAC_PI_2, -FRAC_PI_2] {
+            for heading in [0.25, -2., 3.] {
+                let found = yaw(&looking(pitch, heading));
+                assert!(
+                    (found - heading).abs() < 1e-4,
+                    "pitch {} heading {}: {}",
+                    pitch,
+                    heading,
+                    found
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn recentering_only_turns_the_view() {
+        let mut locomotion = Locomotion::new(1., TurnMode::Snap(45.), 0.5);
+        locomotion.turn_about(Point3::new(3., 0., 1.), 1.);
+        let head = Head {
+            pos: Point3::new(1., 1.7, -0.5),
+            orient: looking(0.3, 0.8),
+        };
+        let before = locomotion.world_from_stage().transform(&head);
+        locomotion.recenter(&head);
+        let after = locomotion.world_from_stage().transform(&head);
+
+        assert!((after.pos - before.pos).norm() < 1e-5);
+        assert!(yaw(&after.orient).abs() < 1e-4);
+        let pitch = |orient: &UnitQuaternion<f32>| (orient * -Vector3::z()).y;
+        assert!((pitch(&after.orient) - pitch(&before.orient)).abs() < 1e-5);
+        // The floor stays where it was
+        assert!((locomotion.world_from_stage().pos.y - 0.5).abs() < 1e-6);
+    }
+}
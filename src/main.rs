@@ -1,652 +1,4416 @@
 extern crate glow as gl;
 extern crate openxr as xr;
 
-use std::net::{SocketAddr, TcpStream};
-
-use cubehead::{AsyncBufferedReceiver, ClientState, Head, ReadState, ServerState};
+use std::f32::consts::{PI, TAU};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use cubehead::{
+    pointer_app_data, Client, ClientState, Connection, Hands, Head, HeadSmoother, HeadTrails,
+    Player, PlayerId, Pointer, Pointers, RemotePointers, ServerState, TERRAIN_EXTENT,
+    TERRAIN_RESOLUTION,
+};
 use glutin::{window::Window, ContextWrapper, PossiblyCurrent};
-use render::Mesh;
+use render::{
+    check_gl_error, BackgroundMode, Billboard, Instance, Mesh, RenderStyle, RgbaImage, Sky,
+};
 use winit_input_helper::WinitInputHelper;
 use xr::opengl::SessionCreateInfo;
 
-use anyhow::{bail, format_err, Result};
+use anyhow::{bail, format_err, Context, Result};
 use gl::HasContext;
 use glutin::dpi::PhysicalSize;
-use nalgebra::{Matrix4, Point3, Quaternion, Unit, UnitQuaternion, Vector3};
+use glutin::platform::run_return::EventLoopExtRunReturn;
+use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3};
 
+mod audio;
+mod bindings;
 mod camera;
+mod camera_path;
+mod comfort;
+mod config;
+mod debug_lines;
+mod dynamic_resolution;
+mod font;
+mod frustum;
+mod gpu_timer;
+mod haptics;
+mod locomotion;
+mod minimap;
+mod obj;
+mod picking;
+mod post;
 mod render;
+mod rooms;
+mod scene;
+mod server_switch;
+mod settings;
 mod shapes;
-
-use camera::{FlyCam, Perspective};
-use shapes::{big_quad_map, rgb_cube};
-
-use clap::Parser;
+mod stats;
+mod text;
+mod ui;
+mod vr_input;
+mod xr_math;
+
+use audio::AudioEngine;
+use bindings::Bindings;
+use camera::{
+    zoomed_sensitivity, DebugCamera, FlatGround, FlyCam, HeightQuery, MovementMode, Orbit,
+    Perspective, ProjectionConfig, FLY_SPEED_RANGE, LOOK_SENSITIVITY_RANGE,
+};
+use camera_path::{CameraPath, PathPlayback, PathRecorder};
+use comfort::Vignette;
+use config::{ClientConfig, ConfigFile, FullscreenMode, Overrides};
+use dynamic_resolution::{scaled_extent, DynamicResolution};
+use frustum::CullCamera;
+use haptics::ProximityHaptics;
+use locomotion::{Locomotion, TurnMode};
+use picking::TriangleMesh;
+use post::PostEffect;
+use rooms::RoomsSpec;
+use server_switch::ServerSwitch;
+use settings::Settings;
+use shapes::{
+    axis_gizmo, beam, big_quad_map_subdivided, checkerboard, clear_spawn, controller,
+    frustum_lines, grid_lines, head_avatar, maze, origin_grid_marker, props_map, rgb_cube,
+    rooms_map, sky_dome, terrain, Bounds,
+};
+use stats::{StatsOverlay, VrFrameCosts};
+use ui::UiLayer;
+use vr_input::{HoldButton, VrActions, VrInput};
+use xr_math::{head_from_xr_pose, mid_eye};
+
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
 const VR_DEPTH_FORMAT: u32 = gl::DEPTH_COMPONENT24;
 
-/// Simple program to greet a person
-#[derive(Parser, Debug)]
-struct Args {
-    /// Use OpenXR instead of windowed mode
-    #[arg(long)]
-    vr: bool,
+/// Color formats accepted for VR swapchains by GL name, most preferred first
+const VR_COLOR_SWAPCHAIN_FORMATS: [(&str, u32); 7] = [
+    ("SRGB8_ALPHA8", gl::SRGB8_ALPHA8),
+    ("RGBA8", gl::RGBA8),
+    ("RGB10_A2", gl::RGB10_A2),
+    ("RGBA16F", gl::RGBA16F),
+    ("SRGB8", gl::SRGB8),
+    ("RGB8", gl::RGB8),
+    ("RGB16F", gl::RGB16F),
+];
 
-    /// Spawn this many desktop clients
-    #[arg(short, long)]
-    clients: Option<usize>,
+/// Depth formats accepted for depth swapchains, most preferred first
+const VR_DEPTH_SWAPCHAIN_FORMATS: [u32; 3] = [
+    VR_DEPTH_FORMAT,
+    gl::DEPTH_COMPONENT32F,
+    gl::DEPTH_COMPONENT16,
+];
 
-    /// Connection address
-    #[arg()]
-    addr: SocketAddr,
-}
+/// Time between updates of the desktop mirror of the VR view
+const MIRROR_INTERVAL: Duration = Duration::from_millis(33);
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Offset from the center of a head to the bottom of its nametag
+const NAMETAG_OFFSET: Vector3<f32> = Vector3::new(0., 0.4, 0.);
 
-    if let Some(count) = args.clients {
-        // Launch many desktop clients for testing
-        let program_name = std::env::args().next().unwrap();
-        for _ in 0..count {
-            std::process::Command::new(&program_name)
-                .arg(args.addr.to_string())
-                .spawn()?;
-        }
-    } else {
-        // Launch a single client
-        unsafe {
-            if args.vr {
-                vr_main(args.addr)?;
-            } else {
-                desktop_main(args.addr)?;
-            }
-        }
-    }
+/// Heads and hands which move further than this (in meters) between updates are not smoothed
+const HEAD_SNAP_DISTANCE: f32 = 2.;
 
-    Ok(())
-}
+/// Seconds between samples of head trails
+const TRAIL_INTERVAL: f32 = 0.05;
 
-unsafe fn desktop_main(addr: SocketAddr) -> Result<()> {
-    let event_loop = glutin::event_loop::EventLoop::new();
-    let window_builder = glutin::window::WindowBuilder::new()
-        .with_title("Hello triangle!")
-        .with_inner_size(glutin::dpi::LogicalSize::new(1024.0, 768.0));
+/// Seconds of motion covered by head trails
+const TRAIL_LENGTH: f32 = 2.;
 
-    let glutin_ctx = glutin::ContextBuilder::new()
-        .with_vsync(true)
-        .build_windowed(window_builder, &event_loop)?
-        .make_current()
-        .unwrap();
+/// Size of the axes drawn on each head with the debug lines, relative to those at the origin
+const HEAD_GIZMO_SCALE: f32 = 0.25;
 
-    let gl = gl::Context::from_loader_function(|s| glutin_ctx.get_proc_address(s) as *const _);
+/// Radius of the sky dome, within the far plane, and how finely it is split
+const DOME_RADIUS: f32 = 100.;
+const DOME_STACKS: u32 = 16;
+const DOME_SLICES: u32 = 32;
 
-    // We handle events differently between targets
-    use glutin::event::{Event, WindowEvent};
-    use glutin::event_loop::ControlFlow;
+/// Linear colors of the sky dome, matching the procedural sky
+const DOME_HORIZON: [f32; 3] = [0.45, 0.55, 0.65];
+const DOME_ZENITH: [f32; 3] = [0.05, 0.15, 0.4];
+const DOME_GROUND: [f32; 3] = [0.1, 0.1, 0.1];
 
-    let mut wih = WinitInputHelper::new();
-    let mut camera = FlyCam::new(Point3::new(0., 4., 0.));
-    let perspective_cfg = Perspective::default();
+/// Clear color behind the sky dome, in sRGB with alpha, which the fog takes after. The horizon
+/// color, encoded.
+const DOME_CLEAR: [f32; 4] = [0.7, 0.77, 0.83, 1.];
 
-    let (map_mesh, head_mesh) = models();
-    let mut engine = render::Engine::new(&gl, &map_mesh, &head_mesh)
-        .map_err(|e| format_err!("Render engine failed to start; {}", e))?;
+/// Size of the cubes head trails are drawn with, in meters
+const TRAIL_CUBE_SIZE: f32 = 0.04;
 
-    let mut client = Client::new(addr)?;
+/// Display periods to wait for a swapchain image before skipping the frame
+const IMAGE_TIMEOUT_PERIODS: i64 = 3;
 
-    let mut proj = perspective_cfg.matrix(0., 0.);
+/// How long swapchain images may keep failing before giving up
+const IMAGE_FAILURE_LIMIT: Duration = Duration::from_secs(5);
 
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+/// How often to check for OpenXR events while the session isn't running
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
-        if wih.update(&event) {
-            camera.update(&wih, 0.05, 2e-3);
-            // Send head position to server
-            let state = ClientState {
-                head: camera.head(),
-            };
-            client.send_state(&state).unwrap();
-        }
+/// How often to send our last pose to the server while the session isn't running
+const PAUSED_SEND_INTERVAL: Duration = Duration::from_millis(500);
 
-        if let Some(ph) = wih.window_resized() {
-            glutin_ctx.resize(ph);
-            gl.scissor(0, 0, ph.width as i32, ph.height as i32);
-            gl.viewport(0, 0, ph.width as i32, ph.height as i32);
-            proj = perspective_cfg.matrix(ph.width as f32, ph.height as f32);
-        }
+/// Furthest a laser pointer reaches, in meters
+const POINTER_RANGE: f32 = 20.;
 
-        let state = client.update_heads().unwrap();
-        let head_mats = head_matrices(&state.heads);
-        engine.update_heads(&gl, &head_mats);
+/// Shortest time between updates of the laser pointers other players see
+const POINTER_SHARE_INTERVAL: Duration = Duration::from_millis(50);
 
-        match event {
-            Event::LoopDestroyed => {
-                return;
-            }
-            Event::MainEventsCleared => {
-                glutin_ctx.window().request_redraw();
-            }
-            Event::RedrawRequested(_) => {
-                engine
-                    .frame(&gl, proj, view_from_head(&camera.head()))
-                    .expect("Engine error");
+/// Other players' laser pointers are dropped once they haven't been refreshed for this long, in
+/// case the message hiding them was lost
+const POINTER_EXPIRY: Duration = Duration::from_millis(500);
 
-                glutin_ctx.swap_buffers().unwrap();
-            }
-            Event::WindowEvent { ref event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                _ => (),
-            },
-            _ => (),
-        }
-    });
-}
+/// Color of our own laser pointers; other players' are in their own colors
+const POINTER_COLOR: [f32; 4] = [1., 0.15, 0.1, 1.];
 
-unsafe fn vr_main(addr: SocketAddr) -> Result<()> {
-    // Load OpenXR from platform-specific location
-    #[cfg(target_os = "linux")]
-    let entry = xr::Entry::load()?;
+/// Width of the marker where a laser pointer hits, in meters
+const POINTER_MARKER_SIZE: f32 = 0.03;
 
-    #[cfg(target_os = "windows")]
-    let entry = xr::Entry::linked();
+/// Width and height in pixels of the round texture of pointer markers
+const POINTER_MARKER_TEXTURE_SIZE: u32 = 64;
 
-    // Application info
-    let app_info = xr::ApplicationInfo {
-        application_name: "Ugly OpenGL",
-        application_version: 0,
-        engine_name: "Ugly Engine",
-        engine_version: 0,
-    };
+/// Color of the frozen culling frustum's edges
+const FROZEN_FRUSTUM_COLOR: [f32; 3] = [0., 1., 1.];
 
-    // Ensure we have the OpenGL extension
-    let available_extensions = entry.enumerate_extensions()?;
-    assert!(available_extensions.khr_opengl_enable);
+/// Range of the desktop field of view and zoomed field of view, in degrees
+const FOV_RANGE: (f32, f32) = (10., 170.);
 
-    // Enable the OpenGL extension
-    let mut extensions = xr::ExtensionSet::default();
-    extensions.khr_opengl_enable = true;
+/// Cells along each side of the maze map, odd so that the origin is in the middle of one; their
+/// size and the height of the walls, in meters
+const MAZE_CELLS: u32 = 11;
+const MAZE_CELL_SIZE: f32 = 3.;
+const MAZE_WALL_HEIGHT: f32 = 2.5;
 
-    // Create instance
-    let xr_instance = entry.create_instance(&app_info, &extensions, &[])?;
-    let instance_props = xr_instance.properties().unwrap();
-    println!(
-        "loaded OpenXR runtime: {} {}",
-        instance_props.runtime_name, instance_props.runtime_version
-    );
+/// Half the side of the ground grid shown with the debug lines, with lines every meter
+const DEBUG_GRID_EXTENT: f32 = 10.;
 
-    // Get headset system
-    let xr_system = xr_instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+/// Quads along each side of the default map, one per meter
+const QUAD_MAP_DIVISIONS: u32 = 20;
 
-    let xr_view_configs = xr_instance.enumerate_view_configurations(xr_system)?;
-    assert_eq!(xr_view_configs.len(), 1);
-    let xr_view_type = xr_view_configs[0];
+/// Half the side of the props map
+const PROPS_EXTENT: f32 = 20.;
 
-    let xr_views = xr_instance.enumerate_view_configuration_views(xr_system, xr_view_type)?;
+/// Room kept around the spawn point, clear of the props and walls of the map
+const SPAWN_CLEARANCE: f32 = 0.5;
 
-    // Check what blend mode is valid for this device (opaque vs transparent displays). We'll just
-    // take the first one available!
-    let xr_environment_blend_mode =
-        xr_instance.enumerate_environment_blend_modes(xr_system, xr_view_type)?[0];
+/// Linear colors of the tiles of the checkerboard map
+const CHECKER_LIGHT: [f32; 3] = [0.6, 0.6, 0.6];
+const CHECKER_DARK: [f32; 3] = [0.15, 0.15, 0.15];
 
-    // TODO: Check this???
-    let _xr_opengl_requirements = xr_instance.graphics_requirements::<xr::OpenGL>(xr_system)?;
+/// Longest player name sent to the server, in characters; longer names are cut short
+const MAX_NAME_CHARS: usize = 32;
 
-    // Create window
-    let event_loop = glutin::event_loop::EventLoop::new();
-    let window_builder = glutin::window::WindowBuilder::new()
-        .with_title("Hello world!")
-        .with_inner_size(glutin::dpi::LogicalSize::new(1024.0f32, 768.0));
+/// Name of players when the OS user's name isn't available
+const FALLBACK_NAME: &str = "player";
 
-    let windowed_context = glutin::ContextBuilder::new()
-        .build_windowed(window_builder, &event_loop)
-        .unwrap();
+/// How long the fly speed is shown in the window title after it changes, and how long camera
+/// settings must stay unchanged before they are saved
+const SETTLE_TIME: Duration = Duration::from_secs(2);
 
-    let (ctx, window) = windowed_context.split();
-    let ctx = ctx.make_current().unwrap();
+/// Height calibration step in VR, in meters
+const HEIGHT_NUDGE: f32 = 0.01;
 
-    // Load OpenGL
-    let gl = gl::Context::from_loader_function(|s| ctx.get_proc_address(s) as *const _);
+/// How long to hold the menu button to recenter the view in VR
+const RECENTER_HOLD: Duration = Duration::from_secs(1);
 
-    let session_create_info = glutin_openxr_opengl_helper::session_create_info(&ctx, &window)?;
+/// Size of the cubes drawn at the joints of tracked hands, in meters
+const JOINT_CUBE_SIZE: f32 = 0.01;
 
-    // Create vertex array
-    let vertex_array = gl
-        .create_vertex_array()
-        .expect("Cannot create vertex array");
-    gl.bind_vertex_array(Some(vertex_array));
+/// Opacity of the newest cube in a head trail
+const TRAIL_ALPHA: f32 = 0.6;
 
-    // Create session
-    let (xr_session, mut xr_frame_waiter, mut xr_frame_stream) =
-        xr_instance.create_session::<xr::OpenGL>(xr_system, &session_create_info)?;
+/// Tint of the selected head
+const SELECTED_TINT: [f32; 3] = [1.6, 1.6, 0.7];
 
-    // Determine swapchain formats
-    let xr_swapchain_formats = xr_session.enumerate_swapchain_formats()?;
+/// Cursor movement in pixels between pressing and releasing the mouse, beyond which it is a drag
+/// rather than a click
+const CLICK_SLOP: f32 = 4.;
 
-    let color_swapchain_format = xr_swapchain_formats
-        .iter()
-        .copied()
-        .find(|&f| f == gl::SRGB8_ALPHA8)
-        .unwrap_or(xr_swapchain_formats[0]);
+/// Players the server hasn't heard from for this long start fading out
+const STALE_AFTER: Duration = Duration::from_secs(3);
 
-    /*
-    let depth_swapchain_format = xr_swapchain_formats
-        .iter()
-        .copied()
-        .find(|&f| f == VR_DEPTH_FORMAT)
-        .expect("No suitable depth format found");
-    */
+/// Time between spawning each client with `--clients`
+const CLIENT_SPAWN_INTERVAL: Duration = Duration::from_millis(50);
 
-    // Create color swapchain
-    let mut swapchain_color_images = vec![];
-    let mut swapchain_depth_images = vec![];
-    let mut xr_swapchains = vec![];
+/// Longest `--clients-exit-after` in seconds, a day, which is still safe to add to the clock
+const MAX_CLIENTS_EXIT_AFTER: f32 = 86_400.;
 
-    // Set up swapchains and get images
-    for &xr_view in &xr_views {
-        let image_types = [(), ()];
+/// How often headless clients send their heads, about once per frame of a windowed client
+const HEADLESS_SEND_INTERVAL: Duration = Duration::from_millis(16);
 
-        let width = xr_view.recommended_image_rect_width;
-        let height = xr_view.recommended_image_rect_height;
+/// Radius of the circle headless clients fly around, in meters, and the seconds each lap takes
+const HEADLESS_RADIUS: f32 = 5.;
+const HEADLESS_LAP_TIME: f32 = 20.;
 
-        let xr_swapchain_create_info = xr::SwapchainCreateInfo::<xr::OpenGL> {
-            create_flags: xr::SwapchainCreateFlags::EMPTY,
-            usage_flags: xr::SwapchainUsageFlags::SAMPLED
-                | xr::SwapchainUsageFlags::COLOR_ATTACHMENT,
-            format: color_swapchain_format,
-            sample_count: xr_view.recommended_swapchain_sample_count,
-            width,
-            height,
-            face_count: 1,
-            array_size: 1,
-            mip_count: 1,
-        };
+/// How often the connected count and send rate of headless clients are printed
+const HEADLESS_REPORT_INTERVAL: Duration = Duration::from_secs(5);
 
-        let xr_swapchain = xr_session.create_swapchain(&xr_swapchain_create_info)?;
+/// Time taken to fade out a stale player
+const STALE_FADE: Duration = Duration::from_secs(1);
 
-        let color_images: Vec<gl::NativeTexture> = xr_swapchain
-            .enumerate_images()?
-            .into_iter()
-            .map(|tex| {
-                gl::Context::create_texture_from_gl_name(tex)
-            })
-            .collect();
+/// Opacity of a player who has stopped moving
+const STALE_ALPHA: f32 = 0.3;
 
-        let mut depth_images = vec![];
+/// Simple program to greet a person
+#[derive(Parser, Debug, Clone)]
+struct Args {
+    /// Use OpenXR instead of windowed mode
+    #[arg(long)]
+    vr: bool,
 
-        for _ in &color_images {
-            depth_images.push(get_vr_depth_texture(&gl, width as i32, height as i32).unwrap());
-        }
+    /// Spawn this many clients for testing, each with the rest of these options and a numbered
+    /// name such as player-3. Their output goes to a log file each.
+    #[arg(short, long)]
+    clients: Option<usize>,
 
-        swapchain_depth_images.push(depth_images);
-        swapchain_color_images.push(color_images);
-        xr_swapchains.push(xr_swapchain);
-    }
+    /// With --clients, stop the spawned clients after this many seconds
+    #[arg(long, requires = "clients", value_parser = parse_exit_after)]
+    clients_exit_after: Option<f32>,
+
+    /// With --clients, run the clients on threads of this process without windows or rendering,
+    /// each flying around a circle, until Ctrl+C
+    #[arg(long, requires = "clients")]
+    headless: bool,
+
+    /// OBJ or glTF file to use as the map, or a built in one: builtin:quad (the default),
+    /// builtin:checker, a checkerboard of 1 m tiles, builtin:maze, a maze of walls to hide in,
+    /// builtin:props, ground scattered with columns, boxes and landmarks, or builtin:rooms, two
+    /// rooms and a corridor. rooms:<file> builds rooms from a RON or TOML spec.
+    #[arg(long, value_parser = parse_map)]
+    map: Option<MapSource>,
+
+    /// Seed of generated maps, the terrain shown with T, builtin:maze and builtin:props; each
+    /// seed gives a different one
+    #[arg(long, default_value_t = 0)]
+    seed: u32,
+
+    /// OBJ or glTF file to use as the head of each player, or classic-cube for the plain colored
+    /// cube without a face
+    #[arg(long, value_parser = parse_avatar)]
+    avatar: Option<AvatarSource>,
+
+    /// Image to texture the map with (requires the `image` feature)
+    #[arg(long)]
+    map_texture: Option<PathBuf>,
 
-    // Create OpenGL framebuffers
-    let mut gl_framebuffers = vec![];
-    for _ in &xr_views {
-        gl_framebuffers.push(
-            gl.create_framebuffer()
-                .map_err(|s| format_err!("Failed to create framebuffer; {}", s))?,
-        );
-    }
+    /// Cull the back faces of the map, which are drawn by default. Only for closed maps with
+    /// consistent winding.
+    #[arg(long)]
+    single_sided_map: bool,
 
-    // Compile shaders
-    let xr_play_space =
-        xr_session.create_reference_space(xr::ReferenceSpaceType::LOCAL, xr::Posef::IDENTITY)?;
+    /// Request a debug context and report GL errors (always on in debug builds)
+    #[arg(long)]
+    gl_debug: bool,
 
-    let mut xr_event_buf = xr::EventDataBuffer::default();
+    /// Multisample anti-aliasing samples for the desktop window (0, 2, 4 or 8)
+    #[arg(long, default_value_t = 0, value_parser = parse_msaa_samples)]
+    msaa: u16,
 
-    let (map_mesh, head_mesh) = models();
-    let mut engine = render::Engine::new(&gl, &map_mesh, &head_mesh)
-        .map_err(|e| format_err!("Render engine failed to start; {}", e))?;
+    /// Multisample anti-aliasing samples for the VR views (0, 2, 4 or 8)
+    #[arg(long, default_value_t = 0, value_parser = parse_msaa_samples)]
+    vr_msaa: u16,
 
-    let mut client = Client::new(addr)?;
+    /// Force the VR swapchain color format, by GL name (such as RGBA8) or number, to debug
+    /// runtime quirks
+    #[arg(long, value_parser = parse_color_format)]
+    vr_format: Option<u32>,
 
-    'main: loop {
-        // Handle OpenXR Events
-        while let Some(event) = xr_instance.poll_event(&mut xr_event_buf)? {
-            match event {
-                xr::Event::InstanceLossPending(_) => break 'main,
-                xr::Event::SessionStateChanged(delta) => {
-                    match delta.state() {
-                        xr::SessionState::IDLE | xr::SessionState::UNKNOWN => {
-                            continue 'main;
-                        }
-                        //xr::SessionState::FOCUSED | xr::SessionState::SYNCHRONIZED | xr::SessionState::VISIBLE => (),
-                        xr::SessionState::STOPPING => {
-                            xr_session.end()?;
-                            break 'main;
-                        }
-                        xr::SessionState::LOSS_PENDING | xr::SessionState::EXITING => {
-                            // ???
-                        }
-                        xr::SessionState::READY => {
-                            dbg!(delta.state());
-                            xr_session.begin(xr_view_type)?;
-                        }
-                        _ => continue 'main,
-                    }
-                }
-                _ => (),
-            }
-        }
+    /// Exit if VR can't start, instead of falling back to the desktop client
+    #[arg(long)]
+    require_vr: bool,
 
-        // --- Wait for our turn to do head-pose dependent computation and render a frame
-        let xr_frame_state = xr_frame_waiter.wait()?;
+    /// Scale of the VR view resolution relative to the runtime's recommendation (0.5 to 2.0)
+    #[arg(long, default_value_t = 1., value_parser = parse_render_scale)]
+    vr_render_scale: f32,
 
-        // Signal to OpenXR that we are beginning graphics work
-        xr_frame_stream.begin()?;
+    /// Don't mirror the left eye to the desktop window in VR
+    #[arg(long)]
+    no_mirror: bool,
 
-        // Early exit
-        if !xr_frame_state.should_render {
-            xr_frame_stream.end(
-                xr_frame_state.predicted_display_time,
-                xr_environment_blend_mode,
-                &[],
-            )?;
-            continue;
-        }
+    /// Always render the whole VR view, instead of rendering less of it while frames run over
+    /// budget
+    #[arg(long)]
+    no_dynamic_resolution: bool,
 
-        // Get head positions from server
-        let state = client.update_heads()?;
-        let head_mats = head_matrices(&state.heads);
-        engine.update_heads(&gl, &head_mats);
-
-        // Get OpenXR Views
-        // TODO: Do this as close to render-time as possible!!
-        let (_xr_view_state_flags, xr_view_poses) = xr_session.locate_views(
-            xr_view_type,
-            xr_frame_state.predicted_display_time,
-            &xr_play_space,
-        )?;
+    /// Directory containing px/nx/py/ny/pz/nz.png cubemap faces for the sky
+    #[arg(long)]
+    skybox: Option<PathBuf>,
 
-        for view_idx in 0..xr_views.len() {
-            // Acquire image
-            let xr_swapchain_img_idx = xr_swapchains[view_idx].acquire_image()?;
-            xr_swapchains[view_idx].wait_image(xr::Duration::from_nanos(1_000_000_000_000))?;
+    /// Background: "sky", "dome" for a cheaper sky drawn as a mesh, "transparent" or an sRGB hex
+    /// color such as "#1a334d". VR headsets which blend with the real world always use a
+    /// transparent background.
+    #[arg(long, default_value = "sky", value_parser = parse_background)]
+    bg: Background,
 
-            // Bind framebuffer
-            gl.bind_framebuffer(gl::FRAMEBUFFER, Some(gl_framebuffers[view_idx]));
+    /// Name shown above your head to other players, up to 32 characters. Defaults to your user
+    /// name, or "player" if it's unavailable.
+    #[arg(long, default_value_t = default_name(), value_parser = parse_name)]
+    name: String,
 
-            // Set scissor and viewport
-            let view = xr_views[view_idx];
-            let w = view.recommended_image_rect_width as i32;
-            let h = view.recommended_image_rect_height as i32;
-            gl.viewport(0, 0, w, h);
-            gl.scissor(0, 0, w, h);
+    /// Play sounds where other players join, leave and come close (requires the `audio` feature)
+    #[arg(long)]
+    audio: bool,
 
-            // Set the texture as the render target
-            let img_idx = xr_swapchain_img_idx as usize;
-            let color_texture = swapchain_color_images[view_idx][img_idx];
-            let depth_texture = swapchain_depth_images[view_idx][img_idx];
+    /// Show the frame time and network statistics overlay on startup (toggle with F1)
+    #[arg(long)]
+    stats: bool,
 
-            gl.framebuffer_texture_2d(
-                gl::FRAMEBUFFER,
-                gl::COLOR_ATTACHMENT0,
-                gl::TEXTURE_2D,
-                Some(color_texture),
-                0,
-            );
+    /// Draw debug lines for the world bounds, the origin and where each head is facing
+    #[arg(long)]
+    debug_lines: bool,
 
-            gl.framebuffer_texture_2d(
-                gl::FRAMEBUFFER,
-                gl::DEPTH_ATTACHMENT,
-                gl::TEXTURE_2D,
-                Some(depth_texture),
-                0,
-            );
+    /// Draw a reference grid on the ground, with lines every meter (toggle with F6)
+    #[arg(long)]
+    grid: bool,
 
-            // Set view and projection matrices
-            let headset_view = xr_view_poses[view_idx];
+    /// Load shaders from this directory (e.g. src/shaders) and reload them when they change
+    #[arg(long)]
+    shader_dir: Option<PathBuf>,
 
-            let view = view_from_pose(&headset_view.pose);
-            let proj = projection_from_fov(&headset_view.fov, 0.01, 1000.);
+    /// Fog density per meter (0 to disable)
+    #[arg(long, default_value_t = 0.)]
+    fog: f32,
 
-            engine.frame(&gl, proj, view).expect("Engine error");
+    /// Cast shadows from the sun onto the map
+    #[arg(long)]
+    shadows: bool,
 
-            // Unbind framebuffer
-            gl.bind_framebuffer(gl::FRAMEBUFFER, None);
+    /// Draw a fading trail of the last few seconds of motion behind each head
+    #[arg(long)]
+    trails: bool,
 
-            // Release image
-            xr_swapchains[view_idx].release_image()?;
-        }
+    /// Keep heads still instead of gently bobbing and breathing
+    #[arg(long)]
+    no_idle_animation: bool,
 
-        // Set up projection views
-        let mut xr_projection_views = vec![];
-        for view_idx in 0..xr_views.len() {
-            // Set up projection view
-            let xr_sub_image = xr::SwapchainSubImage::<xr::OpenGL>::new()
-                .swapchain(&xr_swapchains[view_idx])
-                .image_array_index(0)
-                .image_rect(xr::Rect2Di {
-                    offset: xr::Offset2Di { x: 0, y: 0 },
-                    extent: xr::Extent2Di {
-                        width: xr_views[view_idx].recommended_image_rect_width as i32,
-                        height: xr_views[view_idx].recommended_image_rect_height as i32,
-                    },
-                });
+    /// In VR, don't buzz the controllers when another head comes close
+    #[arg(long)]
+    no_haptics: bool,
 
-            let xr_proj_view = xr::CompositionLayerProjectionView::<xr::OpenGL>::new()
-                .pose(xr_view_poses[view_idx].pose)
-                .fov(xr_view_poses[view_idx].fov)
-                .sub_image(xr_sub_image);
+    /// Distance in meters within which another head buzzes the controllers
+    #[arg(long, default_value_t = 0.75)]
+    haptic_distance: f32,
 
-            xr_projection_views.push(xr_proj_view);
-        }
+    /// Raise the VR play space by this many meters to correct the floor height, overriding the
+    /// saved calibration (adjust it in VR with Page Up and Page Down on the mirror window)
+    #[arg(long, allow_hyphen_values = true)]
+    height_offset: Option<f32>,
 
-        let layers = xr::CompositionLayerProjection::new()
-            .space(&xr_play_space)
-            .views(&xr_projection_views);
+    /// Let the desktop camera fly through the ground
+    #[arg(long)]
+    noclip: bool,
 
-        xr_frame_stream.end(
-            xr_frame_state.predicted_display_time,
-            xr_environment_blend_mode,
-            &[&layers],
-        )?;
+    /// Start the desktop camera walking on the ground rather than flying (G toggles it)
+    #[arg(long)]
+    walk: bool,
 
-        // Update head position in server. This is done after all the display work, so that we
-        // don't introduce latency
-        let state = ClientState {
-            head: head_from_xr_pose(&xr_view_poses[0].pose),
-        };
-        client.send_state(&state)?;
-    }
+    /// Base speed of the desktop camera, in meters per second, overriding the saved setting
+    #[arg(long, value_parser = parse_speed)]
+    speed: Option<f32>,
 
-    Ok(())
-}
+    /// Mouse look sensitivity of the desktop camera, in radians per pixel, overriding the saved
+    /// setting
+    #[arg(long, value_parser = parse_sensitivity)]
+    sensitivity: Option<f32>,
 
-/// Compiles (*_SHADER, <source>) into a shader program for OpenGL
-fn compile_glsl_program(gl: &gl::Context, sources: &[(u32, &str)]) -> Result<gl::Program> {
-    // Compile default shaders
-    unsafe {
-        let program = gl.create_program().expect("Cannot create program");
+    /// Look down when moving the mouse up on the desktop
+    #[arg(long)]
+    invert_y: bool,
 
-        let mut shaders = vec![];
+    /// Vertical field of view of the desktop camera, in degrees, overriding the config
+    #[arg(long, value_parser = parse_fov)]
+    fov: Option<f32>,
 
-        for (stage, shader_source) in sources {
-            let shader = gl.create_shader(*stage).expect("Cannot create shader");
+    /// Distance to the near clip plane, in meters, overriding the config
+    #[arg(long)]
+    near: Option<f32>,
 
-            gl.shader_source(shader, shader_source);
+    /// Distance to the far clip plane, in meters, overriding the config. Unused with reversed
+    /// depth, which has no far plane.
+    #[arg(long)]
+    far: Option<f32>,
 
-            gl.compile_shader(shader);
+    /// Eye height of the desktop camera above the ground, in meters, which it starts at and keeps
+    /// to unless flying with --noclip
+    #[arg(long, default_value_t = 1.7)]
+    eye_height: f32,
 
-            if !gl.get_shader_compile_status(shader) {
-                bail!(
-                    "Failed to compile shader;\n{}",
-                    gl.get_shader_info_log(shader)
-                );
-            }
+    /// Height of the starting head position above the floor in VR, in meters, for runtimes
+    /// without a calibrated floor
+    #[arg(long, default_value_t = 1.6)]
+    floor_offset: f32,
 
-            gl.attach_shader(program, shader);
+    /// In VR, don't move or turn with the thumbsticks
+    #[arg(long)]
+    no_locomotion: bool,
 
-            shaders.push(shader);
-        }
+    /// Walking speed with the left thumbstick in VR, in meters per second. Pressing the
+    /// thumbstick in doubles it.
+    #[arg(long, default_value_t = 1.5)]
+    move_speed: f32,
 
-        gl.link_program(program);
+    /// Degrees turned by each flick of the right thumbstick in VR
+    #[arg(long, default_value_t = 30., value_parser = parse_turn_angle)]
+    snap_turn: f32,
 
-        if !gl.get_program_link_status(program) {
+    /// Turn smoothly at up to this many degrees per second instead of snapping
+    #[arg(long)]
+    smooth_turn: Option<f32>,
+
+    /// In VR, don't darken the edges of the view while moving or turning with the thumbsticks
+    #[arg(long)]
+    no_vignette: bool,
+
+    /// How far the comfort vignette closes in at full speed, from 0 to 1
+    #[arg(long, default_value_t = 0.6, value_parser = parse_vignette_strength)]
+    vignette_strength: f32,
+
+    /// Time constant for smoothing the heads and hands of other players, in seconds (0 to disable)
+    #[arg(long, default_value_t = 0.08)]
+    head_smoothing: f32,
+
+    /// Most other players drawn at once; the server sends the nearest ones
+    #[arg(long, default_value_t = 500, value_parser = parse_max_heads)]
+    max_heads: usize,
+
+    /// Start the desktop client fullscreen: "borderless" (the default), "exclusive" or
+    /// "windowed". Overrides the saved mode; F11 toggles it at runtime.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "borderless",
+        value_parser = parse_fullscreen
+    )]
+    fullscreen: Option<FullscreenMode>,
+
+    /// Split the desktop window between two players, each with its own connection to the server.
+    /// The right half flies with the arrow keys; drag the mouse in a half to look around there.
+    #[arg(long)]
+    split: bool,
+
+    /// Letterbox the desktop view to a fixed aspect ratio, such as 16:9, for recording
+    #[arg(long, value_parser = parse_aspect)]
+    aspect: Option<f32>,
+
+    /// Play back a camera path recorded on the desktop from the start (the playback key plays it
+    /// again)
+    #[arg(long)]
+    play_path: Option<PathBuf>,
+
+    /// Speed of camera path playback, as a multiple of the recorded speed
+    #[arg(long, default_value_t = 1., value_parser = parse_path_speed)]
+    path_speed: f32,
+
+    /// Send the camera path being played back as our head, so that other players see it move
+    #[arg(long)]
+    path_head: bool,
+
+    /// Where the recorded camera path is saved
+    #[arg(long, default_value = "camera_path.ron")]
+    record_path: PathBuf,
+
+    /// Warn when a render pass takes longer than this many milliseconds on the GPU
+    #[arg(long)]
+    gpu_budget: Option<f32>,
+
+    /// How the VR frame combines with the real world: "auto" (opaque where supported),
+    /// "opaque", "additive" or "alpha"
+    #[arg(long, default_value = "auto", value_parser = parse_blend_mode)]
+    blend_mode: BlendMode,
+
+    /// Post-processing effects to apply in order, separated by commas ("fxaa"), or "off"
+    #[arg(long, default_value = "fxaa", value_parser = parse_post)]
+    post: PostChain,
+
+    /// Server address: a host name or IP address, with an optional port (5031 by default). IPv6
+    /// addresses need brackets to be followed by a port, as in [::1]:5031.
+    #[arg(required_unless_present_any = ["dump_bindings", "write_config", "offline"])]
+    addr: Option<String>,
+
+    /// Play without a server, alone, sending nothing over the network. Any server address is
+    /// ignored.
+    #[arg(long, conflicts_with = "headless")]
+    offline: bool,
+
+    /// Print the default key bindings, to paste into the client config file, and exit
+    #[arg(long)]
+    dump_bindings: bool,
+
+    /// Read commands from stdin: "connect <address>" switches servers and "disconnect" goes
+    /// offline
+    #[arg(long)]
+    console: bool,
+
+    /// Client config file to read and save settings in, instead of cubehead/client.toml in the
+    /// user's config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Write the settings in effect, from the config file overridden by the command line, to the
+    /// config file and exit
+    #[arg(long)]
+    write_config: bool,
+}
+
+/// Post-processing effects selected with `--post`. An alias, so that clap parses the whole list
+/// as one value.
+type PostChain = Vec<PostEffect>;
+
+/// Map selected with `--map`
+#[derive(Clone, Debug)]
+enum MapSource {
+    File(PathBuf),
+    Quad,
+    Checker,
+    Maze,
+    Props,
+    /// Rooms from a spec file, or the built in two rooms and a corridor
+    Rooms(Option<PathBuf>),
+}
+
+/// Head mesh selected with `--avatar`
+#[derive(Clone, Debug)]
+enum AvatarSource {
+    File(PathBuf),
+    ClassicCube,
+}
+
+/// Background selected with `--bg`
+#[derive(Copy, Clone, Debug)]
+enum Background {
+    Sky,
+    /// Gradient sky dome mesh following the camera
+    Dome,
+    Transparent,
+    Color([f32; 3]),
+}
+
+/// Environment blend mode selected with `--blend-mode`
+#[derive(Copy, Clone, Debug)]
+enum BlendMode {
+    /// Opaque where the runtime supports it, else the runtime's preferred mode
+    Auto,
+    Forced(xr::EnvironmentBlendMode),
+}
+
+impl Args {
+    /// How long spawned clients run for, if they are stopped
+    fn clients_lifetime(&self) -> Option<Duration> {
+        self.clients_exit_after.map(Duration::from_secs_f32)
+    }
+
+    /// Whether to check for GL errors and install a debug callback
+    fn gl_debug(&self) -> bool {
+        self.gl_debug || cfg!(debug_assertions)
+    }
+
+    /// Address of the server, which is only missing when offline or dumping the bindings
+    fn addr(&self) -> &str {
+        self.addr.as_deref().expect("No server address")
+    }
+
+    /// Connect to the server, or not at all with `--offline`
+    fn connect(&self) -> Result<Connection> {
+        match self.offline {
+            true => Ok(Connection::offline()),
+            false => Connection::online(&self.server_addrs()?),
+        }
+    }
+
+    /// Addresses the server's address resolves to, in the order to try them
+    fn server_addrs(&self) -> Result<Vec<SocketAddr>> {
+        cubehead::resolve_server_addr(self.addr())
+    }
+
+    /// Client config file given with `--config`, or the usual one if the platform has one
+    fn config_path(&self) -> Option<PathBuf> {
+        self.config.clone().or_else(ClientConfig::path)
+    }
+
+    /// Options given which override the config file. The name only counts if it was given
+    /// rather than defaulted, according to `matches`.
+    fn overrides(&self, matches: &clap::ArgMatches) -> Overrides {
+        let name_given = matches.value_source("name") == Some(ValueSource::CommandLine);
+        Overrides {
+            name: name_given.then(|| self.name.clone()),
+            height_offset: self.height_offset,
+            fly_speed: self.speed,
+            look_sensitivity: self.sensitivity,
+            invert_y: self.invert_y,
+            fov: self.fov,
+            near: self.near,
+            far: self.far,
+            fullscreen: self.fullscreen,
+            stats: self.stats,
+            debug_lines: self.debug_lines,
+            grid: self.grid,
+            shadows: self.shadows,
+            trails: self.trails,
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    if args.dump_bindings {
+        let table = toml::to_string(&Bindings::default())?;
+        print!("[bindings]\n{}", table);
+        return Ok(());
+    }
+
+    let required = args.config.is_some() || args.write_config;
+    let config_file = ConfigFile::load(args.config_path(), required)?;
+    // The settings in effect, which the clients read rather than the command line
+    let config = config_file.merged(&args.overrides(&matches));
+    // The name in the config beats the user name, but not --name
+    if let Some(name) = &config.name {
+        args.name = parse_name(name)
+            .map_err(|e| format_err!("Invalid name {:?} in the config; {}", name, e))?;
+    }
+
+    if args.write_config {
+        config_file.write(&config)?;
+        if let Some(path) = config_file.path() {
+            println!("Wrote {}", path.display());
+        }
+        return Ok(());
+    }
+
+    if let Some(count) = args.clients {
+        if args.headless {
+            run_headless_clients(&args, count)?;
+        } else {
+            spawn_clients(&args, count)?;
+        }
+    } else {
+        // Launch a single client
+        unsafe {
+            if args.vr {
+                match probe_vr() {
+                    Ok(vr) => vr_main(args, vr, config, config_file)?,
+                    Err(e) if args.require_vr => return Err(e),
+                    Err(e) => {
+                        eprintln!("VR unavailable, falling back to desktop; {:#}", e);
+                        desktop_main(args, config, config_file)?;
+                    }
+                }
+            } else {
+                desktop_main(args, config, config_file)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Launch `count` clients for testing with the same options as this one, other than their names,
+/// a little apart so that they don't all connect at once. With `--clients-exit-after`, wait and
+/// then stop them.
+fn spawn_clients(args: &Args, count: usize) -> Result<()> {
+    let program_name = std::env::args().next().unwrap();
+    let forwarded = forwarded_args(std::env::args().skip(1));
+    let log_dir = std::env::temp_dir().join("cubehead-clients");
+    std::fs::create_dir_all(&log_dir)
+        .with_context(|| format!("Failed to create {}", log_dir.display()))?;
+
+    let mut children = vec![];
+    for i in 0..count {
+        if i > 0 {
+            std::thread::sleep(CLIENT_SPAWN_INTERVAL);
+        }
+        let log_path = log_dir.join(format!("client-{}.log", i));
+        let log = std::fs::File::create(&log_path)
+            .with_context(|| format!("Failed to create {}", log_path.display()))?;
+        let child = std::process::Command::new(&program_name)
+            .args(&forwarded)
+            .arg("--name")
+            .arg(format!("{}-{}", args.name, i))
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .with_context(|| format!("Failed to spawn client {}", i))?;
+        children.push(child);
+    }
+    println!(
+        "Spawned {} clients, logging to {}",
+        count,
+        log_dir.display()
+    );
+
+    let lifetime = match args.clients_lifetime() {
+        Some(lifetime) => lifetime,
+        None => return Ok(()),
+    };
+    // Stop early if every client has exited on its own
+    let deadline = Instant::now() + lifetime;
+    while Instant::now() < deadline {
+        let mut running = 0;
+        for child in &mut children {
+            if child.try_wait()?.is_none() {
+                running += 1;
+            }
+        }
+        if running == 0 {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    for child in &mut children {
+        if child.try_wait()?.is_none() {
+            child.kill()?;
+            child.wait()?;
+        }
+    }
+    println!("Stopped the clients after {:?}", lifetime);
+    Ok(())
+}
+
+/// Counters shared between headless clients and the thread reporting on them
+#[derive(Default)]
+struct HeadlessShared {
+    /// Set to make every client disconnect
+    stop: AtomicBool,
+    connected: AtomicUsize,
+    /// Total bytes sent by all clients
+    bytes_sent: AtomicU64,
+}
+
+/// Run `count` clients on threads of this process, started a little apart, printing how many are
+/// connected and how fast they send until Ctrl+C, `--clients-exit-after` or every client has
+/// disconnected. Then wait for them all to stop.
+fn run_headless_clients(args: &Args, count: usize) -> Result<()> {
+    let shared = Arc::new(HeadlessShared::default());
+    let handler_shared = shared.clone();
+    ctrlc::set_handler(move || handler_shared.stop.store(true, Ordering::Relaxed))
+        .context("Failed to handle Ctrl+C")?;
+    let deadline = args
+        .clients_lifetime()
+        .map(|lifetime| Instant::now() + lifetime);
+
+    // Look the server up once for all of them
+    let addrs = args.server_addrs()?;
+    let mut threads = vec![];
+    for i in 0..count {
+        if shared.stop.load(Ordering::Relaxed) {
+            break;
+        }
+        if i > 0 {
+            std::thread::sleep(CLIENT_SPAWN_INTERVAL);
+        }
+
+        let (addrs, eye_height, shared) = (addrs.clone(), args.eye_height, shared.clone());
+        let state = ClientState {
+            name: format!("{}-{}", args.name, i),
+            max_players: Some(args.max_heads as u32),
+            ..Default::default()
+        };
+        // Spread the clients evenly around the circle
+        let phase = TAU * i as f32 / count as f32;
+        let thread = std::thread::Builder::new()
+            .name(state.name.clone())
+            .spawn(move || {
+                let name = state.name.clone();
+                if let Err(e) = run_headless_client(&addrs, state, phase, eye_height, &shared) {
+                    eprintln!("Client {} stopped; {:#}", name, e);
+                }
+            })
+            .context("Failed to start a client thread")?;
+        threads.push(thread);
+    }
+
+    let mut window = (Instant::now(), 0);
+    while !shared.stop.load(Ordering::Relaxed)
+        && !threads.iter().all(|thread| thread.is_finished())
+        && deadline.is_none_or(|deadline| Instant::now() < deadline)
+    {
+        std::thread::sleep(Duration::from_millis(100));
+
+        let (since, sent_before) = window;
+        let elapsed = since.elapsed();
+        if elapsed >= HEADLESS_REPORT_INTERVAL {
+            let sent = shared.bytes_sent.load(Ordering::Relaxed);
+            let rate = (sent - sent_before) as f32 / elapsed.as_secs_f32() / 1e3;
+            println!(
+                "{}/{} clients connected, sending {:.1} kB/s",
+                shared.connected.load(Ordering::Relaxed),
+                count,
+                rate
+            );
+            window = (Instant::now(), sent);
+        }
+    }
+
+    shared.stop.store(true, Ordering::Relaxed);
+    for thread in threads {
+        let _ = thread.join();
+    }
+    println!("Stopped all clients");
+    Ok(())
+}
+
+/// Fly one headless client around a circle about the origin, starting `phase` radians around,
+/// until told to stop
+fn run_headless_client(
+    addrs: &[SocketAddr],
+    mut state: ClientState,
+    phase: f32,
+    eye_height: f32,
+    shared: &HeadlessShared,
+) -> Result<()> {
+    let mut client = Client::new(addrs).context("Failed to connect")?;
+    shared.connected.fetch_add(1, Ordering::Relaxed);
+
+    let start = Instant::now();
+    let mut sent = 0;
+    let result = loop {
+        if shared.stop.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+
+        // Facing the way it's going
+        let angle = phase + TAU * start.elapsed().as_secs_f32() / HEADLESS_LAP_TIME;
+        state.head = Head {
+            pos: Point3::new(
+                HEADLESS_RADIUS * angle.cos(),
+                eye_height,
+                HEADLESS_RADIUS * angle.sin(),
+            ),
+            orient: UnitQuaternion::from_axis_angle(&Vector3::y_axis(), PI - angle),
+        };
+        if let Err(e) = client
+            .send_state(&mut state)
+            .and_then(|()| client.update_heads().map(drop))
+        {
+            break Err(e);
+        }
+        // Nothing here uses what other players relay
+        client.take_app_data();
+
+        let total = client.stats().bytes_sent;
+        shared.bytes_sent.fetch_add(total - sent, Ordering::Relaxed);
+        sent = total;
+        std::thread::sleep(HEADLESS_SEND_INTERVAL);
+    };
+
+    shared.connected.fetch_sub(1, Ordering::Relaxed);
+    result
+}
+
+/// This process's arguments, after the program name, to pass on to the clients it spawns,
+/// without those about spawning clients, which would have them spawn more, or the name, which
+/// they each get their own of
+fn forwarded_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut forwarded = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, has_value) = match arg.split_once('=') {
+            Some((flag, _)) if arg.starts_with("--") => (flag, true),
+            _ => (arg.as_str(), false),
+        };
+        if matches!(flag, "--clients" | "-c" | "--clients-exit-after" | "--name") {
+            // Skip the value too, if it's separate
+            if !has_value {
+                args.next();
+            }
+            continue;
+        }
+        // The short form with its value attached, as in -c4 or -c=4
+        if let Some(value) = arg.strip_prefix("-c") {
+            if value.starts_with('=') || value.parse::<usize>().is_ok() {
+                continue;
+            }
+        }
+        forwarded.push(arg);
+    }
+    forwarded
+}
+
+unsafe fn desktop_main(
+    args: Args,
+    mut config: ClientConfig,
+    mut config_file: ConfigFile,
+) -> Result<()> {
+    // Camera settings and the fullscreen mode are saved whenever they are changed
+    let mut fullscreen_mode = config.fullscreen;
+    // What fullscreen toggles to, and whether it dropped out of exclusive fullscreen on losing
+    // focus
+    let fullscreen_kind = match fullscreen_mode {
+        FullscreenMode::Exclusive => FullscreenMode::Exclusive,
+        _ => FullscreenMode::Borderless,
+    };
+    let mut left_exclusive = false;
+
+    let event_loop = glutin::event_loop::EventLoop::new();
+    let mut title = window_title(&args);
+    let window_builder = glutin::window::WindowBuilder::new()
+        .with_title(&title)
+        .with_inner_size(glutin::dpi::LogicalSize::new(1024.0, 768.0))
+        .with_fullscreen(winit_fullscreen(
+            fullscreen_mode,
+            event_loop.primary_monitor(),
+        ));
+
+    let build_context = |samples: u16| {
+        let mut builder = glutin::ContextBuilder::new()
+            .with_vsync(true)
+            .with_srgb(true)
+            .with_gl_debug_flag(args.gl_debug());
+        if samples > 0 {
+            builder = builder.with_multisampling(samples);
+        }
+        builder.build_windowed(window_builder.clone(), &event_loop)
+    };
+
+    // Drivers may refuse the requested sample count, in which case we go without
+    let glutin_ctx = match build_context(args.msaa) {
+        Ok(ctx) => ctx,
+        Err(e) if args.msaa > 0 => {
+            eprintln!(
+                "Failed to create a context with {}x MSAA ({}), disabling MSAA",
+                args.msaa, e
+            );
+            build_context(0)?
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let glutin_ctx = glutin_ctx.make_current().unwrap();
+
+    let mut gl = gl::Context::from_loader_function(|s| glutin_ctx.get_proc_address(s) as *const _);
+    if args.gl_debug() {
+        render::enable_gl_debug(&mut gl);
+    }
+
+    if args.msaa > 0 {
+        gl.enable(gl::MULTISAMPLE);
+    }
+
+    // Shared with the UI renderer
+    let gl = Arc::new(gl);
+
+    // We handle events differently between targets
+    use glutin::event::{Event, WindowEvent};
+    use glutin::event_loop::ControlFlow;
+
+    let mut wih = WinitInputHelper::new();
+    let (map_mesh, map_bounds, map_spawn) = map_model(&args)?;
+    let spawn = clear_spawn(&map_bounds, map_spawn, SPAWN_CLEARANCE);
+    let mut camera = FlyCam::new(spawn + Vector3::y() * args.eye_height);
+    if args.walk {
+        camera.mode = MovementMode::Walk;
+    }
+    let mut engine = create_engine(&gl, &args, &config, &map_mesh)?;
+    drop(map_mesh);
+    add_sky_dome(&gl, &args, &mut engine)?;
+    let mut perspective_cfg = Perspective::default();
+
+    // The pixel format may not be sRGB capable after all, in which case shaders encode instead
+    let srgb = glutin_ctx.get_pixel_format().srgb;
+    if !srgb {
+        eprintln!("Window is not sRGB capable, encoding colors in shaders");
+    }
+    engine.set_srgb_target(srgb);
+
+    let hand_mesh = engine
+        .add_mesh(&gl, &controller(), RenderStyle::Unlit)
+        .context("Failed to upload controller mesh")?;
+    let trail_mesh = engine
+        .add_mesh(&gl, &rgb_cube(TRAIL_CUBE_SIZE), RenderStyle::Unlit)
+        .context("Failed to upload trail mesh")?;
+    let beam_mesh = engine
+        .add_mesh(&gl, &beam(), RenderStyle::Unlit)
+        .context("Failed to upload laser pointer mesh")?;
+    let gizmos = Gizmos::new(&gl, &mut engine)?;
+
+    let mut client = args.connect()?;
+    let server_addr = (!args.offline).then(|| args.addr().to_string());
+    let mut server = ServerSwitch::new(server_addr, args.console)?;
+    let mut client_state = ClientState {
+        head: camera.head(),
+        hands: [None; 2],
+        app_data: vec![],
+        name: args.name.clone(),
+        ping: 0,
+        max_players: Some(args.max_heads as u32),
+    };
+    let mut stats = StatsOverlay::new(config.stats);
+    let mut split = match args.split {
+        true => Some(SplitView::new(&args, &map_bounds, map_spawn)?),
+        false => None,
+    };
+
+    camera.set_speed(config.camera.fly_speed);
+    println!(
+        "Fly speed {} m/s, look sensitivity {} rad/pixel{}",
+        config.camera.fly_speed,
+        config.camera.look_sensitivity,
+        if config.camera.invert_y {
+            ", inverted"
+        } else {
+            ""
+        }
+    );
+    let mut config_changed: Option<Instant> = None;
+    perspective_cfg.projection = projection_config(&config, engine.reverse_z())?;
+    let fov = config.fov.clamp(FOV_RANGE.0, FOV_RANGE.1).to_radians();
+    let zoom_fov = config.zoom_fov.clamp(FOV_RANGE.0, FOV_RANGE.1).to_radians();
+    perspective_cfg.fov = fov;
+    perspective_cfg.target_fov = fov;
+    let mut last_camera_update = Instant::now();
+
+    let mut settings = Settings {
+        camera: config.camera,
+        show_stats: config.stats,
+        debug_lines: config.debug_lines,
+        smooth_heads: args.head_smoothing > 0.,
+        head_smoothing: if args.head_smoothing > 0. {
+            args.head_smoothing
+        } else {
+            Settings::default().head_smoothing
+        },
+        fog: args.fog > 0.,
+        fog_density: if args.fog > 0. {
+            args.fog
+        } else {
+            Settings::default().fog_density
+        },
+        shadows: config.shadows,
+        trails: config.trails,
+        idle_animation: !args.no_idle_animation,
+        grid: config.grid,
+        ..Settings::default()
+    };
+    let mut smoother = HeadSmoother::new(args.head_smoothing, HEAD_SNAP_DISTANCE);
+    let mut trails = HeadTrails::new(TRAIL_INTERVAL, TRAIL_LENGTH);
+    let mut remote_pointers = RemotePointers::new(POINTER_EXPIRY);
+    let mut players: Vec<Player> = vec![];
+    let start_time = Instant::now();
+    let mut last_update = start_time;
+    let mut audio = start_audio(&args);
+    let mut ui = UiLayer::new(&event_loop, gl.clone());
+    let mut show_terrain = false;
+    let mut ground = map_ground(&args, &engine);
+    // Our head is sent to the server from `camera`, but the view comes from here in third person
+    let mut third_person: Option<Orbit> = None;
+    // Replaces the view while set, leaving the head where it was
+    let mut debug_camera: Option<DebugCamera> = None;
+    // Heads are culled from here while frozen, rather than from the view
+    let mut freeze_culling = false;
+    let mut frozen_cull: Option<CullCamera> = None;
+    // Camera paths being recorded and played back, and the one loaded from the command line
+    let mut recorder = PathRecorder::default();
+    let loaded_path = match &args.play_path {
+        Some(path) => Some(CameraPath::load(path)?),
+        None => None,
+    };
+    let mut playback = loaded_path
+        .clone()
+        .map(|path| PathPlayback::new(path, args.path_speed));
+    let mut view_head = camera.head();
+    // Save the window once this frame is drawn
+    let mut screenshot = false;
+    // Player clicked on, and where the mouse was pressed
+    let mut selected: Option<PlayerId> = None;
+    let mut press_pos: Option<(f32, f32)> = None;
+
+    // Viewport within the window, None while minimized. The projection is kept from the last
+    // valid size. In split screen, this is the left half.
+    let size = glutin_ctx.window().inner_size();
+    let mut viewport = letterbox((size.width, size.height), args.aspect);
+    if let Some(split) = &mut split {
+        viewport = split.divide(viewport);
+    }
+    let mut proj = match viewport {
+        Some([_, _, w, h]) => perspective_cfg.matrix(w as f32, h as f32),
+        None => perspective_cfg.matrix(1., 1.),
+    };
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+
+        // The settings panel gets first pick of window events
+        let forward = match &event {
+            Event::WindowEvent { event, .. } => ui.on_event(event),
+            _ => true,
+        };
+
+        if forward && wih.update(&event) {
+            if config.bindings.cycle_debug_mode.pressed(&wih) {
+                settings.debug_mode = settings.debug_mode.next();
+            }
+            if config.bindings.toggle_stats.pressed(&wih) {
+                settings.show_stats = !settings.show_stats;
+            }
+            if config.bindings.toggle_labels_through_walls.pressed(&wih) {
+                settings.labels_through_walls = !settings.labels_through_walls;
+            }
+            if config.bindings.toggle_gamma.pressed(&wih) {
+                settings.gamma_correct = !settings.gamma_correct;
+            }
+            if config.bindings.toggle_terrain.pressed(&wih) {
+                // Swap between the map and generated terrain, standing on whichever is shown
+                show_terrain = !show_terrain;
+                let result = match show_terrain {
+                    true => terrain(TERRAIN_EXTENT, TERRAIN_RESOLUTION, args.seed)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|(mesh, heightfield)| {
+                            engine.update_map(&gl, &mesh)?;
+                            ground = Box::new(heightfield);
+                            Ok(())
+                        }),
+                    false => map_model(&args).and_then(|(mesh, _, _)| {
+                        engine.update_map(&gl, &mesh)?;
+                        ground = map_ground(&args, &engine);
+                        Ok(())
+                    }),
+                };
+                if let Err(e) = result {
+                    eprintln!("Failed to load map; {:#}", e);
+                }
+            }
+            if config.bindings.toggle_grid.pressed(&wih) {
+                settings.grid = !settings.grid;
+            }
+            if config.bindings.toggle_minimap.pressed(&wih) {
+                settings.minimap = !settings.minimap;
+            }
+            if config.bindings.toggle_settings.pressed(&wih) {
+                ui.visible = !ui.visible;
+            }
+            if config.bindings.toggle_walk.pressed(&wih) {
+                camera.mode = match camera.mode {
+                    MovementMode::Fly => MovementMode::Walk,
+                    MovementMode::Walk => MovementMode::Fly,
+                };
+            }
+            if config.bindings.toggle_fullscreen.pressed(&wih) {
+                // Resizing the window rebuilds the viewport and projection as usual
+                fullscreen_mode = match fullscreen_mode {
+                    FullscreenMode::Windowed => fullscreen_kind,
+                    _ => FullscreenMode::Windowed,
+                };
+                left_exclusive = false;
+                let window = glutin_ctx.window();
+                let monitor = window.current_monitor();
+                window.set_fullscreen(winit_fullscreen(fullscreen_mode, monitor));
+            }
+            if config.bindings.toggle_third_person.pressed(&wih) {
+                third_person = match third_person {
+                    Some(_) => None,
+                    None => Some(Orbit::new()),
+                };
+            }
+            if config.bindings.screenshot.pressed(&wih) {
+                screenshot = true;
+            }
+            if config.bindings.add_keyframe.pressed(&wih) {
+                recorder.keyframe(view_head);
+                println!("Camera path keyframe {}", recorder.path.keyframes.len());
+            }
+            if config.bindings.clear_path.pressed(&wih) {
+                recorder.clear();
+                println!("Camera path cleared");
+            }
+            if config.bindings.save_path.pressed(&wih) {
+                match recorder.path.save(&args.record_path) {
+                    Ok(()) => println!(
+                        "Saved {} keyframes to {}",
+                        recorder.path.keyframes.len(),
+                        args.record_path.display()
+                    ),
+                    Err(e) => eprintln!("Failed to save the camera path; {:#}", e),
+                }
+            }
+            if config.bindings.toggle_path_playback.pressed(&wih) {
+                // Play the recorded path, or else the one from the command line
+                let path = match recorder.path.keyframes.is_empty() {
+                    true => loaded_path.clone(),
+                    false => Some(recorder.path.clone()),
+                };
+                playback = match (&playback, path) {
+                    (None, Some(path)) => Some(PathPlayback::new(path, args.path_speed)),
+                    _ => None,
+                };
+            }
+            if config.bindings.freeze_culling.pressed(&wih) {
+                freeze_culling = !freeze_culling;
+            }
+            if config.bindings.toggle_top_down.pressed(&wih) {
+                debug_camera = match debug_camera {
+                    Some(_) => None,
+                    None => Some(DebugCamera::top_down(engine.map_bounds())),
+                };
+            }
+
+            // Clicking without dragging the view selects the head under the cursor
+            if wih.mouse_pressed(0) {
+                press_pos = wih.mouse();
+            }
+            if wih.mouse_released(0) && debug_camera.is_none() {
+                if let (Some(start), Some(end), Some(viewport)) = (press_pos, wih.mouse(), viewport)
+                {
+                    if (end.0 - start.0).hypot(end.1 - start.1) <= CLICK_SLOP {
+                        let window_height = glutin_ctx.window().inner_size().height as f32;
+                        let ndc = picking::cursor_ndc(end, window_height, viewport);
+                        let view = view_from_head(&view_head);
+                        selected = picking::cursor_ray(&(proj * view), view_head.pos, ndc)
+                            .and_then(|ray| pick_head(&ray, &players, engine.head_bounds()));
+                    }
+                }
+            }
+
+            // Move by the real time since the last update, whatever the refresh rate
+            let now = Instant::now();
+            let camera_dt = (now - last_camera_update).as_secs_f32();
+            last_camera_update = now;
+            camera.set_speed(settings.camera.fly_speed);
+            // Scrolling zooms the debug view or in third person, and otherwise sets the fly speed
+            match (&mut debug_camera, &mut third_person) {
+                (Some(debug_camera), _) => debug_camera.zoom(wih.scroll_diff()),
+                (None, Some(orbit)) => orbit.zoom(wih.scroll_diff()),
+                (None, None) => camera.scroll_speed(wih.scroll_diff()),
+            }
+            // Look more slowly while zoomed in, so that aiming stays controllable
+            let look = camera::LookConfig {
+                sensitivity: zoomed_sensitivity(
+                    settings.camera.look_sensitivity,
+                    perspective_cfg.fov,
+                    fov,
+                ),
+                ..settings.camera.look()
+            };
+            // Dragging pans the debug view instead of looking around, and the head stays put, as
+            // it does while playing a camera path
+            match &mut debug_camera {
+                Some(debug_camera) => {
+                    if let (true, Some([_, _, _, h])) = (wih.mouse_held(0), viewport) {
+                        debug_camera.pan(wih.mouse_diff(), h as f32);
+                    }
+                }
+                None if playback.is_some() => (),
+                None => camera.update(
+                    &wih,
+                    camera_dt,
+                    split
+                        .as_ref()
+                        .map_or(look, |split| split.left_look(&wih, look)),
+                    settings.fly_smoothing(),
+                    &config.bindings,
+                ),
+            }
+            if let Some(split) = &mut split {
+                split.camera.set_speed(settings.camera.fly_speed);
+                split.update(&wih, camera_dt, look, settings.fly_smoothing());
+                if !args.noclip || split.camera.mode == MovementMode::Walk {
+                    split.camera.collide(ground.as_ref(), args.eye_height);
+                }
+                if let Err(e) = split.send() {
+                    server.connection_lost(&mut [&mut client, &mut split.client], e);
+                }
+            }
+            settings.camera.fly_speed = camera.speed();
+            // Walking always stands on the ground
+            if !args.noclip || camera.mode == MovementMode::Walk {
+                camera.collide(ground.as_ref(), args.eye_height);
+            }
+            // Zoom while the zoom key or the right mouse button is held
+            let zooming = config.bindings.zoom.held(&wih) || wih.mouse_held(1);
+            perspective_cfg.target_fov = if zooming { zoom_fov } else { fov };
+            if perspective_cfg.approach_fov(camera_dt, config.zoom_time) {
+                if let Some([_, _, w, h]) = viewport {
+                    proj = perspective_cfg.matrix(w as f32, h as f32);
+                }
+            }
+
+            let played = playback.as_ref().and_then(PathPlayback::head);
+            if playback.is_some() && played.is_none() {
+                playback = None;
+                println!("Camera path finished");
+            }
+            view_head = match (played, &third_person) {
+                (Some(head), _) => head,
+                (None, Some(orbit)) => orbit.view(&camera.head(), ground.as_ref()),
+                (None, None) => camera.head(),
+            };
+
+            // Save the camera settings once they have settled, showing the fly speed in the title
+            // meanwhile if it changed
+            let kept = ClientConfig {
+                camera: settings.camera,
+                fullscreen: fullscreen_mode,
+                ..config.clone()
+            };
+            if kept != config {
+                if kept.camera.fly_speed != config.camera.fly_speed {
+                    let speed = kept.camera.fly_speed;
+                    let title = format!("{} - fly speed {:.1} m/s", title, speed);
+                    glutin_ctx.window().set_title(&title);
+                }
+                config = kept;
+                config_changed = Some(now);
+            }
+            if config_changed.is_some_and(|changed| now - changed >= SETTLE_TIME) {
+                config_changed = None;
+                glutin_ctx.window().set_title(&title);
+                let (camera, fullscreen) = (config.camera, config.fullscreen);
+                let saved = config_file.save(|file| {
+                    file.camera = camera;
+                    file.fullscreen = fullscreen;
+                });
+                if let Err(e) = saved {
+                    eprintln!("Failed to save the camera settings; {:#}", e);
+                }
+            }
+            // Send head position to server
+            client_state.head = match played {
+                Some(head) if args.path_head => head,
+                _ => camera.head(),
+            };
+            if let Err(e) = client.send_state(&mut client_state) {
+                server.connection_lost(&mut desktop_connections(&mut client, &mut split), e);
+            }
+        }
+
+        if let Some(ph) = wih.window_resized() {
+            viewport = letterbox((ph.width, ph.height), args.aspect);
+            if let Some(split) = &mut split {
+                viewport = split.divide(viewport);
+            }
+            if let Some([_, _, w, h]) = viewport {
+                glutin_ctx.resize(ph);
+                proj = perspective_cfg.matrix(w as f32, h as f32);
+            }
+        }
+
+        // Start afresh with the heads of a new server
+        if server.update(&mut desktop_connections(&mut client, &mut split)) {
+            title = status_title(&args.name, &server.status());
+            glutin_ctx.window().set_title(&title);
+            selected = None;
+            trails.clear();
+            remote_pointers.clear();
+        }
+        if let Err(e) = client.update_heads().map(drop) {
+            server.connection_lost(&mut desktop_connections(&mut client, &mut split), e);
+        }
+        let app_data = client.take_app_data();
+        let state = client.state();
+
+        // Smooth heads over the real time since the last update
+        let now = Instant::now();
+        let dt = (now - last_update).as_secs_f32();
+        last_update = now;
+        engine.set_time((now - start_time).as_secs_f32());
+        smoother.time_constant = match settings.smooth_heads {
+            true => settings.head_smoothing,
+            false => 0.,
+        };
+        let alphas: Vec<f32> = state.players.iter().map(stale_alpha).collect();
+        remote_pointers.update(&state.players, &app_data, now);
+        players.clone_from(&state.players);
+        smoother.apply(&mut players, dt);
+        if let Some(split) = &mut split {
+            let smoothing = smoother.time_constant;
+            if let Err(e) = split.receive(smoothing, dt) {
+                server.connection_lost(&mut [&mut client, &mut split.client], e);
+            }
+        }
+        if !players.iter().any(|player| Some(player.id) == selected) {
+            selected = None;
+        }
+        if let Some(audio) = &mut audio {
+            audio.update(&camera.head(), &audio_sources(&players));
+        }
+
+        // The debug view shows every head, however far away
+        match debug_camera {
+            Some(_) => engine.set_head_distances(f32::INFINITY, f32::INFINITY),
+            None => {
+                engine.set_head_distances(settings.head_lod_distance, settings.head_draw_distance)
+            }
+        }
+        let mut heads = head_instances(&players, &alphas, selected);
+        if third_person.is_some() || debug_camera.is_some() {
+            heads.push(Instance::new(camera.head().matrix()));
+        }
+        // Matrices to render with, which heads are culled against unless frozen
+        let (proj, view) = match (debug_camera, viewport) {
+            (Some(debug_camera), Some([_, _, w, h])) => {
+                debug_camera.matrices(engine.map_bounds(), w as f32, h as f32, engine.reverse_z())
+            }
+            _ => (proj, view_from_head(&view_head)),
+        };
+        let live_cull = CullCamera {
+            eye: view_head.pos,
+            view_proj: Some(proj * view),
+        };
+        let cull = match freeze_culling {
+            true => *frozen_cull.get_or_insert(live_cull),
+            false => {
+                frozen_cull = None;
+                live_cull
+            }
+        };
+        stats.set_heads(engine.update_heads(&gl, &heads, &cull));
+        let hands = hand_instances(&[None; 2], &players, &alphas);
+        engine.set_instances(&gl, hand_mesh, &hands);
+        let beams = pointer_instances(&[None; 2], &players, &remote_pointers, &alphas);
+        engine.set_instances(&gl, beam_mesh, &beams);
+        let markers = pointer_markers(&[None; 2], &players, &remote_pointers, &alphas);
+        engine.set_billboards(&gl, &markers);
+        if settings.trails {
+            trails.update(&players, dt);
+        } else {
+            trails.clear();
+        }
+        engine.set_instances(&gl, trail_mesh, &trail_instances(&trails));
+        gizmos.set(&gl, &mut engine, settings.debug_lines, &players);
+        stats.set_net(client.stats());
+
+        // Apply settings
+        engine.set_debug_mode(settings.debug_mode);
+        engine.set_labels_through_walls(settings.labels_through_walls);
+        engine.set_debug_lines(
+            settings.debug_lines || freeze_culling,
+            settings.debug_lines_xray,
+        );
+        engine.set_gamma_correct(settings.gamma_correct);
+        engine.set_fog(
+            engine.background_color(),
+            if settings.fog && debug_camera.is_none() {
+                settings.fog_density
+            } else {
+                0.
+            },
+        );
+        engine.set_shadows(settings.shadows);
+        engine.set_grid(settings.grid);
+        engine.set_idle_animation(settings.idle_animation);
+        engine.set_shadow_bias(settings.shadow_bias, settings.shadow_slope_bias);
+        stats.visible = settings.show_stats;
+
+        match event {
+            Event::LoopDestroyed => {
+                ui.destroy();
+                return;
+            }
+            Event::MainEventsCleared => {
+                glutin_ctx.window().request_redraw();
+            }
+            // Don't render while minimized
+            Event::RedrawRequested(_) if viewport.is_none() => (),
+            Event::RedrawRequested(_) => {
+                let window_size = glutin_ctx.window().inner_size();
+                let [x, y, w, h] = viewport.unwrap();
+
+                // Clear the bars around a letterboxed view to black, then keep drawing inside
+                // it. The UI resets the viewport, so it is set every frame.
+                let letterboxed = (w, h) != (window_size.width as i32, window_size.height as i32);
+                if letterboxed {
+                    gl.clear_color(0., 0., 0., 1.);
+                    gl.clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+                }
+
+                engine.clear_debug_lines();
+                if settings.debug_lines {
+                    draw_debug_scene(&mut engine, &players);
+                }
+                if let Some(CullCamera {
+                    view_proj: Some(view_proj),
+                    ..
+                }) = frozen_cull
+                {
+                    let distance = settings.head_draw_distance;
+                    draw_frustum(
+                        &mut engine,
+                        &view_proj,
+                        &perspective_cfg.projection,
+                        distance,
+                    );
+                }
+                engine.render_shadows(&gl);
+                if settings.minimap {
+                    let extent = settings.minimap_extent;
+                    let dots = minimap_dots(&players, extent);
+                    // Carry on without the minimap, rather than report the same error every
+                    // frame
+                    let center = camera.head().pos;
+                    if let Err(e) = engine.render_minimap(&gl, center, extent, &dots) {
+                        eprintln!("Failed to draw the minimap, turning it off; {}", e);
+                        settings.minimap = false;
+                    }
+                }
+
+                gl.viewport(x, y, w, h);
+                if letterboxed {
+                    gl.enable(gl::SCISSOR_TEST);
+                    gl.scissor(x, y, w, h);
+                }
+                engine.frame(&gl, proj, view).expect("Engine error");
+                engine.draw_labels(&gl, &nametags(&players), proj, view);
+                if let Some(split) = &split {
+                    split.draw(&gl, &mut engine, &perspective_cfg, hand_mesh, beam_mesh);
+                }
+                engine.end_frame(&gl);
+
+                stats.frame();
+                stats.set_gpu(engine.gpu_timings());
+                stats.set_render(engine.last_frame_stats());
+                engine.draw_stats(&gl, &stats, (w as u32, h as u32), 8.);
+                if settings.minimap {
+                    let forward = camera.head().orient * -Vector3::z();
+                    let heading = minimap::minimap_heading(forward);
+                    let size = (w as u32, h as u32);
+                    engine.draw_minimap(&gl, size, settings.minimap_size, 8., heading);
+                }
+
+                // egui leaves blending and scissoring on, among other things
+                let status = server.status();
+                let switch_to = ui.draw(
+                    glutin_ctx.window(),
+                    &mut settings,
+                    &players,
+                    &mut selected,
+                    &status,
+                );
+                if let Some(addr) = switch_to {
+                    let mut connections = desktop_connections(&mut client, &mut split);
+                    if let Err(e) = server.connect(&mut connections, &addr) {
+                        eprintln!("{:#}", e);
+                    }
+                }
+                render::set_default_state(&gl);
+
+                if std::mem::take(&mut screenshot) {
+                    save_screenshot(&gl, window_size);
+                }
+                glutin_ctx.swap_buffers().unwrap();
+            }
+            Event::WindowEvent { ref event, .. } => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                // Step down to borderless while another window has focus, rather than leave an
+                // exclusive mode some platforms won't give back, and take it up again on return
+                WindowEvent::Focused(focused) if fullscreen_mode == FullscreenMode::Exclusive => {
+                    let (mode, left) = match *focused {
+                        false => (FullscreenMode::Borderless, true),
+                        true => (FullscreenMode::Exclusive, false),
+                    };
+                    if left != left_exclusive {
+                        left_exclusive = left;
+                        let window = glutin_ctx.window();
+                        window.set_fullscreen(winit_fullscreen(mode, window.current_monitor()));
+                    }
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    });
+}
+
+/// An OpenXR instance with a headset attached, ready for a session
+struct VrSystem {
+    instance: xr::Instance,
+    system: xr::SystemId,
+    extensions: xr::ExtensionSet,
+}
+
+/// Find an OpenXR runtime and a headset, failing with a short explanation of what is missing
+fn probe_vr() -> Result<VrSystem> {
+    let entry = load_openxr()?;
+
+    // Application info
+    let app_info = xr::ApplicationInfo {
+        application_name: "Ugly OpenGL",
+        application_version: 0,
+        engine_name: "Ugly Engine",
+        engine_version: 0,
+    };
+
+    // Ensure we have the OpenGL extension
+    let available_extensions = entry
+        .enumerate_extensions()
+        .map_err(|e| format_err!("Failed to query the OpenXR runtime ({}); is it running?", e))?;
+    if !available_extensions.khr_opengl_enable {
+        bail!("The OpenXR runtime doesn't support OpenGL");
+    }
+
+    // Enable the OpenGL extension, submit depth for reprojection where the runtime takes it and
+    // track bare hands where it can
+    let mut extensions = xr::ExtensionSet::default();
+    extensions.khr_opengl_enable = true;
+    extensions.khr_composition_layer_depth = available_extensions.khr_composition_layer_depth;
+    extensions.ext_hand_tracking = available_extensions.ext_hand_tracking;
+
+    // Create instance
+    let instance = entry
+        .create_instance(&app_info, &extensions, &[])
+        .map_err(|e| match e {
+            xr::sys::Result::ERROR_RUNTIME_UNAVAILABLE => {
+                format_err!("No OpenXR runtime is running")
+            }
+            e => format_err!("Failed to start the OpenXR runtime ({})", e),
+        })?;
+    let instance_props = instance.properties()?;
+    println!(
+        "loaded OpenXR runtime: {} {}",
+        instance_props.runtime_name, instance_props.runtime_version
+    );
+
+    // Get headset system
+    let system = instance
+        .system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
+        .map_err(|e| match e {
+            xr::sys::Result::ERROR_FORM_FACTOR_UNAVAILABLE => {
+                format_err!("No headset is connected")
+            }
+            e => format_err!("No headset available ({})", e),
+        })?;
+
+    Ok(VrSystem {
+        instance,
+        system,
+        extensions,
+    })
+}
+
+/// Load the system's OpenXR loader, or on Windows fall back to the one built in
+fn load_openxr() -> Result<xr::Entry> {
+    match unsafe { xr::Entry::load() } {
+        Ok(entry) => Ok(entry),
+        #[cfg(target_os = "windows")]
+        Err(_) => Ok(xr::Entry::linked()),
+        #[cfg(not(target_os = "windows"))]
+        Err(e) => bail!("No OpenXR loader found ({}); is a runtime installed?", e),
+    }
+}
+
+unsafe fn vr_main(
+    args: Args,
+    vr: VrSystem,
+    mut config: ClientConfig,
+    mut config_file: ConfigFile,
+) -> Result<()> {
+    let VrSystem {
+        instance: xr_instance,
+        system: xr_system,
+        extensions,
+    } = vr;
+
+    // Render in stereo, or in mono on runtimes such as simulators which only offer that
+    let xr_view_configs = xr_instance.enumerate_view_configurations(xr_system)?;
+    let xr_view_type = [
+        xr::ViewConfigurationType::PRIMARY_STEREO,
+        xr::ViewConfigurationType::PRIMARY_MONO,
+    ]
+    .into_iter()
+    .find(|config| xr_view_configs.contains(config))
+    .with_context(|| {
+        format!(
+            "The runtime offers no stereo or mono view configuration, only {:?}",
+            xr_view_configs
+        )
+    })?;
+    println!("View configuration: {:?}", xr_view_type);
+
+    let xr_views = xr_instance.enumerate_view_configuration_views(xr_system, xr_view_type)?;
+
+    // Scale the recommended resolution of each view, within what the runtime supports
+    let view_sizes: Vec<(u32, u32)> = xr_views
+        .iter()
+        .map(|view| scaled_view_size(view, args.vr_render_scale))
+        .collect();
+    for (idx, (width, height)) in view_sizes.iter().enumerate() {
+        println!(
+            "VR view {} at {}x{} (render scale {})",
+            idx, width, height, args.vr_render_scale
+        );
+    }
+
+    // Check what blend mode is valid for this device (opaque vs transparent displays)
+    let xr_environment_blend_mode = choose_blend_mode(
+        args.blend_mode,
+        &xr_instance.enumerate_environment_blend_modes(xr_system, xr_view_type)?,
+    )?;
+    println!("Environment blend mode: {:?}", xr_environment_blend_mode);
+
+    // TODO: Check this???
+    let _xr_opengl_requirements = xr_instance.graphics_requirements::<xr::OpenGL>(xr_system)?;
+
+    // Create window
+    let mut event_loop = glutin::event_loop::EventLoop::new();
+    let window_builder = glutin::window::WindowBuilder::new()
+        .with_title(window_title(&args))
+        .with_inner_size(glutin::dpi::LogicalSize::new(1024.0f32, 768.0));
+
+    let windowed_context = glutin::ContextBuilder::new()
+        .with_gl_debug_flag(args.gl_debug())
+        .build_windowed(window_builder, &event_loop)
+        .unwrap();
+
+    let (ctx, window) = windowed_context.split();
+    let ctx = ctx.make_current().unwrap();
+
+    // Load OpenGL
+    let mut gl = gl::Context::from_loader_function(|s| ctx.get_proc_address(s) as *const _);
+    if args.gl_debug() {
+        render::enable_gl_debug(&mut gl);
+    }
+
+    let session_create_info = glutin_openxr_opengl_helper::session_create_info(&ctx, &window)?;
+
+    // Create vertex array
+    let vertex_array = gl
+        .create_vertex_array()
+        .expect("Cannot create vertex array");
+    gl.bind_vertex_array(Some(vertex_array));
+
+    // Create session
+    let (xr_session, mut xr_frame_waiter, mut xr_frame_stream) =
+        xr_instance.create_session::<xr::OpenGL>(xr_system, &session_create_info)?;
+
+    // Read the controllers through actions, and bare hands if the headset tracks them
+    let hand_tracking =
+        extensions.ext_hand_tracking && xr_instance.supports_hand_tracking(xr_system)?;
+    if hand_tracking {
+        println!("Hand tracking enabled");
+    }
+    let vr_actions = VrActions::new(&xr_instance, &xr_session, hand_tracking)?;
+
+    // Determine swapchain formats
+    let xr_swapchain_formats = xr_session.enumerate_swapchain_formats()?;
+
+    // Alpha blending needs an alpha channel to blend by
+    let color_swapchain_format = choose_color_format(
+        &xr_swapchain_formats,
+        args.vr_format,
+        xr_environment_blend_mode == xr::EnvironmentBlendMode::ALPHA_BLEND,
+    )?;
+    println!(
+        "VR color format: {}",
+        color_format_name(color_swapchain_format)
+    );
+
+    // Depth is only submitted if the runtime supports a depth format we can render to
+    let depth_swapchain_format = VR_DEPTH_SWAPCHAIN_FORMATS
+        .into_iter()
+        .find(|f| xr_swapchain_formats.contains(f))
+        .filter(|_| extensions.khr_composition_layer_depth);
+
+    // Create color swapchain
+    let mut swapchain_color_images = vec![];
+    let mut xr_swapchains = vec![];
+
+    // Set up swapchains and get images
+    for (&xr_view, &(width, height)) in xr_views.iter().zip(&view_sizes) {
+        let image_types = [(), ()];
+
+        let xr_swapchain_create_info = xr::SwapchainCreateInfo::<xr::OpenGL> {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::SAMPLED
+                | xr::SwapchainUsageFlags::COLOR_ATTACHMENT,
+            format: color_swapchain_format,
+            sample_count: xr_view.recommended_swapchain_sample_count,
+            width,
+            height,
+            face_count: 1,
+            array_size: 1,
+            mip_count: 1,
+        };
+
+        let xr_swapchain = xr_session.create_swapchain(&xr_swapchain_create_info)?;
+
+        let color_images: Vec<gl::NativeTexture> = xr_swapchain
+            .enumerate_images()?
+            .into_iter()
+            .map(|tex| {
+                gl::Context::create_texture_from_gl_name(tex)
+            })
+            .collect();
+
+        swapchain_color_images.push(color_images);
+        xr_swapchains.push(xr_swapchain);
+    }
+
+    // Create a depth swapchain for each view, if depth is submitted
+    let mut depth_swapchains = vec![];
+    if let Some(format) = depth_swapchain_format {
+        println!("Submitting depth to the runtime for reprojection");
+        for &(width, height) in &view_sizes {
+            depth_swapchains.push(VrDepthSwapchain::new(&xr_session, format, width, height)?);
+        }
+    }
+    let depth_format = depth_swapchain_format.unwrap_or(VR_DEPTH_FORMAT);
+
+    // Create OpenGL framebuffers
+    let mut gl_framebuffers = vec![];
+    for _ in &xr_views {
+        gl_framebuffers.push(
+            gl.create_framebuffer()
+                .map_err(|s| format_err!("Failed to create framebuffer; {}", s))?,
+        );
+    }
+
+    // Create a depth buffer for each view, unless the views render into depth swapchains
+    let mut depth_buffers = vec![];
+    for &(width, height) in view_sizes.iter().filter(|_| depth_swapchains.is_empty()) {
+        depth_buffers.push(
+            VrDepthBuffer::new(&gl, width as i32, height as i32)
+                .map_err(|s| format_err!("Failed to create depth buffer; {}", s))?,
+        );
+    }
+
+    // Create multisampled render targets, which are resolved into the swapchain images
+    let mut msaa_targets = vec![];
+    if args.vr_msaa > 0 {
+        let samples = i32::from(args.vr_msaa).min(gl.get_parameter_i32(gl::MAX_SAMPLES));
+        println!("Using {}x MSAA for VR views", samples);
+
+        for &(width, height) in &view_sizes {
+            msaa_targets.push(
+                create_msaa_target(
+                    &gl,
+                    samples,
+                    color_swapchain_format,
+                    depth_format,
+                    width as i32,
+                    height as i32,
+                )
+                .map_err(|s| format_err!("Failed to create MSAA target; {}", s))?,
+            );
+        }
+    }
+
+    check_gl_error!(&gl, "VR framebuffer setup");
+
+    // Play on the calibrated floor where the runtime has one, else around the starting head
+    // position with the floor `--floor-offset` below it
+    let (xr_play_space_type, floor_offset) = if xr_session
+        .enumerate_reference_spaces()?
+        .contains(&xr::ReferenceSpaceType::STAGE)
+    {
+        println!("Using the STAGE reference space");
+        (xr::ReferenceSpaceType::STAGE, 0.)
+    } else {
+        println!(
+            "Using the LOCAL reference space, with the floor {} m below the starting head position",
+            args.floor_offset
+        );
+        (xr::ReferenceSpaceType::LOCAL, args.floor_offset)
+    };
+    let xr_play_space =
+        xr_session.create_reference_space(xr_play_space_type, xr::Posef::IDENTITY)?;
+    // Between the eyes, facing forward
+    let xr_view_space =
+        xr_session.create_reference_space(xr::ReferenceSpaceType::VIEW, xr::Posef::IDENTITY)?;
+
+    let mut xr_event_buf = xr::EventDataBuffer::default();
+
+    // The map is kept for aiming laser pointers at
+    let (map_mesh, _, _) = map_model(&args)?;
+    let mut engine = create_engine(&gl, &args, &config, &map_mesh)?;
+    let map_collider = TriangleMesh::new(&map_mesh);
+    drop(map_mesh);
+    engine.set_debug_lines(config.debug_lines, false);
+    engine.set_srgb_target(takes_linear_output(color_swapchain_format));
+
+    // Darken the edges of the view last, after any other effects. At zero strength it never
+    // would.
+    let mut vignette = (!args.no_vignette && args.vignette_strength > 0.).then(Vignette::new);
+    if vignette.is_some() {
+        let mut effects = args.post.clone();
+        effects.push(PostEffect::Vignette);
+        engine.set_post_effects(&gl, &effects);
+    }
+
+    // Let the real world show through where nothing is drawn
+    let background = vr_background(xr_environment_blend_mode);
+    let transparent = background.is_some();
+    if let Some(background) = background {
+        engine.set_background(background);
+        engine.set_fog(engine.background_color(), args.fog);
+    } else {
+        add_sky_dome(&gl, &args, &mut engine)?;
+    }
+
+    let hand_mesh = engine
+        .add_mesh(&gl, &controller(), RenderStyle::Unlit)
+        .context("Failed to upload controller mesh")?;
+
+    let trail_mesh = engine
+        .add_mesh(&gl, &rgb_cube(TRAIL_CUBE_SIZE), RenderStyle::Unlit)
+        .context("Failed to upload trail mesh")?;
+
+    let joint_mesh = engine
+        .add_mesh(&gl, &rgb_cube(JOINT_CUBE_SIZE), RenderStyle::Unlit)
+        .context("Failed to upload hand joint mesh")?;
+
+    let beam_mesh = engine
+        .add_mesh(&gl, &beam(), RenderStyle::Unlit)
+        .context("Failed to upload laser pointer mesh")?;
+    let gizmos = Gizmos::new(&gl, &mut engine)?;
+
+    let mut client = args.connect()?;
+    let server_addr = (!args.offline).then(|| args.addr().to_string());
+    let mut server = ServerSwitch::new(server_addr, args.console)?;
+    let mut stats = StatsOverlay::new(config.stats);
+    let mut dynamic_resolution = (!args.no_dynamic_resolution).then(DynamicResolution::new);
+    let mut smoother = HeadSmoother::new(args.head_smoothing, HEAD_SNAP_DISTANCE);
+    let mut trails = HeadTrails::new(TRAIL_INTERVAL, TRAIL_LENGTH);
+    let start_time = Instant::now();
+    let mut last_update = start_time;
+    let mut audio = start_audio(&args);
+    let mut haptics = (!args.no_haptics).then(|| ProximityHaptics::new(args.haptic_distance));
+    let turn = match args.smooth_turn {
+        Some(speed) => TurnMode::Smooth(speed),
+        None => TurnMode::Snap(args.snap_turn),
+    };
+    // The height calibration is saved whenever it is adjusted
+    let projection = projection_config(&config, engine.reverse_z())?;
+    let mut height_nudge = 0.;
+
+    let mut locomotion =
+        Locomotion::new(args.move_speed, turn, floor_offset + config.height_offset);
+    let mut recenter_hold = HoldButton::default();
+    // Moves of the play space announced by the runtime, with the time they take effect
+    let mut pending_rebases: Vec<(xr::Time, Head)> = vec![];
+    // Our head within the play space, as last located
+    let mut stage_head = Head::default();
+
+    let mut session_state = xr::SessionState::UNKNOWN;
+    // Color images acquired but not yet ready, see `acquire_swapchain_image`
+    let mut color_pending: Vec<Option<u32>> = vec![None; xr_views.len()];
+    // When swapchain images started failing, and in how many frames
+    let mut image_failures: Option<(Instant, u32)> = None;
+    let mut session_running = false;
+    let mut last_paused_send = Instant::now();
+    // Also sent while paused, so that the server doesn't forget us
+    let mut client_state = ClientState {
+        name: args.name.clone(),
+        max_players: Some(args.max_heads as u32),
+        ..ClientState::default()
+    };
+    let mut recenter_key = false;
+    // Pointers last relayed to other players, and when
+    let mut shared_pointers: Pointers = [None; 2];
+    let mut last_pointer_share = Instant::now();
+    let mut remote_pointers = RemotePointers::new(POINTER_EXPIRY);
+
+    let mut mirror_size = window.inner_size();
+    let mut last_mirror = Instant::now();
+    // Closing the mirror window ends the session, asking the runtime once
+    let mut window_closed = false;
+    let mut exit_requested = false;
+
+    'main: loop {
+        // Keep the window responsive
+        event_loop.run_return(|event, _, control_flow| {
+            use glutin::event::{ElementState, Event, KeyboardInput, WindowEvent};
+            *control_flow = glutin::event_loop::ControlFlow::Exit;
+            match event {
+                Event::WindowEvent {
+                    event: WindowEvent::Resized(size),
+                    ..
+                } => {
+                    ctx.resize(size);
+                    mirror_size = size;
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } => window_closed = true,
+                // Keys on the mirror window recenter and calibrate the floor height, for whoever
+                // is watching
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(key),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    let bindings = &config.bindings;
+                    if bindings.recenter.contains(key) {
+                        recenter_key = true;
+                    }
+                    if bindings.raise_floor.contains(key) {
+                        height_nudge += HEIGHT_NUDGE;
+                    }
+                    if bindings.lower_floor.contains(key) {
+                        height_nudge -= HEIGHT_NUDGE;
+                    }
+                }
+                _ => (),
+            }
+        });
+
+        // The runtime winds a running session down through STOPPING and EXITING
+        if window_closed && !exit_requested {
+            if !session_running {
+                break 'main;
+            }
+            xr_session.request_exit()?;
+            exit_requested = true;
+        }
+
+        // Handle OpenXR Events
+        while let Some(event) = xr_instance.poll_event(&mut xr_event_buf)? {
+            match event {
+                xr::Event::InstanceLossPending(_) => {
+                    // Every GL and OpenXR object here belongs to the instance, so there is no
+                    // recovering short of starting over
+                    eprintln!("OpenXR instance is being lost, exiting");
+                    break 'main;
+                }
+                // Keep the world where it was when the runtime moves the play space, such as
+                // after recalibrating the floor. The space itself stays valid and moves at the
+                // change time.
+                xr::Event::ReferenceSpaceChangePending(change)
+                    if change.reference_space_type() == xr_play_space_type
+                        && change.pose_valid() =>
+                {
+                    let old_from_new = head_from_xr_pose(&change.pose_in_previous_space());
+                    pending_rebases.push((change.change_time(), old_from_new));
+                }
+                xr::Event::SessionStateChanged(delta) => {
+                    println!("OpenXR session {:?} -> {:?}", session_state, delta.state());
+                    session_state = delta.state();
+                    match session_state {
+                        // Frames may be submitted from begin until end
+                        xr::SessionState::READY => {
+                            xr_session.begin(xr_view_type)?;
+                            session_running = true;
+                        }
+                        xr::SessionState::STOPPING => {
+                            xr_session.end()?;
+                            session_running = false;
+                        }
+                        xr::SessionState::EXITING => break 'main,
+                        // The session can't be recreated without rebuilding every swapchain and
+                        // GL resource above, so leave that to a restart
+                        xr::SessionState::LOSS_PENDING => {
+                            eprintln!("OpenXR session lost, exiting");
+                            break 'main;
+                        }
+                        // IDLE, SYNCHRONIZED, VISIBLE and FOCUSED need nothing from us
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        // Start afresh with the heads of a new server
+        if server.update(&mut [&mut client]) {
+            window.set_title(&status_title(&args.name, &server.status()));
+            trails.clear();
+            remote_pointers.clear();
+        }
+
+        // Until the runtime lets us run, stay connected without spinning
+        if !session_running {
+            if shared_pointers.iter().any(Option::is_some) {
+                shared_pointers = [None; 2];
+                client_state
+                    .app_data
+                    .push(pointer_app_data(&shared_pointers));
+            }
+            if let Err(e) = client.update_heads().map(drop) {
+                server.connection_lost(&mut [&mut client], e);
+            }
+            // Pointers aren't drawn while paused
+            client.take_app_data();
+            if last_paused_send.elapsed() >= PAUSED_SEND_INTERVAL {
+                if let Err(e) = client.send_state(&mut client_state) {
+                    server.connection_lost(&mut [&mut client], e);
+                }
+                last_paused_send = Instant::now();
+            }
+            std::thread::sleep(PAUSED_POLL_INTERVAL);
+            continue;
+        }
+
+        // --- Wait for our turn to do head-pose dependent computation and render a frame
+        let xr_frame_state = xr_frame_waiter.wait()?;
+        let frame_start = Instant::now();
+        let period = xr_frame_state.predicted_display_period.as_nanos();
+        stats.vr_frames().display_time(
+            frame_start,
+            xr_frame_state.predicted_display_time.as_nanos(),
+            period,
+        );
+
+        // Signal to OpenXR that we are beginning graphics work
+        xr_frame_stream.begin()?;
+
+        // Early exit
+        if !xr_frame_state.should_render {
+            stats.vr_frames().not_rendered(frame_start);
+            xr_frame_stream.end(
+                xr_frame_state.predicted_display_time,
+                xr_environment_blend_mode,
+                &[],
+            )?;
+            continue;
+        }
+
+        // Get head positions from server
+        let mut costs = VrFrameCosts::default();
+        if let Err(e) = client.update_heads().map(drop) {
+            server.connection_lost(&mut [&mut client], e);
+        }
+        let app_data = client.take_app_data();
+        let state = client.state();
+
+        // Smooth heads over the real time since the last frame
+        let now = Instant::now();
+        let dt = (now - last_update).as_secs_f32();
+        last_update = now;
+        engine.set_time((now - start_time).as_secs_f32());
+        let alphas: Vec<f32> = state.players.iter().map(stale_alpha).collect();
+        remote_pointers.update(&state.players, &app_data, now);
+        let mut players = state.players.clone();
+        smoother.apply(&mut players, dt);
+        costs.network = ms_since(frame_start);
+
+        // Read the controllers, and find the head for everything but the view matrices, which
+        // come from poses located right before rendering
+        let time = xr_frame_state.predicted_display_time;
+        // Poses located from now on are in the moved play space
+        while let Some(&(change_time, old_from_new)) = pending_rebases.first() {
+            if change_time.as_nanos() > time.as_nanos() {
+                break;
+            }
+            locomotion.rebase(&old_from_new);
+            pending_rebases.remove(0);
+        }
+        let vr_input = vr_actions.update(&xr_session, &xr_play_space, time)?;
+        if let Some(head) = vr_input::locate(&xr_view_space, &xr_play_space, time)? {
+            stage_head = head;
+        }
+
+        // Move the play space with the thumbsticks, then bring our own poses into the world
+        if !args.no_locomotion {
+            locomotion.update(&vr_input, &stage_head, dt);
+        }
+        if let Some(vignette) = &mut vignette {
+            let (speed, turn_rate) = match args.no_locomotion {
+                true => (0., 0.),
+                false => locomotion.motion(),
+            };
+            let target = comfort::target_radius(speed, turn_rate, args.vignette_strength);
+            vignette.update(target, dt);
+            engine.set_vignette_radius(vignette.radius());
+        }
+
+        if height_nudge != 0. {
+            locomotion.raise(height_nudge);
+            config.height_offset += height_nudge;
+            height_nudge = 0.;
+            println!("Height offset {:.2} m", config.height_offset);
+            let height_offset = config.height_offset;
+            if let Err(e) = config_file.save(|file| file.height_offset = height_offset) {
+                eprintln!("Failed to save the height offset; {:#}", e);
+            }
+        }
+
+        // Holding either menu button recenters the view
+        let menu_held = vr_input.hands.iter().any(|hand| hand.menu);
+        if recenter_hold.update(now, menu_held, RECENTER_HOLD) || recenter_key {
+            locomotion.recenter(&stage_head);
+            recenter_key = false;
+        }
+        let world_from_stage = locomotion.world_from_stage();
+        let head = world_from_stage.transform(&stage_head);
+        let vr_input = vr_input.transformed(&world_from_stage);
+
+        // Our own hands are drawn from these poses rather than the server's copy so that they
+        // don't lag behind
+        let local_hands: Hands = vr_input.hand_poses();
+        engine.set_instances(&gl, joint_mesh, &joint_instances(&vr_input));
+
+        // Aim a laser from each controller, and relay those with the primary button held to
+        // everyone else. Their endpoints are only refreshed every `POINTER_SHARE_INTERVAL`, but
+        // they appear and disappear straight away.
+        let local_pointers: Pointers = [0, 1].map(|i| {
+            let aim = vr_input.hands[i].aim?;
+            Some(cast_pointer(
+                &aim,
+                &map_collider,
+                &players,
+                engine.head_bounds(),
+            ))
+        });
+        let shared: Pointers =
+            [0, 1].map(|i| local_pointers[i].filter(|_| vr_input.hands[i].primary));
+        let toggled =
+            (shared.iter().zip(&shared_pointers)).any(|(a, b)| a.is_some() != b.is_some());
+        let pointing = shared.iter().any(Option::is_some);
+        if toggled || (pointing && now - last_pointer_share >= POINTER_SHARE_INTERVAL) {
+            client_state.app_data.push(pointer_app_data(&shared));
+            shared_pointers = shared;
+            last_pointer_share = now;
+        }
+        let beams = pointer_instances(&local_pointers, &players, &remote_pointers, &alphas);
+        engine.set_instances(&gl, beam_mesh, &beams);
+        let markers = pointer_markers(&local_pointers, &players, &remote_pointers, &alphas);
+        engine.set_billboards(&gl, &markers);
+        stats.set_vr_input(Some(vr_input));
+
+        let hands = hand_instances(&local_hands, &players, &alphas);
+        engine.set_instances(&gl, hand_mesh, &hands);
+        if config.trails {
+            trails.update(&players, dt);
+            engine.set_instances(&gl, trail_mesh, &trail_instances(&trails));
+        }
+        let labels = nametags(&players);
+
+        if config.debug_lines {
+            engine.clear_debug_lines();
+            draw_debug_scene(&mut engine, &players);
+        }
+        gizmos.set(&gl, &mut engine, config.debug_lines, &players);
+
+        // Heads are drawn in less detail by distance from our head
+        let heads = head_instances(&players, &alphas, None);
+        let heads_start = Instant::now();
+        let cull = CullCamera {
+            eye: head.pos,
+            view_proj: None,
+        };
+        stats.set_heads(engine.update_heads(&gl, &heads, &cull));
+        costs.heads = ms_since(heads_start);
+
+        // Listen from between the eyes
+        if let Some(audio) = &mut audio {
+            audio.update(&head, &audio_sources(&players));
+        }
+
+        // Buzz the controllers when someone comes close to our head or hands
+        if let Some(haptics) = &mut haptics {
+            let remote: Vec<Head> = players.iter().map(|player| player.head).collect();
+            let pulses = haptics.update(Instant::now(), &head, &local_hands, &remote);
+            for (hand, amplitude) in pulses.into_iter().enumerate() {
+                if let Some(amplitude) = amplitude {
+                    vr_actions.vibrate(&xr_session, hand, amplitude, haptics::PULSE_DURATION)?;
+                }
+            }
+        }
+        stats.set_gpu(engine.gpu_timings());
+        stats.set_render(engine.last_frame_stats());
+
+        // Render into the bottom left of each swapchain image, only part of it while frames run
+        // over budget
+        let render_scale = dynamic_resolution.as_ref().map(|dynamic| dynamic.scale());
+        let render_sizes: Vec<(i32, i32)> = view_sizes
+            .iter()
+            .map(|&(width, height)| {
+                let scale = render_scale.unwrap_or(1.);
+                (scaled_extent(width, scale), scaled_extent(height, scale))
+            })
+            .collect();
+        let pixels = render_sizes.iter().map(|&(w, h)| w as u64 * h as u64).sum();
+        stats.set_pixels(Some(pixels));
+        stats.set_render_scale(render_scale);
+        stats.frame();
+
+        // Locate the eyes as late as possible, so that the prediction is as fresh as it can be
+        let (_xr_view_state_flags, xr_view_poses) =
+            xr_session.locate_views(xr_view_type, time, &xr_play_space)?;
+        let eyes: Vec<Head> = xr_view_poses
+            .iter()
+            .map(|view| world_from_stage.transform(&head_from_xr_pose(&view.pose)))
+            .collect();
+
+        // Billboards face between the eyes, so that both eyes see them turned the same way
+        engine.set_billboard_facing(Some(mid_eye(&eyes).orient));
+
+        // Both eyes share one shadow map
+        let shadows_start = Instant::now();
+        engine.render_shadows(&gl);
+        costs.shadows = ms_since(shadows_start);
+
+        // Mirror at a lower rate, to leave the headset most of the GPU time
+        let mirror = !args.no_mirror && last_mirror.elapsed() >= MIRROR_INTERVAL;
+
+        // Images which aren't ready in time or fail while the runtime hiccups are skipped, and
+        // the frame goes out without layers
+        let image_timeout = xr::Duration::from_nanos(
+            xr_frame_state.predicted_display_period.as_nanos() * IMAGE_TIMEOUT_PERIODS,
+        );
+        let mut image_error = None;
+        let mut views_rendered = 0;
+
+        for view_idx in 0..xr_views.len() {
+            let eye_start = Instant::now();
+
+            // Acquire images
+            let acquired = acquire_view_images(
+                &mut xr_swapchains[view_idx],
+                &mut color_pending[view_idx],
+                depth_swapchains.get_mut(view_idx),
+                image_timeout,
+            );
+            let (xr_swapchain_img_idx, depth_texture) = match acquired {
+                Ok(Some(images)) => images,
+                Ok(None) => {
+                    image_error.get_or_insert((view_idx, xr::sys::Result::TIMEOUT_EXPIRED));
+                    continue;
+                }
+                Err(e) => {
+                    image_error.get_or_insert((view_idx, e));
+                    continue;
+                }
+            };
+
+            // Bind framebuffer
+            gl.bind_framebuffer(gl::FRAMEBUFFER, Some(gl_framebuffers[view_idx]));
+
+            // Set scissor and viewport
+            let (w, h) = render_sizes[view_idx];
+            gl.viewport(0, 0, w, h);
+            gl.scissor(0, 0, w, h);
+
+            // Set the texture as the render target
+            let img_idx = xr_swapchain_img_idx as usize;
+            let color_texture = swapchain_color_images[view_idx][img_idx];
+
+            gl.framebuffer_texture_2d(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                Some(color_texture),
+                0,
+            );
+
+            // Render depth into the depth swapchain image, or else our own depth buffer
+            match depth_texture {
+                Some(depth_texture) => {
+                    gl.framebuffer_texture_2d(
+                        gl::FRAMEBUFFER,
+                        gl::DEPTH_ATTACHMENT,
+                        gl::TEXTURE_2D,
+                        Some(depth_texture),
+                        0,
+                    );
+                }
+                None => {
+                    // Match the depth buffer to the size of the swapchain images, so that it
+                    // isn't reallocated whenever the render scale changes
+                    let (full_w, full_h) = view_sizes[view_idx];
+                    let depth_buffer = &mut depth_buffers[view_idx];
+                    depth_buffer
+                        .resize(&gl, full_w as i32, full_h as i32)
+                        .map_err(|s| format_err!("Failed to resize depth buffer; {}", s))?;
+                    gl.framebuffer_renderbuffer(
+                        gl::FRAMEBUFFER,
+                        gl::DEPTH_ATTACHMENT,
+                        gl::RENDERBUFFER,
+                        Some(depth_buffer.renderbuffer),
+                    );
+                }
+            }
+
+            // Set view and projection matrices. The compositor still gets the poses within the
+            // play space, below.
+            let headset_view = xr_view_poses[view_idx];
+
+            let view = view_from_head(&eyes[view_idx]);
+            let proj = projection_from_fov(&headset_view.fov, &projection);
+
+            // Render into the multisampled target instead, if any
+            let msaa_target = msaa_targets.get(view_idx);
+            if let Some(target) = msaa_target {
+                gl.bind_framebuffer(gl::FRAMEBUFFER, Some(target.framebuffer));
+            }
+
+            engine.frame(&gl, proj, view).expect("Engine error");
+            engine.draw_labels(&gl, &labels, proj, view);
+
+            // Keep the overlay away from the edges of the lenses
+            let margin = w.min(h) as f32 * 0.3;
+            engine.draw_stats(&gl, &stats, (w as u32, h as u32), margin);
+
+            // Resolve into the swapchain images. Depth is only needed if it is submitted.
+            if let Some(target) = msaa_target {
+                let mask = match depth_swapchains.is_empty() {
+                    true => gl::COLOR_BUFFER_BIT,
+                    false => gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT,
+                };
+                gl.bind_framebuffer(gl::READ_FRAMEBUFFER, Some(target.framebuffer));
+                gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, Some(gl_framebuffers[view_idx]));
+                gl.blit_framebuffer(0, 0, w, h, 0, 0, w, h, mask, gl::NEAREST);
+            }
+
+            // Copy the left eye to the window while we still own the image
+            if mirror && view_idx == 0 {
+                blit_mirror(&gl, gl_framebuffers[view_idx], (w, h), mirror_size);
+            }
+
+            // Unbind framebuffer
+            gl.bind_framebuffer(gl::FRAMEBUFFER, None);
+
+            // Release images
+            let released = match depth_swapchains.get_mut(view_idx) {
+                Some(depth) => xr_swapchains[view_idx]
+                    .release_image()
+                    .and(depth.swapchain.release_image()),
+                None => xr_swapchains[view_idx].release_image(),
+            };
+            match released {
+                Ok(()) => views_rendered += 1,
+                Err(e) => {
+                    image_error.get_or_insert((view_idx, e));
+                }
+            }
+            if let Some(cost) = costs.eyes.get_mut(view_idx) {
+                *cost = ms_since(eye_start);
+            }
+        }
+        engine.end_frame(&gl);
+
+        // Let the session state machine deal with a lost session
+        if let Some((_, xr::sys::Result::ERROR_SESSION_LOST)) = image_error {
+            eprintln!("OpenXR session lost while rendering");
+            session_running = false;
+            continue;
+        }
+
+        // Put up with failures for a while, as the runtime may be restarting
+        match (image_error, &mut image_failures) {
+            (Some((view_idx, e)), None) => {
+                eprintln!("Skipping frames; view {} swapchain image: {}", view_idx, e);
+                image_failures = Some((Instant::now(), 1));
+            }
+            (Some((view_idx, e)), Some((since, count))) => {
+                *count += 1;
+                if since.elapsed() >= IMAGE_FAILURE_LIMIT {
+                    bail!(
+                        "Swapchain images failed for {} frames; view {}: {}",
+                        count,
+                        view_idx,
+                        e
+                    );
+                }
+            }
+            (None, Some((_, count))) => {
+                println!("Swapchain images recovered after {} failed frames", count);
+                image_failures = None;
+            }
+            (None, None) => (),
+        }
+
+        // Submit the views only if every one of them was rendered
+        if views_rendered == xr_views.len() {
+            // Describe the depth of each view. These are chained to the projection views by
+            // pointer, so they must outlive the end of the frame. The compositor samples only the
+            // part of each image rendered to.
+            let image_rects: Vec<xr::Rect2Di> = render_sizes
+                .iter()
+                .map(|&(width, height)| xr::Rect2Di {
+                    offset: xr::Offset2Di { x: 0, y: 0 },
+                    extent: xr::Extent2Di { width, height },
+                })
+                .collect();
+            let (min_depth, max_depth, near_z, far_z) = vr_depth_range(&projection);
+            let depth_infos: Vec<xr::sys::CompositionLayerDepthInfoKHR> = depth_swapchains
+                .iter()
+                .zip(&image_rects)
+                .map(
+                    |(depth, &image_rect)| xr::sys::CompositionLayerDepthInfoKHR {
+                        ty: xr::sys::CompositionLayerDepthInfoKHR::TYPE,
+                        next: std::ptr::null(),
+                        sub_image: xr::sys::SwapchainSubImage {
+                            swapchain: depth.swapchain.as_raw(),
+                            image_rect,
+                            image_array_index: 0,
+                        },
+                        min_depth,
+                        max_depth,
+                        near_z,
+                        far_z,
+                    },
+                )
+                .collect();
+
+            // Set up projection views
+            let mut xr_projection_views = vec![];
+            for view_idx in 0..xr_views.len() {
+                // Set up projection view
+                let xr_sub_image = xr::SwapchainSubImage::<xr::OpenGL>::new()
+                    .swapchain(&xr_swapchains[view_idx])
+                    .image_array_index(0)
+                    .image_rect(image_rects[view_idx]);
+
+                let xr_proj_view = xr::CompositionLayerProjectionView::<xr::OpenGL>::new()
+                    .pose(xr_view_poses[view_idx].pose)
+                    .fov(xr_view_poses[view_idx].fov)
+                    .sub_image(xr_sub_image);
+
+                // The safe wrapper has no way to chain structures, so attach depth to the raw one
+                let xr_proj_view = match depth_infos.get(view_idx) {
+                    Some(depth_info) => {
+                        let mut raw = xr_proj_view.into_raw();
+                        raw.next = depth_info as *const _ as *const std::ffi::c_void;
+                        xr::CompositionLayerProjectionView::from_raw(raw)
+                    }
+                    None => xr_proj_view,
+                };
+
+                xr_projection_views.push(xr_proj_view);
+            }
+
+            // Colors are premultiplied by alpha, as the clear color is transparent black
+            let layer_flags = match transparent {
+                true => xr::CompositionLayerFlags::BLEND_TEXTURE_SOURCE_ALPHA,
+                false => xr::CompositionLayerFlags::EMPTY,
+            };
+
+            let layers = xr::CompositionLayerProjection::new()
+                .layer_flags(layer_flags)
+                .space(&xr_play_space)
+                .views(&xr_projection_views);
+
+            xr_frame_stream.end(
+                xr_frame_state.predicted_display_time,
+                xr_environment_blend_mode,
+                &[&layers],
+            )?;
+        } else {
+            xr_frame_stream.end(
+                xr_frame_state.predicted_display_time,
+                xr_environment_blend_mode,
+                &[],
+            )?;
+        }
+
+        costs.total = ms_since(frame_start);
+        stats
+            .vr_frames()
+            .frame(Instant::now(), costs, period as f32 / 1e6);
+
+        // The GPU may be the bottleneck even when the CPU is done in time
+        if let Some(dynamic) = &mut dynamic_resolution {
+            let gpu = engine.gpu_timings().map_or(0., |gpu| gpu.total());
+            dynamic.frame(costs.total.max(gpu), period as f32 / 1e6);
+        }
+
+        if mirror {
+            ctx.swap_buffers()?;
+            last_mirror = Instant::now();
+        }
+
+        // Update head position in server. This is done after all the display work, so that we
+        // don't introduce latency, with the head located again for the freshest prediction
+        stage_head = match vr_input::locate(&xr_view_space, &xr_play_space, time)? {
+            Some(head) => head,
+            None => {
+                let eyes: Vec<Head> = xr_view_poses
+                    .iter()
+                    .map(|view| head_from_xr_pose(&view.pose))
+                    .collect();
+                mid_eye(&eyes)
+            }
+        };
+        client_state.head = locomotion.world_from_stage().transform(&stage_head);
+        client_state.hands = local_hands;
+        if let Err(e) = client.send_state(&mut client_state) {
+            server.connection_lost(&mut [&mut client], e);
+        }
+        stats.set_net(client.stats());
+    }
+
+    // Clean up GL objects
+    for depth_buffer in depth_buffers {
+        depth_buffer.delete(&gl);
+    }
+    for framebuffer in gl_framebuffers {
+        gl.delete_framebuffer(framebuffer);
+    }
+
+    Ok(())
+}
+
+/// Compiles (*_SHADER, <source>) into a shader program for OpenGL
+fn compile_glsl_program(gl: &gl::Context, sources: &[(u32, &str)]) -> Result<gl::Program> {
+    // Compile default shaders
+    unsafe {
+        let program = gl
+            .create_program()
+            .map_err(anyhow::Error::msg)
+            .context("Cannot create program")?;
+
+        let mut shaders = vec![];
+
+        for (stage, shader_source) in sources {
+            let shader = gl
+                .create_shader(*stage)
+                .map_err(anyhow::Error::msg)
+                .context("Cannot create shader")?;
+
+            gl.shader_source(shader, shader_source);
+
+            gl.compile_shader(shader);
+
+            if !gl.get_shader_compile_status(shader) {
+                bail!(
+                    "Failed to compile shader;\n{}",
+                    gl.get_shader_info_log(shader)
+                );
+            }
+
+            gl.attach_shader(program, shader);
+
+            shaders.push(shader);
+        }
+
+        gl.link_program(program);
+
+        if !gl.get_program_link_status(program) {
             bail!("{}", gl.get_program_info_log(program));
         }
 
-        for shader in shaders {
-            gl.detach_shader(program, shader);
-            gl.delete_shader(shader);
+        for shader in shaders {
+            gl.detach_shader(program, shader);
+            gl.delete_shader(shader);
+        }
+
+        Ok(program)
+    }
+}
+
+/*
+ * According to their respective specifications, the
+ * OpenXR and OpenGL APIs both use a **Right Handed** coordinate system.
+ */
+
+/// Creates a projection matrix for the given fov, with the clip planes and depth mapping of
+/// `projection`
+pub fn projection_from_fov(fov: &xr::Fovf, projection: &ProjectionConfig) -> Matrix4<f32> {
+    projection.frustum(
+        fov.angle_left.tan(),
+        fov.angle_right.tan(),
+        fov.angle_down.tan(),
+        fov.angle_up.tan(),
+    )
+}
+
+/// Creates a view matrix for the given head pose
+pub fn view_from_head(head: &Head) -> Matrix4<f32> {
+    // Invert this quaternion, orienting the world into NDC space
+    let orient_inv = head.orient.inverse();
+
+    // Represent the rotation in homogeneous coordinates
+    let rotation = orient_inv.to_homogeneous();
+
+    // Invert this translation, translating the world into NDC space
+    let trans = Matrix4::new_translation(&-head.pos.coords);
+
+    rotation * trans
+}
+
+/// Milliseconds elapsed since `start`
+fn ms_since(start: Instant) -> f32 {
+    start.elapsed().as_secs_f32() * 1e3
+}
+
+/// A small cube at each tracked joint of our own bare hands
+fn joint_instances(input: &VrInput) -> Vec<Instance> {
+    input
+        .hands
+        .iter()
+        .filter_map(|hand| hand.tracked.as_ref())
+        .flat_map(|tracked| tracked.joints.iter().flatten())
+        .map(|joint| Instance::new(joint.matrix()))
+        .collect()
+}
+
+/// Controller instances for our own hands and those of other players, faded like their heads.
+/// The server never sends us our own state, so local hands are not drawn twice.
+fn hand_instances(local: &Hands, players: &[Player], alphas: &[f32]) -> Vec<Instance> {
+    let remote = players
+        .iter()
+        .zip(alphas)
+        .flat_map(|(player, &alpha)| player.hands.iter().flatten().map(move |hand| (hand, alpha)));
+
+    local
+        .iter()
+        .flatten()
+        .map(|hand| (hand, 1.))
+        .chain(remote)
+        .map(|(hand, alpha)| Instance {
+            tint: [1., 1., 1., alpha],
+            ..Instance::new(hand.matrix())
+        })
+        .collect()
+}
+
+/// Opacity of a player, fading out those the server hasn't heard from in a while
+fn stale_alpha(player: &Player) -> f32 {
+    let silent = Duration::from_millis(player.since_update.into());
+    let fade = silent.saturating_sub(STALE_AFTER).as_secs_f32() / STALE_FADE.as_secs_f32();
+    1. - (1. - STALE_ALPHA) * fade.min(1.)
+}
+
+/// The desktop client's connections to the server: its own, and the second view's with `--split`
+fn desktop_connections<'a>(
+    client: &'a mut Connection,
+    split: &'a mut Option<SplitView>,
+) -> Vec<&'a mut Connection> {
+    let mut connections = vec![client];
+    if let Some(split) = split {
+        connections.push(&mut split.client);
+    }
+    connections
+}
+
+/// Second player of `--split`, seen on the right half of the window, with its own connection
+/// and fly camera so that each half sees the other's head come back from the server
+struct SplitView {
+    client: Connection,
+    state: ClientState,
+    camera: FlyCam,
+    bindings: Bindings,
+    smoother: HeadSmoother,
+    players: Vec<Player>,
+    alphas: Vec<f32>,
+    pointers: RemotePointers,
+    /// Right half of the window, None while minimized
+    viewport: Option<[i32; 4]>,
+}
+
+impl SplitView {
+    fn new(args: &Args, map_bounds: &[Bounds], map_spawn: Point3<f32>) -> Result<Self> {
+        let client = args
+            .connect()
+            .context("Failed to connect the second view")?;
+        // Start beside the first player, facing the same way
+        let spawn = clear_spawn(map_bounds, map_spawn + Vector3::x(), SPAWN_CLEARANCE);
+        let camera = FlyCam::new(spawn + Vector3::y() * args.eye_height);
+        let state = ClientState {
+            head: camera.head(),
+            hands: [None; 2],
+            app_data: vec![],
+            name: format!("{} 2", args.name),
+            ping: 0,
+            max_players: Some(args.max_heads as u32),
+        };
+
+        Ok(Self {
+            client,
+            state,
+            camera,
+            bindings: Bindings::split_view(),
+            smoother: HeadSmoother::new(args.head_smoothing, HEAD_SNAP_DISTANCE),
+            players: vec![],
+            alphas: vec![],
+            pointers: RemotePointers::new(POINTER_EXPIRY),
+            viewport: None,
+        })
+    }
+
+    /// Split a viewport in two side by side, keeping the right half and returning the left.
+    /// Both halves are the same size, leaving out the last column of an odd width, so that they
+    /// can share render targets.
+    fn divide(&mut self, viewport: Option<[i32; 4]>) -> Option<[i32; 4]> {
+        let [x, y, w, h] = viewport?;
+        let half = w / 2;
+        self.viewport = (half > 0).then(|| [x + half, y, half, h]);
+        Some([x, y, half.max(1), h])
+    }
+
+    /// Whether the cursor is over the right half
+    fn under_cursor(&self, wih: &WinitInputHelper) -> bool {
+        match (self.viewport, wih.mouse()) {
+            (Some([x, ..]), Some((cursor_x, _))) => cursor_x >= x as f32,
+            _ => false,
+        }
+    }
+
+    /// How the left half looks around, only dragging while the cursor is over it
+    fn left_look(&self, wih: &WinitInputHelper, look: camera::LookConfig) -> camera::LookConfig {
+        match self.under_cursor(wih) {
+            true => camera::LookConfig {
+                sensitivity: 0.,
+                ..look
+            },
+            false => look,
+        }
+    }
+
+    /// Fly by the split view keys, looking around by dragging over the right half
+    fn update(
+        &mut self,
+        wih: &WinitInputHelper,
+        dt: f32,
+        look: camera::LookConfig,
+        smoothing: camera::FlySmoothing,
+    ) {
+        let look = match self.under_cursor(wih) {
+            true => look,
+            false => camera::LookConfig {
+                sensitivity: 0.,
+                ..look
+            },
+        };
+        self.camera.update(wih, dt, look, smoothing, &self.bindings);
+    }
+
+    fn send(&mut self) -> Result<()> {
+        self.state.head = self.camera.head();
+        self.client.send_state(&mut self.state)
+    }
+
+    /// Take in the latest heads from the server, smoothing them over `dt` seconds
+    fn receive(&mut self, smoothing: f32, dt: f32) -> Result<()> {
+        self.client.update_heads()?;
+        let app_data = self.client.take_app_data();
+        let state = self.client.state();
+        self.alphas = state.players.iter().map(stale_alpha).collect();
+        self.pointers
+            .update(&state.players, &app_data, Instant::now());
+        self.players.clone_from(&state.players);
+        self.smoother.time_constant = smoothing;
+        self.smoother.apply(&mut self.players, dt);
+        Ok(())
+    }
+
+    /// Draw the right half. This replaces the heads, hands and pointers of the engine, which the
+    /// left half sets again before its next frame.
+    unsafe fn draw(
+        &self,
+        gl: &gl::Context,
+        engine: &mut render::Engine,
+        perspective: &Perspective,
+        hand_mesh: render::MeshHandle,
+        beam_mesh: render::MeshHandle,
+    ) {
+        let [x, y, w, h] = match self.viewport {
+            Some(viewport) => viewport,
+            None => return,
+        };
+        let proj = perspective.matrix(w as f32, h as f32);
+        let head = self.camera.head();
+        let view = view_from_head(&head);
+        let cull = CullCamera {
+            eye: head.pos,
+            view_proj: Some(proj * view),
+        };
+
+        let (players, pointers, alphas) = (&self.players, &self.pointers, &self.alphas);
+        engine.update_heads(gl, &head_instances(players, alphas, None), &cull);
+        engine.set_instances(gl, hand_mesh, &hand_instances(&[None; 2], players, alphas));
+        engine.set_instances(
+            gl,
+            beam_mesh,
+            &pointer_instances(&[None; 2], players, pointers, alphas),
+        );
+        engine.set_billboards(gl, &pointer_markers(&[None; 2], players, pointers, alphas));
+
+        // The scissor box keeps the clear to this half
+        gl.viewport(x, y, w, h);
+        gl.enable(gl::SCISSOR_TEST);
+        gl.scissor(x, y, w, h);
+        engine.frame(gl, proj, view).expect("Engine error");
+        engine.draw_labels(gl, &nametags(players), proj, view);
+    }
+}
+
+/// Head instances with the given opacities, highlighting the selected player
+fn head_instances(players: &[Player], alphas: &[f32], selected: Option<PlayerId>) -> Vec<Instance> {
+    players
+        .iter()
+        .zip(alphas)
+        .map(|(player, &alpha)| {
+            let [r, g, b] = match Some(player.id) == selected {
+                true => SELECTED_TINT,
+                false => [1.; 3],
+            };
+            Instance {
+                tint: [r, g, b, alpha],
+                phase: idle_phase(player.id),
+                ..Instance::new(player.head.matrix())
+            }
+        })
+        .collect()
+}
+
+/// Start playing sound cues if asked to, carrying on silently if that fails
+fn start_audio(args: &Args) -> Option<AudioEngine> {
+    if !args.audio {
+        return None;
+    }
+    AudioEngine::new().map_err(|e| eprintln!("{:#}", e)).ok()
+}
+
+/// Where each player's sounds come from
+fn audio_sources(players: &[Player]) -> Vec<(PlayerId, Head)> {
+    players
+        .iter()
+        .map(|player| (player.id, player.head))
+        .collect()
+}
+
+/// Phase of a player's idle animation, spread out so that consecutive ids don't move together
+fn idle_phase(id: PlayerId) -> f32 {
+    golden_fraction(id)
+}
+
+/// A fraction in 0..1 for each id, stepping by the golden ratio so that neighboring ids are far
+/// apart
+fn golden_fraction(id: PlayerId) -> f32 {
+    (id as f32 * 0.618_034).fract()
+}
+
+/// The player whose head the ray hits first, if any
+fn pick_head(
+    ray: &picking::Ray,
+    players: &[Player],
+    bounds: (Point3<f32>, Point3<f32>),
+) -> Option<PlayerId> {
+    head_hit(ray, players, bounds).map(|(_, id)| id)
+}
+
+/// Distance along the ray to the first head it hits, and whose it is. Heads are tested against
+/// a bounding sphere first, then against their bounding box.
+fn head_hit(
+    ray: &picking::Ray,
+    players: &[Player],
+    bounds: (Point3<f32>, Point3<f32>),
+) -> Option<(f32, PlayerId)> {
+    let (min, max) = bounds;
+    let radius = min.coords.abs().sup(&max.coords.abs()).norm();
+
+    players
+        .iter()
+        .filter_map(|player| {
+            picking::ray_sphere(ray, player.head.pos, radius)?;
+            let distance = picking::ray_box(ray, &player.head.matrix(), bounds)?;
+            Some((distance, player.id))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Laser from a controller's aim pose along its -Z, stopping at the map or a remote head, or
+/// after `POINTER_RANGE` if it misses
+fn cast_pointer(
+    aim: &Head,
+    map: &TriangleMesh,
+    players: &[Player],
+    head_bounds: (Point3<f32>, Point3<f32>),
+) -> Pointer {
+    let ray = picking::Ray {
+        origin: aim.pos,
+        dir: aim.orient * -Vector3::z(),
+    };
+    let head = head_hit(&ray, players, head_bounds).map(|(distance, _)| distance);
+    let hit = (map.cast(&ray).into_iter().chain(head))
+        .reduce(f32::min)
+        .filter(|&distance| distance <= POINTER_RANGE);
+
+    Pointer {
+        from: ray.origin,
+        to: ray.origin + ray.dir * hit.unwrap_or(POINTER_RANGE),
+        hit: hit.is_some(),
+    }
+}
+
+/// Our own laser pointers and those other players are showing us, with their colors, faded like
+/// the players' heads
+fn pointers<'a>(
+    local: &'a Pointers,
+    players: &'a [Player],
+    remote: &'a RemotePointers,
+    alphas: &'a [f32],
+) -> impl Iterator<Item = (Pointer, [f32; 4])> + 'a {
+    let remote = players
+        .iter()
+        .zip(alphas)
+        .flat_map(move |(player, &alpha)| {
+            let [r, g, b, _] = player_color(player.id);
+            remote
+                .get(player.id)
+                .into_iter()
+                .flatten()
+                .map(move |pointer| (pointer, [r, g, b, alpha]))
+        });
+    local
+        .iter()
+        .flatten()
+        .map(|&pointer| (pointer, POINTER_COLOR))
+        .chain(remote)
+}
+
+/// Beams stretched along each laser pointer
+fn pointer_instances(
+    local: &Pointers,
+    players: &[Player],
+    remote: &RemotePointers,
+    alphas: &[f32],
+) -> Vec<Instance> {
+    pointers(local, players, remote, alphas)
+        .map(|(pointer, tint)| {
+            let along = pointer.to - pointer.from;
+            // Only a beam pointing straight along +Z has no shortest rotation
+            let rotation = UnitQuaternion::rotation_between(&-Vector3::z(), &along)
+                .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::y_axis(), PI));
+            let transform = Matrix4::new_translation(&pointer.from.coords)
+                * rotation.to_homogeneous()
+                * Matrix4::new_nonuniform_scaling(&Vector3::new(1., 1., along.norm()));
+            Instance {
+                tint,
+                ..Instance::new(transform)
+            }
+        })
+        .collect()
+}
+
+/// A marker where each laser pointer hits something
+fn pointer_markers(
+    local: &Pointers,
+    players: &[Player],
+    remote: &RemotePointers,
+    alphas: &[f32],
+) -> Vec<Billboard> {
+    pointers(local, players, remote, alphas)
+        .filter(|(pointer, _)| pointer.hit)
+        .map(|(pointer, color)| Billboard::new(pointer.to, POINTER_MARKER_SIZE, color))
+        .collect()
+}
+
+/// Small cubes along the trail behind each head, fading with age
+fn trail_instances(trails: &HeadTrails) -> Vec<Instance> {
+    trails
+        .samples()
+        .map(|(pos, alpha)| Instance {
+            tint: [1., 1., 1., alpha * TRAIL_ALPHA],
+            ..Instance::new(Matrix4::new_translation(&pos.coords))
+        })
+        .collect()
+}
+
+/// Axes at the world origin and on each head, with a cross on the ground at the origin, shown
+/// along with the debug lines
+struct Gizmos {
+    axes: render::MeshHandle,
+    marker: render::MeshHandle,
+}
+
+impl Gizmos {
+    fn new(gl: &gl::Context, engine: &mut render::Engine) -> Result<Self> {
+        let axes = axis_gizmo(1., 0.02)?;
+        let axes = engine
+            .add_mesh(gl, &axes, RenderStyle::Unlit)
+            .context("Failed to upload axis gizmo mesh")?;
+        let marker = origin_grid_marker(0.5, 0.02)?;
+        let marker = engine
+            .add_mesh(gl, &marker, RenderStyle::Unlit)
+            .context("Failed to upload origin marker mesh")?;
+        Ok(Self { axes, marker })
+    }
+
+    /// Show the gizmos at the origin and on the heads of `players`, or hide them
+    fn set(&self, gl: &gl::Context, engine: &mut render::Engine, show: bool, players: &[Player]) {
+        let (axes, markers) = match show {
+            true => {
+                let origin = Instance::new(Matrix4::identity());
+                let scale = Matrix4::new_scaling(HEAD_GIZMO_SCALE);
+                let heads = players
+                    .iter()
+                    .map(|player| Instance::new(player.head.matrix() * scale));
+                (std::iter::once(origin).chain(heads).collect(), vec![origin])
+            }
+            false => (vec![], vec![]),
+        };
+        engine.set_instances(gl, self.axes, &axes);
+        engine.set_instances(gl, self.marker, &markers);
+    }
+}
+
+/// Debug lines for the map bounds, a ground grid, the world origin and the direction each head
+/// is facing
+fn draw_debug_scene(engine: &mut render::Engine, players: &[Player]) {
+    let (min, max) = engine.map_bounds();
+    engine.debug_aabb(min, max, [1., 1., 0.]);
+    let grid = grid_lines(DEBUG_GRID_EXTENT, 1., 5).expect("Debug grid dimensions are valid");
+    engine.debug_line_mesh(&grid);
+    engine.debug_axes(&Matrix4::identity(), 1.);
+
+    for player in players {
+        let forward = player.head.orient * -Vector3::z();
+        engine.debug_line(player.head.pos, player.head.pos + forward, [1., 0., 1.]);
+    }
+}
+
+/// Save the window to a PNG in the working directory, named after the time
+fn save_screenshot(gl: &gl::Context, size: PhysicalSize<u32>) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let path = PathBuf::from(format!("cubehead-{}.png", secs));
+    match RgbaImage::read_window(gl, size.width, size.height).save(&path) {
+        Ok(()) => println!("Saved a screenshot to {}", path.display()),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Draw the edges of a frustum out to `distance` meters, or its far plane if nearer
+fn draw_frustum(
+    engine: &mut render::Engine,
+    view_proj: &Matrix4<f32>,
+    projection: &ProjectionConfig,
+    distance: f32,
+) {
+    let near = projection.ndc_depth(projection.near);
+    let far = projection.ndc_depth(distance.clamp(projection.near, projection.far));
+    if let Some(inv) = view_proj.try_inverse() {
+        engine.debug_line_mesh(&frustum_lines(&inv, near, far, FROZEN_FRUSTUM_COLOR));
+    }
+}
+
+/// A color for each player, spread around the hue circle so that neighboring ids differ
+fn player_color(id: PlayerId) -> [f32; 4] {
+    // In sixths of the hue circle
+    let hue = golden_fraction(id) * 6.;
+    let x = 1. - (hue % 2. - 1.).abs();
+    let [r, g, b] = match hue as u32 {
+        0 => [1., x, 0.],
+        1 => [x, 1., 0.],
+        2 => [0., 1., x],
+        3 => [0., x, 1.],
+        4 => [x, 0., 1.],
+        _ => [1., 0., x],
+    };
+    [r, g, b, 1.]
+}
+
+/// Dots in each player's color for the minimap, sized for a minimap `extent` meters across
+fn minimap_dots(players: &[Player], extent: f32) -> Vec<Billboard> {
+    players
+        .iter()
+        .map(|player| Billboard::new(player.head.pos, extent * 0.04, player_color(player.id)))
+        .collect()
+}
+
+/// Nametag anchors just above each player's head
+fn nametags(players: &[Player]) -> Vec<(Point3<f32>, &str)> {
+    players
+        .iter()
+        .map(|player| (player.head.pos + NAMETAG_OFFSET, player.name.as_str()))
+        .collect()
+}
+
+/// The map selected by the arguments, the bounds of any props or walls on it to spawn clear of,
+/// and where to start looking for a clear spawn point
+fn map_model(args: &Args) -> Result<(Mesh, Vec<Bounds>, Point3<f32>)> {
+    let (mesh, bounds) = match &args.map {
+        Some(MapSource::File(path)) => (scene::load_map(path)?, vec![]),
+        Some(MapSource::Checker) => (checkerboard(10., 1., CHECKER_LIGHT, CHECKER_DARK), vec![]),
+        Some(MapSource::Maze) => {
+            let (mesh, maze) = maze(
+                MAZE_CELLS,
+                MAZE_CELLS,
+                MAZE_CELL_SIZE,
+                MAZE_WALL_HEIGHT,
+                args.seed,
+            )?;
+            let (x, z) = maze.spawn_cell();
+            return Ok((mesh, vec![], maze.cell_center(x, z, MAZE_CELL_SIZE)));
+        }
+        Some(MapSource::Props) => props_map(PROPS_EXTENT, args.seed)?,
+        Some(MapSource::Rooms(path)) => {
+            let spec = match path {
+                Some(path) => RoomsSpec::load(path)?,
+                None => RoomsSpec::two_rooms_and_corridor(),
+            };
+            rooms_map(&spec)?
+        }
+        Some(MapSource::Quad) | None => (big_quad_map_subdivided(10., QUAD_MAP_DIVISIONS)?, vec![]),
+    };
+    Ok((mesh, bounds, Point3::origin()))
+}
+
+/// Ground for the desktop camera to stand on. The built in maps are flat, and other maps are
+/// treated as flat at the bottom of their bounds, so that the camera at least can't fall out of
+/// the world.
+fn map_ground(args: &Args, engine: &render::Engine) -> Box<dyn HeightQuery> {
+    match &args.map {
+        Some(MapSource::File(_)) => Box::new(FlatGround(engine.map_bounds().0.y)),
+        _ => Box::new(FlatGround(0.)),
+    }
+}
+
+/// The head mesh selected by the arguments
+fn avatar_model(args: &Args) -> Result<Mesh> {
+    match &args.avatar {
+        Some(AvatarSource::File(path)) => scene::load_avatar(path),
+        Some(AvatarSource::ClassicCube) => Ok(rgb_cube(0.25)),
+        None => Ok(head_avatar(0.25)),
+    }
+}
+
+/// Set up the render engine with the given map, and the avatar and textures selected by the
+/// arguments
+fn create_engine(
+    gl: &gl::Context,
+    args: &Args,
+    config: &ClientConfig,
+    map_mesh: &Mesh,
+) -> Result<render::Engine> {
+    let head_mesh = avatar_model(args)?;
+    let config = render::EngineConfig {
+        max_heads: args.max_heads,
+        ..Default::default()
+    };
+    let mut engine = render::Engine::new(gl, map_mesh, &head_mesh, config)
+        .context("Render engine failed to start")?;
+
+    // Pointer markers are round
+    let disc = engine
+        .load_texture(gl, &RgbaImage::disc(POINTER_MARKER_TEXTURE_SIZE))
+        .context("Failed to upload the pointer marker texture")?;
+    engine.set_billboard_texture(Some(disc));
+
+    if let Some(dir) = &args.shader_dir {
+        engine
+            .set_shader_dir(gl, dir.clone())
+            .with_context(|| format!("Failed to load shaders from {}", dir.display()))?;
+    }
+
+    engine.set_map_double_sided(!args.single_sided_map);
+
+    if let Some(path) = &args.map_texture {
+        let image = load_image_or_checkerboard(path);
+        let texture = engine
+            .load_texture(gl, &image)
+            .context("Failed to upload map texture")?;
+        engine.set_map_texture(Some(texture));
+    }
+
+    // Use the procedural sky unless a cubemap loads successfully
+    let mut sky = Sky::Gradient;
+    if let (Some(dir), Background::Sky) = (&args.skybox, args.bg) {
+        match RgbaImage::load_cube_faces(dir) {
+            Ok(faces) => {
+                let cubemap = engine
+                    .load_cubemap(gl, &faces)
+                    .context("Failed to upload skybox")?;
+                sky = Sky::Cubemap(cubemap);
+            }
+            Err(e) => eprintln!("Failed to load skybox; {}. Using a gradient instead", e),
+        }
+    }
+
+    match args.bg {
+        Background::Sky => engine.set_background(BackgroundMode::Sky(sky)),
+        Background::Dome => {
+            let [r, g, b, a] = DOME_CLEAR;
+            engine.set_clear_color(r, g, b, a);
+        }
+        Background::Transparent => engine.set_background(BackgroundMode::Transparent),
+        Background::Color([r, g, b]) => engine.set_clear_color(r, g, b, 1.),
+    }
+    engine.set_fog(engine.background_color(), args.fog);
+    engine.set_shadows(config.shadows);
+    engine.set_grid(config.grid);
+    engine.set_idle_animation(!args.no_idle_animation);
+    engine.set_post_effects(gl, &args.post);
+    engine.set_gpu_budget(args.gpu_budget);
+
+    Ok(engine)
+}
+
+/// Add the sky dome if the arguments select it
+fn add_sky_dome(gl: &gl::Context, args: &Args, engine: &mut render::Engine) -> Result<()> {
+    if !matches!(args.bg, Background::Dome) {
+        return Ok(());
+    }
+    let mesh = sky_dome(
+        DOME_RADIUS,
+        DOME_STACKS,
+        DOME_SLICES,
+        DOME_HORIZON,
+        DOME_ZENITH,
+        DOME_GROUND,
+    )?;
+    let dome = engine
+        .add_mesh(gl, &mesh, RenderStyle::Unlit)
+        .context("Failed to upload sky dome")?;
+    engine.set_follow_camera(dome, true);
+    engine.set_instances(gl, dome, &[Instance::new(Matrix4::identity())]);
+    Ok(())
+}
+
+/// Load an image, falling back to a checkerboard so that a bad path doesn't stop the client
+fn load_image_or_checkerboard(path: &Path) -> RgbaImage {
+    RgbaImage::load(path).unwrap_or_else(|e| {
+        eprintln!(
+            "Failed to load {}; {}. Using a checkerboard instead",
+            path.display(),
+            e
+        );
+        RgbaImage::checkerboard(256, 8)
+    })
+}
+
+/// Copy the color of a VR view into the window, scaled to fit and centered with black bars
+unsafe fn blit_mirror(
+    gl: &gl::Context,
+    view_framebuffer: gl::NativeFramebuffer,
+    (src_w, src_h): (i32, i32),
+    window: PhysicalSize<u32>,
+) {
+    let (dst_w, dst_h) = (window.width as i32, window.height as i32);
+    if dst_w == 0 || dst_h == 0 {
+        return;
+    }
+
+    // Letterbox to the aspect ratio of the view
+    let scale = (dst_w as f32 / src_w as f32).min(dst_h as f32 / src_h as f32);
+    let (w, h) = ((src_w as f32 * scale) as i32, (src_h as f32 * scale) as i32);
+    let (x, y) = ((dst_w - w) / 2, (dst_h - h) / 2);
+
+    gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, None);
+    gl.clear_color(0., 0., 0., 1.);
+    gl.clear(gl::COLOR_BUFFER_BIT);
+
+    // The view is already encoded for display, so copy it without any sRGB conversion
+    gl.disable(gl::FRAMEBUFFER_SRGB);
+
+    gl.bind_framebuffer(gl::READ_FRAMEBUFFER, Some(view_framebuffer));
+    gl.blit_framebuffer(
+        0,
+        0,
+        src_w,
+        src_h,
+        x,
+        y,
+        x + w,
+        y + h,
+        gl::COLOR_BUFFER_BIT,
+        gl::LINEAR,
+    );
+
+    // Leave the view bound as it was
+    gl.bind_framebuffer(gl::FRAMEBUFFER, Some(view_framebuffer));
+    check_gl_error!(gl, "blit_mirror");
+}
+
+/// Multisampled framebuffer with color and depth renderbuffers
+struct MsaaTarget {
+    framebuffer: gl::NativeFramebuffer,
+    _color: gl::NativeRenderbuffer,
+    _depth: gl::NativeRenderbuffer,
+}
+
+fn create_msaa_target(
+    gl: &gl::Context,
+    samples: i32,
+    color_format: u32,
+    depth_format: u32,
+    width: i32,
+    height: i32,
+) -> Result<MsaaTarget, String> {
+    unsafe {
+        let framebuffer = gl.create_framebuffer()?;
+        gl.bind_framebuffer(gl::FRAMEBUFFER, Some(framebuffer));
+
+        let color = gl.create_renderbuffer()?;
+        gl.bind_renderbuffer(gl::RENDERBUFFER, Some(color));
+        gl.renderbuffer_storage_multisample(gl::RENDERBUFFER, samples, color_format, width, height);
+        gl.framebuffer_renderbuffer(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::RENDERBUFFER,
+            Some(color),
+        );
+
+        let depth = gl.create_renderbuffer()?;
+        gl.bind_renderbuffer(gl::RENDERBUFFER, Some(depth));
+        gl.renderbuffer_storage_multisample(gl::RENDERBUFFER, samples, depth_format, width, height);
+        gl.framebuffer_renderbuffer(
+            gl::FRAMEBUFFER,
+            gl::DEPTH_ATTACHMENT,
+            gl::RENDERBUFFER,
+            Some(depth),
+        );
+
+        let status = gl.check_framebuffer_status(gl::FRAMEBUFFER);
+
+        gl.bind_renderbuffer(gl::RENDERBUFFER, None);
+        gl.bind_framebuffer(gl::FRAMEBUFFER, None);
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("Framebuffer incomplete (status 0x{:X})", status));
+        }
+
+        Ok(MsaaTarget {
+            framebuffer,
+            _color: color,
+            _depth: depth,
+        })
+    }
+}
+
+fn parse_map(s: &str) -> Result<MapSource, String> {
+    match s.strip_prefix("builtin:") {
+        Some("quad") => Ok(MapSource::Quad),
+        Some("checker") => Ok(MapSource::Checker),
+        Some("maze") => Ok(MapSource::Maze),
+        Some("props") => Ok(MapSource::Props),
+        Some("rooms") => Ok(MapSource::Rooms(None)),
+        Some(other) => Err(format!(
+            "Unknown built in map \"{}\"; must be quad, checker, maze, props or rooms",
+            other
+        )),
+        None => match s.strip_prefix("rooms:") {
+            Some(path) => Ok(MapSource::Rooms(Some(path.into()))),
+            None => Ok(MapSource::File(s.into())),
+        },
+    }
+}
+
+/// Title of the desktop and VR mirror windows, naming the player and the server
+fn window_title(args: &Args) -> String {
+    match args.offline {
+        true => status_title(&args.name, "(offline)"),
+        false => status_title(&args.name, &format!("@ {}", args.addr())),
+    }
+}
+
+/// Window title naming the player, followed by where they are connected
+fn status_title(name: &str, status: &str) -> String {
+    format!("cubehead \u{2014} {} {}", name, status)
+}
+
+/// The OS user's name if it makes a valid player name, else `FALLBACK_NAME`
+fn default_name() -> String {
+    ["USER", "USERNAME"]
+        .into_iter()
+        .find_map(|var| std::env::var(var).ok())
+        .and_then(|name| parse_name(&name).ok())
+        .unwrap_or_else(|| FALLBACK_NAME.into())
+}
+
+/// Trim a player name and cut it down to `MAX_NAME_CHARS`, rejecting empty names and control
+/// characters
+fn parse_name(s: &str) -> Result<String, String> {
+    let name = s.trim();
+    if name.is_empty() {
+        return Err("Name is empty".into());
+    }
+    if name.chars().any(char::is_control) {
+        return Err("Name contains control characters".into());
+    }
+    Ok(name
+        .chars()
+        .take(MAX_NAME_CHARS)
+        .collect::<String>()
+        .trim_end()
+        .into())
+}
+
+fn parse_avatar(s: &str) -> Result<AvatarSource, String> {
+    match s {
+        "classic-cube" => Ok(AvatarSource::ClassicCube),
+        _ => Ok(AvatarSource::File(s.into())),
+    }
+}
+
+fn parse_background(s: &str) -> Result<Background, String> {
+    match s {
+        "sky" => return Ok(Background::Sky),
+        "dome" => return Ok(Background::Dome),
+        "transparent" => return Ok(Background::Transparent),
+        _ => (),
+    }
+
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let value = match (hex.len(), u32::from_str_radix(hex, 16)) {
+        (6, Ok(value)) => value,
+        _ => return Err("Must be sky, dome, transparent or a hex color like #1a334d".into()),
+    };
+
+    let channel = |shift: u32| ((value >> shift) & 0xff) as f32 / 255.;
+    Ok(Background::Color([channel(16), channel(8), channel(0)]))
+}
+
+/// Whether an environment blend mode shows the real world through transparent parts of the frame
+fn blends_with_environment(mode: xr::EnvironmentBlendMode) -> bool {
+    mode == xr::EnvironmentBlendMode::ALPHA_BLEND || mode == xr::EnvironmentBlendMode::ADDITIVE
+}
+
+/// The background a VR display needs instead of the configured one, which is transparent if the
+/// display blends the frame with the real world
+fn vr_background(mode: xr::EnvironmentBlendMode) -> Option<BackgroundMode> {
+    blends_with_environment(mode).then_some(BackgroundMode::Transparent)
+}
+
+/// The color format to render VR views in among those the runtime supports: `forced` if given,
+/// else the most preferred of ours, else the runtime's first choice. With `alpha`, only formats
+/// with an alpha channel are chosen.
+fn choose_color_format(supported: &[u32], forced: Option<u32>, alpha: bool) -> Result<u32> {
+    let offered = || {
+        let names: Vec<String> = supported.iter().map(|&f| color_format_name(f)).collect();
+        names.join(", ")
+    };
+
+    if let Some(format) = forced {
+        if !supported.contains(&format) {
+            bail!(
+                "The runtime doesn't offer the {} color format, only {}",
+                color_format_name(format),
+                offered()
+            );
         }
+        return Ok(format);
+    }
 
-        Ok(program)
+    let preferred = VR_COLOR_SWAPCHAIN_FORMATS
+        .iter()
+        .map(|&(_, format)| format)
+        .filter(|&format| !alpha || has_alpha(format))
+        .find(|format| supported.contains(format));
+    match preferred {
+        Some(format) => Ok(format),
+        None if alpha => bail!("No color format with alpha among {}", offered()),
+        None => supported
+            .first()
+            .copied()
+            .context("The runtime offers no color formats"),
     }
 }
 
-/*
- * According to their respective specifications, the
- * OpenXR and OpenGL APIs both use a **Right Handed** coordinate system.
- */
+fn has_alpha(format: u32) -> bool {
+    [gl::SRGB8_ALPHA8, gl::RGBA8, gl::RGB10_A2, gl::RGBA16F].contains(&format)
+}
 
-/// Creates a view matrix for the given pose
-pub fn view_from_pose(pose: &xr::Posef) -> Matrix4<f32> {
-    view_from_head(&head_from_xr_pose(pose))
+/// Whether a color format takes linear values from the shaders: sRGB formats encode in
+/// hardware, and float formats are linear already. Other formats get gamma from the shaders.
+fn takes_linear_output(format: u32) -> bool {
+    [gl::SRGB8_ALPHA8, gl::SRGB8, gl::RGBA16F, gl::RGB16F].contains(&format)
 }
 
-/// Creates a projection matrix for the given fov
-pub fn projection_from_fov(fov: &xr::Fovf, near: f32, far: f32) -> Matrix4<f32> {
-    // See https://gitlab.freedesktop.org/monado/demos/openxr-simple-example/-/blob/master/main.c
-    // XrMatrix4x4f_CreateProjectionFov()
+/// GL name of a color format, or its number if we don't know it
+fn color_format_name(format: u32) -> String {
+    VR_COLOR_SWAPCHAIN_FORMATS
+        .iter()
+        .find(|&&(_, f)| f == format)
+        .map_or_else(|| format!("{:#06x}", format), |(name, _)| name.to_string())
+}
 
-    let tan_left = fov.angle_left.tan();
-    let tan_right = fov.angle_right.tan();
+fn parse_color_format(s: &str) -> Result<u32, String> {
+    let upper = s.to_ascii_uppercase();
+    let name = upper.strip_prefix("GL_").unwrap_or(&upper);
+    if let Some(&(_, format)) = VR_COLOR_SWAPCHAIN_FORMATS.iter().find(|(n, _)| *n == name) {
+        return Ok(format);
+    }
+    let number = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    number.map_err(|_| {
+        let names: Vec<&str> = VR_COLOR_SWAPCHAIN_FORMATS.iter().map(|(n, _)| *n).collect();
+        format!("Expected one of {} or a number", names.join(", "))
+    })
+}
 
-    let tan_up = fov.angle_up.tan();
-    let tan_down = fov.angle_down.tan();
+/// The blend mode to use among those the runtime supports, in its order of preference
+fn choose_blend_mode(
+    requested: BlendMode,
+    supported: &[xr::EnvironmentBlendMode],
+) -> Result<xr::EnvironmentBlendMode> {
+    match requested {
+        BlendMode::Auto => supported
+            .iter()
+            .copied()
+            .find(|&mode| mode == xr::EnvironmentBlendMode::OPAQUE)
+            .or_else(|| supported.first().copied())
+            .context("The runtime supports no environment blend modes"),
+        BlendMode::Forced(mode) if supported.contains(&mode) => Ok(mode),
+        BlendMode::Forced(mode) => bail!(
+            "The runtime doesn't support the {:?} blend mode, only {:?}",
+            mode,
+            supported
+        ),
+    }
+}
 
-    let tan_width = tan_right - tan_left;
-    let tan_height = tan_up - tan_down;
+fn parse_blend_mode(s: &str) -> Result<BlendMode, String> {
+    match s {
+        "auto" => Ok(BlendMode::Auto),
+        "opaque" => Ok(BlendMode::Forced(xr::EnvironmentBlendMode::OPAQUE)),
+        "additive" => Ok(BlendMode::Forced(xr::EnvironmentBlendMode::ADDITIVE)),
+        "alpha" => Ok(BlendMode::Forced(xr::EnvironmentBlendMode::ALPHA_BLEND)),
+        _ => Err("Expected auto, opaque, additive or alpha".into()),
+    }
+}
 
-    let a11 = 2.0 / tan_width;
-    let a22 = 2.0 / tan_height;
+fn parse_post(s: &str) -> Result<PostChain, String> {
+    if s == "off" {
+        return Ok(vec![]);
+    }
 
-    let a31 = (tan_right + tan_left) / tan_width;
-    let a32 = (tan_up + tan_down) / tan_height;
+    s.split(',')
+        .map(|name| match name.trim() {
+            "fxaa" => Ok(PostEffect::Fxaa),
+            other => Err(format!(
+                "Unknown effect \"{}\"; must be fxaa, or off",
+                other
+            )),
+        })
+        .collect()
+}
 
-    let a33 = -far / (far - near);
+fn parse_exit_after(s: &str) -> Result<f32, String> {
+    match s.parse() {
+        Ok(secs) if (0. ..=MAX_CLIENTS_EXIT_AFTER).contains(&secs) => Ok(secs),
+        _ => Err(format!(
+            "Must be a number of seconds from 0 to {}",
+            MAX_CLIENTS_EXIT_AFTER
+        )),
+    }
+}
 
-    let a43 = -(far * near) / (far - near);
+fn parse_path_speed(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(speed) if speed > 0. && speed.is_finite() => Ok(speed),
+        _ => Err("Must be a positive number".into()),
+    }
+}
 
-    Matrix4::new(
-        a11, 0.0, a31, 0.0, //
-        0.0, a22, a32, 0.0, //
-        0.0, 0.0, a33, a43, //
-        0.0, 0.0, -1.0, 0.0, //
-    )
+fn parse_fullscreen(s: &str) -> Result<FullscreenMode, String> {
+    match s {
+        "windowed" => Ok(FullscreenMode::Windowed),
+        "borderless" => Ok(FullscreenMode::Borderless),
+        "exclusive" => Ok(FullscreenMode::Exclusive),
+        _ => Err("Must be borderless, exclusive or windowed".into()),
+    }
 }
 
-/// Creates a view matrix for the given pose
-pub fn head_from_xr_pose(pose: &xr::Posef) -> Head {
-    // Convert the rotation quaternion from OpenXR to nalgebra
-    let orient = pose.orientation;
-    let orient = Quaternion::new(orient.w, orient.x, orient.y, orient.z);
-    let orient = Unit::try_new(orient, 0.0).expect("Not a unit orienternion");
+/// Fullscreen of the window in `mode` on `monitor`, or on the current monitor for borderless if
+/// None. Exclusive fullscreen takes the largest video mode with the highest refresh rate, and
+/// falls back to borderless where the monitor lists none.
+fn winit_fullscreen(
+    mode: FullscreenMode,
+    monitor: Option<glutin::monitor::MonitorHandle>,
+) -> Option<glutin::window::Fullscreen> {
+    use glutin::window::Fullscreen;
+    let video_mode = |monitor: &glutin::monitor::MonitorHandle| {
+        monitor.video_modes().max_by_key(|video_mode| {
+            let size = video_mode.size();
+            (
+                size.width * size.height,
+                video_mode.refresh_rate_millihertz(),
+            )
+        })
+    };
+    match mode {
+        FullscreenMode::Windowed => None,
+        FullscreenMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+        FullscreenMode::Exclusive => match monitor.as_ref().and_then(video_mode) {
+            Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+            None => Some(Fullscreen::Borderless(monitor)),
+        },
+    }
+}
 
-    // Convert the position vector from OpenXR to nalgebra
-    let pos = pose.position;
-    let pos = Point3::new(pos.x, pos.y, pos.z);
+fn parse_aspect(s: &str) -> Result<f32, String> {
+    let aspect = match s.split_once(':') {
+        Some((w, h)) => match (w.parse::<f32>(), h.parse::<f32>()) {
+            (Ok(w), Ok(h)) if h > 0. => w / h,
+            _ => f32::NAN,
+        },
+        None => s.parse().unwrap_or(f32::NAN),
+    };
 
-    Head { pos, orient }
+    if aspect.is_finite() && aspect > 0. {
+        Ok(aspect)
+    } else {
+        Err("Must be a ratio like 16:9, or a number like 1.78".into())
+    }
 }
 
-/// Creates a view matrix for the given head pose
-pub fn view_from_head(head: &Head) -> Matrix4<f32> {
-    // Invert this quaternion, orienting the world into NDC space
-    let orient_inv = head.orient.inverse();
+/// Viewport `[x, y, width, height]` for a window of the given size, centered with bars along two
+/// sides if `aspect` (width / height) is given. None if the window has no area, as when
+/// minimized.
+fn letterbox((width, height): (u32, u32), aspect: Option<f32>) -> Option<[i32; 4]> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let (width, height) = (width as i32, height as i32);
 
-    // Represent the rotation in homogeneous coordinates
-    let rotation = orient_inv.to_homogeneous();
+    let aspect = match aspect {
+        Some(aspect) => aspect,
+        None => return Some([0, 0, width, height]),
+    };
 
-    // Invert this translation, translating the world into NDC space
-    let trans = Matrix4::new_translation(&-head.pos.coords);
+    if width as f32 / height as f32 > aspect {
+        // Bars at the sides
+        let w = ((height as f32 * aspect).round() as i32).clamp(1, width);
+        Some([(width - w) / 2, 0, w, height])
+    } else {
+        // Bars at the top and bottom
+        let h = ((width as f32 / aspect).round() as i32).clamp(1, height);
+        Some([0, (height - h) / 2, width, h])
+    }
+}
 
-    rotation * trans
+fn parse_max_heads(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(n) if (1..=render::MAX_HEADS_LIMIT).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "Must be a number from 1 to {}",
+            render::MAX_HEADS_LIMIT
+        )),
+    }
+}
+
+fn parse_turn_angle(s: &str) -> Result<f32, String> {
+    match s.parse() {
+        Ok(degrees) if degrees > 0. && degrees <= 180. => Ok(degrees),
+        _ => Err("Must be a number of degrees from 0 to 180".into()),
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f32, String> {
+    let (min, max) = FLY_SPEED_RANGE;
+    match s.parse() {
+        Ok(speed) if (min..=max).contains(&speed) => Ok(speed),
+        _ => Err(format!(
+            "Must be a number of meters per second from {} to {}",
+            min, max
+        )),
+    }
+}
+
+fn parse_sensitivity(s: &str) -> Result<f32, String> {
+    let (min, max) = LOOK_SENSITIVITY_RANGE;
+    match s.parse() {
+        Ok(sensitivity) if (min..=max).contains(&sensitivity) => Ok(sensitivity),
+        _ => Err(format!(
+            "Must be a number of radians per pixel from {} to {}",
+            min, max
+        )),
+    }
+}
+
+fn parse_fov(s: &str) -> Result<f32, String> {
+    let (min, max) = FOV_RANGE;
+    match s.parse() {
+        Ok(fov) if fov > min && fov < max => Ok(fov),
+        _ => Err(format!(
+            "Must be a number of degrees between {} and {}",
+            min, max
+        )),
+    }
+}
+
+/// Clip planes of the settings in effect
+fn projection_config(config: &ClientConfig, reverse_z: bool) -> Result<ProjectionConfig> {
+    let (near, far) = (config.near, config.far);
+    if !(near > 0.) {
+        bail!("The near plane must be further than 0 m, not {} m", near);
+    }
+    if !(far > near) {
+        bail!(
+            "The far plane ({} m) must be further than the near plane ({} m)",
+            far,
+            near
+        );
+    }
+    Ok(ProjectionConfig {
+        near,
+        far,
+        reverse_z,
+    })
+}
+
+fn parse_vignette_strength(s: &str) -> Result<f32, String> {
+    match s.parse() {
+        Ok(strength) if (0.0..=1.).contains(&strength) => Ok(strength),
+        _ => Err("Must be a number from 0 to 1".into()),
+    }
+}
+
+fn parse_render_scale(s: &str) -> Result<f32, String> {
+    match s.parse() {
+        Ok(scale) if (0.5..=2.).contains(&scale) => Ok(scale),
+        _ => Err("Must be a number from 0.5 to 2.0".into()),
+    }
 }
 
-struct Client {
-    tcp_stream: TcpStream,
-    msg_buf: AsyncBufferedReceiver,
-    latest_state: ServerState,
+/// Size of a VR view scaled from the recommended resolution, clamped to the runtime's maximum
+fn scaled_view_size(view: &xr::ViewConfigurationView, scale: f32) -> (u32, u32) {
+    let scaled = |recommended: u32, max: u32| {
+        ((recommended as f32 * scale).round() as u32).clamp(1, max.max(1))
+    };
+    (
+        scaled(view.recommended_image_rect_width, view.max_image_rect_width),
+        scaled(
+            view.recommended_image_rect_height,
+            view.max_image_rect_height,
+        ),
+    )
+}
+
+fn parse_msaa_samples(s: &str) -> Result<u16, String> {
+    match s.parse() {
+        Ok(n @ (0 | 2 | 4 | 8)) => Ok(n),
+        _ => Err("Must be one of 0, 2, 4 or 8".into()),
+    }
+}
+
+/// Depth range of the VR views, as the (min_depth, max_depth, near_z, far_z) of
+/// XrCompositionLayerDepthInfoKHR. The runtime maps depth values between min_depth and
+/// max_depth to distances between near_z and far_z, so this must match `projection_from_fov`.
+///
+/// - Reversed depth, under 0..1 clip control, is 1 at `near` and falls to 0 at infinity. OpenXR
+///   takes this as a near_z (the distance at min_depth) of infinity and a far_z of `near`.
+/// - Otherwise the projection puts `near` at -1 and `far` at 1 in normalized device coordinates,
+///   which OpenGL's default -1..1 clip range maps to 0 and 1 in the depth buffer.
+fn vr_depth_range(projection: &ProjectionConfig) -> (f32, f32, f32, f32) {
+    if projection.reverse_z {
+        (0., 1., f32::INFINITY, projection.near)
+    } else {
+        (0., 1., projection.near, projection.far)
+    }
 }
 
-impl Client {
-    /// Connect to server
-    pub fn new(addr: SocketAddr) -> Result<Self> {
-        let tcp_stream = TcpStream::connect(addr)?;
-        tcp_stream.set_nonblocking(true)?;
-        let msg_buf = AsyncBufferedReceiver::new();
+/// Swapchain of depth textures for a VR view, submitted along with its color for better
+/// reprojection (XR_KHR_composition_layer_depth)
+struct VrDepthSwapchain {
+    swapchain: xr::Swapchain<xr::OpenGL>,
+    images: Vec<gl::NativeTexture>,
+    /// Image acquired but not yet ready, see `acquire_swapchain_image`
+    pending: Option<u32>,
+}
 
+impl VrDepthSwapchain {
+    fn new(
+        session: &xr::Session<xr::OpenGL>,
+        format: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            format,
+            sample_count: 1,
+            width,
+            height,
+            face_count: 1,
+            array_size: 1,
+            mip_count: 1,
+        })?;
+        let images = swapchain
+            .enumerate_images()?
+            .into_iter()
+            .map(|tex| unsafe { gl::Context::create_texture_from_gl_name(tex) })
+            .collect();
         Ok(Self {
-            tcp_stream,
-            latest_state: ServerState::default(),
-            msg_buf,
+            swapchain,
+            images,
+            pending: None,
         })
     }
 
-    /// Send our own head position
-    pub fn send_state(&mut self, head: &ClientState) -> Result<()> {
-        Ok(cubehead::serialize_msg(head, &mut self.tcp_stream)?)
+    /// Acquire the next image and wait until it can be rendered to, or None if it wasn't ready
+    /// within `timeout`
+    fn acquire(&mut self, timeout: xr::Duration) -> xr::Result<Option<gl::NativeTexture>> {
+        let idx = acquire_swapchain_image(&mut self.swapchain, &mut self.pending, timeout)?;
+        Ok(idx.map(|idx| self.images[idx as usize]))
     }
+}
 
-    /// Get latest head positions
-    pub fn update_heads(&mut self) -> Result<&ServerState> {
-        self.poll()?;
+/// Acquire a swapchain image and wait until it can be rendered to, returning its index, or None
+/// if it wasn't ready within `timeout`. OpenXR doesn't allow acquiring another image until that
+/// one is waited on, so it is kept in `pending` and waited on again next time.
+fn acquire_swapchain_image(
+    swapchain: &mut xr::Swapchain<xr::OpenGL>,
+    pending: &mut Option<u32>,
+    timeout: xr::Duration,
+) -> xr::Result<Option<u32>> {
+    let idx = match pending.take() {
+        Some(idx) => idx,
+        None => swapchain.acquire_image()?,
+    };
 
-        Ok(&self.latest_state)
+    // The safe wrapper treats timing out as success, so call the runtime directly
+    let info = xr::sys::SwapchainImageWaitInfo {
+        ty: xr::sys::SwapchainImageWaitInfo::TYPE,
+        next: std::ptr::null(),
+        timeout,
+    };
+    let result =
+        unsafe { (swapchain.instance().fp().wait_swapchain_image)(swapchain.as_raw(), &info) };
+    match result {
+        xr::sys::Result::TIMEOUT_EXPIRED => {
+            *pending = Some(idx);
+            Ok(None)
+        }
+        result if result.into_raw() < 0 => {
+            *pending = Some(idx);
+            Err(result)
+        }
+        _ => Ok(Some(idx)),
     }
+}
 
-    /// Receive head positions of all players
-    fn poll(&mut self) -> Result<()> {
-        let mut latest = None;
-        while let ReadState::Complete(msg) = self.msg_buf.read(&mut self.tcp_stream)? {
-            latest = Some(msg);
+/// Acquire the color image of a view, and its depth image if depth is submitted. Either both are
+/// acquired or neither is.
+fn acquire_view_images(
+    color: &mut xr::Swapchain<xr::OpenGL>,
+    color_pending: &mut Option<u32>,
+    depth: Option<&mut VrDepthSwapchain>,
+    timeout: xr::Duration,
+) -> xr::Result<Option<(u32, Option<gl::NativeTexture>)>> {
+    let color_idx = match acquire_swapchain_image(color, color_pending, timeout)? {
+        Some(idx) => idx,
+        None => return Ok(None),
+    };
+    let depth_texture = match depth.map(|depth| depth.acquire(timeout)) {
+        None => None,
+        Some(Ok(Some(texture))) => Some(texture),
+        Some(Ok(None)) => {
+            color.release_image()?;
+            return Ok(None);
         }
+        Some(Err(e)) => {
+            color.release_image()?;
+            return Err(e);
+        }
+    };
+    Ok(Some((color_idx, depth_texture)))
+}
+
+/// Depth renderbuffer for a VR view
+struct VrDepthBuffer {
+    renderbuffer: gl::NativeRenderbuffer,
+    width: i32,
+    height: i32,
+}
 
-        if let Some(state) = latest {
-            self.latest_state = bincode::deserialize(&state)?;
+impl VrDepthBuffer {
+    fn new(gl: &gl::Context, width: i32, height: i32) -> Result<Self, String> {
+        unsafe {
+            let renderbuffer = gl.create_renderbuffer()?;
+            gl.bind_renderbuffer(gl::RENDERBUFFER, Some(renderbuffer));
+            gl.renderbuffer_storage(gl::RENDERBUFFER, VR_DEPTH_FORMAT, width, height);
+            gl.bind_renderbuffer(gl::RENDERBUFFER, None);
+
+            Ok(Self {
+                renderbuffer,
+                width,
+                height,
+            })
         }
+    }
 
+    /// Recreate the renderbuffer if the size of the view changed
+    fn resize(&mut self, gl: &gl::Context, width: i32, height: i32) -> Result<(), String> {
+        if (self.width, self.height) != (width, height) {
+            let old = std::mem::replace(self, Self::new(gl, width, height)?);
+            old.delete(gl);
+        }
         Ok(())
     }
-}
 
-fn head_matrices(heads: &[Head]) -> Vec<[[f32; 4]; 4]> {
-    heads.iter().map(|head| *head.matrix().as_ref()).collect()
+    fn delete(self, gl: &gl::Context) {
+        unsafe {
+            gl.delete_renderbuffer(self.renderbuffer);
+        }
+    }
 }
 
-fn models() -> (Mesh, Mesh) {
-    (big_quad_map(10.), rgb_cube(0.25))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use nalgebra::{Vector2, Vector4};
+
+    #[test]
+    fn backgrounds_are_parsed_by_name_or_hex_color() {
+        assert!(matches!(parse_background("sky"), Ok(Background::Sky)));
+        assert!(matches!(parse_background("dome"), Ok(Background::Dome)));
+        assert!(matches!(
+            parse_background("transparent"),
+            Ok(Background::Transparent)
+        ));
+        assert!(matches!(
+            parse_background("#ff0033"),
+            Ok(Background::Color([r, g, b])) if r == 1. && g == 0. && b == 0.2
+        ));
+        // The hash is optional, and either case works
+        assert!(matches!(
+            parse_background("00FFff"),
+            Ok(Background::Color([r, g, b])) if r == 0. && g == 1. && b == 1.
+        ));
+        for bad in ["Sky", "#fff", "#ff00334", "#gg0000", "", "#"] {
+            assert_eq!(
+                parse_background(bad).err().as_deref(),
+                Some("Must be sky, dome, transparent or a hex color like #1a334d"),
+                "{:?}",
+                bad
+            );
+        }
+    }
 
-fn get_vr_depth_texture(
-    gl: &gl::Context,
-    width: i32,
-    height: i32,
-) -> Result<gl::NativeTexture, String> {
-    unsafe {
-        let depth_tex = gl.create_texture()?;
-        gl.bind_texture(gl::TEXTURE_2D, Some(depth_tex));
-        gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
-        gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
-        gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
-        gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
-        gl.tex_image_2d(
-            gl::TEXTURE_2D,
-            0,
-            VR_DEPTH_FORMAT as _,
-            width,
-            height,
-            0,
-            gl::DEPTH_COMPONENT,
-            gl::UNSIGNED_INT,
-            None,
+    #[test]
+    fn names_are_trimmed_and_cut_to_length() {
+        assert_eq!(parse_name("  Ada \t").as_deref(), Ok("Ada"));
+        assert_eq!(parse_name("Ada Lovelace").as_deref(), Ok("Ada Lovelace"));
+        let long = "x".repeat(MAX_NAME_CHARS + 8);
+        assert_eq!(parse_name(&long), Ok("x".repeat(MAX_NAME_CHARS)));
+        // Counted in characters rather than bytes
+        let wide = "é".repeat(MAX_NAME_CHARS + 1);
+        assert_eq!(parse_name(&wide), Ok("é".repeat(MAX_NAME_CHARS)));
+        // A space left at the end of the cut is trimmed too
+        let spaced = format!("{} tail", "x".repeat(MAX_NAME_CHARS - 1));
+        assert_eq!(parse_name(&spaced), Ok("x".repeat(MAX_NAME_CHARS - 1)));
+
+        for empty in ["", "   ", "\t\n"] {
+            assert_eq!(parse_name(empty).as_deref(), Err("Name is empty"));
+        }
+        for control in ["Ada\nLovelace", "a\u{7}", "\u{1b}[31mred"] {
+            assert_eq!(
+                parse_name(control).as_deref(),
+                Err("Name contains control characters"),
+                "{:?}",
+                control
+            );
+        }
+
+        // Whatever the OS user is called, the default is a name we'd accept
+        assert_eq!(parse_name(FALLBACK_NAME).as_deref(), Ok(FALLBACK_NAME));
+        let name = default_name();
+        assert_eq!(parse_name(&name), Ok(name));
+    }
+
+    #[test]
+    fn background_defaults_to_the_sky() {
+        let args = Args::try_parse_from(["cubehead", "127.0.0.1:5031"]).unwrap();
+        assert!(matches!(args.bg, Background::Sky));
+        let args = Args::try_parse_from(["cubehead", "127.0.0.1:5031", "--bg", "#000000"]).unwrap();
+        assert!(matches!(args.bg, Background::Color(rgb) if rgb == [0.; 3]));
+        assert!(Args::try_parse_from(["cubehead", "127.0.0.1:5031", "--bg", "purple"]).is_err());
+    }
+
+    #[test]
+    fn blending_displays_get_a_transparent_background() {
+        use xr::EnvironmentBlendMode as Mode;
+        assert_eq!(
+            vr_background(Mode::ALPHA_BLEND),
+            Some(BackgroundMode::Transparent)
+        );
+        assert_eq!(
+            vr_background(Mode::ADDITIVE),
+            Some(BackgroundMode::Transparent)
+        );
+        assert_eq!(vr_background(Mode::OPAQUE), None);
+    }
+
+    #[test]
+    fn command_line_is_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn letterboxes_center_the_view() {
+        assert_eq!(letterbox((800, 600), None), Some([0, 0, 800, 600]));
+        assert_eq!(letterbox((0, 600), Some(1.)), None);
+        assert_eq!(letterbox((800, 0), None), None);
+
+        // Too wide: bars at the sides
+        assert_eq!(letterbox((1000, 500), Some(1.)), Some([250, 0, 500, 500]));
+        // Too tall: bars at the top and bottom
+        let aspect = parse_aspect("16:9").unwrap();
+        assert_eq!(
+            letterbox((1600, 1200), Some(aspect)),
+            Some([0, 150, 1600, 900])
+        );
+        // Just right
+        assert_eq!(
+            letterbox((1920, 1080), Some(aspect)),
+            Some([0, 0, 1920, 1080])
+        );
+    }
+
+    #[test]
+    fn letterboxes_keep_a_pixel() {
+        assert_eq!(letterbox((1, 1000), Some(100.)), Some([0, 499, 1, 1]));
+        assert_eq!(letterbox((1000, 1), Some(0.01)), Some([499, 0, 1, 1]));
+    }
+
+    #[test]
+    fn aspect_ratios_are_parsed() {
+        assert_eq!(parse_aspect("2:1"), Ok(2.));
+        assert_eq!(parse_aspect("1.5"), Ok(1.5));
+        for bad in ["0", "-1", "1:0", "16:", "wide", "inf"] {
+            assert!(parse_aspect(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn auto_blend_mode_prefers_opaque() {
+        use xr::EnvironmentBlendMode as Mode;
+        let choose = |supported: &[Mode]| choose_blend_mode(BlendMode::Auto, supported).ok();
+        assert_eq!(
+            choose(&[Mode::ALPHA_BLEND, Mode::OPAQUE]),
+            Some(Mode::OPAQUE)
+        );
+        // Otherwise the runtime's first choice
+        assert_eq!(
+            choose(&[Mode::ADDITIVE, Mode::ALPHA_BLEND]),
+            Some(Mode::ADDITIVE)
+        );
+        assert_eq!(choose(&[]), None);
+    }
+
+    #[test]
+    fn forced_blend_modes_must_be_offered() {
+        use xr::EnvironmentBlendMode as Mode;
+        let supported = [Mode::OPAQUE, Mode::ADDITIVE];
+        let forced = choose_blend_mode(BlendMode::Forced(Mode::ADDITIVE), &supported);
+        assert_eq!(forced.ok(), Some(Mode::ADDITIVE));
+
+        let error = choose_blend_mode(BlendMode::Forced(Mode::ALPHA_BLEND), &supported)
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("The runtime doesn't support the "));
+    }
+
+    #[test]
+    fn blend_modes_are_parsed() {
+        use xr::EnvironmentBlendMode as Mode;
+        assert!(matches!(parse_blend_mode("auto"), Ok(BlendMode::Auto)));
+        assert!(matches!(
+            parse_blend_mode("alpha"),
+            Ok(BlendMode::Forced(mode)) if mode == Mode::ALPHA_BLEND
+        ));
+        assert!(parse_blend_mode("blend").is_err());
+    }
+
+    #[test]
+    fn color_formats_follow_our_preference() {
+        // The runtime's own order doesn't matter when we know some of its formats
+        let supported = [0x1234, gl::RGB8, gl::RGBA8, gl::SRGB8_ALPHA8];
+        let chosen = choose_color_format(&supported, None, false).unwrap();
+        assert_eq!(chosen, gl::SRGB8_ALPHA8);
+        let chosen = choose_color_format(&supported[..3], None, false).unwrap();
+        assert_eq!(chosen, gl::RGBA8);
+
+        // Only formats we don't know: take the runtime's favorite
+        let chosen = choose_color_format(&[0x1234, 0x5678], None, false).unwrap();
+        assert_eq!(chosen, 0x1234);
+        assert!(choose_color_format(&[], None, false).is_err());
+    }
+
+    #[test]
+    fn blending_needs_a_color_format_with_alpha() {
+        let supported = [gl::SRGB8, gl::RGB8, gl::RGB10_A2];
+        assert_eq!(
+            choose_color_format(&supported, None, false).unwrap(),
+            gl::SRGB8
+        );
+        assert_eq!(
+            choose_color_format(&supported, None, true).unwrap(),
+            gl::RGB10_A2
+        );
+        let error = choose_color_format(&supported[..2], None, true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No color format with alpha among SRGB8, RGB8"
+        );
+    }
+
+    #[test]
+    fn forced_color_formats_must_be_offered() {
+        let supported = [gl::SRGB8_ALPHA8, gl::RGBA16F];
+        let chosen = choose_color_format(&supported, Some(gl::RGBA16F), false).unwrap();
+        assert_eq!(chosen, gl::RGBA16F);
+        let error = choose_color_format(&supported, Some(0x1234), false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The runtime doesn't offer the 0x1234 color format, only SRGB8_ALPHA8, RGBA16F"
+        );
+    }
+
+    #[test]
+    fn color_formats_are_parsed_by_name_or_number() {
+        assert_eq!(parse_color_format("RGBA8"), Ok(gl::RGBA8));
+        assert_eq!(parse_color_format("GL_srgb8_alpha8"), Ok(gl::SRGB8_ALPHA8));
+        assert_eq!(parse_color_format("gl_rgba8"), Ok(gl::RGBA8));
+        assert_eq!(parse_color_format("0x8c43"), Ok(0x8c43));
+        assert_eq!(parse_color_format("32856"), Ok(32856));
+        assert!(parse_color_format("RGBA9").is_err());
+        assert_eq!(color_format_name(gl::RGB10_A2), "RGB10_A2");
+        assert_eq!(color_format_name(0x1234), "0x1234");
+    }
+
+    #[test]
+    fn spawned_clients_get_the_other_args() {
+        let forwarded = |args: &[&str]| forwarded_args(args.iter().map(|arg| arg.to_string()));
+        let none: [&str; 0] = [];
+        assert_eq!(
+            forwarded(&["-c4", "--map", "builtin:maze"]),
+            ["--map", "builtin:maze"]
+        );
+        assert_eq!(forwarded(&["-c", "4", "--vr"]), ["--vr"]);
+        assert_eq!(forwarded(&["-c=4", "--stats"]), ["--stats"]);
+        assert_eq!(forwarded(&["--name=x", "--grid"]), ["--grid"]);
+        assert_eq!(forwarded(&["--name", "x", "--grid"]), ["--grid"]);
+        assert_eq!(
+            forwarded(&["--clients-exit-after", "5", "--seed", "3"]),
+            ["--seed", "3"]
+        );
+        assert_eq!(forwarded(&["--clients=2", "--clients-exit-after=5"]), none);
+        // Values that only look like the short flag are kept
+        assert_eq!(
+            forwarded(&["--map", "-castle.obj", "-c", "2"]),
+            ["--map", "-castle.obj"]
         );
+    }
+
+    #[test]
+    fn exit_after_is_a_time_the_clock_can_reach() {
+        assert_eq!(parse_exit_after("5"), Ok(5.));
+        assert_eq!(parse_exit_after("0.5"), Ok(0.5));
+        assert_eq!(parse_exit_after("0"), Ok(0.));
+        for bad in ["-1", "inf", "NaN", "1e30", "soon"] {
+            assert!(parse_exit_after(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn clip_planes_are_checked() {
+        let config = |near, far| ClientConfig {
+            near,
+            far,
+            ..ClientConfig::default()
+        };
+        let projection = projection_config(&config(0.1, 100.), true).unwrap();
+        assert_eq!(
+            projection,
+            ProjectionConfig {
+                near: 0.1,
+                far: 100.,
+                reverse_z: true
+            }
+        );
+
+        let error = |near, far| {
+            projection_config(&config(near, far), false)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error(0., 100.),
+            "The near plane must be further than 0 m, not 0 m"
+        );
+        assert_eq!(
+            error(f32::NAN, 100.),
+            "The near plane must be further than 0 m, not NaN m"
+        );
+        assert_eq!(
+            error(1., 1.),
+            "The far plane (1 m) must be further than the near plane (1 m)"
+        );
+        assert_eq!(
+            error(1., 0.5),
+            "The far plane (0.5 m) must be further than the near plane (1 m)"
+        );
+    }
+
+    #[test]
+    fn fov_is_parsed_within_its_range() {
+        assert_eq!(parse_fov("90"), Ok(90.));
+        assert_eq!(parse_fov("10.5"), Ok(10.5));
+        for bad in ["10", "170", "0", "-45", "wide", ""] {
+            assert_eq!(
+                parse_fov(bad),
+                Err("Must be a number of degrees between 10 and 170".into()),
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn players_fade_once_the_server_stops_hearing_from_them() {
+        let alpha = |since: Duration| {
+            stale_alpha(&Player {
+                since_update: since.as_millis() as u32,
+                ..Player::default()
+            })
+        };
+        assert_eq!(alpha(Duration::ZERO), 1.);
+        assert_eq!(alpha(STALE_AFTER), 1.);
+        let halfway = alpha(STALE_AFTER + STALE_FADE / 2);
+        assert!((halfway - (1. + STALE_ALPHA) / 2.).abs() < 1e-6);
+        assert_eq!(alpha(STALE_AFTER + STALE_FADE), STALE_ALPHA);
+        assert_eq!(alpha(Duration::from_millis(u32::MAX.into())), STALE_ALPHA);
+    }
+
+    #[test]
+    fn headset_projections_follow_the_depth_mode() {
+        // Wider to the outside than to the nose, as for a left eye
+        let fov = xr::Fovf {
+            angle_left: -0.9,
+            angle_right: 0.7,
+            angle_up: 0.8,
+            angle_down: -0.85,
+        };
+        let mut projection = ProjectionConfig {
+            near: 0.05,
+            far: 500.,
+            reverse_z: false,
+        };
+        let project = |projection: &ProjectionConfig, x: f32, y: f32, distance: f32| {
+            let proj = projection_from_fov(&fov, projection);
+            let clip = proj * Vector4::new(x, y, -distance, 1.);
+            clip.xyz() / clip.w
+        };
+
+        // The edges of the field of view land on the edges of clip space, whatever the mode
+        for reverse_z in [false, true] {
+            projection.reverse_z = reverse_z;
+            let distance = 3.;
+            let left_bottom = project(
+                &projection,
+                distance * fov.angle_left.tan(),
+                distance * fov.angle_down.tan(),
+                distance,
+            );
+            let right_top = project(
+                &projection,
+                distance * fov.angle_right.tan(),
+                distance * fov.angle_up.tan(),
+                distance,
+            );
+            assert!((left_bottom.xy() - Vector2::new(-1., -1.)).norm() < 1e-5);
+            assert!((right_top.xy() - Vector2::new(1., 1.)).norm() < 1e-5);
+            assert!((left_bottom.z - projection.ndc_depth(distance)).abs() < 1e-5);
+        }
+
+        projection.reverse_z = false;
+        assert!((project(&projection, 0., 0., 0.05).z + 1.).abs() < 1e-5);
+        assert!((project(&projection, 0., 0., 500.).z - 1.).abs() < 1e-3);
 
-        Ok(depth_tex)
+        projection.reverse_z = true;
+        assert!((project(&projection, 0., 0., 0.05).z - 1.).abs() < 1e-6);
+        assert!(project(&projection, 0., 0., 1e6).z > 0.);
     }
 }
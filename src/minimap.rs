@@ -0,0 +1,128 @@
+//! Top-down camera for the minimap, and the mapping from the world onto it. North (negative Z)
+//! is up on the minimap, and east (positive X) is to the right.
+use nalgebra::{Matrix4, Point2, Point3, Vector2, Vector3};
+
+/// Width and height of the minimap texture in pixels
+pub const MINIMAP_TEXTURE_SIZE: i32 = 256;
+
+/// Number of `Engine::render_minimap` calls between redraws of the minimap texture
+pub const MINIMAP_INTERVAL: u32 = 4;
+
+/// View looking straight down at `center` from `height` meters above it
+pub fn minimap_view(center: Point3<f32>, height: f32) -> Matrix4<f32> {
+    let eye = center + Vector3::y() * height;
+    Matrix4::look_at_rh(&eye, &center, &-Vector3::z())
+}
+
+/// Orthographic projection covering `extent` meters on each side, and depths from the camera
+/// down to `depth` meters below it. With `zero_to_one`, depth is mapped to 0..1 for glClipControl
+/// rather than -1..1.
+pub fn minimap_proj(extent: f32, depth: f32, zero_to_one: bool) -> Matrix4<f32> {
+    let half = extent / 2.;
+    let proj = Matrix4::new_orthographic(-half, half, -half, half, 0., depth);
+    if zero_to_one {
+        Matrix4::new_nonuniform_scaling(&Vector3::new(1., 1., 0.5))
+            .append_translation(&Vector3::new(0., 0., 0.5))
+            * proj
+    } else {
+        proj
+    }
+}
+
+/// Where a point in the world lands on the minimap, from (0, 0) at the bottom left to (1, 1) at
+/// the top right. Points off the minimap are outside that range.
+pub fn world_to_minimap(view_proj: &Matrix4<f32>, point: Point3<f32>) -> Point2<f32> {
+    let clip = view_proj.transform_point(&point);
+    Point2::new(clip.x * 0.5 + 0.5, clip.y * 0.5 + 0.5)
+}
+
+/// Whether a dot `size` meters wide at `pos` shows at least partly on a minimap covering
+/// `extent` meters on each side
+pub fn shows_on_minimap(
+    view_proj: &Matrix4<f32>,
+    pos: Point3<f32>,
+    size: f32,
+    extent: f32,
+) -> bool {
+    let point = world_to_minimap(view_proj, pos);
+    let margin = size / 2. / extent;
+    let range = -margin..=1. + margin;
+    range.contains(&point.x) && range.contains(&point.y)
+}
+
+/// Direction on the minimap of a direction in the world, or None if it points straight up or
+/// down
+pub fn minimap_heading(forward: Vector3<f32>) -> Option<Vector2<f32>> {
+    Vector2::new(forward.x, -forward.z).try_normalize(1e-6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(found: Point2<f32>, expected: [f32; 2]) {
+        let expected = Point2::from(expected);
+        assert!(
+            (found - expected).norm() < 1e-5,
+            "{} != {}",
+            found,
+            expected
+        );
+    }
+
+    #[test]
+    fn north_is_up_and_east_is_right() {
+        let center = Point3::new(10., 0., -4.);
+        let view_proj = minimap_proj(20., 100., false) * minimap_view(center, 50.);
+        assert_close(world_to_minimap(&view_proj, center), [0.5, 0.5]);
+        let north = center - Vector3::z() * 10.;
+        assert_close(world_to_minimap(&view_proj, north), [0.5, 1.]);
+        let east = center + Vector3::x() * 5.;
+        assert_close(world_to_minimap(&view_proj, east), [0.75, 0.5]);
+        let southwest = center + Vector3::new(-10., 0., 10.);
+        assert_close(world_to_minimap(&view_proj, southwest), [0., 0.]);
+
+        // Height makes no difference, and points beyond the extent fall off the map
+        let above = center + Vector3::new(5., 30., 0.);
+        assert_close(world_to_minimap(&view_proj, above), [0.75, 0.5]);
+        let far = center + Vector3::x() * 20.;
+        assert!(world_to_minimap(&view_proj, far).x > 1.);
+    }
+
+    #[test]
+    fn dots_show_until_they_are_wholly_off_the_edge() {
+        let center = Point3::new(10., 0., -4.);
+        let view_proj = minimap_proj(20., 100., false) * minimap_view(center, 50.);
+        let shows =
+            |x: f32, z: f32| shows_on_minimap(&view_proj, center + Vector3::new(x, 0., z), 2., 20.);
+        assert!(shows(0., 0.));
+        assert!(shows(-10., 10.));
+        // Half the dot hangs over the edge, and then it is just off it
+        assert!(shows(10.9, 0.));
+        assert!(shows(0., -10.9));
+        assert!(!shows(11.1, 0.));
+        assert!(!shows(0., 11.1));
+        assert!(!shows(-30., -30.));
+    }
+
+    #[test]
+    fn depth_covers_the_camera_down_to_the_depth() {
+        let view = minimap_view(Point3::origin(), 50.);
+        for (zero_to_one, near) in [(false, -1.), (true, 0.)] {
+            let view_proj = minimap_proj(20., 100., zero_to_one) * view;
+            let depth = |y: f32| view_proj.transform_point(&Point3::new(0., y, 0.)).z;
+            assert!((depth(50.) - near).abs() < 1e-5);
+            assert!((depth(-50.) - 1.).abs() < 1e-5);
+            assert!(depth(0.) > near && depth(0.) < 1.);
+        }
+    }
+
+    #[test]
+    fn headings_ignore_pitch() {
+        let heading = minimap_heading(-Vector3::z()).unwrap();
+        assert!((heading - Vector2::y()).norm() < 1e-6);
+        let heading = minimap_heading(Vector3::new(3., -4., 0.)).unwrap();
+        assert!((heading - Vector2::x()).norm() < 1e-6);
+        assert_eq!(minimap_heading(Vector3::y()), None);
+    }
+}
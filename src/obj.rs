@@ -0,0 +1,360 @@
+//! Minimal Wavefront OBJ loader
+//!
+//! Supports positions (with the common `v x y z r g b` vertex color extension), texture
+//! coordinates, normals, polygonal faces (triangulated as fans), negative indices, and diffuse
+//! material colors from `mtllib`. Vertices without a normal are given a smooth one.
+use crate::render::{Mesh, NormalMode, Vertex};
+use anyhow::{bail, format_err, Context, Result};
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Color used when the file specifies neither vertex colors nor materials
+const DEFAULT_COLOR: [f32; 3] = [0.8; 3];
+
+/// Load an OBJ file as a single mesh
+pub fn load_obj(path: &Path) -> Result<Mesh> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    parse_obj(BufReader::new(file), dir).with_context(|| format!("In {}", path.display()))
+}
+
+/// Parse OBJ data line by line; `dir` is where material libraries are looked up
+pub fn parse_obj<R: BufRead>(reader: R, dir: &Path) -> Result<Mesh> {
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut colors: Vec<Option<[f32; 3]>> = vec![];
+    let mut uvs: Vec<[f32; 2]> = vec![];
+    let mut normals: Vec<[f32; 3]> = vec![];
+
+    let mut materials: HashMap<String, [f32; 3]> = HashMap::new();
+    let mut material_color = DEFAULT_COLOR;
+
+    let mut mesh = Mesh {
+        indices: vec![],
+        vertices: vec![],
+    };
+
+    // Map from (position, uv, normal, color) to an index in the mesh
+    let mut vertex_cache: HashMap<(usize, Option<usize>, Option<usize>, [u32; 3]), u32> =
+        HashMap::new();
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let err = |msg: String| format_err!("line {}: {}", line_no, msg);
+
+        let line = line.map_err(|e| err(e.to_string()))?;
+        let line = line.split('#').next().unwrap().trim();
+
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => {
+                let nums = parse_floats(words).map_err(err)?;
+                match nums.len() {
+                    3 | 4 => colors.push(None),
+                    6 => colors.push(Some([nums[3], nums[4], nums[5]])),
+                    n => return Err(err(format!("Expected 3, 4 or 6 values for v, got {}", n))),
+                }
+                positions.push([nums[0], nums[1], nums[2]]);
+            }
+            "vt" => {
+                let nums = parse_floats(words).map_err(err)?;
+                if nums.is_empty() {
+                    return Err(err("Expected texture coordinates after vt".into()));
+                }
+                uvs.push([nums[0], nums.get(1).copied().unwrap_or(0.)]);
+            }
+            "vn" => {
+                let nums = parse_floats(words).map_err(err)?;
+                match nums.as_slice() {
+                    [x, y, z] => normals.push([*x, *y, *z]),
+                    _ => return Err(err(format!("Expected 3 values for vn, got {}", nums.len()))),
+                }
+            }
+            "f" => {
+                let mut face = vec![];
+                for word in words {
+                    let mut parts = word.split('/');
+                    let pos_idx = parts
+                        .next()
+                        .ok_or_else(|| err(format!("Empty face vertex {:?}", word)))
+                        .and_then(|s| resolve_index(s, positions.len()).map_err(err))?;
+
+                    let uv_idx = match parts.next() {
+                        Some("") | None => None,
+                        Some(s) => Some(resolve_index(s, uvs.len()).map_err(err)?),
+                    };
+
+                    let normal_idx = match parts.next() {
+                        Some("") | None => None,
+                        Some(s) => Some(resolve_index(s, normals.len()).map_err(err)?),
+                    };
+
+                    let color = colors[pos_idx].unwrap_or(material_color);
+                    let key = (pos_idx, uv_idx, normal_idx, color.map(f32::to_bits));
+
+                    let idx = *vertex_cache.entry(key).or_insert_with(|| {
+                        let uv = uv_idx.map(|i| uvs[i]).unwrap_or([0.; 2]);
+                        let normal = normal_idx.map(|i| normals[i]).unwrap_or([0.; 3]);
+                        let vertex =
+                            Vertex::new(positions[pos_idx], color).with_normal_uv(normal, uv);
+                        mesh.vertices.push(vertex);
+                        (mesh.vertices.len() - 1) as u32
+                    });
+
+                    face.push(idx);
+                }
+
+                if face.len() < 3 {
+                    return Err(err(format!("Face has only {} vertices", face.len())));
+                }
+
+                // Triangulate as a fan; OBJ uses counter-clockwise winding like we do
+                for i in 1..face.len() - 1 {
+                    mesh.indices.extend([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "mtllib" => {
+                for name in words {
+                    let path = dir.join(name);
+                    match load_mtl(&path) {
+                        Ok(lib) => materials.extend(lib),
+                        Err(e) => eprintln!("line {}: Skipping material library; {:#}", line_no, e),
+                    }
+                }
+            }
+            "usemtl" => {
+                let name = words.next().unwrap_or("");
+                material_color = match materials.get(name) {
+                    Some(color) => *color,
+                    None => {
+                        eprintln!("line {}: Unknown material {:?}", line_no, name);
+                        DEFAULT_COLOR
+                    }
+                };
+            }
+            // Groups, objects, smoothing groups, lines and points are not needed
+            "g" | "o" | "s" | "l" | "p" => (),
+            other => eprintln!("line {}: Ignoring unknown keyword {:?}", line_no, other),
+        }
+    }
+
+    if mesh.indices.is_empty() {
+        bail!("No faces found");
+    }
+
+    // Normals from the file may not be unit length
+    for vertex in &mut mesh.vertices {
+        vertex.normal = vertex
+            .normal
+            .try_normalize(0.)
+            .unwrap_or_else(Vector3::zeros);
+    }
+    mesh.compute_normals(NormalMode::Smooth, true);
+
+    Ok(mesh)
+}
+
+/// Load the diffuse colors from a material library
+fn load_mtl(path: &Path) -> Result<HashMap<String, [f32; 3]>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut materials = HashMap::new();
+    let mut current = None;
+
+    for (line_idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap().trim();
+        let mut words = line.split_whitespace();
+
+        match words.next() {
+            Some("newmtl") => {
+                let name = words.next().unwrap_or("").to_string();
+                materials.insert(name.clone(), DEFAULT_COLOR);
+                current = Some(name);
+            }
+            Some("Kd") => {
+                let nums = parse_floats(words)
+                    .map_err(|e| format_err!("{}:{}: {}", path.display(), line_idx + 1, e))?;
+                if let (Some(name), [r, g, b]) = (&current, nums.as_slice()) {
+                    materials.insert(name.clone(), [*r, *g, *b]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(materials)
+}
+
+/// Parse the remaining words of a line as floats
+fn parse_floats<'a>(words: impl Iterator<Item = &'a str>) -> Result<Vec<f32>, String> {
+    words
+        .map(|w| w.parse().map_err(|_| format!("Invalid number {:?}", w)))
+        .collect()
+}
+
+/// Convert a 1-based (or negative, relative to the end) OBJ index to a 0-based index
+fn resolve_index(s: &str, len: usize) -> Result<usize, String> {
+    let idx: i64 = s.parse().map_err(|_| format!("Invalid index {:?}", s))?;
+
+    let resolved = match idx {
+        0 => return Err("Index 0 is not valid in OBJ".into()),
+        i if i > 0 => i - 1,
+        i => len as i64 + i,
+    };
+
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(format!("Index {} out of range ({} elements)", idx, len));
+    }
+
+    Ok(resolved as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    fn parse(text: &str) -> Result<Mesh> {
+        parse_obj(text.as_bytes(), Path::new("."))
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let mesh = parse(
+            "# A unit square and a triangle beside it\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 2 0 0\n\
+             o square\n\
+             f 1 2 3 4\n\
+             f 2 5 3 # shares an edge\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 1, 4, 2]);
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.vertices[4].pos, Point3::new(2., 0., 0.));
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.color, Vector3::from(DEFAULT_COLOR));
+            // No normals in the file, so they are smoothed from the faces
+            assert!((vertex.normal - Vector3::z()).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn indices_count_back_from_the_end() {
+        let mesh = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 0 0 1\nf 1 -1 2\n").unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 3, 1]);
+        assert_eq!(resolve_index("1", 3), Ok(0));
+        assert_eq!(resolve_index("-1", 3), Ok(2));
+        assert_eq!(resolve_index("-3", 3), Ok(0));
+    }
+
+    #[test]
+    fn vertices_are_shared_only_when_identical() {
+        let mesh = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0 0.5 0.25 1\n\
+             vt 0 0\nvt 1\n\
+             vn 0 0 2\n\
+             f 1/1/1 2/2/1 3//1\n\
+             f 1/1/1 3//1 2/1/1\n",
+        )
+        .unwrap();
+        // The second corner differs in its UV, so it is a vertex of its own
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.vertices[1].uv, [1., 0.]);
+        assert_eq!(mesh.vertices[3].uv, [0., 0.]);
+        assert_eq!(mesh.vertices[2].color, Vector3::new(0.5, 0.25, 1.));
+        // Normals from the file are kept, at unit length
+        assert!(mesh.vertices.iter().all(|v| v.normal == Vector3::z()));
+    }
+
+    #[test]
+    fn windows_line_endings_are_accepted() {
+        let mesh = parse("v 0 0 0\r\nv 1 0 0 1\r\nv 0 1 0\r\nf 1 2 3\r\n").unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.vertices[1].pos, Point3::new(1., 0., 0.));
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_by_line() {
+        let cases = [
+            ("v 0 0\n", "line 1: Expected 3, 4 or 6 values for v, got 2"),
+            ("v 0 0 x\n", "line 1: Invalid number \"x\""),
+            ("vt\n", "line 1: Expected texture coordinates after vt"),
+            ("vn 0 1\n", "line 1: Expected 3 values for vn, got 2"),
+            (
+                "v 0 0 0\nv 1 0 0\nf 1 2\n",
+                "line 3: Face has only 2 vertices",
+            ),
+            (
+                "v 0 0 0\n\nf 1 1 0\n",
+                "line 3: Index 0 is not valid in OBJ",
+            ),
+            (
+                "v 0 0 0\nf 1 2 1\n",
+                "line 2: Index 2 out of range (1 elements)",
+            ),
+            (
+                "v 0 0 0\nf 1 -2 1\n",
+                "line 2: Index -2 out of range (1 elements)",
+            ),
+            (
+                "v 0 0 0\nf 1/1 1 1\n",
+                "line 2: Index 1 out of range (0 elements)",
+            ),
+            ("v 0 0 0\nf 1 a 1\n", "line 2: Invalid index \"a\""),
+            ("v 0 0 0\ng group\n", "No faces found"),
+            ("", "No faces found"),
+        ];
+        for (text, message) in cases {
+            assert_eq!(parse(text).unwrap_err().to_string(), message, "{:?}", text);
+        }
+
+        let latin1 = parse_obj(&b"v 0 0 0\n# caf\xe9\n"[..], Path::new("."));
+        assert_eq!(
+            latin1.unwrap_err().to_string(),
+            "line 2: stream did not contain valid UTF-8"
+        );
+    }
+
+    #[test]
+    fn materials_color_the_faces_after_them() {
+        let dir = std::env::temp_dir().join(format!("cubehead-obj-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("paint.mtl"),
+            "newmtl red\nKd 1 0 0\nnewmtl plain # no Kd\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("model.obj"),
+            "mtllib paint.mtl missing.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             f 1 2 3\n\
+             usemtl red\nf 1 2 3\n\
+             usemtl plain\nf 1 2 3\n\
+             usemtl unknown\nf 1 2 3\n",
+        )
+        .unwrap();
+        let mesh = load_obj(&dir.join("model.obj")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Each color gets its own copies of the corners
+        let colors: Vec<_> = mesh
+            .indices
+            .iter()
+            .map(|&i| mesh.vertices[i as usize].color)
+            .collect();
+        let (default, red) = (Vector3::from(DEFAULT_COLOR), Vector3::x());
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(colors[..3], [default; 3]);
+        assert_eq!(colors[3..6], [red; 3]);
+        assert_eq!(colors[6..], [default; 6]);
+    }
+}
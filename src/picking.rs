@@ -0,0 +1,283 @@
+//! Rays cast from the cursor or a controller into the world, for selecting and pointing at things
+use crate::render::Mesh;
+use nalgebra::{Matrix4, Point2, Point3, Vector3};
+
+/// A half-line in world space
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// Unit length
+    pub dir: Vector3<f32>,
+}
+
+/// Ray from `eye` through a point on screen, given in normalized device coordinates. None if the
+/// projection can't be inverted.
+pub fn cursor_ray(view_proj: &Matrix4<f32>, eye: Point3<f32>, ndc: Point2<f32>) -> Option<Ray> {
+    // NDC depth 1 is in front of the eye with both standard and reversed depth
+    let inv = view_proj.try_inverse()?;
+    let point = inv.transform_point(&Point3::new(ndc.x, ndc.y, 1.));
+    let dir = (point - eye).try_normalize(1e-9)?;
+    Some(Ray { origin: eye, dir })
+}
+
+/// Cursor position in pixels from the top left of the window, as normalized device coordinates
+/// of a viewport given as (x, y, width, height) from the bottom left
+pub fn cursor_ndc(cursor: (f32, f32), window_height: f32, viewport: [i32; 4]) -> Point2<f32> {
+    let [x, y, width, height] = viewport.map(|v| v as f32);
+    let from_bottom = window_height - cursor.1;
+    Point2::new(
+        (cursor.0 - x) / width * 2. - 1.,
+        (from_bottom - y) / height * 2. - 1.,
+    )
+}
+
+/// Distance along the ray to where it enters a sphere, or None if it misses. Rays starting
+/// inside the sphere hit at 0.
+pub fn ray_sphere(ray: &Ray, center: Point3<f32>, radius: f32) -> Option<f32> {
+    let to_center = center - ray.origin;
+    let along = to_center.dot(&ray.dir);
+    let miss_sq = to_center.norm_squared() - along * along;
+    let radius_sq = radius * radius;
+    if miss_sq > radius_sq {
+        return None;
+    }
+
+    let half_chord = (radius_sq - miss_sq).sqrt();
+    let (near, far) = (along - half_chord, along + half_chord);
+    if far < 0. {
+        return None;
+    }
+    Some(near.max(0.))
+}
+
+/// Distance along the ray to where it enters a box, given by its corners in the space of
+/// `transform`, or None if it misses. Rays starting inside the box hit at 0.
+pub fn ray_box(
+    ray: &Ray,
+    transform: &Matrix4<f32>,
+    (min, max): (Point3<f32>, Point3<f32>),
+) -> Option<f32> {
+    // Distances are the same in both spaces, since the direction isn't renormalized
+    let inv = transform.try_inverse()?;
+    let origin = inv.transform_point(&ray.origin);
+    let dir = inv.transform_vector(&ray.dir);
+
+    // Intersect the slabs between each pair of faces
+    let (mut near, mut far) = (0., f32::INFINITY);
+    for axis in 0..3 {
+        if dir[axis].abs() < 1e-9 {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let a = (min[axis] - origin[axis]) / dir[axis];
+        let b = (max[axis] - origin[axis]) / dir[axis];
+        near = a.min(b).max(near);
+        far = a.max(b).min(far);
+        if near > far {
+            return None;
+        }
+    }
+    Some(near)
+}
+
+/// Distance along the ray to where it hits a triangle from either side, or None if it misses
+pub fn ray_triangle(ray: &Ray, [a, b, c]: &[Point3<f32>; 3]) -> Option<f32> {
+    // Möller-Trumbore, solving for the barycentric coordinates (u, v) of the hit
+    let (ab, ac) = (b - a, c - a);
+    let p = ray.dir.cross(&ac);
+    let det = ab.dot(&p);
+    if det.abs() < 1e-9 {
+        return None;
+    }
+
+    let to_origin = ray.origin - a;
+    let u = to_origin.dot(&p) / det;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(&ab);
+    let v = ray.dir.dot(&q) / det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let distance = ac.dot(&q) / det;
+    (distance >= 0.).then_some(distance)
+}
+
+/// Triangles of a static mesh kept on the CPU, such as the map, for casting rays against
+pub struct TriangleMesh {
+    triangles: Vec<[Point3<f32>; 3]>,
+    bounds: (Point3<f32>, Point3<f32>),
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: &Mesh) -> Self {
+        let triangles: Vec<[Point3<f32>; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|tri| [0, 1, 2].map(|i| mesh.vertices[tri[i] as usize].pos))
+            .collect();
+
+        let mut corners = triangles.iter().flatten();
+        let first = corners.next().copied().unwrap_or_else(Point3::origin);
+        let bounds = corners.fold((first, first), |(min, max), p| (min.inf(p), max.sup(p)));
+
+        Self { triangles, bounds }
+    }
+
+    /// Distance along the ray to the nearest triangle it hits, or None if it misses them all
+    pub fn cast(&self, ray: &Ray) -> Option<f32> {
+        // Most rays pointing at the sky miss the whole mesh
+        ray_box(ray, &Matrix4::identity(), self.bounds)?;
+        self.triangles
+            .iter()
+            .filter_map(|triangle| ray_triangle(ray, triangle))
+            .reduce(f32::min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Vertex;
+
+    fn ray(origin: [f32; 3], dir: [f32; 3]) -> Ray {
+        Ray {
+            origin: origin.into(),
+            dir: Vector3::from(dir).normalize(),
+        }
+    }
+
+    #[test]
+    fn rays_enter_spheres_at_their_surface() {
+        let forward = ray([0., 0., 10.], [0., 0., -1.]);
+        assert_eq!(ray_sphere(&forward, Point3::origin(), 2.), Some(8.));
+        // A glancing hit still counts
+        let glancing = ray([2., 0., 10.], [0., 0., -1.]);
+        assert_eq!(ray_sphere(&glancing, Point3::origin(), 2.), Some(10.));
+        let off = ray([3., 0., 10.], [0., 0., -1.]);
+        assert_eq!(ray_sphere(&off, Point3::origin(), 2.), None);
+    }
+
+    #[test]
+    fn spheres_behind_the_ray_are_missed() {
+        let away = ray([0., 0., 10.], [0., 0., 1.]);
+        assert_eq!(ray_sphere(&away, Point3::origin(), 2.), None);
+        // Starting inside hits straight away, whichever way it points
+        let inside = ray([0., 1., 0.], [1., 0., 0.]);
+        assert_eq!(ray_sphere(&inside, Point3::origin(), 2.), Some(0.));
+        let inside = ray([0., 1., 0.], [0., -1., 0.]);
+        assert_eq!(ray_sphere(&inside, Point3::origin(), 2.), Some(0.));
+    }
+
+    #[test]
+    fn rays_enter_transformed_boxes() {
+        let cube = (Point3::new(-1., -1., -1.), Point3::new(1., 1., 1.));
+        let forward = ray([0., 0., 10.], [0., 0., -1.]);
+        assert_eq!(ray_box(&forward, &Matrix4::identity(), cube), Some(9.));
+        let moved = Matrix4::new_translation(&Vector3::new(0., 0., 4.));
+        assert_eq!(ray_box(&forward, &moved, cube), Some(5.));
+        let aside = Matrix4::new_translation(&Vector3::new(3., 0., 0.));
+        assert_eq!(ray_box(&forward, &aside, cube), None);
+
+        // Parallel to a face, inside or outside its slab
+        let along = ray([0.5, 0., 10.], [0., 0., -1.]);
+        assert_eq!(ray_box(&along, &Matrix4::identity(), cube), Some(9.));
+        let beside = ray([1.5, 0., 10.], [0., 0., -1.]);
+        assert_eq!(ray_box(&beside, &Matrix4::identity(), cube), None);
+
+        let inside = ray([0., 0., 0.], [1., 1., 0.]);
+        assert_eq!(ray_box(&inside, &Matrix4::identity(), cube), Some(0.));
+        let behind = ray([0., 0., 10.], [0., 0., 1.]);
+        assert_eq!(ray_box(&behind, &Matrix4::identity(), cube), None);
+    }
+
+    #[test]
+    fn rays_hit_triangles_from_either_side() {
+        let triangle = [
+            Point3::new(0., 0., 0.),
+            Point3::new(1., 0., 0.),
+            Point3::new(0., 1., 0.),
+        ];
+        let front = ray([0.25, 0.25, 4.], [0., 0., -1.]);
+        assert_eq!(ray_triangle(&front, &triangle), Some(4.));
+        let back = ray([0.25, 0.25, -2.], [0., 0., 1.]);
+        assert_eq!(ray_triangle(&back, &triangle), Some(2.));
+
+        // Past the long edge, behind, and edge on
+        let outside = ray([0.75, 0.75, 4.], [0., 0., -1.]);
+        assert_eq!(ray_triangle(&outside, &triangle), None);
+        let away = ray([0.25, 0.25, 4.], [0., 0., 1.]);
+        assert_eq!(ray_triangle(&away, &triangle), None);
+        let edge_on = ray([-1., 0.25, 0.], [1., 0., 0.]);
+        assert_eq!(ray_triangle(&edge_on, &triangle), None);
+    }
+
+    #[test]
+    fn meshes_are_hit_at_the_nearest_triangle() {
+        let vertex = |x, y, z| Vertex {
+            pos: Point3::new(x, y, z),
+            normal: Vector3::zeros(),
+            uv: [0.; 2],
+            color: Vector3::zeros(),
+        };
+        // Two squares, one behind the other
+        let mesh = Mesh {
+            vertices: vec![
+                vertex(-1., -1., 0.),
+                vertex(1., -1., 0.),
+                vertex(1., 1., 0.),
+                vertex(-1., 1., 0.),
+                vertex(-1., -1., -2.),
+                vertex(1., -1., -2.),
+                vertex(1., 1., -2.),
+                vertex(-1., 1., -2.),
+            ],
+            indices: vec![4, 5, 6, 4, 6, 7, 0, 1, 2, 0, 2, 3],
+        };
+        let triangles = TriangleMesh::new(&mesh);
+        let forward = ray([0.5, -0.25, 3.], [0., 0., -1.]);
+        assert_eq!(triangles.cast(&forward), Some(3.));
+        let up = ray([0.5, -0.25, 3.], [0., 1., 0.]);
+        assert_eq!(triangles.cast(&up), None);
+
+        let empty = TriangleMesh::new(&Mesh {
+            vertices: vec![],
+            indices: vec![],
+        });
+        assert_eq!(empty.cast(&forward), None);
+    }
+
+    #[test]
+    fn cursors_map_to_the_viewport() {
+        let viewport = [100, 0, 200, 100];
+        let center = cursor_ndc((200., 150.), 200., viewport);
+        assert_eq!(center, Point2::new(0., 0.));
+        let corner = cursor_ndc((100., 200.), 200., viewport);
+        assert_eq!(corner, Point2::new(-1., -1.));
+        let corner = cursor_ndc((300., 100.), 200., viewport);
+        assert_eq!(corner, Point2::new(1., 1.));
+    }
+
+    #[test]
+    fn cursor_rays_go_through_the_screen() {
+        let eye = Point3::new(0., 1., 5.);
+        let view = Matrix4::look_at_rh(&eye, &Point3::new(0., 1., 0.), &Vector3::y());
+        let proj = Matrix4::new_perspective(1., 1., 0.1, 100.);
+        let view_proj = proj * view;
+
+        let center = cursor_ray(&view_proj, eye, Point2::origin()).unwrap();
+        assert_eq!(center.origin, eye);
+        assert!((center.dir - -Vector3::z()).norm() < 1e-4);
+
+        // A 1 radian field of view puts the top edge half of that above the middle
+        let top = cursor_ray(&view_proj, eye, Point2::new(0., 1.)).unwrap();
+        assert!((top.dir.y.asin() - 0.5).abs() < 1e-3);
+        assert!(top.dir.x.abs() < 1e-4);
+
+        assert!(cursor_ray(&Matrix4::zeros(), eye, Point2::origin()).is_none());
+    }
+}
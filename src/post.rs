@@ -0,0 +1,360 @@
+//! Off-screen render targets for post-processing, applied by the `Engine` at the end of a frame
+use crate::render::RenderError;
+use glow::HasContext;
+use std::num::NonZeroU32;
+
+/// Targets are allocated in steps of this many pixels on each side, and drawn into from the
+/// bottom left corner, so that small changes in the size drawn, such as from dynamic resolution,
+/// reuse them
+const SIZE_STEP: i32 = 128;
+
+/// A fullscreen effect applied to the rendered frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    /// Fast approximate anti-aliasing, for when multisampling is unavailable
+    Fxaa,
+    /// Darken the edges of the view by the engine's vignette radius, for comfort while moving
+    /// artificially in VR
+    Vignette,
+}
+
+/// Framebuffer with a color texture, and optionally a depth texture
+pub struct PostTarget {
+    pub framebuffer: gl::NativeFramebuffer,
+    pub color: gl::NativeTexture,
+    pub depth: Option<gl::NativeTexture>,
+}
+
+impl PostTarget {
+    pub fn new(
+        gl: &gl::Context,
+        size: (i32, i32),
+        color_format: u32,
+        with_depth: bool,
+    ) -> Result<Self, RenderError> {
+        unsafe {
+            let color = create_target_texture(
+                gl,
+                size,
+                color_format,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                gl::LINEAR,
+            )?;
+            let depth = if with_depth {
+                Some(create_target_texture(
+                    gl,
+                    size,
+                    gl::DEPTH_COMPONENT24,
+                    gl::DEPTH_COMPONENT,
+                    gl::UNSIGNED_INT,
+                    gl::NEAREST,
+                )?)
+            } else {
+                None
+            };
+
+            let framebuffer = gl.create_framebuffer()?;
+            gl.bind_framebuffer(gl::FRAMEBUFFER, Some(framebuffer));
+            gl.framebuffer_texture_2d(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                Some(color),
+                0,
+            );
+            gl.framebuffer_texture_2d(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_ATTACHMENT,
+                gl::TEXTURE_2D,
+                depth,
+                0,
+            );
+
+            let status = gl.check_framebuffer_status(gl::FRAMEBUFFER);
+            gl.bind_framebuffer(gl::FRAMEBUFFER, None);
+
+            let target = Self {
+                framebuffer,
+                color,
+                depth,
+            };
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                target.delete(gl);
+                return Err(RenderError::FramebufferIncomplete {
+                    target: "Post-processing",
+                    status,
+                });
+            }
+
+            Ok(target)
+        }
+    }
+
+    pub fn delete(&self, gl: &gl::Context) {
+        unsafe {
+            gl.delete_framebuffer(self.framebuffer);
+            gl.delete_texture(self.color);
+            if let Some(depth) = self.depth {
+                gl.delete_texture(depth);
+            }
+        }
+    }
+}
+
+/// Multisampled framebuffer the scene is drawn to when the destination is multisampled, resolved
+/// into the single sampled scene target before the effects read it
+pub struct MultisampleTarget {
+    pub framebuffer: gl::NativeFramebuffer,
+    color: gl::NativeRenderbuffer,
+    depth: gl::NativeRenderbuffer,
+}
+
+impl MultisampleTarget {
+    pub fn new(
+        gl: &gl::Context,
+        (width, height): (i32, i32),
+        color_format: u32,
+        samples: i32,
+    ) -> Result<Self, RenderError> {
+        unsafe {
+            let framebuffer = gl.create_framebuffer()?;
+            gl.bind_framebuffer(gl::FRAMEBUFFER, Some(framebuffer));
+
+            // The formats match the scene target, which resolving requires
+            let renderbuffer = |format, attachment| -> Result<_, RenderError> {
+                let renderbuffer = gl.create_renderbuffer()?;
+                gl.bind_renderbuffer(gl::RENDERBUFFER, Some(renderbuffer));
+                gl.renderbuffer_storage_multisample(
+                    gl::RENDERBUFFER,
+                    samples,
+                    format,
+                    width,
+                    height,
+                );
+                gl.framebuffer_renderbuffer(
+                    gl::FRAMEBUFFER,
+                    attachment,
+                    gl::RENDERBUFFER,
+                    Some(renderbuffer),
+                );
+                Ok(renderbuffer)
+            };
+            let color = renderbuffer(color_format, gl::COLOR_ATTACHMENT0)?;
+            let depth = renderbuffer(gl::DEPTH_COMPONENT24, gl::DEPTH_ATTACHMENT)?;
+
+            let status = gl.check_framebuffer_status(gl::FRAMEBUFFER);
+            gl.bind_renderbuffer(gl::RENDERBUFFER, None);
+            gl.bind_framebuffer(gl::FRAMEBUFFER, None);
+
+            let target = Self {
+                framebuffer,
+                color,
+                depth,
+            };
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                target.delete(gl);
+                return Err(RenderError::FramebufferIncomplete {
+                    target: "Multisampled post-processing",
+                    status,
+                });
+            }
+
+            Ok(target)
+        }
+    }
+
+    /// Resolve the color and depth samples into `scene`, leaving it bound for drawing
+    pub fn resolve(&self, gl: &gl::Context, scene: &PostTarget, (width, height): (i32, i32)) {
+        unsafe {
+            gl.bind_framebuffer(gl::READ_FRAMEBUFFER, Some(self.framebuffer));
+            gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, Some(scene.framebuffer));
+            gl.blit_framebuffer(
+                0,
+                0,
+                width,
+                height,
+                0,
+                0,
+                width,
+                height,
+                gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT,
+                gl::NEAREST,
+            );
+            gl.bind_framebuffer(gl::READ_FRAMEBUFFER, None);
+        }
+    }
+
+    pub fn delete(&self, gl: &gl::Context) {
+        unsafe {
+            gl.delete_framebuffer(self.framebuffer);
+            gl.delete_renderbuffer(self.color);
+            gl.delete_renderbuffer(self.depth);
+        }
+    }
+}
+
+/// The intermediate targets used when rendering to one destination framebuffer. Each VR eye has
+/// its own, so that one eye's post-processing never waits on the other's.
+pub struct PostTargets {
+    /// Framebuffer the final pass draws to; None is the window
+    pub destination: Option<gl::NativeFramebuffer>,
+    /// Size allocated, at least the size drawn
+    pub size: (i32, i32),
+    pub color_format: u32,
+    /// Samples per pixel of the destination, which the scene is drawn with too; 0 if it isn't
+    /// multisampled
+    pub samples: i32,
+    /// The scene is rendered here, with depth
+    pub scene: PostTarget,
+    /// Where the scene is rendered instead, and then resolved into `scene`, if the destination
+    /// is multisampled
+    pub multisample: Option<MultisampleTarget>,
+    /// Color targets that effects before the last one draw to, alternating between them. The
+    /// scene is never drawn over, since its depth is read by every pass.
+    pub swap: Vec<PostTarget>,
+}
+
+impl PostTargets {
+    pub fn new(
+        gl: &gl::Context,
+        destination: Option<gl::NativeFramebuffer>,
+        size: (i32, i32),
+        color_format: u32,
+        samples: i32,
+        effects: usize,
+    ) -> Result<Self, RenderError> {
+        let size = allocated_size(size);
+        let mut targets = Self {
+            destination,
+            size,
+            color_format,
+            samples,
+            scene: PostTarget::new(gl, size, color_format, true)?,
+            multisample: None,
+            swap: vec![],
+        };
+
+        if samples > 0 {
+            match MultisampleTarget::new(gl, size, color_format, samples) {
+                Ok(target) => targets.multisample = Some(target),
+                Err(e) => {
+                    targets.delete(gl);
+                    return Err(e);
+                }
+            }
+        }
+
+        for _ in 0..swap_count(effects) {
+            match PostTarget::new(gl, size, color_format, false) {
+                Ok(target) => targets.swap.push(target),
+                Err(e) => {
+                    targets.delete(gl);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(targets)
+    }
+
+    /// Whether these targets can be reused for a frame of `size` with the given parameters
+    pub fn matches(
+        &self,
+        size: (i32, i32),
+        color_format: u32,
+        samples: i32,
+        effects: usize,
+    ) -> bool {
+        self.size == allocated_size(size)
+            && self.color_format == color_format
+            && self.samples == samples
+            && self.swap.len() == swap_count(effects)
+    }
+
+    /// Framebuffer the scene is drawn to
+    pub fn scene_framebuffer(&self) -> gl::NativeFramebuffer {
+        match &self.multisample {
+            Some(multisample) => multisample.framebuffer,
+            None => self.scene.framebuffer,
+        }
+    }
+
+    pub fn delete(&self, gl: &gl::Context) {
+        self.scene.delete(gl);
+        if let Some(multisample) = &self.multisample {
+            multisample.delete(gl);
+        }
+        for swap in &self.swap {
+            swap.delete(gl);
+        }
+    }
+}
+
+/// Size to allocate targets at for drawing `size`, rounded up to whole steps
+fn allocated_size((width, height): (i32, i32)) -> (i32, i32) {
+    let round_up = |n: i32| (n.max(1) + SIZE_STEP - 1) / SIZE_STEP * SIZE_STEP;
+    (round_up(width), round_up(height))
+}
+
+/// Number of swap targets needed to chain the given number of effects
+fn swap_count(effects: usize) -> usize {
+    effects.saturating_sub(1).min(2)
+}
+
+/// The draw framebuffer currently bound, so that it can be rebound after drawing elsewhere
+pub fn bound_draw_framebuffer(gl: &gl::Context) -> Option<gl::NativeFramebuffer> {
+    unsafe {
+        let name = gl.get_parameter_i32(gl::DRAW_FRAMEBUFFER_BINDING);
+        NonZeroU32::new(name as u32).map(gl::NativeFramebuffer)
+    }
+}
+
+/// Samples per pixel of the draw framebuffer currently bound, 0 if it isn't multisampled
+pub fn bound_draw_samples(gl: &gl::Context) -> i32 {
+    unsafe { gl.get_parameter_i32(gl::SAMPLES) }
+}
+
+unsafe fn create_target_texture(
+    gl: &gl::Context,
+    (width, height): (i32, i32),
+    internal_format: u32,
+    format: u32,
+    ty: u32,
+    filter: u32,
+) -> Result<gl::NativeTexture, RenderError> {
+    let tex = gl.create_texture()?;
+    gl.bind_texture(gl::TEXTURE_2D, Some(tex));
+    gl.tex_image_2d(
+        gl::TEXTURE_2D,
+        0,
+        internal_format as i32,
+        width,
+        height,
+        0,
+        format,
+        ty,
+        None,
+    );
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as _);
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as _);
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+    gl.bind_texture(gl::TEXTURE_2D, None);
+    Ok(tex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_allocated_in_steps() {
+        assert_eq!(allocated_size((1, 128)), (128, 128));
+        assert_eq!(allocated_size((129, 1000)), (256, 1024));
+        assert_eq!(allocated_size((0, 0)), (128, 128));
+        // Dynamic resolution shrinking a view a little keeps its targets
+        assert_eq!(allocated_size((1900, 1900)), (1920, 1920));
+        assert_eq!(allocated_size((1843, 1843)), (1920, 1920));
+    }
+}
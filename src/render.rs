@@ -1,13 +1,164 @@
+use crate::comfort;
+use crate::debug_lines::{DebugLines, LineMesh, LineVertex};
+use crate::frustum::{side_planes, sphere_inside, CullCamera};
+use crate::gpu_timer::{GpuPass, GpuTimer, GpuTimings};
+use crate::minimap::{self, MINIMAP_INTERVAL, MINIMAP_TEXTURE_SIZE};
+use crate::post::{self, PostEffect, PostTarget, PostTargets};
+use crate::stats::StatsOverlay;
+use crate::text::{self, TextVertex};
 use bytemuck::{Pod, Zeroable};
 use cubehead::Head;
 use glow::HasContext;
-use nalgebra::{Matrix4, Point3, Vector3};
+use nalgebra::{Matrix3, Matrix4, Point3, UnitQuaternion, Vector2, Vector3, Vector4};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-/// Vertex representation used by the rendering engine
+/// Whether `check_gl_error!` polls for errors
+static GL_DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// If GL debugging is enabled, log any pending GL errors along with the call site
+macro_rules! check_gl_error {
+    ($gl:expr, $context:expr) => {
+        if $crate::render::gl_debug_enabled() {
+            for err in $crate::render::take_gl_errors($gl) {
+                eprintln!(
+                    "GL error {} at {}:{} ({})",
+                    $crate::render::gl_error_name(err),
+                    file!(),
+                    line!(),
+                    $context
+                );
+            }
+        }
+    };
+}
+pub(crate) use check_gl_error;
+
+/// Errors from the render engine
+#[derive(Debug)]
+pub enum RenderError {
+    /// A shader stage failed to compile; `log` is the driver's info log
+    ShaderCompile {
+        shader: &'static str,
+        stage: u32,
+        log: String,
+    },
+    /// A shader program failed to link
+    ProgramLink { shader: &'static str, log: String },
+    /// A shader lacks a uniform the engine sets, usually because it was optimized out
+    MissingUniform {
+        shader: &'static str,
+        uniform: &'static str,
+    },
+    /// A shader source file couldn't be read
+    ShaderRead {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// GL failed to create an object, such as a buffer or texture
+    ObjectCreate(String),
+    /// A framebuffer is missing attachments or uses unsupported formats
+    FramebufferIncomplete { target: &'static str, status: u32 },
+    /// GL reported errors during something which must succeed
+    Gl {
+        context: &'static str,
+        errors: Vec<u32>,
+    },
+    /// An image couldn't be decoded
+    ImageLoad { path: PathBuf, error: String },
+    /// An image couldn't be encoded or written
+    ImageSave { path: PathBuf, error: String },
+    /// An image's pixel data doesn't match its size
+    ImageSize { width: u32, height: u32, len: usize },
+    /// The engine was configured with values out of range
+    InvalidConfig(String),
+    /// A mesh failed validation before upload
+    InvalidMesh(MeshError),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::ShaderCompile { shader, stage, log } => write!(
+                f,
+                "Failed to compile the {} stage of shader \"{}\"; {}",
+                shader_stage_name(*stage),
+                shader,
+                log
+            ),
+            Self::ProgramLink { shader, log } => {
+                write!(f, "Failed to link shader \"{}\"; {}", shader, log)
+            }
+            Self::MissingUniform { shader, uniform } => write!(
+                f,
+                "Shader \"{}\" has no uniform \"{}\" (was it optimized out?)",
+                shader, uniform
+            ),
+            Self::ShaderRead { path, error } => {
+                write!(f, "Failed to read {}; {}", path.display(), error)
+            }
+            Self::ObjectCreate(e) => write!(f, "Failed to create GL object; {}", e),
+            Self::FramebufferIncomplete { target, status } => write!(
+                f,
+                "{} framebuffer incomplete (status 0x{:X})",
+                target, status
+            ),
+            Self::Gl { context, errors } => {
+                let names: Vec<&str> = errors.iter().map(|e| gl_error_name(*e)).collect();
+                write!(f, "GL errors {}: {}", context, names.join(", "))
+            }
+            Self::ImageLoad { path, error } => write!(f, "{}: {}", path.display(), error),
+            Self::ImageSave { path, error } => {
+                write!(f, "Failed to save {}; {}", path.display(), error)
+            }
+            Self::ImageSize { width, height, len } => write!(
+                f,
+                "A {}x{} image has {} bytes of pixel data instead of {}",
+                width,
+                height,
+                len,
+                *width as usize * *height as usize * 4
+            ),
+            Self::InvalidConfig(e) => write!(f, "{}", e),
+            Self::InvalidMesh(e) => write!(f, "Invalid mesh; {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ShaderRead { error, .. } => Some(error),
+            Self::InvalidMesh(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<MeshError> for RenderError {
+    fn from(e: MeshError) -> Self {
+        Self::InvalidMesh(e)
+    }
+}
+
+/// GL object creation reports errors as strings
+impl From<String> for RenderError {
+    fn from(e: String) -> Self {
+        Self::ObjectCreate(e)
+    }
+}
+
+/// Vertex representation used by the rendering engine. Fields must match `VERTEX_ATTRIBS`.
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct Vertex {
     pub pos: Point3<f32>,
+    /// Unit length, or zero if unknown
+    pub normal: Vector3<f32>,
+    pub uv: [f32; 2],
+    /// Linear RGB; shaders output linear color, which is encoded as sRGB once on output
     pub color: Vector3<f32>,
 }
 
@@ -15,6 +166,50 @@ pub struct Vertex {
 unsafe impl Zeroable for Vertex {}
 unsafe impl Pod for Vertex {}
 
+/// Shader location and float count of each field of `Vertex`, in order
+const VERTEX_ATTRIBS: [(u32, usize); 4] = [
+    (0, 3), // pos
+    (3, 3), // normal
+    (2, 2), // uv
+    (1, 3), // color
+];
+
+/// Total size in bytes of a list of float attributes
+const fn attribs_size(attribs: &[(u32, usize)]) -> usize {
+    let mut floats = 0;
+    let mut i = 0;
+    while i < attribs.len() {
+        floats += attribs[i].1;
+        i += 1;
+    }
+    floats * std::mem::size_of::<f32>()
+}
+
+// Catch fields being added to Vertex without an attribute, or the other way around
+const _: () = assert!(std::mem::size_of::<Vertex>() == attribs_size(&VERTEX_ATTRIBS));
+
+/// Shader location and float count of each field of `Instance`, after those of `Vertex`. A
+/// mat4 takes up four locations, one per column.
+const INSTANCE_ATTRIBS: [(u32, usize); 6] = [
+    (4, 4), // transform
+    (5, 4),
+    (6, 4),
+    (7, 4),
+    (8, 4), // tint
+    (9, 1), // phase
+];
+
+/// Shader location and float count of each field of `Billboard`
+const BILLBOARD_ATTRIBS: [(u32, usize); 4] = [
+    (0, 3), // pos
+    (1, 1), // size
+    (2, 4), // color
+    (3, 4), // uv_rect
+];
+
+const _: () = assert!(std::mem::size_of::<Instance>() == attribs_size(&INSTANCE_ATTRIBS));
+const _: () = assert!(std::mem::size_of::<Billboard>() == attribs_size(&BILLBOARD_ATTRIBS));
+
 /// A 4x4 matrix as nested arrays
 type RawMatrix = [[f32; 4]; 4];
 
@@ -25,293 +220,4645 @@ pub struct Mesh {
     pub vertices: Vec<Vertex>,
 }
 
-const MAX_HEADS: usize = 500;
+/// Chains `Mesh` operations, for putting together meshes from simpler ones
+pub struct MeshBuilder {
+    mesh: Mesh,
+}
 
-/// Rendering engine state
-pub struct Engine {
-    // NOTE: We do not call destructors!
-    map: GpuMesh,
-    head: GpuMesh,
+impl MeshBuilder {
+    pub fn new(mesh: Mesh) -> Self {
+        Self { mesh }
+    }
 
-    head_inst_vbo: gl::NativeBuffer,
-    head_count: usize,
+    /// As `Mesh::transform`
+    pub fn transform(mut self, matrix: &Matrix4<f32>) -> Self {
+        self.mesh.transform(matrix);
+        self
+    }
 
-    map_shader: gl::Program,
-    head_shader: gl::Program,
-}
+    /// As `Mesh::append`
+    pub fn append(mut self, other: &Mesh) -> Result<Self, MeshError> {
+        self.mesh.append(other)?;
+        Ok(self)
+    }
 
-struct GpuMesh {
-    vao: gl::VertexArray,
-    _vbo: gl::NativeBuffer,
-    _ebo: gl::NativeBuffer,
-    index_count: i32,
-}
+    /// As `Mesh::recolor`
+    pub fn recolor(self, color: [f32; 3]) -> Self {
+        self.map_colors(|_| color.into())
+    }
 
-impl Engine {
-    pub fn new(gl: &gl::Context, map_mesh: &Mesh, head_mesh: &Mesh) -> Result<Self, String> {
-        unsafe {
-            // Enable backface culling
-            gl.enable(gl::CULL_FACE);
+    /// As `Mesh::map_colors`
+    pub fn map_colors(mut self, f: impl FnMut(Vector3<f32>) -> Vector3<f32>) -> Self {
+        self.mesh.map_colors(f);
+        self
+    }
 
-            // Enable depth buffering
-            gl.enable(gl::DEPTH_TEST);
-            gl.depth_func(gl::LESS);
+    pub fn build(self) -> Mesh {
+        self.mesh
+    }
+}
 
-            // Compile shaders
-            let map_shader = compile_glsl_program(
-                &gl,
-                &[
-                    (gl::VERTEX_SHADER, include_str!("shaders/map.vert")),
-                    (gl::FRAGMENT_SHADER, include_str!("shaders/unlit.frag")),
-                ],
-            )?;
+/// How `Mesh::compute_normals` shades across the edges between triangles
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NormalMode {
+    /// Each triangle gets its own vertices and shows as a flat facet. This changes the vertex
+    /// and index counts.
+    Flat,
+    /// Vertices shared between triangles average their normals, shading smoothly across
+    Smooth,
+}
 
-            // Compile shaders
-            let head_shader = compile_glsl_program(
-                &gl,
-                &[
-                    (gl::VERTEX_SHADER, include_str!("shaders/head.vert")),
-                    (gl::FRAGMENT_SHADER, include_str!("shaders/unlit.frag")),
-                ],
-            )?;
-
-            // Upload head mesh
-            let head = upload_mesh(gl, gl::STATIC_DRAW, head_mesh)?;
-
-            // Upload map mesh
-            let map = upload_mesh(gl, gl::DYNAMIC_DRAW, map_mesh)?;
-
-            // Create head instance buffer
-            gl.bind_vertex_array(Some(head.vao));
-            let head_inst_vbo = gl.create_buffer()?;
-            gl.bind_buffer(gl::ARRAY_BUFFER, Some(head_inst_vbo));
-            gl.buffer_data_size(
-                gl::ARRAY_BUFFER,
-                (std::mem::size_of::<RawMatrix>() * MAX_HEADS) as i32,
-                gl::DYNAMIC_DRAW,
-            );
-            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+/// Problems found by `Mesh::validate`
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshError {
+    /// There are no triangles
+    Empty,
+    /// The number of indices is not a multiple of 3
+    PartialTriangle { indices: usize },
+    /// The index at position `at` refers past the end of the vertices
+    IndexOutOfRange {
+        at: usize,
+        index: u32,
+        vertices: usize,
+    },
+    /// A vertex has an infinite or NaN attribute
+    NonFinite { vertex: usize },
+    /// More vertices or indices than the limits allow
+    TooLarge { vertices: usize, indices: usize },
+    /// More vertices than u32 indices can refer to
+    IndexOverflow { vertices: usize },
+}
 
-            // Set up instance buffer
-            gl.bind_buffer(gl::ARRAY_BUFFER, Some(head_inst_vbo));
-            for i in 0..4 {
-                let attrib_idx = 2 + i;
-                gl.enable_vertex_attrib_array(attrib_idx);
-                gl.vertex_attrib_pointer_f32(
-                    attrib_idx,
-                    4,
-                    gl::FLOAT,
-                    false,
-                    std::mem::size_of::<RawMatrix>() as i32,
-                    i as i32 * std::mem::size_of::<[f32; 4]>() as i32,
-                );
-                gl.vertex_attrib_divisor(attrib_idx, 1);
+impl std::fmt::Display for MeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "mesh has no triangles"),
+            Self::PartialTriangle { indices } => {
+                write!(f, "{} indices is not a whole number of triangles", indices)
+            }
+            Self::IndexOutOfRange {
+                at,
+                index,
+                vertices,
+            } => write!(
+                f,
+                "index {} at position {} is out of range for {} vertices",
+                index, at, vertices
+            ),
+            Self::NonFinite { vertex } => write!(f, "vertex {} is not finite", vertex),
+            Self::TooLarge { vertices, indices } => write!(
+                f,
+                "{} vertices and {} indices is over the limit",
+                vertices, indices
+            ),
+            Self::IndexOverflow { vertices } => {
+                write!(f, "{} vertices is too many for 32-bit indices", vertices)
             }
-            gl.bind_buffer(gl::ARRAY_BUFFER, None);
-            gl.bind_vertex_array(None);
-
-            Ok(Self {
-                head_inst_vbo,
-                head_count: 0,
-                head,
-                map,
-                map_shader,
-                head_shader,
-            })
         }
     }
+}
 
-    /// Update head positions  
-    pub fn update_heads(&mut self, gl: &gl::Context, heads: &[RawMatrix]) {
-        assert!(heads.len() <= MAX_HEADS);
-        unsafe {
-            gl.bind_buffer(gl::ARRAY_BUFFER, Some(self.head_inst_vbo));
-            gl.buffer_sub_data_u8_slice(gl::ARRAY_BUFFER, 0, bytemuck::cast_slice(heads));
-            gl.bind_buffer(gl::ARRAY_BUFFER, None);
-            self.head_count = heads.len();
+impl std::error::Error for MeshError {}
+
+/// Largest mesh the engine will upload
+#[derive(Copy, Clone, Debug)]
+pub struct MeshLimits {
+    pub max_vertices: usize,
+    pub max_indices: usize,
+}
+
+impl Default for MeshLimits {
+    fn default() -> Self {
+        Self {
+            max_vertices: 1 << 22,
+            max_indices: 3 << 22,
         }
     }
+}
 
-    /// The given heads will be rendered using the provided projection matrix and view Head
-    /// position
-    pub fn frame(
-        &mut self,
-        gl: &gl::Context,
-        proj: Matrix4<f32>,
-        view: Matrix4<f32>,
-        //view: Head,
-    ) -> Result<(), String> {
-        unsafe {
-            // Clear depth and color buffers
-            gl.clear_color(0.1, 0.2, 0.3, 1.0);
-            gl.clear_depth_f32(1.);
-            gl.clear(gl::COLOR_BUFFER_BIT | gl::STENCIL_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+/// 8-bit RGBA image, rows are tightly packed starting from the top
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
 
-            let set_camera_uniforms = |shader| {
-                // Set camera matrix
-                gl.uniform_matrix_4_f32_slice(
-                    gl.get_uniform_location(shader, "view").as_ref(),
-                    false,
-                    view.as_slice(),
-                );
+/// Handle to a texture owned by the `Engine`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TextureId(usize);
 
-                gl.uniform_matrix_4_f32_slice(
-                    gl.get_uniform_location(shader, "proj").as_ref(),
-                    false,
-                    proj.as_slice(),
-                );
-            };
+/// Handle to a mesh owned by the `Engine`. It is valid from `Engine::add_mesh` until it is
+/// passed to `Engine::remove_mesh`; using it afterwards panics.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MeshHandle {
+    index: usize,
+    generation: u32,
+}
+
+/// How a mesh is shaded
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RenderStyle {
+    /// Vertex colors
+    Unlit,
+    /// A texture, sampled with the vertex UVs
+    Textured(TextureId),
+    /// Vertex colors with the given opacity, blended over the opaque geometry
+    Translucent { alpha: f32 },
+}
 
-            // Draw map
-            gl.use_program(Some(self.map_shader));
-            set_camera_uniforms(self.map_shader);
+/// Placement of one copy of a mesh
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Instance {
+    pub transform: RawMatrix,
+    /// Multiplies the color of the mesh. Instances with an alpha below 1 are drawn translucent,
+    /// whatever the style of the mesh.
+    pub tint: [f32; 4],
+    /// Offset of the idle animation of heads, in cycles, so that heads don't move in sync. An
+    /// attribute of its own rather than hashed from the transform, which would make the phase
+    /// drift as the head moves.
+    pub phase: f32,
+}
 
-            gl.bind_vertex_array(Some(self.map.vao));
-            gl.draw_elements(gl::TRIANGLES, self.map.index_count, gl::UNSIGNED_INT, 0);
-            gl.bind_vertex_array(None);
+// Allow Instance to be cast to bytes using bytemuck
+unsafe impl Zeroable for Instance {}
+unsafe impl Pod for Instance {}
 
-            // Draw heads
-            gl.use_program(Some(self.head_shader));
-            set_camera_uniforms(self.head_shader);
+/// A camera-facing square, drawn with alpha blending after the opaque geometry
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Billboard {
+    /// Center in world space
+    pub pos: [f32; 3],
+    /// Width and height in meters
+    pub size: f32,
+    /// Linear RGBA, multiplying the billboard texture if there is one
+    pub color: [f32; 4],
+    /// Region of the billboard texture shown, as (u, v) of the top left and bottom right
+    pub uv_rect: [f32; 4],
+}
 
-            gl.bind_vertex_array(Some(self.head.vao));
-            gl.draw_elements_instanced(
-                gl::TRIANGLES,
-                self.head.index_count,
-                gl::UNSIGNED_INT,
-                0,
-                self.head_count as i32,
-            );
-            gl.bind_vertex_array(None);
+// Allow Billboard to be cast to bytes using bytemuck
+unsafe impl Zeroable for Billboard {}
+unsafe impl Pod for Billboard {}
 
-            Ok(())
+impl Billboard {
+    /// A billboard showing the whole texture
+    pub fn new(pos: Point3<f32>, size: f32, color: [f32; 4]) -> Self {
+        Self {
+            pos: pos.into(),
+            size,
+            color,
+            uv_rect: [0., 0., 1., 1.],
         }
     }
 }
 
-/// Creates a view matrix for the given head position
-pub fn view_from_head(head: &Head) -> Matrix4<f32> {
-    // Invert this quaternion, orienting the world into NDC space
-    // Represent the rotation in homogeneous coordinates
-    let rotation = head.orient.inverse().to_homogeneous();
+/// Default background color, also cleared to behind the sky, in sRGB with alpha
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.2, 0.3, 1.];
 
-    // Invert this translation, translating the world into NDC space
-    let translation = Matrix4::new_translation(&-head.pos.coords);
+/// Horizon color of the procedural sky, in linear RGB
+const HORIZON_COLOR: [f32; 3] = [0.45, 0.55, 0.65];
 
-    // Compose the view
-    rotation * translation
-}
+/// Background of the minimap where there is no map, in linear RGB
+const MINIMAP_BACKGROUND: [f32; 3] = [0.02, 0.02, 0.03];
 
-/// Compiles (*_SHADER, <source>) into a shader program for OpenGL
-fn compile_glsl_program(gl: &gl::Context, sources: &[(u32, &str)]) -> Result<gl::Program, String> {
-    // Compile default shaders
-    unsafe {
-        let program = gl.create_program().expect("Cannot create program");
+/// Fog density given to the shaders when fog is off
+const NO_FOG: f32 = 0.;
 
-        let mut shaders = vec![];
+/// Height of a line of the statistics overlay in pixels, one font pixel per screen pixel
+const STATS_LINE_HEIGHT: f32 = 16.;
 
-        for (stage, shader_source) in sources {
-            let shader = gl.create_shader(*stage).expect("Cannot create shader");
+/// What is drawn behind all other geometry
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sky {
+    /// Procedural vertical gradient with a sun disc
+    Gradient,
+    /// Cubemap loaded with `Engine::load_cubemap`
+    Cubemap(TextureId),
+}
 
-            gl.shader_source(shader, shader_source);
+/// Upper limit of `EngineConfig::max_heads`
+pub const MAX_HEADS_LIMIT: usize = 100_000;
 
-            gl.compile_shader(shader);
+/// Heads further away than this are drawn as simple boxes, by default
+pub const DEFAULT_HEAD_LOD_DISTANCE: f32 = 40.;
 
-            if !gl.get_shader_compile_status(shader) {
-                return Err(gl.get_shader_info_log(shader));
-            }
+/// Heads further away than this are not drawn, by default
+pub const DEFAULT_HEAD_DRAW_DISTANCE: f32 = 250.;
 
-            gl.attach_shader(program, shader);
+/// Fraction of the draw distance at which heads start to fade out
+const HEAD_FADE_START: f32 = 0.8;
 
-            shaders.push(shader);
-        }
+/// Extra room given to heads at the edges of the view before they are culled, in meters, so that
+/// their shadows don't vanish as they leave it
+const HEAD_CULL_MARGIN: f32 = 2.;
 
-        gl.link_program(program);
+/// Number of heads drawn in detail and as boxes by `Engine::update_heads`, and the number left
+/// out for being too far away, out of view or over the head limit
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HeadCounts {
+    pub full: usize,
+    pub lod: usize,
+    pub culled: usize,
+}
 
-        if !gl.get_program_link_status(program) {
-            return Err(gl.get_program_info_log(program));
-        }
+/// What the engine did over one displayed frame, from `Engine::last_frame_stats`.
+///
+/// Counters add up across every view and pass between calls to `Engine::end_frame`. Any new pass
+/// must report here:
+/// - each draw call through `FrameStats::draw`, fullscreen passes included
+/// - each write to a GPU buffer through `FrameStats::upload`
+/// - instances given to the engine but left out of drawing through `FrameStats::cull`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    /// Instances drawn, summed over draw calls
+    pub instances: u64,
+    /// Instances left out for being too far away or over a limit
+    pub culled: u64,
+    /// Estimated triangles drawn. Lines count as none.
+    pub triangles: u64,
+    /// Bytes written to GPU buffers
+    pub upload_bytes: u64,
+}
 
-        for shader in shaders {
-            gl.detach_shader(program, shader);
-            gl.delete_shader(shader);
-        }
+impl FrameStats {
+    /// Record a draw call of `instances` copies of `triangles` triangles
+    fn draw(&mut self, instances: usize, triangles: usize) {
+        debug_assert!(instances > 0, "draw call without instances");
+        self.draw_calls += 1;
+        self.instances += instances as u64;
+        self.triangles += instances as u64 * triangles as u64;
+    }
 
-        Ok(program)
+    /// Record a write of `bytes` to a GPU buffer
+    fn upload(&mut self, bytes: usize) {
+        self.upload_bytes += bytes as u64;
+    }
+
+    /// Record that `kept` of `submitted` instances are drawn, and the rest culled
+    fn cull(&mut self, submitted: usize, kept: usize) {
+        debug_assert!(
+            kept <= submitted,
+            "kept {} of {} instances",
+            kept,
+            submitted
+        );
+        self.culled += (submitted - kept) as u64;
     }
 }
 
-impl Vertex {
-    pub fn new(pos: [f32; 3], color: [f32; 3]) -> Self {
+/// Options fixed when the engine is created
+#[derive(Copy, Clone, Debug)]
+pub struct EngineConfig {
+    /// Most heads drawn at once; space for this many is allocated up front. Must be between 1
+    /// and `MAX_HEADS_LIMIT`.
+    pub max_heads: usize,
+    /// Meshes larger than this are rejected by `Engine::add_mesh` and `Engine::update_mesh`
+    pub mesh_limits: MeshLimits,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
         Self {
-            pos: pos.into(),
-            color: color.into(),
+            max_heads: 500,
+            mesh_limits: MeshLimits::default(),
         }
     }
 }
 
-fn set_vertex_attrib(gl: &gl::Context) {
-    unsafe {
-        // Set vertex attributes
-        gl.enable_vertex_attrib_array(0);
-        gl.vertex_attrib_pointer_f32(
-            0,
-            3,
-            gl::FLOAT,
-            false,
-            std::mem::size_of::<Vertex>() as i32,
-            0,
-        );
+/// How the background of each frame is filled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BackgroundMode {
+    /// A solid color, in sRGB with alpha
+    Solid([f32; 4]),
+    /// Transparent black, for displays which blend the frame with the real world
+    Transparent,
+    /// A sky drawn behind all other geometry
+    Sky(Sky),
+}
 
-        gl.enable_vertex_attrib_array(1);
-        gl.vertex_attrib_pointer_f32(
-            1,
-            3,
-            gl::FLOAT,
-            false,
-            std::mem::size_of::<Vertex>() as i32,
-            3 * std::mem::size_of::<f32>() as i32,
-        );
-    }
+/// How the engine shades geometry, for debugging
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugMode {
+    /// Normal rendering
+    Solid,
+    /// Triangle edges only
+    Wireframe,
+    /// Face normals (computed from screen-space derivatives) as colors
+    Normals,
 }
 
-/// Uploads a mesh; does not unbind vertex array
-fn upload_mesh(gl: &gl::Context, usage: u32, mesh: &Mesh) -> Result<GpuMesh, String> {
-    unsafe {
-        // Map buffer
-        let vao = gl.create_vertex_array()?;
-        let vbo = gl.create_buffer()?;
-        let ebo = gl.create_buffer()?;
+/// Value of the `debug_mode` uniform for wireframes drawn from barycentric coordinates, where
+/// glPolygonMode is unavailable
+const BARYCENTRIC_WIREFRAME: i32 = 3;
 
-        gl.bind_vertex_array(Some(vao));
+/// Rendering engine state
+pub struct Engine {
+    // NOTE: We do not call destructors!
+    /// Mesh registry, drawn in order
+    meshes: Vec<MeshSlot>,
+    map: MeshHandle,
+    head: MeshHandle,
+    /// Box with the bounds of the head mesh, drawn for distant heads
+    head_lod: MeshHandle,
+    max_heads: usize,
+    mesh_limits: MeshLimits,
+    head_lod_distance: f32,
+    head_draw_distance: f32,
+    /// Seconds on a monotonic clock, driving the idle animation of heads
+    time: f32,
+    idle_animation: bool,
 
-        // Write vertices
-        gl.bind_buffer(gl::ARRAY_BUFFER, Some(vbo));
-        gl.buffer_data_u8_slice(
-            gl::ARRAY_BUFFER,
-            bytemuck::cast_slice(&mesh.vertices),
-            usage,
-        );
+    unlit_shader: Shader,
+    textured_shader: Shader,
 
-        // Write vertices
-        gl.bind_buffer(gl::ELEMENT_ARRAY_BUFFER, Some(ebo));
-        gl.buffer_data_u8_slice(
-            gl::ELEMENT_ARRAY_BUFFER,
-            bytemuck::cast_slice(&mesh.indices),
-            usage,
-        );
+    sky_shader: Shader,
+    /// Attributeless vertex array for fullscreen passes
+    empty_vao: gl::VertexArray,
+    grid_shader: Shader,
+    /// Draw a reference grid on the ground plane
+    grid: bool,
 
-        // Set vertex attributes
-        set_vertex_attrib(gl);
+    billboard_shader: Shader,
+    billboards: BillboardBuffer,
+    billboard_texture: Option<TextureId>,
+    /// Orientation billboards face, instead of that of each view
+    billboard_facing: Option<UnitQuaternion<f32>>,
 
-        // Unbind vertex array
-        gl.bind_vertex_array(None);
+    minimap_shader: Shader,
+    /// Created on first use
+    minimap_target: Option<PostTarget>,
+    /// Dots marking each head on the minimap
+    minimap_dots: BillboardBuffer,
+    /// Calls to `render_minimap` left until it redraws
+    minimap_countdown: u32,
 
-        Ok(GpuMesh {
-            vao,
-            _vbo: vbo,
-            _ebo: ebo,
-            index_count: mesh.indices.len() as i32,
-        })
+    textures: Vec<gl::NativeTexture>,
+    background: BackgroundMode,
+    sun_dir: Vector3<f32>,
+    /// Fog color in linear RGB, and density per meter
+    fog: ([f32; 3], f32),
+
+    shadow_shader: Shader,
+    /// Depth-only framebuffer the shadow map is rendered to
+    shadow_fbo: gl::NativeFramebuffer,
+    shadow_map: gl::NativeTexture,
+    shadows: bool,
+    /// Constant and slope-scaled depth bias, in shadow map depth units
+    shadow_bias: (f32, f32),
+    /// Projection from world space into the shadow map, from the last `render_shadows`
+    light_view_proj: Matrix4<f32>,
+
+    /// Effects applied to each frame, in order. With none, frames are drawn straight to the
+    /// bound framebuffer.
+    post_effects: Vec<PostEffect>,
+    /// Radius of the clear part of the view left by `PostEffect::Vignette`
+    vignette_radius: f32,
+    fxaa_shader: Shader,
+    vignette_shader: Shader,
+    /// Intermediate targets for each destination framebuffer, created on first use
+    post_targets: Vec<PostTargets>,
+
+    /// None if the context has no timer queries
+    gpu_timer: Option<GpuTimer>,
+    /// Counters for the frame in progress, and the last one completed
+    frame_stats: FrameStats,
+    last_frame_stats: FrameStats,
+
+    debug_mode: DebugMode,
+    /// Whether glPolygonMode is available (desktop GL only)
+    polygon_mode_supported: bool,
+
+    text_shader: Shader,
+    text_vao: gl::VertexArray,
+    text_vbo: gl::NativeBuffer,
+    /// Label vertices, rebuilt for each view
+    text_vertices: Vec<TextVertex>,
+    font_atlas: TextureId,
+    labels_through_walls: bool,
+
+    lines_shader: Shader,
+    lines_vao: gl::VertexArray,
+    lines_vbo: gl::NativeBuffer,
+    debug_lines: DebugLines,
+    /// Number of line vertices in `lines_vbo`
+    lines_uploaded: i32,
+    /// Axis-aligned bounds of the map
+    map_bounds: (Point3<f32>, Point3<f32>),
+    /// Bounds of the head mesh, in its own space
+    head_bounds: (Point3<f32>, Point3<f32>),
+
+    /// Directory shaders are reloaded from when they change
+    shader_dir: Option<PathBuf>,
+    last_shader_poll: Instant,
+
+    /// Whether the render target encodes sRGB in hardware when GL_FRAMEBUFFER_SRGB is enabled
+    srgb_target: bool,
+    /// Whether linear shader output is encoded as sRGB at all, for comparison
+    gamma_correct: bool,
+    /// Whether GL_FRAMEBUFFER_SRGB can be toggled (desktop GL only; GLES always encodes)
+    framebuffer_srgb_supported: bool,
+    /// Whether depth is reversed (1 near, 0 at infinity) for precision at distance. Requires
+    /// glClipControl; projection matrices must match, see `reverse_z`.
+    reverse_z: bool,
+}
+
+/// State saved by `Engine::begin_post` for `end_post`
+struct PostFrame {
+    /// Index into `Engine::post_targets`
+    targets: usize,
+    /// Viewport of the original framebuffer
+    viewport: [i32; 4],
+    /// Whether the scissor test was enabled
+    scissor: bool,
+}
+
+/// A shader program and its uniform locations
+struct Shader {
+    desc: &'static ShaderDesc,
+    program: gl::Program,
+    uniforms: ShaderUniforms,
+    /// Latest modification time of the source files, if loaded from files
+    modified: Option<SystemTime>,
+}
+
+/// The sources of a shader program
+struct ShaderDesc {
+    name: &'static str,
+    /// Stage, file name within the shader directory, and the embedded source
+    stages: &'static [(u32, &'static str, &'static str)],
+    /// Uniforms the program must have
+    required: &'static [&'static str],
+}
+
+/// A shader stage from a file in src/shaders
+macro_rules! shader_stage {
+    ($stage:expr, $file:literal) => {
+        ($stage, $file, include_str!(concat!("shaders/", $file)))
+    };
+}
+
+/// Code shared by the shaders of a stage, inserted after the #version line of each
+const PRELUDES: &[(u32, &str, &str)] = &[
+    shader_stage!(gl::VERTEX_SHADER, "common.vert"),
+    shader_stage!(gl::FRAGMENT_SHADER, "common.frag"),
+];
+
+const UNLIT_SHADER: ShaderDesc = ShaderDesc {
+    name: "unlit",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "mesh.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "unlit.frag"),
+    ],
+    required: &[
+        "view",
+        "proj",
+        "debug_mode",
+        "encode_srgb",
+        "fog_color",
+        "fog_density",
+        "alpha",
+        "fade_range",
+        "time",
+        "idle",
+        "light_view_proj",
+        "shadows",
+        "shadow_map",
+        "sun_dir",
+        "shadow_bias",
+        "shadow_slope_bias",
+    ],
+};
+
+const TEXTURED_SHADER: ShaderDesc = ShaderDesc {
+    name: "textured",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "mesh.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "textured.frag"),
+    ],
+    required: &[
+        "view",
+        "proj",
+        "debug_mode",
+        "tex",
+        "encode_srgb",
+        "fog_color",
+        "fog_density",
+        "alpha",
+        "fade_range",
+        "time",
+        "idle",
+        "light_view_proj",
+        "shadows",
+        "shadow_map",
+        "sun_dir",
+        "shadow_bias",
+        "shadow_slope_bias",
+    ],
+};
+
+const SKY_SHADER: ShaderDesc = ShaderDesc {
+    name: "sky",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "sky.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "sky.frag"),
+    ],
+    required: &[
+        "inv_view_proj",
+        "sun_dir",
+        "use_cubemap",
+        "cubemap",
+        "encode_srgb",
+    ],
+};
+
+const GRID_SHADER: ShaderDesc = ShaderDesc {
+    name: "grid",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "grid.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "grid.frag"),
+    ],
+    required: &[
+        "view_proj",
+        "inv_view_proj",
+        "camera_pos",
+        "depth_zero_to_one",
+        "encode_srgb",
+    ],
+};
+
+const BILLBOARD_SHADER: ShaderDesc = ShaderDesc {
+    name: "billboard",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "billboard.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "billboard.frag"),
+    ],
+    required: &[
+        "view",
+        "proj",
+        "facing",
+        "use_texture",
+        "tex",
+        "fog_color",
+        "fog_density",
+        "encode_srgb",
+    ],
+};
+
+const MINIMAP_SHADER: ShaderDesc = ShaderDesc {
+    name: "minimap",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "minimap.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "minimap.frag"),
+    ],
+    required: &["rect", "tex", "border", "heading", "encode_srgb"],
+};
+
+const SHADOW_SHADER: ShaderDesc = ShaderDesc {
+    name: "shadow",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "shadow.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "shadow.frag"),
+    ],
+    required: &["light_view_proj", "time", "idle"],
+};
+
+const FXAA_SHADER: ShaderDesc = ShaderDesc {
+    name: "fxaa",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "post.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "fxaa.frag"),
+    ],
+    required: &["source", "depth", "texel_size", "linear_source", "uv_scale"],
+};
+
+const VIGNETTE_SHADER: ShaderDesc = ShaderDesc {
+    name: "vignette",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "post.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "vignette.frag"),
+    ],
+    required: &["source", "depth", "center", "radius", "uv_scale"],
+};
+
+const TEXT_SHADER: ShaderDesc = ShaderDesc {
+    name: "text",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "text.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "text.frag"),
+    ],
+    required: &["view", "proj", "tex", "encode_srgb"],
+};
+
+const LINES_SHADER: ShaderDesc = ShaderDesc {
+    name: "lines",
+    stages: &[
+        shader_stage!(gl::VERTEX_SHADER, "lines.vert"),
+        shader_stage!(gl::FRAGMENT_SHADER, "lines.frag"),
+    ],
+    required: &["view", "proj", "encode_srgb"],
+};
+
+/// Width and height of the shadow map in texels
+const SHADOW_MAP_SIZE: i32 = 2048;
+
+/// How often shader files are checked for changes
+const SHADER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Locations of every uniform used by the engine's shaders, looked up once when the program is
+/// created. Uniforms a program doesn't use are None. New uniforms go here, and in the list of
+/// required uniforms of each shader which uses them.
+struct ShaderUniforms<L = gl::UniformLocation> {
+    view: Option<L>,
+    proj: Option<L>,
+    debug_mode: Option<L>,
+    tex: Option<L>,
+    inv_view_proj: Option<L>,
+    view_proj: Option<L>,
+    camera_pos: Option<L>,
+    depth_zero_to_one: Option<L>,
+    facing: Option<L>,
+    use_texture: Option<L>,
+    rect: Option<L>,
+    border: Option<L>,
+    heading: Option<L>,
+    sun_dir: Option<L>,
+    use_cubemap: Option<L>,
+    cubemap: Option<L>,
+    encode_srgb: Option<L>,
+    fog_color: Option<L>,
+    fog_density: Option<L>,
+    alpha: Option<L>,
+    fade_range: Option<L>,
+    time: Option<L>,
+    idle: Option<L>,
+    light_view_proj: Option<L>,
+    shadows: Option<L>,
+    shadow_map: Option<L>,
+    shadow_bias: Option<L>,
+    shadow_slope_bias: Option<L>,
+    source: Option<L>,
+    depth: Option<L>,
+    texel_size: Option<L>,
+    uv_scale: Option<L>,
+    linear_source: Option<L>,
+    center: Option<L>,
+    radius: Option<L>,
+}
+
+struct GpuMesh {
+    vao: gl::VertexArray,
+    vbo: gl::NativeBuffer,
+    ebo: gl::NativeBuffer,
+    /// Allocated sizes of `vbo` and `ebo` in bytes
+    vbo_capacity: usize,
+    ebo_capacity: usize,
+    usage: u32,
+    index_count: i32,
+    /// The vertices of each triangle in turn, with their count, for wireframes drawn from
+    /// barycentric coordinates when glPolygonMode is unavailable
+    unindexed: Option<(gl::NativeBuffer, i32)>,
+}
+
+/// Instance buffer of billboards, with a vertex array reading it. Quad corners come from the
+/// vertex index, so there is no vertex buffer.
+struct BillboardBuffer {
+    vao: gl::VertexArray,
+    vbo: gl::NativeBuffer,
+    /// Allocated size of `vbo` in bytes
+    capacity: usize,
+    count: usize,
+}
+
+impl BillboardBuffer {
+    fn new(gl: &gl::Context) -> Result<Self, RenderError> {
+        unsafe {
+            let vao = gl.create_vertex_array()?;
+            let vbo = gl.create_buffer()?;
+            gl.bind_vertex_array(Some(vao));
+            gl.bind_buffer(gl::ARRAY_BUFFER, Some(vbo));
+            set_float_attribs(
+                gl,
+                &BILLBOARD_ATTRIBS,
+                std::mem::size_of::<Billboard>(),
+                0,
+                1,
+            );
+            gl.bind_vertex_array(None);
+            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+
+            Ok(Self {
+                vao,
+                vbo,
+                capacity: 0,
+                count: 0,
+            })
+        }
+    }
+
+    fn set(&mut self, gl: &gl::Context, billboards: &[Billboard], stats: &mut FrameStats) {
+        let needed = std::mem::size_of_val(billboards);
+        unsafe {
+            if needed > self.capacity {
+                let size = grown_capacity(needed, self.capacity);
+                reserve_buffer(gl, self.vbo, &mut self.capacity, size);
+            }
+            gl.bind_buffer(gl::ARRAY_BUFFER, Some(self.vbo));
+            gl.buffer_sub_data_u8_slice(gl::ARRAY_BUFFER, 0, bytemuck::cast_slice(billboards));
+            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+        }
+        stats.upload(needed);
+        self.count = billboards.len();
+    }
+
+    fn draw(&self, gl: &gl::Context, stats: &mut FrameStats) {
+        if self.count == 0 {
+            return;
+        }
+        unsafe {
+            gl.bind_vertex_array(Some(self.vao));
+            gl.draw_arrays_instanced(gl::TRIANGLE_STRIP, 0, 4, self.count as i32);
+            gl.bind_vertex_array(None);
+        }
+        stats.draw(self.count, 2);
+    }
+}
+
+/// Registry slot. Removing a mesh empties its slot for reuse and bumps the generation, so that
+/// old handles to it can be detected.
+struct MeshSlot {
+    generation: u32,
+    entry: Option<MeshEntry>,
+}
+
+/// A mesh in the registry, with its instances
+struct MeshEntry {
+    gpu: GpuMesh,
+    style: RenderStyle,
+    instance_vbo: gl::NativeBuffer,
+    /// Allocated size of `instance_vbo` in bytes
+    instance_capacity: usize,
+    /// Drawn without backface culling
+    double_sided: bool,
+    /// Drawn first around the camera, like a sky
+    follow_camera: bool,
+    /// Copy of the instance buffer, with opaque instances first
+    instances: Vec<Instance>,
+    opaque_count: usize,
+    /// The instances before `first_translucent` as last uploaded, to find which changed. The
+    /// translucent ones are reordered in the buffer while drawing, so they aren't tracked.
+    uploaded: Vec<Instance>,
+}
+
+impl MeshEntry {
+    /// Index of the first instance drawn in the translucent pass
+    fn first_translucent(&self) -> usize {
+        match self.style {
+            RenderStyle::Translucent { .. } => 0,
+            _ => self.opaque_count,
+        }
+    }
+
+    /// Draw instances of the mesh, turning backface culling off for the draw if it is double
+    /// sided
+    fn draw(&self, gl: &gl::Context, instances: i32, unindexed: bool, stats: &mut FrameStats) {
+        if !self.double_sided {
+            self.gpu.draw(gl, instances, unindexed, stats);
+            return;
+        }
+        unsafe {
+            let culling = gl.is_enabled(gl::CULL_FACE);
+            gl.disable(gl::CULL_FACE);
+            self.gpu.draw(gl, instances, unindexed, stats);
+            if culling {
+                gl.enable(gl::CULL_FACE);
+            }
+        }
+    }
+}
+
+impl Engine {
+    pub fn new(
+        gl: &gl::Context,
+        map_mesh: &Mesh,
+        head_mesh: &Mesh,
+        config: EngineConfig,
+    ) -> Result<Self, RenderError> {
+        if !(1..=MAX_HEADS_LIMIT).contains(&config.max_heads) {
+            return Err(RenderError::InvalidConfig(format!(
+                "Maximum head count must be between 1 and {}, not {}",
+                MAX_HEADS_LIMIT, config.max_heads
+            )));
+        }
+
+        unsafe {
+            set_default_state(gl);
+
+            // Compile shaders
+            let unlit_shader = Shader::new(gl, &UNLIT_SHADER, None)?;
+            let textured_shader = Shader::new(gl, &TEXTURED_SHADER, None)?;
+            let sky_shader = Shader::new(gl, &SKY_SHADER, None)?;
+            let grid_shader = Shader::new(gl, &GRID_SHADER, None)?;
+            let billboard_shader = Shader::new(gl, &BILLBOARD_SHADER, None)?;
+            let minimap_shader = Shader::new(gl, &MINIMAP_SHADER, None)?;
+            let shadow_shader = Shader::new(gl, &SHADOW_SHADER, None)?;
+            let fxaa_shader = Shader::new(gl, &FXAA_SHADER, None)?;
+            let vignette_shader = Shader::new(gl, &VIGNETTE_SHADER, None)?;
+            let text_shader = Shader::new(gl, &TEXT_SHADER, None)?;
+            let lines_shader = Shader::new(gl, &LINES_SHADER, None)?;
+
+            let empty_vao = gl.create_vertex_array()?;
+            let billboards = BillboardBuffer::new(gl)?;
+            let minimap_dots = BillboardBuffer::new(gl)?;
+            let (shadow_fbo, shadow_map) = create_shadow_map(gl)?;
+
+            // Anything going wrong during setup is fatal
+            let errors = take_gl_errors(gl);
+            if !errors.is_empty() {
+                return Err(RenderError::Gl {
+                    context: "during setup",
+                    errors,
+                });
+            }
+
+            let polygon_mode_supported = !gl.version().is_embedded;
+
+            // Map clip space depth to 0..1 rather than -1..1, so that reversed depth keeps its
+            // precision near the far end
+            let reverse_z = clip_control_supported(gl);
+            if reverse_z {
+                gl.clip_control(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+            }
+
+            // Create label buffers, filled for each view
+            let text_vao = gl.create_vertex_array()?;
+            let text_vbo = gl.create_buffer()?;
+            gl.bind_vertex_array(Some(text_vao));
+            gl.bind_buffer(gl::ARRAY_BUFFER, Some(text_vbo));
+            let stride = std::mem::size_of::<TextVertex>() as i32;
+            gl.enable_vertex_attrib_array(0);
+            gl.vertex_attrib_pointer_f32(0, 3, gl::FLOAT, false, stride, 0);
+            gl.enable_vertex_attrib_array(1);
+            gl.vertex_attrib_pointer_f32(
+                1,
+                2,
+                gl::FLOAT,
+                false,
+                stride,
+                3 * std::mem::size_of::<f32>() as i32,
+            );
+            gl.enable_vertex_attrib_array(2);
+            gl.vertex_attrib_pointer_f32(
+                2,
+                4,
+                gl::FLOAT,
+                false,
+                stride,
+                5 * std::mem::size_of::<f32>() as i32,
+            );
+            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+            gl.bind_vertex_array(None);
+
+            // Create debug line buffers, filled when lines change
+            let lines_vao = gl.create_vertex_array()?;
+            let lines_vbo = gl.create_buffer()?;
+            gl.bind_vertex_array(Some(lines_vao));
+            gl.bind_buffer(gl::ARRAY_BUFFER, Some(lines_vbo));
+            let stride = std::mem::size_of::<LineVertex>() as i32;
+            gl.enable_vertex_attrib_array(0);
+            gl.vertex_attrib_pointer_f32(0, 3, gl::FLOAT, false, stride, 0);
+            gl.enable_vertex_attrib_array(1);
+            gl.vertex_attrib_pointer_f32(
+                1,
+                3,
+                gl::FLOAT,
+                false,
+                stride,
+                3 * std::mem::size_of::<f32>() as i32,
+            );
+            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+            gl.bind_vertex_array(None);
+
+            let errors = take_gl_errors(gl);
+            if !errors.is_empty() {
+                return Err(RenderError::Gl {
+                    context: "uploading meshes",
+                    errors,
+                });
+            }
+
+            // Placeholder handles, replaced once the engine exists
+            let placeholder = MeshHandle {
+                index: 0,
+                generation: 0,
+            };
+
+            let mut engine = Self {
+                meshes: vec![],
+                map: placeholder,
+                head: placeholder,
+                head_lod: placeholder,
+                max_heads: config.max_heads,
+                mesh_limits: config.mesh_limits,
+                head_lod_distance: DEFAULT_HEAD_LOD_DISTANCE,
+                head_draw_distance: DEFAULT_HEAD_DRAW_DISTANCE,
+                time: 0.,
+                idle_animation: true,
+                unlit_shader,
+                textured_shader,
+                sky_shader,
+                empty_vao,
+                grid_shader,
+                grid: false,
+                billboard_shader,
+                billboards,
+                billboard_texture: None,
+                billboard_facing: None,
+                minimap_shader,
+                minimap_target: None,
+                minimap_dots,
+                minimap_countdown: 0,
+                textures: vec![],
+                background: BackgroundMode::Solid(CLEAR_COLOR),
+                sun_dir: Vector3::new(0.3, 0.6, -0.75).normalize(),
+                fog: (HORIZON_COLOR, NO_FOG),
+                shadow_shader,
+                shadow_fbo,
+                shadow_map,
+                shadows: false,
+                shadow_bias: (0.002, 0.005),
+                light_view_proj: Matrix4::identity(),
+                post_effects: vec![],
+                vignette_radius: comfort::OPEN_RADIUS,
+                fxaa_shader,
+                vignette_shader,
+                post_targets: vec![],
+                gpu_timer: GpuTimer::new(gl),
+                frame_stats: FrameStats::default(),
+                last_frame_stats: FrameStats::default(),
+                debug_mode: DebugMode::Solid,
+                polygon_mode_supported,
+                text_shader,
+                text_vao,
+                text_vbo,
+                text_vertices: vec![],
+                font_atlas: TextureId(0),
+                labels_through_walls: false,
+                lines_shader,
+                lines_vao,
+                lines_vbo,
+                debug_lines: DebugLines::default(),
+                lines_uploaded: 0,
+                map_bounds: mesh_bounds(map_mesh),
+                head_bounds: mesh_bounds(head_mesh),
+                shader_dir: None,
+                last_shader_poll: Instant::now(),
+                srgb_target: false,
+                gamma_correct: true,
+                framebuffer_srgb_supported: !gl.version().is_embedded,
+                reverse_z,
+            };
+
+            // The font atlas is always the first texture
+            engine.font_atlas = engine.load_texture(gl, &text::font_atlas())?;
+
+            // The map is drawn once, in place, and replaced by `update_map`. Heads have no
+            // instances until `update_heads`. Maps are often open surfaces, so both sides of
+            // them are drawn.
+            engine.map =
+                engine.add_mesh_with_usage(gl, map_mesh, RenderStyle::Unlit, gl::DYNAMIC_DRAW)?;
+            engine.set_double_sided(engine.map, true);
+            engine.set_instances(gl, engine.map, &[Instance::new(Matrix4::identity())]);
+            engine.head = engine.add_mesh(gl, head_mesh, RenderStyle::Unlit)?;
+            engine.reserve_instances(gl, engine.head, config.max_heads);
+            engine.head_lod = engine.add_mesh(gl, &lod_box(head_mesh), RenderStyle::Unlit)?;
+
+            Ok(engine)
+        }
+    }
+
+    /// Upload an sRGB encoded image as a mipmapped, repeating texture
+    pub fn load_texture(
+        &mut self,
+        gl: &gl::Context,
+        image: &RgbaImage,
+    ) -> Result<TextureId, RenderError> {
+        if image.data.len() != image.width as usize * image.height as usize * 4 {
+            return Err(RenderError::ImageSize {
+                width: image.width,
+                height: image.height,
+                len: image.data.len(),
+            });
+        }
+
+        unsafe {
+            let tex = gl.create_texture()?;
+            gl.bind_texture(gl::TEXTURE_2D, Some(tex));
+
+            // Rows of non-power-of-two images are not necessarily aligned
+            gl.pixel_store_i32(gl::UNPACK_ALIGNMENT, 1);
+
+            // Images are authored in sRGB; let the sampler convert to linear for us
+            gl.tex_image_2d(
+                gl::TEXTURE_2D,
+                0,
+                gl::SRGB8_ALPHA8 as i32,
+                image.width as i32,
+                image.height as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                Some(&image.data),
+            );
+            gl.generate_mipmap(gl::TEXTURE_2D);
+
+            // Sampler state
+            gl.tex_parameter_i32(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_MIN_FILTER,
+                gl::LINEAR_MIPMAP_LINEAR as _,
+            );
+            gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+            gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as _);
+            gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as _);
+
+            gl.bind_texture(gl::TEXTURE_2D, None);
+
+            self.textures.push(tex);
+            Ok(TextureId(self.textures.len() - 1))
+        }
+    }
+
+    /// Upload six sRGB encoded images as a cubemap, in the order +X, -X, +Y, -Y, +Z, -Z
+    pub fn load_cubemap(
+        &mut self,
+        gl: &gl::Context,
+        faces: &[RgbaImage; 6],
+    ) -> Result<TextureId, RenderError> {
+        unsafe {
+            let tex = gl.create_texture()?;
+            gl.bind_texture(gl::TEXTURE_CUBE_MAP, Some(tex));
+            gl.pixel_store_i32(gl::UNPACK_ALIGNMENT, 1);
+
+            for (i, face) in faces.iter().enumerate() {
+                assert_eq!(
+                    face.data.len(),
+                    face.width as usize * face.height as usize * 4
+                );
+
+                gl.tex_image_2d(
+                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + i as u32,
+                    0,
+                    gl::SRGB8_ALPHA8 as i32,
+                    face.width as i32,
+                    face.height as i32,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    Some(&face.data),
+                );
+            }
+
+            let target = gl::TEXTURE_CUBE_MAP;
+            gl.tex_parameter_i32(target, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+            gl.tex_parameter_i32(target, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+            gl.tex_parameter_i32(target, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+            gl.tex_parameter_i32(target, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+            gl.tex_parameter_i32(target, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as _);
+            gl.enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+
+            gl.bind_texture(gl::TEXTURE_CUBE_MAP, None);
+
+            self.textures.push(tex);
+            Ok(TextureId(self.textures.len() - 1))
+        }
+    }
+
+    /// Set what is drawn behind all other geometry
+    pub fn set_background(&mut self, background: BackgroundMode) {
+        self.background = background;
+    }
+
+    /// Fill the background with a solid color, given in sRGB
+    pub fn set_clear_color(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        self.set_background(BackgroundMode::Solid([r, g, b, a]));
+    }
+
+    /// Color in linear RGB that distant geometry should fade to, to blend in with the background
+    pub fn background_color(&self) -> [f32; 3] {
+        match self.background {
+            BackgroundMode::Solid([r, g, b, _]) => [r, g, b].map(srgb_to_linear),
+            BackgroundMode::Transparent => [0.; 3],
+            BackgroundMode::Sky(_) => HORIZON_COLOR,
+        }
+    }
+
+    /// Set whether a reference grid with lines every meter is drawn over the ground plane
+    pub fn set_grid(&mut self, enabled: bool) {
+        self.grid = enabled;
+    }
+
+    /// Set whether the map and heads cast shadows from the sun. `render_shadows` must be called
+    /// before the frames which use them.
+    pub fn set_shadows(&mut self, enabled: bool) {
+        self.shadows = enabled;
+    }
+
+    /// Set the depth bias used when sampling the shadow map. The slope-scaled part is added on
+    /// surfaces at a grazing angle to the sun. Too little bias gives shadow acne; too much
+    /// detaches shadows from their casters.
+    pub fn set_shadow_bias(&mut self, constant: f32, slope: f32) {
+        self.shadow_bias = (constant, slope);
+    }
+
+    /// Set the effects applied to each frame, in order. With none, the scene is drawn straight to
+    /// the bound framebuffer, and intermediate targets are freed.
+    pub fn set_post_effects(&mut self, gl: &gl::Context, effects: &[PostEffect]) {
+        self.post_effects = effects.to_vec();
+        if effects.is_empty() {
+            for targets in self.post_targets.drain(..) {
+                targets.delete(gl);
+            }
+        }
+    }
+
+    /// Set the fog color (in linear RGB, like the sky) and density per meter. Fog thickens with
+    /// the square of the distance from the eye; a density of zero turns it off.
+    pub fn set_fog(&mut self, color: [f32; 3], density: f32) {
+        self.fog = (color, density.max(NO_FOG));
+    }
+
+    /// Set the texture used to draw the map, or None to use vertex colors only
+    pub fn set_map_texture(&mut self, texture: Option<TextureId>) {
+        let style = texture.map_or(RenderStyle::Unlit, RenderStyle::Textured);
+        self.set_style(self.map, style);
+    }
+
+    /// Set the time in seconds, from a monotonic clock, which animates idle heads
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    /// Set whether heads gently bob and breathe. Some people find any motion they don't cause
+    /// uncomfortable in VR.
+    pub fn set_idle_animation(&mut self, enabled: bool) {
+        self.idle_animation = enabled;
+    }
+
+    /// Set whether the back of the map is drawn, which it is by default
+    pub fn set_map_double_sided(&mut self, double_sided: bool) {
+        self.set_double_sided(self.map, double_sided);
+    }
+
+    /// Set how geometry is shaded
+    pub fn set_debug_mode(&mut self, mode: DebugMode) {
+        self.debug_mode = mode;
+    }
+
+    /// Set whether labels are visible through other geometry
+    pub fn set_labels_through_walls(&mut self, through_walls: bool) {
+        self.labels_through_walls = through_walls;
+    }
+
+    /// Enable debug lines, optionally drawn on top of all other geometry. While disabled, the
+    /// `debug_*` methods do nothing.
+    pub fn set_debug_lines(&mut self, enabled: bool, xray: bool) {
+        self.debug_lines.enabled = enabled;
+        self.debug_lines.xray = xray;
+        if !enabled {
+            self.debug_lines.clear();
+        }
+    }
+
+    /// Remove all debug lines; call once per frame before adding new ones
+    pub fn clear_debug_lines(&mut self) {
+        self.debug_lines.clear();
+    }
+
+    /// Draw a line from `a` to `b` until the next `clear_debug_lines`
+    pub fn debug_line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) {
+        self.debug_lines.line(a, b, color);
+    }
+
+    /// Draw the axes of a transform as red, green and blue lines
+    pub fn debug_axes(&mut self, transform: &Matrix4<f32>, size: f32) {
+        self.debug_lines.axes(transform, size);
+    }
+
+    /// Draw the segments of a line mesh
+    pub fn debug_line_mesh(&mut self, mesh: &LineMesh) {
+        self.debug_lines.mesh(mesh);
+    }
+
+    /// Draw the edges of an axis-aligned box
+    pub fn debug_aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 3]) {
+        self.debug_lines.aabb(min, max, color);
+    }
+
+    /// Whether projection matrices passed to `frame` must use reversed depth, with an infinite far
+    /// plane. Otherwise they use the standard OpenGL convention.
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    /// Call once per displayed frame, after drawing every view, to read back GPU timings and
+    /// start counting the next frame's statistics
+    pub fn end_frame(&mut self, gl: &gl::Context) {
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.end_frame(gl);
+        }
+        self.last_frame_stats = std::mem::take(&mut self.frame_stats);
+    }
+
+    /// Draw calls, instances and uploads of the frame before the last `end_frame`
+    pub fn last_frame_stats(&self) -> FrameStats {
+        self.last_frame_stats
+    }
+
+    /// GPU time spent on each pass of a recent frame, or None if timer queries are unsupported
+    pub fn gpu_timings(&self) -> Option<GpuTimings> {
+        self.gpu_timer.as_ref()?.timings().copied()
+    }
+
+    /// Log a warning when a single pass takes longer than `budget` milliseconds on the GPU
+    pub fn set_gpu_budget(&mut self, budget: Option<f32>) {
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.set_budget(budget);
+        }
+    }
+
+    /// Minimum and maximum corners of the map's bounding box
+    pub fn map_bounds(&self) -> (Point3<f32>, Point3<f32>) {
+        self.map_bounds
+    }
+
+    /// Minimum and maximum corners of the head mesh's bounding box, relative to the head
+    pub fn head_bounds(&self) -> (Point3<f32>, Point3<f32>) {
+        self.head_bounds
+    }
+
+    /// Set whether the render target is sRGB encoded. If it isn't, shaders encode their output
+    /// themselves.
+    pub fn set_srgb_target(&mut self, srgb: bool) {
+        self.srgb_target = srgb;
+    }
+
+    /// Set whether output is converted from linear to sRGB. Turning this off shows how the scene
+    /// looked before colors were handled correctly.
+    pub fn set_gamma_correct(&mut self, gamma_correct: bool) {
+        self.gamma_correct = gamma_correct;
+    }
+
+    /// Load shaders from the files in `dir` instead of the embedded sources, and reload them
+    /// whenever the files change
+    pub fn set_shader_dir(&mut self, gl: &gl::Context, dir: PathBuf) -> Result<(), RenderError> {
+        for shader in self.shaders_mut() {
+            shader.reload(gl, Some(&dir))?;
+        }
+        self.shader_dir = Some(dir);
+        Ok(())
+    }
+
+    /// Add a mesh to the registry. It is drawn once per instance given to `set_instances`, and
+    /// has none to begin with.
+    pub fn add_mesh(
+        &mut self,
+        gl: &gl::Context,
+        mesh: &Mesh,
+        style: RenderStyle,
+    ) -> Result<MeshHandle, RenderError> {
+        self.add_mesh_with_usage(gl, mesh, style, gl::STATIC_DRAW)
+    }
+
+    /// Add a mesh whose buffers are allocated with the given usage hint, `DYNAMIC_DRAW` for
+    /// meshes that are replaced while running
+    fn add_mesh_with_usage(
+        &mut self,
+        gl: &gl::Context,
+        mesh: &Mesh,
+        style: RenderStyle,
+        usage: u32,
+    ) -> Result<MeshHandle, RenderError> {
+        mesh.validate_within(&self.mesh_limits)?;
+        let gpu = upload_mesh(
+            gl,
+            usage,
+            mesh,
+            !self.polygon_mode_supported,
+            &mut self.frame_stats,
+        )?;
+        let instance_vbo = create_instance_buffer(gl, gpu.vao)?;
+
+        let entry = MeshEntry {
+            gpu,
+            style,
+            instance_vbo,
+            instance_capacity: 0,
+            double_sided: false,
+            follow_camera: false,
+            instances: vec![],
+            uploaded: vec![],
+            opaque_count: 0,
+        };
+
+        // Reuse the slot of a removed mesh, if any
+        let index = match self.meshes.iter().position(|slot| slot.entry.is_none()) {
+            Some(index) => {
+                self.meshes[index].entry = Some(entry);
+                index
+            }
+            None => {
+                self.meshes.push(MeshSlot {
+                    generation: 0,
+                    entry: Some(entry),
+                });
+                self.meshes.len() - 1
+            }
+        };
+
+        Ok(MeshHandle {
+            index,
+            generation: self.meshes[index].generation,
+        })
+    }
+
+    /// Delete a mesh and its instances. The handle is invalid afterwards.
+    pub fn remove_mesh(&mut self, gl: &gl::Context, handle: MeshHandle) {
+        self.entry_mut(handle);
+        let slot = &mut self.meshes[handle.index];
+        let entry = slot.entry.take().unwrap();
+        slot.generation += 1;
+
+        entry.gpu.delete(gl);
+        unsafe {
+            gl.delete_buffer(entry.instance_vbo);
+        }
+    }
+
+    /// Replace the vertices and indices of a mesh, keeping its instances. An invalid mesh leaves
+    /// the old one in place.
+    pub fn update_mesh(
+        &mut self,
+        gl: &gl::Context,
+        handle: MeshHandle,
+        mesh: &Mesh,
+    ) -> Result<(), RenderError> {
+        mesh.validate_within(&self.mesh_limits)?;
+        let bytes = self.entry_mut(handle).gpu.update(gl, mesh);
+        self.frame_stats.upload(bytes);
+        check_gl_error!(gl, "update_mesh");
+        Ok(())
+    }
+
+    /// Set how a mesh is shaded
+    pub fn set_style(&mut self, handle: MeshHandle, style: RenderStyle) {
+        self.entry_mut(handle).style = style;
+    }
+
+    /// Draw both sides of a mesh's triangles, for open surfaces or inconsistent winding. Meshes
+    /// are single sided when added.
+    pub fn set_double_sided(&mut self, handle: MeshHandle, double_sided: bool) {
+        self.entry_mut(handle).double_sided = double_sided;
+    }
+
+    /// Keep a mesh centered on the camera, for skies. Its opaque instances are drawn before
+    /// anything else and without writing depth, so that all other geometry covers them, and
+    /// without fog or shadows. They must fit within the far plane. Meshes don't follow the camera
+    /// when added.
+    pub fn set_follow_camera(&mut self, handle: MeshHandle, follow: bool) {
+        self.entry_mut(handle).follow_camera = follow;
+    }
+
+    /// Set where copies of a mesh are drawn. Only instances which changed since the last call
+    /// are uploaded, so calling this every frame with mostly still instances is cheap.
+    pub fn set_instances(&mut self, gl: &gl::Context, handle: MeshHandle, instances: &[Instance]) {
+        let entry = self.entry_mut(handle);
+
+        // Opaque instances go first, so that they can be drawn in one call
+        entry.instances.clear();
+        entry
+            .instances
+            .extend(instances.iter().filter(|i| i.tint[3] >= 1.));
+        entry.opaque_count = entry.instances.len();
+        entry
+            .instances
+            .extend(instances.iter().filter(|i| i.tint[3] < 1.));
+
+        let needed = entry.instances.len() * std::mem::size_of::<Instance>();
+        if needed > entry.instance_capacity {
+            let count =
+                grown_capacity(needed, entry.instance_capacity) / std::mem::size_of::<Instance>();
+            self.reserve_instances(gl, handle, count);
+        }
+
+        let entry = self.entry_mut(handle);
+        let first = entry.first_translucent();
+        let size = std::mem::size_of::<Instance>();
+        let mut written = 0;
+        unsafe {
+            gl.bind_buffer(gl::ARRAY_BUFFER, Some(entry.instance_vbo));
+
+            for run in instance_writes(&entry.uploaded, &entry.instances, first) {
+                written += run.len() * size;
+                gl.buffer_sub_data_u8_slice(
+                    gl::ARRAY_BUFFER,
+                    (run.start * size) as i32,
+                    bytemuck::cast_slice(&entry.instances[run]),
+                );
+            }
+            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+        }
+        entry.uploaded.clear();
+        entry.uploaded.extend_from_slice(&entry.instances[..first]);
+        self.frame_stats.upload(written);
+
+        check_gl_error!(gl, "set_instances");
+    }
+
+    /// Allocate space for at least `count` instances of a mesh. The current instances are
+    /// discarded from the buffer if it has to grow, so callers must upload them again.
+    fn reserve_instances(&mut self, gl: &gl::Context, handle: MeshHandle, count: usize) {
+        let entry = self.entry_mut(handle);
+        let size = count * std::mem::size_of::<Instance>();
+        if size > entry.instance_capacity {
+            entry.uploaded.clear();
+        }
+        unsafe {
+            reserve_buffer(gl, entry.instance_vbo, &mut entry.instance_capacity, size);
+        }
+    }
+
+    /// Replace the billboards drawn each frame
+    pub fn set_billboards(&mut self, gl: &gl::Context, billboards: &[Billboard]) {
+        self.billboards.set(gl, billboards, &mut self.frame_stats);
+        check_gl_error!(gl, "set_billboards");
+    }
+
+    /// Set the texture billboards are drawn with, or None for plain colored squares
+    pub fn set_billboard_texture(&mut self, texture: Option<TextureId>) {
+        self.billboard_texture = texture;
+    }
+
+    /// Make billboards face a fixed orientation rather than each view's. In VR, giving the
+    /// orientation between the eyes keeps the two eyes from seeing differently turned quads.
+    pub fn set_billboard_facing(&mut self, facing: Option<UnitQuaternion<f32>>) {
+        self.billboard_facing = facing;
+    }
+
+    /// Replace the map mesh
+    pub fn update_map(&mut self, gl: &gl::Context, mesh: &Mesh) -> Result<(), RenderError> {
+        self.update_mesh(gl, self.map, mesh)?;
+        self.map_bounds = mesh_bounds(mesh);
+        Ok(())
+    }
+
+    /// Set the distances beyond which heads are drawn as boxes, and not drawn at all. Heads fade
+    /// out over the last part of the draw distance.
+    pub fn set_head_distances(&mut self, lod: f32, draw: f32) {
+        self.head_draw_distance = draw.max(0.);
+        self.head_lod_distance = lod.clamp(0., self.head_draw_distance);
+    }
+
+    /// Update head positions and opacities, as seen from `cull`, which is usually the camera
+    /// rendered from. Heads beyond `EngineConfig::max_heads` or the draw distance, or out of
+    /// view, are not drawn, and distant heads are drawn as boxes.
+    pub fn update_heads(
+        &mut self,
+        gl: &gl::Context,
+        heads: &[Instance],
+        cull: &CullCamera,
+    ) -> HeadCounts {
+        let planes = cull.view_proj.as_ref().map(side_planes);
+        let (min, max) = self.head_bounds;
+        let radius = min.coords.norm().max(max.coords.norm()) + HEAD_CULL_MARGIN;
+
+        let count = heads.len().min(self.max_heads);
+        let mut full = vec![];
+        let mut lod = vec![];
+        let mut left_out = 0;
+        for head in &heads[..count] {
+            let [x, y, z, _] = head.transform[3];
+            let pos = Point3::new(x, y, z);
+            let in_view = planes.is_none_or(|planes| sphere_inside(&planes, pos, radius));
+            let distance = (pos - cull.eye).norm();
+            if !in_view {
+                left_out += 1;
+            } else if distance <= self.head_lod_distance {
+                full.push(*head);
+            } else if distance <= self.head_draw_distance {
+                lod.push(*head);
+            } else {
+                left_out += 1;
+            }
+        }
+        let culled = left_out + heads.len() - count;
+        debug_assert_eq!(full.len() + lod.len() + culled, heads.len());
+
+        self.set_instances(gl, self.head, &full);
+        self.set_instances(gl, self.head_lod, &lod);
+        self.frame_stats.cull(heads.len(), full.len() + lod.len());
+
+        HeadCounts {
+            full: full.len(),
+            lod: lod.len(),
+            culled,
+        }
+    }
+
+    /// Render the shadow map from the sun, covering the map. Call once per frame before `frame`;
+    /// in VR both eyes share it. Does nothing while shadows are off.
+    pub fn render_shadows(&mut self, gl: &gl::Context) {
+        if !self.shadows {
+            return;
+        }
+
+        // Fit an orthographic projection around the map, with some room above it for heads
+        let (min, max) = self.map_bounds;
+        let center = nalgebra::center(&min, &max);
+        let radius = ((max - min).norm() / 2.).max(1.) + 2.;
+        let eye = center + self.sun_dir * radius * 2.;
+        let up = if self.sun_dir.y.abs() > 0.99 {
+            Vector3::z()
+        } else {
+            Vector3::y()
+        };
+        let light_view = Matrix4::look_at_rh(&eye, &center, &up);
+        let light_proj =
+            Matrix4::new_orthographic(-radius, radius, -radius, radius, radius, radius * 3.);
+        self.light_view_proj = light_proj * light_view;
+
+        unsafe {
+            let mut viewport = [0; 4];
+            gl.get_parameter_i32_slice(gl::VIEWPORT, &mut viewport);
+            let scissor = gl.is_enabled(gl::SCISSOR_TEST);
+            gl.disable(gl::SCISSOR_TEST);
+
+            gl.bind_framebuffer(gl::FRAMEBUFFER, Some(self.shadow_fbo));
+            gl.viewport(0, 0, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE);
+            gl.depth_func(gl::LESS);
+            gl.clear_depth_f32(1.);
+            gl.clear(gl::DEPTH_BUFFER_BIT);
+
+            // The shadow map is not reversed. Under clip control, depth must be remapped from
+            // -1..1 to 0..1 so that the shader's lookups stay the same.
+            let light_view_proj = if self.reverse_z {
+                Matrix4::new_nonuniform_scaling(&Vector3::new(1., 1., 0.5))
+                    .append_translation(&Vector3::new(0., 0., 0.5))
+                    * self.light_view_proj
+            } else {
+                self.light_view_proj
+            };
+
+            gl.use_program(Some(self.shadow_shader.program));
+            gl.uniform_matrix_4_f32_slice(
+                self.shadow_shader.uniforms.light_view_proj.as_ref(),
+                false,
+                light_view_proj.as_slice(),
+            );
+            let uniforms = &self.shadow_shader.uniforms;
+            gl.uniform_1_f32(uniforms.time.as_ref(), self.time);
+
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.begin(gl, GpuPass::Shadows);
+            }
+
+            // Only opaque instances cast shadows, and skies don't
+            for (slot_idx, slot) in self.meshes.iter().enumerate() {
+                let entry = match &slot.entry {
+                    Some(entry) if !entry.follow_camera => entry,
+                    _ => continue,
+                };
+                let count = entry.first_translucent();
+                if count > 0 {
+                    let idle = self.idle_animation && slot_idx == self.head.index;
+                    gl.uniform_1_i32(uniforms.idle.as_ref(), idle as i32);
+                    entry.draw(gl, count as i32, false, &mut self.frame_stats);
+                }
+            }
+
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end(gl);
+            }
+
+            gl.bind_framebuffer(gl::FRAMEBUFFER, None);
+            let [x, y, width, height] = viewport;
+            gl.viewport(x, y, width, height);
+            if scissor {
+                gl.enable(gl::SCISSOR_TEST);
+            }
+
+            check_gl_error!(gl, "render_shadows");
+        }
+    }
+
+    /// Set the radius of the clear part of the view left by `PostEffect::Vignette`, in normalized
+    /// device coordinates about the direction of view
+    pub fn set_vignette_radius(&mut self, radius: f32) {
+        self.vignette_radius = radius;
+    }
+
+    /// The post effects which change the frame. The vignette is skipped while it is open, but
+    /// targets stay allocated for every effect so that it can close without reallocating them.
+    fn active_post_effects(&self) -> impl Iterator<Item = PostEffect> + '_ {
+        self.post_effects
+            .iter()
+            .copied()
+            .filter(|effect| match effect {
+                PostEffect::Vignette => comfort::darkens(self.vignette_radius),
+                PostEffect::Fxaa => true,
+            })
+    }
+
+    /// Draw the scene into the current viewport. Clearing is limited to the scissor box while the
+    /// scissor test is enabled, so several views can share one framebuffer.
+    pub fn frame(
+        &mut self,
+        gl: &gl::Context,
+        proj: Matrix4<f32>,
+        view: Matrix4<f32>,
+        //view: Head,
+    ) -> Result<(), RenderError> {
+        self.poll_shader_changes(gl);
+
+        // Render the scene off-screen first if it is post-processed
+        let post = if self.active_post_effects().next().is_none() {
+            None
+        } else {
+            Some(self.begin_post(gl)?)
+        };
+
+        // Put back once the frame is drawn, as the passes below borrow the engine
+        let mut timer = self.gpu_timer.take();
+        let mut stats = std::mem::take(&mut self.frame_stats);
+
+        unsafe {
+            // Let the hardware encode our linear output, if it can
+            if self.framebuffer_srgb_supported {
+                if self.gamma_correct && self.srgb_target {
+                    gl.enable(gl::FRAMEBUFFER_SRGB);
+                } else {
+                    gl.disable(gl::FRAMEBUFFER_SRGB);
+                }
+            }
+
+            // Clear depth and color buffers. The clear color is given in sRGB, and is only
+            // encoded by the hardware.
+            let [r, g, b, a] = match self.background {
+                BackgroundMode::Solid(color) => color,
+                BackgroundMode::Transparent => [0.; 4],
+                BackgroundMode::Sky(_) => CLEAR_COLOR,
+            };
+            let [r, g, b] = if self.gamma_correct && self.srgb_target {
+                [r, g, b].map(srgb_to_linear)
+            } else {
+                [r, g, b]
+            };
+            gl.clear_color(r, g, b, a);
+            gl.clear_depth_f32(if self.reverse_z { 0. } else { 1. });
+            gl.clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            gl.depth_func(self.depth_func());
+
+            // Draw the sky first, behind everything
+            if let BackgroundMode::Sky(sky) = self.background {
+                self.draw_sky(gl, sky, &proj, &view, &mut stats);
+            }
+
+            let debug_mode = self.debug_mode;
+            let debug_mode_uniform = match debug_mode {
+                DebugMode::Wireframe if !self.polygon_mode_supported => BARYCENTRIC_WIREFRAME,
+                mode => mode as i32,
+            };
+            let encode_srgb = self.encode_srgb();
+            let ([fog_r, fog_g, fog_b], fog_density) = self.fog;
+            let shadows = self.shadows;
+            let (shadow_bias, shadow_slope_bias) = self.shadow_bias;
+            let light_view_proj = self.light_view_proj;
+            let sun_dir = self.sun_dir;
+
+            // The shadow map lives on texture unit 1, leaving 0 for mesh textures
+            gl.active_texture(gl::TEXTURE1);
+            gl.bind_texture(gl::TEXTURE_2D, Some(self.shadow_map));
+            gl.active_texture(gl::TEXTURE0);
+
+            let time = self.time;
+            let use_shader = |shader: &Shader, alpha: f32, fade: [f32; 2], idle: bool| {
+                gl.use_program(Some(shader.program));
+
+                // Set camera matrix
+                let uniforms = &shader.uniforms;
+                gl.uniform_matrix_4_f32_slice(uniforms.view.as_ref(), false, view.as_slice());
+                gl.uniform_matrix_4_f32_slice(uniforms.proj.as_ref(), false, proj.as_slice());
+
+                gl.uniform_1_i32(uniforms.debug_mode.as_ref(), debug_mode_uniform);
+                gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), encode_srgb as i32);
+
+                gl.uniform_3_f32(uniforms.fog_color.as_ref(), fog_r, fog_g, fog_b);
+                gl.uniform_1_f32(uniforms.fog_density.as_ref(), fog_density);
+                gl.uniform_1_f32(uniforms.alpha.as_ref(), alpha);
+                gl.uniform_2_f32(uniforms.fade_range.as_ref(), fade[0], fade[1]);
+                gl.uniform_1_f32(uniforms.time.as_ref(), time);
+                gl.uniform_1_i32(uniforms.idle.as_ref(), idle as i32);
+
+                gl.uniform_1_i32(uniforms.shadows.as_ref(), shadows as i32);
+                gl.uniform_1_i32(uniforms.shadow_map.as_ref(), 1);
+                gl.uniform_matrix_4_f32_slice(
+                    uniforms.light_view_proj.as_ref(),
+                    false,
+                    light_view_proj.as_slice(),
+                );
+                gl.uniform_3_f32(uniforms.sun_dir.as_ref(), sun_dir.x, sun_dir.y, sun_dir.z);
+                gl.uniform_1_f32(uniforms.shadow_bias.as_ref(), shadow_bias);
+                gl.uniform_1_f32(uniforms.shadow_slope_bias.as_ref(), shadow_slope_bias);
+            };
+            // Only heads fade out with distance
+            let fade_range = |slot_idx: usize| {
+                if slot_idx == self.head.index || slot_idx == self.head_lod.index {
+                    [
+                        self.head_draw_distance * HEAD_FADE_START,
+                        self.head_draw_distance,
+                    ]
+                } else {
+                    [0.; 2]
+                }
+            };
+            // Only detailed heads are animated; the boxes are too far away for it to show
+            let idle = |slot_idx: usize| self.idle_animation && slot_idx == self.head.index;
+            let use_style = |style: RenderStyle, slot_idx: usize| match style {
+                RenderStyle::Unlit => {
+                    use_shader(&self.unlit_shader, 1., fade_range(slot_idx), idle(slot_idx))
+                }
+                RenderStyle::Translucent { alpha } => use_shader(
+                    &self.unlit_shader,
+                    alpha,
+                    fade_range(slot_idx),
+                    idle(slot_idx),
+                ),
+                RenderStyle::Textured(TextureId(idx)) => {
+                    use_shader(
+                        &self.textured_shader,
+                        1.,
+                        fade_range(slot_idx),
+                        idle(slot_idx),
+                    );
+
+                    gl.active_texture(gl::TEXTURE0);
+                    gl.bind_texture(gl::TEXTURE_2D, Some(self.textures[idx]));
+                    gl.uniform_1_i32(self.textured_shader.uniforms.tex.as_ref(), 0);
+                }
+            };
+
+            // Wireframes use the polygon mode where possible. Otherwise the shaders keep the
+            // fragments near the edges of triangles drawn without sharing vertices.
+            let wireframe = debug_mode == DebugMode::Wireframe;
+            let unindexed = wireframe && !self.polygon_mode_supported;
+            // Triangles rasterized as lines count as none
+            let lines_from = stats.triangles;
+            if wireframe && self.polygon_mode_supported {
+                gl.polygon_mode(gl::FRONT_AND_BACK, gl::LINE);
+            }
+
+            // Then meshes following the camera, seen from its position and covered by everything
+            // drawn after them
+            let mut sky_view = view;
+            sky_view.fixed_slice_mut::<3, 1>(0, 3).fill(0.);
+            gl.depth_mask(false);
+            for (index, slot) in self.meshes.iter().enumerate() {
+                let entry = match &slot.entry {
+                    Some(entry) if entry.follow_camera && entry.first_translucent() > 0 => entry,
+                    _ => continue,
+                };
+
+                use_style(entry.style, index);
+                let uniforms = match entry.style {
+                    RenderStyle::Textured(_) => &self.textured_shader.uniforms,
+                    _ => &self.unlit_shader.uniforms,
+                };
+                gl.uniform_matrix_4_f32_slice(uniforms.view.as_ref(), false, sky_view.as_slice());
+                gl.uniform_1_f32(uniforms.fog_density.as_ref(), NO_FOG);
+                gl.uniform_1_i32(uniforms.shadows.as_ref(), 0);
+                entry.draw(gl, entry.first_translucent() as i32, unindexed, &mut stats);
+            }
+            gl.depth_mask(true);
+
+            // Draw the opaque instances of each mesh in the registry
+            for (index, slot) in self.meshes.iter().enumerate() {
+                let entry = match &slot.entry {
+                    Some(entry) if !entry.follow_camera && entry.first_translucent() > 0 => entry,
+                    _ => continue,
+                };
+
+                // Time the map and heads separately
+                let pass = if index == self.map.index {
+                    Some(GpuPass::Map)
+                } else if index == self.head.index || index == self.head_lod.index {
+                    Some(GpuPass::Heads)
+                } else {
+                    None
+                };
+                if let (Some(timer), Some(pass)) = (&mut timer, pass) {
+                    timer.begin(gl, pass);
+                }
+
+                use_style(entry.style, index);
+                entry.draw(gl, entry.first_translucent() as i32, unindexed, &mut stats);
+
+                if let (Some(timer), Some(_)) = (&mut timer, pass) {
+                    timer.end(gl);
+                }
+            }
+
+            // The grid blends over the opaque geometry, and under anything translucent
+            if self.grid && !wireframe {
+                self.draw_grid(gl, &proj, &view, &mut stats);
+            }
+
+            // Then the translucent instances, back to front by the depth of their origins. Only
+            // whole instances are sorted, so intersecting translucent meshes may blend in the
+            // wrong order where they overlap.
+            let mut translucent: Vec<(f32, usize, usize)> = vec![];
+            for (slot_idx, slot) in self.meshes.iter().enumerate() {
+                if let Some(entry) = slot.entry.as_ref().filter(|e| !e.follow_camera) {
+                    let first = entry.first_translucent();
+                    for (i, instance) in entry.instances.iter().enumerate().skip(first) {
+                        let origin = Vector4::from(instance.transform[3]);
+                        translucent.push(((view * origin).z, slot_idx, i));
+                    }
+                }
+            }
+            // Further away is more negative
+            translucent.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            if !translucent.is_empty() {
+                if let Some(timer) = &mut timer {
+                    timer.begin(gl, GpuPass::Translucent);
+                }
+
+                // Alpha accumulates coverage, so that the frame composites correctly over a
+                // transparent background
+                gl.enable(gl::BLEND);
+                gl.blend_func_separate(
+                    gl::SRC_ALPHA,
+                    gl::ONE_MINUS_SRC_ALPHA,
+                    gl::ONE,
+                    gl::ONE_MINUS_SRC_ALPHA,
+                );
+                gl.depth_mask(false);
+
+                // Rewrite the translucent part of each instance buffer in drawing order, so that
+                // consecutive instances of the same mesh are drawn in one call
+                let mut sorted: Vec<Vec<Instance>> = vec![vec![]; self.meshes.len()];
+                for &(_, slot_idx, i) in &translucent {
+                    let entry = self.meshes[slot_idx].entry.as_ref().unwrap();
+                    sorted[slot_idx].push(entry.instances[i]);
+                }
+                for (slot, instances) in self.meshes.iter().zip(&sorted) {
+                    if let (Some(entry), false) = (&slot.entry, instances.is_empty()) {
+                        stats.upload(std::mem::size_of_val(instances.as_slice()));
+                        gl.bind_buffer(gl::ARRAY_BUFFER, Some(entry.instance_vbo));
+                        gl.buffer_sub_data_u8_slice(
+                            gl::ARRAY_BUFFER,
+                            (entry.first_translucent() * std::mem::size_of::<Instance>()) as i32,
+                            bytemuck::cast_slice(instances),
+                        );
+                    }
+                }
+
+                // Index of the next instance to draw, per mesh
+                let mut cursors: Vec<usize> = self
+                    .meshes
+                    .iter()
+                    .map(|slot| slot.entry.as_ref().map_or(0, |e| e.first_translucent()))
+                    .collect();
+
+                // Consecutive instances of the same mesh, as (slot, length)
+                let mut runs: Vec<(usize, usize)> = vec![];
+                for &(_, slot_idx, _) in &translucent {
+                    match runs.last_mut() {
+                        Some((last, len)) if *last == slot_idx => *len += 1,
+                        _ => runs.push((slot_idx, 1)),
+                    }
+                }
+
+                for (slot_idx, len) in runs {
+                    let entry = self.meshes[slot_idx].entry.as_ref().unwrap();
+                    let first = cursors[slot_idx];
+                    cursors[slot_idx] += len;
+
+                    use_style(entry.style, slot_idx);
+
+                    // Start reading instances from the first one of the run
+                    gl.bind_vertex_array(Some(entry.gpu.vao));
+                    gl.bind_buffer(gl::ARRAY_BUFFER, Some(entry.instance_vbo));
+                    set_instance_attribs(gl, first);
+
+                    entry.draw(gl, len as i32, unindexed, &mut stats);
+
+                    gl.bind_vertex_array(Some(entry.gpu.vao));
+                    gl.bind_buffer(gl::ARRAY_BUFFER, Some(entry.instance_vbo));
+                    set_instance_attribs(gl, 0);
+                    gl.bind_vertex_array(None);
+                }
+                gl.bind_buffer(gl::ARRAY_BUFFER, None);
+
+                gl.depth_mask(true);
+                gl.disable(gl::BLEND);
+
+                if let Some(timer) = &mut timer {
+                    timer.end(gl);
+                }
+            }
+
+            // Billboards are not sorted, with each other or the translucent instances
+            if self.billboards.count > 0 {
+                if let Some(timer) = &mut timer {
+                    timer.begin(gl, GpuPass::Translucent);
+                }
+                let facing = match self.billboard_facing {
+                    Some(orient) => orient.inverse().to_rotation_matrix().into_inner(),
+                    None => view.fixed_slice::<3, 3>(0, 0).into_owned(),
+                };
+                self.draw_billboards(
+                    gl,
+                    &self.billboards,
+                    &proj,
+                    &view,
+                    &facing,
+                    false,
+                    &mut stats,
+                );
+                if let Some(timer) = &mut timer {
+                    timer.end(gl);
+                }
+            }
+
+            gl.bind_texture(gl::TEXTURE_2D, None);
+            gl.active_texture(gl::TEXTURE1);
+            gl.bind_texture(gl::TEXTURE_2D, None);
+            gl.active_texture(gl::TEXTURE0);
+
+            // Don't leak the wireframe state to whoever renders next
+            if wireframe && self.polygon_mode_supported {
+                gl.polygon_mode(gl::FRONT_AND_BACK, gl::FILL);
+                stats.triangles = lines_from;
+            }
+
+            if self.debug_lines.enabled {
+                self.draw_debug_lines(gl, &proj, &view, &mut stats);
+            }
+
+            if let Some(post) = post {
+                if let Some(timer) = &mut timer {
+                    timer.begin(gl, GpuPass::Post);
+                }
+                self.end_post(gl, post, &proj, &mut stats);
+                if let Some(timer) = &mut timer {
+                    timer.end(gl);
+                }
+            }
+            self.gpu_timer = timer;
+            self.frame_stats = stats;
+
+            // Double-sided meshes must not leak their culling state to other passes
+            if gl_debug_enabled() && !gl.is_enabled(gl::CULL_FACE) {
+                eprintln!("GL state: backface culling left disabled after frame");
+            }
+            check_gl_error!(gl, "frame");
+
+            Ok(())
+        }
+    }
+
+    /// Draw camera-facing text labels above the given points, on top of the last `frame`.
+    /// Labels far away from the camera are skipped.
+    pub fn draw_labels(
+        &mut self,
+        gl: &gl::Context,
+        labels: &[(Point3<f32>, &str)],
+        proj: Matrix4<f32>,
+        view: Matrix4<f32>,
+    ) {
+        let (eye, right, up) = text::camera_basis(&view);
+
+        self.text_vertices.clear();
+        for (anchor, label) in labels {
+            if let Some(height) = text::label_height((anchor - eye).norm()) {
+                text::layout_label(&mut self.text_vertices, label, *anchor, right, up, height);
+            }
+        }
+
+        let mut stats = std::mem::take(&mut self.frame_stats);
+        self.draw_text(gl, &proj, &view, !self.labels_through_walls, &mut stats);
+        self.frame_stats = stats;
+        check_gl_error!(gl, "draw_labels");
+    }
+
+    /// Draw the map and the given dots from above into the minimap texture, centered on
+    /// `center` and covering `extent` meters on each side. Only every `MINIMAP_INTERVAL`th call
+    /// redraws, so this can be called every frame.
+    pub fn render_minimap(
+        &mut self,
+        gl: &gl::Context,
+        center: Point3<f32>,
+        extent: f32,
+        dots: &[Billboard],
+    ) -> Result<(), RenderError> {
+        if self.minimap_countdown > 0 {
+            self.minimap_countdown -= 1;
+            return Ok(());
+        }
+        self.minimap_countdown = MINIMAP_INTERVAL - 1;
+
+        if self.minimap_target.is_none() {
+            let size = (MINIMAP_TEXTURE_SIZE, MINIMAP_TEXTURE_SIZE);
+            self.minimap_target = Some(PostTarget::new(gl, size, gl::RGBA8, true)?);
+        }
+        let framebuffer = self.minimap_target.as_ref().unwrap().framebuffer;
+
+        // Look down from above the highest point of the map, to below its lowest
+        let (min, max) = self.map_bounds;
+        let height = (max.y - center.y).max(0.) + 2.;
+        let depth = height + (center.y - min.y).max(0.) + 1.;
+        let view = minimap::minimap_view(center, height);
+        let proj = minimap::minimap_proj(extent, depth, self.reverse_z);
+        let facing = view.fixed_slice::<3, 3>(0, 0).into_owned();
+
+        // Dots off the edge aren't worth uploading
+        let view_proj = proj * view;
+        let dots: Vec<Billboard> = dots
+            .iter()
+            .filter(|dot| minimap::shows_on_minimap(&view_proj, dot.pos.into(), dot.size, extent))
+            .copied()
+            .collect();
+        self.minimap_dots.set(gl, &dots, &mut self.frame_stats);
+        let mut stats = std::mem::take(&mut self.frame_stats);
+
+        unsafe {
+            let mut viewport = [0; 4];
+            gl.get_parameter_i32_slice(gl::VIEWPORT, &mut viewport);
+            let scissor = gl.is_enabled(gl::SCISSOR_TEST);
+            let destination = post::bound_draw_framebuffer(gl);
+
+            gl.bind_framebuffer(gl::FRAMEBUFFER, Some(framebuffer));
+            gl.disable(gl::SCISSOR_TEST);
+            gl.viewport(0, 0, MINIMAP_TEXTURE_SIZE, MINIMAP_TEXTURE_SIZE);
+            let [r, g, b] = MINIMAP_BACKGROUND;
+            gl.clear_color(r, g, b, 1.);
+            gl.clear_depth_f32(1.);
+            gl.clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            gl.depth_func(gl::LESS);
+
+            // The map only, in its usual colors but without fog or shadows
+            if let Some(entry) = &self.meshes[self.map.index].entry {
+                let shader = match entry.style {
+                    RenderStyle::Textured(_) => &self.textured_shader,
+                    _ => &self.unlit_shader,
+                };
+                let uniforms = &shader.uniforms;
+                gl.use_program(Some(shader.program));
+                gl.uniform_matrix_4_f32_slice(uniforms.view.as_ref(), false, view.as_slice());
+                gl.uniform_matrix_4_f32_slice(uniforms.proj.as_ref(), false, proj.as_slice());
+                gl.uniform_1_i32(uniforms.debug_mode.as_ref(), DebugMode::Solid as i32);
+                gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), 0);
+                gl.uniform_1_f32(uniforms.fog_density.as_ref(), NO_FOG);
+                gl.uniform_1_f32(uniforms.alpha.as_ref(), 1.);
+                gl.uniform_2_f32(uniforms.fade_range.as_ref(), 0., 0.);
+                gl.uniform_1_i32(uniforms.idle.as_ref(), 0);
+                gl.uniform_1_i32(uniforms.shadows.as_ref(), 0);
+                if let RenderStyle::Textured(TextureId(idx)) = entry.style {
+                    gl.active_texture(gl::TEXTURE0);
+                    gl.bind_texture(gl::TEXTURE_2D, Some(self.textures[idx]));
+                    gl.uniform_1_i32(uniforms.tex.as_ref(), 0);
+                }
+                entry.draw(gl, entry.first_translucent() as i32, false, &mut stats);
+                gl.bind_texture(gl::TEXTURE_2D, None);
+            }
+
+            self.draw_billboards(
+                gl,
+                &self.minimap_dots,
+                &proj,
+                &view,
+                &facing,
+                true,
+                &mut stats,
+            );
+
+            gl.bind_framebuffer(gl::FRAMEBUFFER, destination);
+            let [x, y, width, height] = viewport;
+            gl.viewport(x, y, width, height);
+            if scissor {
+                gl.enable(gl::SCISSOR_TEST);
+            }
+            gl.depth_func(self.depth_func());
+        }
+        self.frame_stats = stats;
+
+        check_gl_error!(gl, "render_minimap");
+        Ok(())
+    }
+
+    /// Draw the minimap in the top-right corner of the viewport, `pixels` wide and `margin` pixels
+    /// from the corner, with an arrow in the middle pointing along `heading` (see
+    /// `minimap::minimap_heading`). Does nothing before `render_minimap`.
+    pub fn draw_minimap(
+        &mut self,
+        gl: &gl::Context,
+        size: (u32, u32),
+        pixels: f32,
+        margin: f32,
+        heading: Option<Vector2<f32>>,
+    ) {
+        let target = match &self.minimap_target {
+            Some(target) => target,
+            None => return,
+        };
+
+        // Corners in normalized device coordinates
+        let (width, height) = (size.0 as f32, size.1 as f32);
+        let right = 1. - 2. * margin / width;
+        let top = 1. - 2. * margin / height;
+        let left = right - 2. * pixels / width;
+        let bottom = top - 2. * pixels / height;
+        let heading = heading.unwrap_or_else(Vector2::zeros);
+
+        unsafe {
+            gl.disable(gl::DEPTH_TEST);
+            gl.enable(gl::BLEND);
+            gl.blend_func_separate(
+                gl::SRC_ALPHA,
+                gl::ONE_MINUS_SRC_ALPHA,
+                gl::ONE,
+                gl::ONE_MINUS_SRC_ALPHA,
+            );
+
+            let uniforms = &self.minimap_shader.uniforms;
+            gl.use_program(Some(self.minimap_shader.program));
+            gl.uniform_4_f32(uniforms.rect.as_ref(), left, bottom, right, top);
+            gl.uniform_1_f32(uniforms.border.as_ref(), 2. / pixels.max(1.));
+            gl.uniform_2_f32(uniforms.heading.as_ref(), heading.x, heading.y);
+            gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), self.encode_srgb() as i32);
+
+            gl.active_texture(gl::TEXTURE0);
+            gl.bind_texture(gl::TEXTURE_2D, Some(target.color));
+            gl.uniform_1_i32(uniforms.tex.as_ref(), 0);
+
+            gl.bind_vertex_array(Some(self.empty_vao));
+            gl.draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+            gl.bind_vertex_array(None);
+            gl.bind_texture(gl::TEXTURE_2D, None);
+
+            gl.disable(gl::BLEND);
+            gl.enable(gl::DEPTH_TEST);
+        }
+        self.frame_stats.draw(1, 2);
+
+        check_gl_error!(gl, "draw_minimap");
+    }
+
+    /// Draw the statistics overlay in the top-left corner of a viewport of the given size, if it
+    /// is visible. `margin` is the distance from the corner in pixels.
+    pub fn draw_stats(
+        &mut self,
+        gl: &gl::Context,
+        stats: &StatsOverlay,
+        size: (u32, u32),
+        margin: f32,
+    ) {
+        if !stats.visible {
+            return;
+        }
+
+        // Work in pixels, with the origin at the bottom left
+        let (width, height) = (size.0 as f32, size.1 as f32);
+        let proj = Matrix4::new_orthographic(0., width, 0., height, -1., 1.);
+        let (right, up) = (Vector3::x(), Vector3::y());
+
+        let lines = stats.lines();
+        let graph_height = 40.;
+        let bar_width = 2.;
+        let frame_times = stats.frame_times();
+
+        let text_width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as f32
+            * text::glyph_width(STATS_LINE_HEIGHT);
+        let panel_width = text_width.max(frame_times.capacity() as f32 * bar_width);
+        let panel_height = lines.len() as f32 * STATS_LINE_HEIGHT + graph_height;
+        let top = (height - margin).round();
+        let left = margin.round();
+
+        self.text_vertices.clear();
+
+        // Darken the background for legibility
+        let padding = 4.;
+        text::layout_rect(
+            &mut self.text_vertices,
+            Point3::new(left - padding, top - panel_height - padding, 0.),
+            right * (panel_width + padding * 2.),
+            up * (panel_height + padding * 2.),
+            [0., 0., 0., 0.6],
+        );
+
+        for (i, line) in lines.iter().enumerate() {
+            let baseline = top - (i + 1) as f32 * STATS_LINE_HEIGHT;
+            text::layout_text(
+                &mut self.text_vertices,
+                line,
+                Point3::new(left, baseline, 0.),
+                right,
+                up,
+                STATS_LINE_HEIGHT,
+                [1.; 4],
+            );
+        }
+
+        // Frame time graph, scaled so the top is 33 ms (30 FPS)
+        let graph_bottom = top - panel_height;
+        for (i, ms) in frame_times.iter().enumerate() {
+            let color = if ms > 1e3 / 60. + 1. {
+                [1., 0.3, 0.2, 1.]
+            } else {
+                [0.3, 1., 0.4, 1.]
+            };
+            let bar_height = (ms / 33.3).min(1.) * graph_height;
+            text::layout_rect(
+                &mut self.text_vertices,
+                Point3::new(left + i as f32 * bar_width, graph_bottom, 0.),
+                right * bar_width,
+                up * bar_height,
+                color,
+            );
+        }
+
+        let mut stats = std::mem::take(&mut self.frame_stats);
+        self.draw_text(gl, &proj, &Matrix4::identity(), false, &mut stats);
+        self.frame_stats = stats;
+        check_gl_error!(gl, "draw_stats");
+    }
+}
+
+impl Engine {
+    /// Depth comparison passing for nearer fragments
+    fn depth_func(&self) -> u32 {
+        if self.reverse_z {
+            gl::GREATER
+        } else {
+            gl::LESS
+        }
+    }
+
+    /// Whether shaders must encode their output as sRGB themselves
+    fn encode_srgb(&self) -> bool {
+        self.gamma_correct && !self.srgb_target
+    }
+
+    /// The registry entry of a handle, which must still be valid
+    fn entry_mut(&mut self, handle: MeshHandle) -> &mut MeshEntry {
+        let slot = &mut self.meshes[handle.index];
+        match &mut slot.entry {
+            Some(entry) if slot.generation == handle.generation => entry,
+            _ => panic!("Use of removed mesh {:?}", handle),
+        }
+    }
+
+    /// Bind the off-screen targets for the bound framebuffer and viewport, creating or resizing
+    /// them as needed. Returns what `end_post` needs to draw back to the original framebuffer.
+    fn begin_post(&mut self, gl: &gl::Context) -> Result<PostFrame, RenderError> {
+        let destination = post::bound_draw_framebuffer(gl);
+        let samples = post::bound_draw_samples(gl);
+        let mut viewport = [0; 4];
+        unsafe {
+            gl.get_parameter_i32_slice(gl::VIEWPORT, &mut viewport);
+        }
+        let size = (viewport[2], viewport[3]);
+
+        // Store colors the same way the destination does, so passes copy them unchanged
+        let color_format = if self.gamma_correct && self.srgb_target {
+            gl::SRGB8_ALPHA8
+        } else {
+            gl::RGBA8
+        };
+        let effects = self.post_effects.len();
+
+        let idx = match self
+            .post_targets
+            .iter()
+            .position(|t| t.destination == destination)
+        {
+            Some(idx) if self.post_targets[idx].matches(size, color_format, samples, effects) => {
+                idx
+            }
+            Some(idx) => {
+                // Recreate targets after a resize past a step of their allocated size
+                self.post_targets[idx].delete(gl);
+                self.post_targets[idx] =
+                    PostTargets::new(gl, destination, size, color_format, samples, effects)?;
+                idx
+            }
+            None => {
+                let targets =
+                    PostTargets::new(gl, destination, size, color_format, samples, effects)?;
+                self.post_targets.push(targets);
+                self.post_targets.len() - 1
+            }
+        };
+
+        // The scissor box is in the coordinates of the original framebuffer, so it only applies
+        // to the final pass
+        let scissor = unsafe { gl.is_enabled(gl::SCISSOR_TEST) };
+
+        unsafe {
+            let scene = self.post_targets[idx].scene_framebuffer();
+            gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, Some(scene));
+            gl.viewport(0, 0, size.0, size.1);
+            gl.disable(gl::SCISSOR_TEST);
+        }
+
+        Ok(PostFrame {
+            targets: idx,
+            viewport,
+            scissor,
+        })
+    }
+
+    /// Apply each effect in turn, the last one drawing to the original framebuffer along with
+    /// the depth of the scene, so that labels drawn afterwards are still hidden behind it
+    fn end_post(
+        &self,
+        gl: &gl::Context,
+        frame: PostFrame,
+        proj: &Matrix4<f32>,
+        stats: &mut FrameStats,
+    ) {
+        let targets = &self.post_targets[frame.targets];
+        let (width, height) = targets.size;
+        let drawn = (frame.viewport[2], frame.viewport[3]);
+        let linear_source = self.gamma_correct && self.srgb_target;
+
+        // Straight ahead lands here, which isn't the middle of the asymmetric views of a headset
+        let center = (-proj[(0, 2)], -proj[(1, 2)]);
+
+        if let Some(multisample) = &targets.multisample {
+            multisample.resolve(gl, &targets.scene, drawn);
+        }
+
+        unsafe {
+            gl.depth_func(gl::ALWAYS);
+            gl.bind_vertex_array(Some(self.empty_vao));
+
+            gl.active_texture(gl::TEXTURE1);
+            gl.bind_texture(gl::TEXTURE_2D, targets.scene.depth);
+
+            let effects: Vec<PostEffect> = self.active_post_effects().collect();
+            let mut source = targets.scene.color;
+            for (i, effect) in effects.iter().enumerate() {
+                let last = i + 1 == effects.len();
+                if last {
+                    gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, targets.destination);
+                    let [x, y, w, h] = frame.viewport;
+                    gl.viewport(x, y, w, h);
+                    if frame.scissor {
+                        gl.enable(gl::SCISSOR_TEST);
+                    }
+                } else {
+                    let swap = &targets.swap[i % 2];
+                    gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, Some(swap.framebuffer));
+                }
+
+                let shader = match effect {
+                    PostEffect::Fxaa => &self.fxaa_shader,
+                    PostEffect::Vignette => &self.vignette_shader,
+                };
+                gl.use_program(Some(shader.program));
+
+                let uniforms = &shader.uniforms;
+                gl.uniform_1_i32(uniforms.source.as_ref(), 0);
+                gl.uniform_1_i32(uniforms.depth.as_ref(), 1);
+                gl.uniform_2_f32(
+                    uniforms.texel_size.as_ref(),
+                    1. / width as f32,
+                    1. / height as f32,
+                );
+                gl.uniform_2_f32(
+                    uniforms.uv_scale.as_ref(),
+                    drawn.0 as f32 / width as f32,
+                    drawn.1 as f32 / height as f32,
+                );
+                gl.uniform_1_i32(uniforms.linear_source.as_ref(), linear_source as i32);
+                gl.uniform_2_f32(uniforms.center.as_ref(), center.0, center.1);
+                gl.uniform_1_f32(uniforms.radius.as_ref(), self.vignette_radius);
+
+                gl.active_texture(gl::TEXTURE0);
+                gl.bind_texture(gl::TEXTURE_2D, Some(source));
+
+                gl.draw_arrays(gl::TRIANGLES, 0, 3);
+                stats.draw(1, 1);
+
+                if !last {
+                    source = targets.swap[i % 2].color;
+                }
+            }
+
+            gl.active_texture(gl::TEXTURE1);
+            gl.bind_texture(gl::TEXTURE_2D, None);
+            gl.active_texture(gl::TEXTURE0);
+            gl.bind_texture(gl::TEXTURE_2D, None);
+
+            gl.bind_vertex_array(None);
+            gl.depth_func(self.depth_func());
+        }
+    }
+
+    fn shaders_mut(&mut self) -> [&mut Shader; 11] {
+        [
+            &mut self.unlit_shader,
+            &mut self.textured_shader,
+            &mut self.sky_shader,
+            &mut self.grid_shader,
+            &mut self.billboard_shader,
+            &mut self.minimap_shader,
+            &mut self.shadow_shader,
+            &mut self.fxaa_shader,
+            &mut self.vignette_shader,
+            &mut self.text_shader,
+            &mut self.lines_shader,
+        ]
+    }
+
+    /// Rebuild shaders whose files changed, checking at most once per `SHADER_POLL_INTERVAL`.
+    /// Shaders which fail to build keep their previous program.
+    fn poll_shader_changes(&mut self, gl: &gl::Context) {
+        let dir = match &self.shader_dir {
+            Some(dir) if self.last_shader_poll.elapsed() >= SHADER_POLL_INTERVAL => dir.clone(),
+            _ => return,
+        };
+        self.last_shader_poll = Instant::now();
+
+        for shader in self.shaders_mut() {
+            let modified = shader.files_modified(&dir);
+            if modified <= shader.modified {
+                continue;
+            }
+
+            match shader.reload(gl, Some(&dir)) {
+                Ok(()) => eprintln!("Reloaded shader \"{}\"", shader.desc.name),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    // Don't report the same error again until the files change
+                    shader.modified = modified;
+                }
+            }
+        }
+    }
+
+    /// Upload debug lines if they changed, and draw them
+    fn draw_debug_lines(
+        &mut self,
+        gl: &gl::Context,
+        proj: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+        stats: &mut FrameStats,
+    ) {
+        unsafe {
+            if self.debug_lines.take_dirty() {
+                let data: &[u8] = bytemuck::cast_slice(self.debug_lines.vertices());
+                gl.bind_buffer(gl::ARRAY_BUFFER, Some(self.lines_vbo));
+                gl.buffer_data_u8_slice(gl::ARRAY_BUFFER, data, gl::STREAM_DRAW);
+                stats.upload(data.len());
+                gl.bind_buffer(gl::ARRAY_BUFFER, None);
+                self.lines_uploaded = self.debug_lines.vertices().len() as i32;
+            }
+
+            if self.lines_uploaded == 0 {
+                return;
+            }
+
+            if self.debug_lines.xray {
+                gl.disable(gl::DEPTH_TEST);
+            }
+
+            let uniforms = &self.lines_shader.uniforms;
+            gl.use_program(Some(self.lines_shader.program));
+            gl.uniform_matrix_4_f32_slice(uniforms.view.as_ref(), false, view.as_slice());
+            gl.uniform_matrix_4_f32_slice(uniforms.proj.as_ref(), false, proj.as_slice());
+            gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), self.encode_srgb() as i32);
+
+            gl.bind_vertex_array(Some(self.lines_vao));
+            gl.draw_arrays(gl::LINES, 0, self.lines_uploaded);
+            gl.bind_vertex_array(None);
+            stats.draw(1, 0);
+
+            gl.enable(gl::DEPTH_TEST);
+        }
+    }
+
+    /// Upload and draw `text_vertices` with blending, without writing depth
+    fn draw_text(
+        &self,
+        gl: &gl::Context,
+        proj: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+        depth_test: bool,
+        stats: &mut FrameStats,
+    ) {
+        if self.text_vertices.is_empty() {
+            return;
+        }
+
+        unsafe {
+            let data: &[u8] = bytemuck::cast_slice(&self.text_vertices);
+            gl.bind_buffer(gl::ARRAY_BUFFER, Some(self.text_vbo));
+            gl.buffer_data_u8_slice(gl::ARRAY_BUFFER, data, gl::STREAM_DRAW);
+            stats.upload(data.len());
+            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+
+            // Blend over the scene without occluding anything drawn afterwards
+            gl.enable(gl::BLEND);
+            gl.blend_func_separate(
+                gl::SRC_ALPHA,
+                gl::ONE_MINUS_SRC_ALPHA,
+                gl::ONE,
+                gl::ONE_MINUS_SRC_ALPHA,
+            );
+            gl.depth_mask(false);
+            if !depth_test {
+                gl.disable(gl::DEPTH_TEST);
+            }
+
+            let uniforms = &self.text_shader.uniforms;
+            gl.use_program(Some(self.text_shader.program));
+            gl.uniform_matrix_4_f32_slice(uniforms.view.as_ref(), false, view.as_slice());
+            gl.uniform_matrix_4_f32_slice(uniforms.proj.as_ref(), false, proj.as_slice());
+            gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), self.encode_srgb() as i32);
+
+            gl.active_texture(gl::TEXTURE0);
+            gl.bind_texture(gl::TEXTURE_2D, Some(self.textures[self.font_atlas.0]));
+            gl.uniform_1_i32(uniforms.tex.as_ref(), 0);
+
+            gl.bind_vertex_array(Some(self.text_vao));
+            gl.draw_arrays(gl::TRIANGLES, 0, self.text_vertices.len() as i32);
+            gl.bind_vertex_array(None);
+            stats.draw(1, self.text_vertices.len() / 3);
+            gl.bind_texture(gl::TEXTURE_2D, None);
+
+            gl.enable(gl::DEPTH_TEST);
+            gl.depth_mask(true);
+            gl.disable(gl::BLEND);
+        }
+    }
+
+    /// Alpha-blended pass over some billboards, depth tested without writing depth. `facing` is
+    /// the world to view rotation they face. Plain billboards have no fog or texture, and are
+    /// not sRGB encoded.
+    fn draw_billboards(
+        &self,
+        gl: &gl::Context,
+        buffer: &BillboardBuffer,
+        proj: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+        facing: &Matrix3<f32>,
+        plain: bool,
+        stats: &mut FrameStats,
+    ) {
+        let ([fog_r, fog_g, fog_b], fog_density) = self.fog;
+        let fog_density = if plain { NO_FOG } else { fog_density };
+        let encode_srgb = self.encode_srgb() && !plain;
+        let texture = self.billboard_texture.filter(|_| !plain);
+
+        unsafe {
+            gl.enable(gl::BLEND);
+            gl.blend_func_separate(
+                gl::SRC_ALPHA,
+                gl::ONE_MINUS_SRC_ALPHA,
+                gl::ONE,
+                gl::ONE_MINUS_SRC_ALPHA,
+            );
+            gl.depth_mask(false);
+
+            let uniforms = &self.billboard_shader.uniforms;
+            gl.use_program(Some(self.billboard_shader.program));
+            gl.uniform_matrix_4_f32_slice(uniforms.view.as_ref(), false, view.as_slice());
+            gl.uniform_matrix_4_f32_slice(uniforms.proj.as_ref(), false, proj.as_slice());
+            gl.uniform_matrix_3_f32_slice(uniforms.facing.as_ref(), false, facing.as_slice());
+            gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), encode_srgb as i32);
+            gl.uniform_3_f32(uniforms.fog_color.as_ref(), fog_r, fog_g, fog_b);
+            gl.uniform_1_f32(uniforms.fog_density.as_ref(), fog_density);
+
+            match texture {
+                Some(TextureId(idx)) => {
+                    gl.active_texture(gl::TEXTURE0);
+                    gl.bind_texture(gl::TEXTURE_2D, Some(self.textures[idx]));
+                    gl.uniform_1_i32(uniforms.tex.as_ref(), 0);
+                    gl.uniform_1_i32(uniforms.use_texture.as_ref(), 1);
+                }
+                None => gl.uniform_1_i32(uniforms.use_texture.as_ref(), 0),
+            }
+
+            buffer.draw(gl, stats);
+
+            gl.depth_mask(true);
+            gl.disable(gl::BLEND);
+        }
+    }
+
+    /// Fullscreen pass tracing the ground plane, depth tested against the scene without writing
+    /// depth
+    fn draw_grid(
+        &self,
+        gl: &gl::Context,
+        proj: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+        stats: &mut FrameStats,
+    ) {
+        let view_proj = proj * view;
+        let inv_view_proj = view_proj.try_inverse().unwrap_or_else(Matrix4::identity);
+        let camera_pos = view
+            .try_inverse()
+            .map_or_else(Point3::origin, |inv| inv.transform_point(&Point3::origin()));
+
+        unsafe {
+            // The grid may lie exactly on the map, so it wins ties
+            gl.depth_func(if self.reverse_z {
+                gl::GEQUAL
+            } else {
+                gl::LEQUAL
+            });
+            gl.depth_mask(false);
+            gl.enable(gl::BLEND);
+            gl.blend_func_separate(
+                gl::SRC_ALPHA,
+                gl::ONE_MINUS_SRC_ALPHA,
+                gl::ONE,
+                gl::ONE_MINUS_SRC_ALPHA,
+            );
+
+            let uniforms = &self.grid_shader.uniforms;
+            gl.use_program(Some(self.grid_shader.program));
+            gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), self.encode_srgb() as i32);
+            gl.uniform_1_i32(uniforms.depth_zero_to_one.as_ref(), self.reverse_z as i32);
+            gl.uniform_matrix_4_f32_slice(uniforms.view_proj.as_ref(), false, view_proj.as_slice());
+            gl.uniform_matrix_4_f32_slice(
+                uniforms.inv_view_proj.as_ref(),
+                false,
+                inv_view_proj.as_slice(),
+            );
+            gl.uniform_3_f32(
+                uniforms.camera_pos.as_ref(),
+                camera_pos.x,
+                camera_pos.y,
+                camera_pos.z,
+            );
+
+            gl.bind_vertex_array(Some(self.empty_vao));
+            gl.draw_arrays(gl::TRIANGLES, 0, 3);
+            gl.bind_vertex_array(None);
+            stats.draw(1, 1);
+
+            gl.disable(gl::BLEND);
+            gl.depth_mask(true);
+            gl.depth_func(self.depth_func());
+        }
+    }
+
+    /// Fullscreen pass which neither reads nor writes depth
+    fn draw_sky(
+        &self,
+        gl: &gl::Context,
+        sky: Sky,
+        proj: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+        stats: &mut FrameStats,
+    ) {
+        let inv_view_proj = (proj * strip_translation(view))
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity);
+
+        unsafe {
+            gl.disable(gl::DEPTH_TEST);
+            gl.depth_mask(false);
+
+            let uniforms = &self.sky_shader.uniforms;
+            gl.use_program(Some(self.sky_shader.program));
+            gl.uniform_1_i32(uniforms.encode_srgb.as_ref(), self.encode_srgb() as i32);
+            gl.uniform_matrix_4_f32_slice(
+                uniforms.inv_view_proj.as_ref(),
+                false,
+                inv_view_proj.as_slice(),
+            );
+            gl.uniform_3_f32(
+                uniforms.sun_dir.as_ref(),
+                self.sun_dir.x,
+                self.sun_dir.y,
+                self.sun_dir.z,
+            );
+
+            match sky {
+                Sky::Gradient => gl.uniform_1_i32(uniforms.use_cubemap.as_ref(), 0),
+                Sky::Cubemap(TextureId(idx)) => {
+                    gl.active_texture(gl::TEXTURE0);
+                    gl.bind_texture(gl::TEXTURE_CUBE_MAP, Some(self.textures[idx]));
+                    gl.uniform_1_i32(uniforms.cubemap.as_ref(), 0);
+                    gl.uniform_1_i32(uniforms.use_cubemap.as_ref(), 1);
+                }
+            }
+
+            gl.bind_vertex_array(Some(self.empty_vao));
+            gl.draw_arrays(gl::TRIANGLES, 0, 3);
+            gl.bind_vertex_array(None);
+            gl.bind_texture(gl::TEXTURE_CUBE_MAP, None);
+            stats.draw(1, 1);
+
+            gl.depth_mask(true);
+            gl.enable(gl::DEPTH_TEST);
+        }
+    }
+}
+
+impl<L> ShaderUniforms<L> {
+    /// Look up each uniform once with `get`, failing if one the shader requires is missing
+    fn lookup(
+        desc: &ShaderDesc,
+        mut get: impl FnMut(&'static str) -> Option<L>,
+    ) -> Result<Self, RenderError> {
+        let mut found = vec![];
+        let mut get = |uniform| {
+            let location = get(uniform);
+            if location.is_some() {
+                found.push(uniform);
+            }
+            location
+        };
+        let uniforms = ShaderUniforms {
+            view: get("view"),
+            proj: get("proj"),
+            debug_mode: get("debug_mode"),
+            tex: get("tex"),
+            inv_view_proj: get("inv_view_proj"),
+            view_proj: get("view_proj"),
+            camera_pos: get("camera_pos"),
+            depth_zero_to_one: get("depth_zero_to_one"),
+            facing: get("facing"),
+            use_texture: get("use_texture"),
+            rect: get("rect"),
+            border: get("border"),
+            heading: get("heading"),
+            sun_dir: get("sun_dir"),
+            use_cubemap: get("use_cubemap"),
+            cubemap: get("cubemap"),
+            encode_srgb: get("encode_srgb"),
+            fog_color: get("fog_color"),
+            fog_density: get("fog_density"),
+            alpha: get("alpha"),
+            fade_range: get("fade_range"),
+            time: get("time"),
+            idle: get("idle"),
+            light_view_proj: get("light_view_proj"),
+            shadows: get("shadows"),
+            shadow_map: get("shadow_map"),
+            shadow_bias: get("shadow_bias"),
+            shadow_slope_bias: get("shadow_slope_bias"),
+            source: get("source"),
+            depth: get("depth"),
+            texel_size: get("texel_size"),
+            uv_scale: get("uv_scale"),
+            linear_source: get("linear_source"),
+            center: get("center"),
+            radius: get("radius"),
+        };
+
+        match desc
+            .required
+            .iter()
+            .find(|uniform| !found.contains(*uniform))
+        {
+            Some(&uniform) => Err(RenderError::MissingUniform {
+                shader: desc.name,
+                uniform,
+            }),
+            None => Ok(uniforms),
+        }
+    }
+}
+
+impl Shader {
+    /// Compile a program from the files in `dir`, or from the embedded sources if None, checking
+    /// that it has each of the required uniforms
+    fn new(
+        gl: &gl::Context,
+        desc: &'static ShaderDesc,
+        dir: Option<&Path>,
+    ) -> Result<Self, RenderError> {
+        let mut sources = vec![];
+        let mut modified = None;
+        let mut read = |file: &str, embedded: &str| match dir {
+            Some(dir) => {
+                let path = dir.join(file);
+                modified = modified.max(file_modified(&path));
+                std::fs::read_to_string(&path)
+                    .map_err(|error| RenderError::ShaderRead { path, error })
+            }
+            None => Ok(embedded.to_string()),
+        };
+        for &(stage, file, embedded) in desc.stages {
+            let mut source = read(file, embedded)?;
+            if let Some(&(_, file, embedded)) = prelude(stage) {
+                source = with_prelude(&source, &read(file, embedded)?);
+            }
+            sources.push((stage, source));
+        }
+
+        let sources: Vec<(u32, &str)> = sources.iter().map(|(s, src)| (*s, src.as_str())).collect();
+        let program = compile_glsl_program(gl, desc.name, &sources)?;
+
+        let uniforms = ShaderUniforms::lookup(desc, |uniform| unsafe {
+            gl.get_uniform_location(program, uniform)
+        });
+        match uniforms {
+            Ok(uniforms) => Ok(Self {
+                desc,
+                program,
+                uniforms,
+                modified,
+            }),
+            Err(e) => {
+                unsafe { gl.delete_program(program) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Rebuild the program, replacing this one only if that succeeds
+    fn reload(&mut self, gl: &gl::Context, dir: Option<&Path>) -> Result<(), RenderError> {
+        let new = Shader::new(gl, self.desc, dir)?;
+        unsafe {
+            gl.delete_program(self.program);
+        }
+        *self = new;
+        Ok(())
+    }
+
+    /// Latest modification time of the source files in `dir`, including the preludes
+    fn files_modified(&self, dir: &Path) -> Option<SystemTime> {
+        let preludes = self.desc.stages.iter().filter_map(|(s, _, _)| prelude(*s));
+        preludes
+            .chain(self.desc.stages)
+            .filter_map(|(_, file, _)| file_modified(&dir.join(file)))
+            .max()
+    }
+}
+
+/// The code shared by the shaders of `stage`, if there is any
+fn prelude(stage: u32) -> Option<&'static (u32, &'static str, &'static str)> {
+    PRELUDES.iter().find(|(s, _, _)| *s == stage)
+}
+
+/// Insert `prelude` after the #version line of `source`, numbering the lines after it as they are
+/// in the file, so that compile errors point at the right line
+fn with_prelude(source: &str, prelude: &str) -> String {
+    match source.split_once('\n') {
+        Some((version, rest)) => format!("{}\n{}\n#line 2\n{}", version, prelude, rest),
+        None => source.to_string(),
+    }
+}
+
+fn file_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl GpuMesh {
+    /// Delete the GL objects of the mesh
+    fn delete(self, gl: &gl::Context) {
+        unsafe {
+            gl.delete_vertex_array(self.vao);
+            gl.delete_buffer(self.vbo);
+            gl.delete_buffer(self.ebo);
+            if let Some((unindexed_vbo, _)) = self.unindexed {
+                gl.delete_buffer(unindexed_vbo);
+            }
+        }
+    }
+
+    /// Replace the mesh data, reusing the existing buffers when the new data fits. Returns the
+    /// number of bytes uploaded.
+    fn update(&mut self, gl: &gl::Context, mesh: &Mesh) -> usize {
+        let mut written = std::mem::size_of_val(mesh.vertices.as_slice())
+            + std::mem::size_of_val(mesh.indices.as_slice());
+        unsafe {
+            // The element buffer binding belongs to the vertex array
+            gl.bind_vertex_array(Some(self.vao));
+            write_buffer(
+                gl,
+                gl::ARRAY_BUFFER,
+                self.vbo,
+                &mut self.vbo_capacity,
+                bytemuck::cast_slice(&mesh.vertices),
+                self.usage,
+            );
+            write_buffer(
+                gl,
+                gl::ELEMENT_ARRAY_BUFFER,
+                self.ebo,
+                &mut self.ebo_capacity,
+                bytemuck::cast_slice(&mesh.indices),
+                self.usage,
+            );
+            gl.bind_vertex_array(None);
+            gl.bind_buffer(gl::ARRAY_BUFFER, None);
+
+            self.index_count = mesh.indices.len() as i32;
+
+            if let Some((unindexed_vbo, vertex_count)) = &mut self.unindexed {
+                let vertices: Vec<Vertex> = mesh
+                    .indices
+                    .iter()
+                    .map(|&i| mesh.vertices[i as usize])
+                    .collect();
+
+                gl.bind_buffer(gl::ARRAY_BUFFER, Some(*unindexed_vbo));
+                gl.buffer_data_u8_slice(
+                    gl::ARRAY_BUFFER,
+                    bytemuck::cast_slice(&vertices),
+                    self.usage,
+                );
+                gl.bind_buffer(gl::ARRAY_BUFFER, None);
+
+                *vertex_count = vertices.len() as i32;
+                written += std::mem::size_of_val(vertices.as_slice());
+            }
+        }
+        written
+    }
+
+    /// Draw the given number of instances, from the indices or from the unindexed copy of the
+    /// vertices
+    fn draw(&self, gl: &gl::Context, instances: i32, unindexed: bool, stats: &mut FrameStats) {
+        if instances <= 0 {
+            return;
+        }
+        unsafe {
+            gl.bind_vertex_array(Some(self.vao));
+            match self.unindexed {
+                Some((unindexed_vbo, vertex_count)) if unindexed => {
+                    // Temporarily point the vertex attributes at the copy
+                    gl.bind_buffer(gl::ARRAY_BUFFER, Some(unindexed_vbo));
+                    set_vertex_attrib(gl);
+                    gl.draw_arrays_instanced(gl::TRIANGLES, 0, vertex_count, instances);
+                    gl.bind_buffer(gl::ARRAY_BUFFER, Some(self.vbo));
+                    set_vertex_attrib(gl);
+                    gl.bind_buffer(gl::ARRAY_BUFFER, None);
+                }
+                _ => gl.draw_elements_instanced(
+                    gl::TRIANGLES,
+                    self.index_count,
+                    gl::UNSIGNED_INT,
+                    0,
+                    instances,
+                ),
+            }
+            gl.bind_vertex_array(None);
+        }
+        stats.draw(instances as usize, self.index_count as usize / 3);
+    }
+}
+
+impl DebugMode {
+    /// The next mode, cycling back to `Solid`
+    pub fn next(self) -> Self {
+        match self {
+            DebugMode::Solid => DebugMode::Wireframe,
+            DebugMode::Wireframe => DebugMode::Normals,
+            DebugMode::Normals => DebugMode::Solid,
+        }
+    }
+}
+
+/// Set the GL state the engine relies on. Call this after other renderers have used the
+/// context.
+pub fn set_default_state(gl: &gl::Context) {
+    unsafe {
+        // Enable backface culling
+        gl.enable(gl::CULL_FACE);
+
+        // Enable depth buffering
+        gl.enable(gl::DEPTH_TEST);
+        gl.depth_func(gl::LESS);
+        gl.depth_mask(true);
+
+        gl.disable(gl::BLEND);
+        gl.disable(gl::SCISSOR_TEST);
+        gl.use_program(None);
+        gl.bind_vertex_array(None);
+    }
+}
+
+/// Creates a view matrix for the given head position
+pub fn view_from_head(head: &Head) -> Matrix4<f32> {
+    // Invert this quaternion, orienting the world into NDC space
+    // Represent the rotation in homogeneous coordinates
+    let rotation = head.orient.inverse().to_homogeneous();
+
+    // Invert this translation, translating the world into NDC space
+    let translation = Matrix4::new_translation(&-head.pos.coords);
+
+    // Compose the view
+    rotation * translation
+}
+
+/// Enable GL error checking, and log messages from the driver if KHR_debug is available.
+/// The callback is owned by the context, and is freed along with it.
+pub fn enable_gl_debug(gl: &mut gl::Context) {
+    GL_DEBUG.store(true, Ordering::Relaxed);
+
+    unsafe {
+        if !gl.supports_debug() {
+            eprintln!("KHR_debug is not available, falling back to glGetError");
+            return;
+        }
+
+        gl.enable(gl::DEBUG_OUTPUT);
+        // Report messages on the thread (and in the call) which caused them
+        gl.enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl.debug_message_callback(|_source, msg_type, id, severity, message| {
+            let severity = match severity {
+                gl::DEBUG_SEVERITY_HIGH => "high",
+                gl::DEBUG_SEVERITY_MEDIUM => "medium",
+                gl::DEBUG_SEVERITY_LOW => "low",
+                // Notifications are too chatty to be useful
+                _ => return,
+            };
+
+            let msg_type = match msg_type {
+                gl::DEBUG_TYPE_ERROR => "error",
+                gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+                gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+                gl::DEBUG_TYPE_PERFORMANCE => "performance",
+                gl::DEBUG_TYPE_PORTABILITY => "portability",
+                _ => "other",
+            };
+
+            eprintln!("GL {} ({}, id {}): {}", msg_type, severity, id, message);
+        });
+    }
+}
+
+/// Whether GL error checking is enabled
+pub fn gl_debug_enabled() -> bool {
+    GL_DEBUG.load(Ordering::Relaxed)
+}
+
+/// Drain the pending GL errors
+pub fn take_gl_errors(gl: &gl::Context) -> Vec<u32> {
+    let mut errors = vec![];
+    unsafe {
+        // Bounded, since a lost context may report errors forever
+        while errors.len() < 32 {
+            match gl.get_error() {
+                gl::NO_ERROR => break,
+                err => errors.push(err),
+            }
+        }
+    }
+    errors
+}
+
+/// Human-readable name of a GL error code
+pub fn gl_error_name(err: u32) -> &'static str {
+    match err {
+        gl::INVALID_ENUM => "INVALID_ENUM",
+        gl::INVALID_VALUE => "INVALID_VALUE",
+        gl::INVALID_OPERATION => "INVALID_OPERATION",
+        gl::INVALID_FRAMEBUFFER_OPERATION => "INVALID_FRAMEBUFFER_OPERATION",
+        gl::OUT_OF_MEMORY => "OUT_OF_MEMORY",
+        gl::STACK_UNDERFLOW => "STACK_UNDERFLOW",
+        gl::STACK_OVERFLOW => "STACK_OVERFLOW",
+        _ => "unknown error",
+    }
+}
+
+/// Name of a shader stage, for error messages
+fn shader_stage_name(stage: u32) -> &'static str {
+    match stage {
+        gl::VERTEX_SHADER => "vertex",
+        gl::FRAGMENT_SHADER => "fragment",
+        gl::GEOMETRY_SHADER => "geometry",
+        gl::COMPUTE_SHADER => "compute",
+        _ => "unknown",
+    }
+}
+
+/// Removes the translation from a view matrix, leaving only its rotation
+pub fn strip_translation(view: &Matrix4<f32>) -> Matrix4<f32> {
+    let mut rotation = *view;
+    rotation[(0, 3)] = 0.;
+    rotation[(1, 3)] = 0.;
+    rotation[(2, 3)] = 0.;
+    rotation
+}
+
+/// The outcome of compiling the `stage` of shader `name`, with the info `log` attached if it
+/// failed. The log is only fetched then.
+fn compile_status(
+    name: &'static str,
+    stage: u32,
+    compiled: bool,
+    log: impl FnOnce() -> String,
+) -> Result<(), RenderError> {
+    if compiled {
+        Ok(())
+    } else {
+        Err(RenderError::ShaderCompile {
+            shader: name,
+            stage,
+            log: log(),
+        })
+    }
+}
+
+/// Compiles (*_SHADER, <source>) into a shader program for OpenGL. `name` identifies the shader
+/// in errors.
+fn compile_glsl_program(
+    gl: &gl::Context,
+    name: &'static str,
+    sources: &[(u32, &str)],
+) -> Result<gl::Program, RenderError> {
+    // Compile default shaders
+    unsafe {
+        let program = gl.create_program().map_err(|e| {
+            RenderError::ObjectCreate(format!("program of shader \"{}\"; {}", name, e))
+        })?;
+
+        let mut shaders = vec![];
+
+        for (stage, shader_source) in sources {
+            let shader = match gl.create_shader(*stage) {
+                Ok(shader) => shader,
+                Err(e) => {
+                    for shader in shaders {
+                        gl.delete_shader(shader);
+                    }
+                    gl.delete_program(program);
+                    return Err(RenderError::ObjectCreate(format!(
+                        "{} stage of shader \"{}\"; {}",
+                        shader_stage_name(*stage),
+                        name,
+                        e
+                    )));
+                }
+            };
+
+            gl.shader_source(shader, shader_source);
+
+            gl.compile_shader(shader);
+
+            let compiled = gl.get_shader_compile_status(shader);
+            let status = compile_status(name, *stage, compiled, || gl.get_shader_info_log(shader));
+            if let Err(e) = status {
+                gl.delete_shader(shader);
+                for shader in shaders {
+                    gl.delete_shader(shader);
+                }
+                gl.delete_program(program);
+                return Err(e);
+            }
+
+            gl.attach_shader(program, shader);
+
+            shaders.push(shader);
+        }
+
+        gl.link_program(program);
+
+        let linked = gl.get_program_link_status(program);
+
+        for shader in shaders {
+            gl.detach_shader(program, shader);
+            gl.delete_shader(shader);
+        }
+
+        if !linked {
+            let log = gl.get_program_info_log(program);
+            gl.delete_program(program);
+            return Err(RenderError::ProgramLink { shader: name, log });
+        }
+
+        Ok(program)
+    }
+}
+
+impl Instance {
+    /// An untinted instance with the given transform
+    pub fn new(transform: Matrix4<f32>) -> Self {
+        Self {
+            transform: *transform.as_ref(),
+            tint: [1.; 4],
+            phase: 0.,
+        }
+    }
+}
+
+impl Vertex {
+    /// A vertex with no normal and zero texture coordinates
+    pub fn new(pos: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            pos: pos.into(),
+            normal: Vector3::zeros(),
+            uv: [0.; 2],
+            color: color.into(),
+        }
+    }
+
+    /// Set the normal and texture coordinates of this vertex
+    pub fn with_normal_uv(mut self, normal: [f32; 3], uv: [f32; 2]) -> Self {
+        self.normal = normal.into();
+        self.uv = uv;
+        self
+    }
+}
+
+impl Mesh {
+    /// Transform positions by `matrix`, and normals to match. Mirroring transforms reverse the
+    /// winding, so that front faces stay in front.
+    pub fn transform(&mut self, matrix: &Matrix4<f32>) {
+        // Leave the mesh exactly as it was, rather than renormalizing
+        if *matrix == Matrix4::identity() {
+            return;
+        }
+
+        // Normals transform by the inverse transpose, to stay perpendicular under non-uniform scale
+        let linear = matrix.fixed_slice::<3, 3>(0, 0).into_owned();
+        let normal_matrix = linear.try_inverse().unwrap_or(linear).transpose();
+        for vertex in &mut self.vertices {
+            vertex.pos = matrix.transform_point(&vertex.pos);
+            vertex.normal = (normal_matrix * vertex.normal)
+                .try_normalize(0.)
+                .unwrap_or_else(Vector3::zeros);
+        }
+
+        if linear.determinant() < 0. {
+            for tri in self.indices.chunks_exact_mut(3) {
+                tri.swap(1, 2);
+            }
+        }
+    }
+
+    /// Add the triangles of `other` after those of this mesh
+    pub fn append(&mut self, other: &Mesh) -> Result<(), MeshError> {
+        check_indexable(self.vertices.len() + other.vertices.len())?;
+        let base = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices.extend(other.indices.iter().map(|i| i + base));
+        Ok(())
+    }
+
+    /// Give every vertex the same linear color
+    pub fn recolor(&mut self, color: [f32; 3]) {
+        self.map_colors(|_| color.into());
+    }
+
+    /// Replace the linear color of every vertex with `f` of it
+    pub fn map_colors(&mut self, mut f: impl FnMut(Vector3<f32>) -> Vector3<f32>) {
+        for vertex in &mut self.vertices {
+            vertex.color = f(vertex.color);
+        }
+    }
+
+    /// Check that the mesh is safe to upload, within the default `MeshLimits`
+    pub fn validate(&self) -> Result<(), MeshError> {
+        self.validate_within(&MeshLimits::default())
+    }
+
+    /// Check that the mesh is safe to upload: whole triangles, indices in range, finite vertex
+    /// data and a size within `limits`. Degenerate triangles are allowed, with a warning.
+    pub fn validate_within(&self, limits: &MeshLimits) -> Result<(), MeshError> {
+        if self.vertices.len() > limits.max_vertices || self.indices.len() > limits.max_indices {
+            return Err(MeshError::TooLarge {
+                vertices: self.vertices.len(),
+                indices: self.indices.len(),
+            });
+        }
+        if self.indices.is_empty() {
+            return Err(MeshError::Empty);
+        }
+        if self.indices.len() % 3 != 0 {
+            return Err(MeshError::PartialTriangle {
+                indices: self.indices.len(),
+            });
+        }
+
+        if let Some(at) = self
+            .indices
+            .iter()
+            .position(|&i| i as usize >= self.vertices.len())
+        {
+            return Err(MeshError::IndexOutOfRange {
+                at,
+                index: self.indices[at],
+                vertices: self.vertices.len(),
+            });
+        }
+
+        if let Some(vertex) = self.vertices.iter().position(|v| {
+            let floats = v.pos.iter().chain(&v.normal).chain(&v.uv).chain(&v.color);
+            !floats.copied().all(f32::is_finite)
+        }) {
+            return Err(MeshError::NonFinite { vertex });
+        }
+
+        let degenerate = self.degenerate_triangles();
+        if degenerate > 0 {
+            eprintln!("Mesh has {} degenerate triangles", degenerate);
+        }
+
+        Ok(())
+    }
+
+    /// Number of triangles with no area, which `validate_within` warns about
+    fn degenerate_triangles(&self) -> usize {
+        self.indices
+            .chunks_exact(3)
+            .filter(|tri| {
+                let [a, b, c] = [0, 1, 2].map(|i| self.vertices[tri[i] as usize].pos);
+                (b - a).cross(&(c - a)) == Vector3::zeros()
+            })
+            .count()
+    }
+
+    /// Compute vertex normals from the triangles, keeping those which are already unit length if
+    /// `keep_existing`. Degenerate triangles contribute nothing, and vertices only used by them
+    /// are left without a normal.
+    pub fn compute_normals(&mut self, mode: NormalMode, keep_existing: bool) {
+        match mode {
+            NormalMode::Flat => self.compute_flat_normals(keep_existing),
+            NormalMode::Smooth => self.compute_smooth_normals(keep_existing),
+        }
+    }
+
+    /// Give each triangle its own vertices, facing the way the triangle does
+    fn compute_flat_normals(&mut self, keep_existing: bool) {
+        let mut vertices = Vec::with_capacity(self.indices.len());
+        for tri in self.indices.chunks_exact(3) {
+            let corners = [0, 1, 2].map(|i| self.vertices[tri[i] as usize]);
+            let [a, b, c] = corners.map(|v| v.pos);
+            let normal = (b - a)
+                .cross(&(c - a))
+                .try_normalize(0.)
+                .unwrap_or_else(Vector3::zeros);
+            for mut vertex in corners {
+                if !(keep_existing && is_unit(&vertex.normal)) {
+                    vertex.normal = normal;
+                }
+                vertices.push(vertex);
+            }
+        }
+
+        self.indices = (0..vertices.len() as u32).collect();
+        self.vertices = vertices;
+    }
+
+    /// Give vertices the average normal of the triangles using them, weighted by area
+    fn compute_smooth_normals(&mut self, keep_existing: bool) {
+        let replace: Vec<bool> = self
+            .vertices
+            .iter()
+            .map(|v| !(keep_existing && is_unit(&v.normal)))
+            .collect();
+        for (vertex, _) in self.vertices.iter_mut().zip(&replace).filter(|(_, r)| **r) {
+            vertex.normal = Vector3::zeros();
+        }
+
+        for tri in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[tri[i] as usize].pos);
+            // Length is twice the area of the triangle
+            let normal = (b - a).cross(&(c - a));
+            for &idx in tri {
+                if replace[idx as usize] {
+                    self.vertices[idx as usize].normal += normal;
+                }
+            }
+        }
+
+        for (vertex, _) in self.vertices.iter_mut().zip(&replace).filter(|(_, r)| **r) {
+            vertex.normal = vertex
+                .normal
+                .try_normalize(0.)
+                .unwrap_or_else(Vector3::zeros);
+        }
+    }
+}
+
+/// Check that the last of `vertices` is still within reach of a u32 index
+fn check_indexable(vertices: usize) -> Result<(), MeshError> {
+    match vertices as u64 > u64::from(u32::MAX) + 1 {
+        true => Err(MeshError::IndexOverflow { vertices }),
+        false => Ok(()),
+    }
+}
+
+/// Whether a normal is unit length, within rounding
+fn is_unit(normal: &Vector3<f32>) -> bool {
+    (normal.norm_squared() - 1.).abs() < 1e-3
+}
+
+impl RgbaImage {
+    /// Generate a white disc filling the image, with a soft edge a pixel wide and a transparent
+    /// surround, for round billboards
+    pub fn disc(size: u32) -> Self {
+        let radius = size as f32 / 2.;
+        let mut data = Vec::with_capacity((size * size * 4) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let dx = x as f32 + 0.5 - radius;
+                let dy = y as f32 + 0.5 - radius;
+                let coverage = (radius - dx.hypot(dy) + 0.5).clamp(0., 1.);
+                data.extend_from_slice(&[0xFF, 0xFF, 0xFF, (coverage * 255.).round() as u8]);
+            }
+        }
+
+        Self {
+            width: size,
+            height: size,
+            data,
+        }
+    }
+
+    /// Generate a grey checkerboard, used in place of textures which could not be loaded
+    pub fn checkerboard(size: u32, cells: u32) -> Self {
+        let cell_size = (size / cells.max(1)).max(1);
+        let mut data = Vec::with_capacity((size * size * 4) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let light = ((x / cell_size) + (y / cell_size)) % 2 == 0;
+                let v = if light { 0xC0 } else { 0x40 };
+                data.extend_from_slice(&[v, v, v, 0xFF]);
+            }
+        }
+
+        Self {
+            width: size,
+            height: size,
+            data,
+        }
+    }
+
+    /// Decode an image file (PNG, JPEG, ...)
+    #[cfg(feature = "image")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RenderError> {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .map_err(|e| RenderError::ImageLoad {
+                path: path.to_path_buf(),
+                error: e.to_string(),
+            })?
+            .to_rgba8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            data: image.into_raw(),
+        })
+    }
+
+    /// Decode an image file; requires the `image` feature
+    #[cfg(not(feature = "image"))]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RenderError> {
+        Err(RenderError::ImageLoad {
+            path: path.as_ref().to_path_buf(),
+            error: "cubehead was built without the `image` feature".into(),
+        })
+    }
+
+    /// Encode the image as a PNG file
+    #[cfg(feature = "image")]
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RenderError> {
+        let path = path.as_ref();
+        image::save_buffer_with_format(
+            path,
+            &self.data,
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+            image::ImageFormat::Png,
+        )
+        .map_err(|e| RenderError::ImageSave {
+            path: path.to_path_buf(),
+            error: e.to_string(),
+        })
+    }
+
+    /// Encode the image as a PNG file; requires the `image` feature
+    #[cfg(not(feature = "image"))]
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RenderError> {
+        Err(RenderError::ImageSave {
+            path: path.as_ref().to_path_buf(),
+            error: "cubehead was built without the `image` feature".into(),
+        })
+    }
+
+    /// Read the bottom left `width` by `height` pixels of the window's framebuffer, as it will be
+    /// shown. Alpha is made opaque, since the window is.
+    pub fn read_window(gl: &gl::Context, width: u32, height: u32) -> Self {
+        let row = width as usize * 4;
+        let mut data = vec![0; row * height as usize];
+        unsafe {
+            gl.bind_framebuffer(gl::READ_FRAMEBUFFER, None);
+            gl.pixel_store_i32(gl::PACK_ALIGNMENT, 1);
+            gl.read_pixels(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                gl::PixelPackData::Slice(&mut data),
+            );
+        }
+
+        // GL reads rows from the bottom up
+        let mut data: Vec<u8> = data.chunks_exact(row).rev().flatten().copied().collect();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel[3] = 0xFF;
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Load the faces of a cubemap from `px.png`, `nx.png`, `py.png`, `ny.png`, `pz.png` and
+    /// `nz.png` in the given directory. The faces must be square and all the same size.
+    pub fn load_cube_faces(dir: &Path) -> Result<[Self; 6], RenderError> {
+        let mut faces: Vec<Self> = vec![];
+        for name in ["px", "nx", "py", "ny", "pz", "nz"] {
+            let path = dir.join(name).with_extension("png");
+            let face = Self::load(&path)?;
+            let size = faces.first().map_or(face.width, |first| first.width);
+            if (face.width, face.height) != (size, size) {
+                return Err(RenderError::ImageLoad {
+                    path,
+                    error: format!(
+                        "Cubemap faces must be square and all the same size; expected {0}x{0}, \
+                         found {1}x{2}",
+                        size, face.width, face.height
+                    ),
+                });
+            }
+            faces.push(face);
+        }
+
+        Ok(faces.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+fn set_vertex_attrib(gl: &gl::Context) {
+    unsafe {
+        set_float_attribs(gl, &VERTEX_ATTRIBS, std::mem::size_of::<Vertex>(), 0, 0);
+    }
+}
+
+/// Point float attributes of the bound vertex array at the bound array buffer. The attributes
+/// are tightly packed in the given order, in elements of `stride` bytes starting at byte `base`,
+/// and advance once per `divisor` instances (or per vertex, if 0).
+unsafe fn set_float_attribs(
+    gl: &gl::Context,
+    attribs: &[(u32, usize)],
+    stride: usize,
+    base: usize,
+    divisor: u32,
+) {
+    let mut offset = 0;
+    for &(location, size) in attribs {
+        gl.enable_vertex_attrib_array(location);
+        gl.vertex_attrib_pointer_f32(
+            location,
+            size as i32,
+            gl::FLOAT,
+            false,
+            stride as i32,
+            (base + offset * std::mem::size_of::<f32>()) as i32,
+        );
+        gl.vertex_attrib_divisor(location, divisor);
+        offset += size;
+    }
+}
+
+/// Decode an sRGB encoded color component
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Minimum and maximum corners of the bounding box of a mesh
+fn mesh_bounds(mesh: &Mesh) -> (Point3<f32>, Point3<f32>) {
+    let mut min = Point3::from([f32::INFINITY; 3]);
+    let mut max = Point3::from([f32::NEG_INFINITY; 3]);
+    for vertex in &mesh.vertices {
+        min = min.inf(&vertex.pos);
+        max = max.sup(&vertex.pos);
+    }
+    (min, max)
+}
+
+/// A box with the bounds and average color of a mesh, as a cheap stand-in for it
+fn lod_box(mesh: &Mesh) -> Mesh {
+    let (min, max) = mesh_bounds(mesh);
+    let color = mesh.vertices.iter().map(|v| v.color).sum::<Vector3<f32>>()
+        / mesh.vertices.len().max(1) as f32;
+
+    // Corner i has the max x, y and z if bits 0, 1 and 2 are set
+    let vertices = (0..8)
+        .map(|i| {
+            let pick = |bit, min: f32, max: f32| if i & bit != 0 { max } else { min };
+            let pos = [
+                pick(1, min.x, max.x),
+                pick(2, min.y, max.y),
+                pick(4, min.z, max.z),
+            ];
+            Vertex::new(pos, color.into())
+        })
+        .collect();
+
+    let mut lod = Mesh {
+        vertices,
+        indices: vec![
+            0, 4, 6, 0, 6, 2, // -X
+            5, 1, 3, 5, 3, 7, // +X
+            0, 1, 5, 0, 5, 4, // -Y
+            3, 2, 6, 3, 6, 7, // +Y
+            0, 2, 3, 0, 3, 1, // -Z
+            4, 5, 7, 4, 7, 6, // +Z
+        ],
+    };
+    // Shaded as a box, rather than rounded off at the corners
+    lod.compute_normals(NormalMode::Flat, false);
+    lod
+}
+
+/// Uploads a mesh, optionally with an unindexed copy of its vertices for wireframe rendering
+fn upload_mesh(
+    gl: &gl::Context,
+    usage: u32,
+    mesh: &Mesh,
+    with_unindexed: bool,
+    stats: &mut FrameStats,
+) -> Result<GpuMesh, RenderError> {
+    unsafe {
+        // Map buffer
+        let vao = gl.create_vertex_array()?;
+        let vbo = gl.create_buffer()?;
+        let ebo = gl.create_buffer()?;
+
+        // Set vertex attributes, and attach the element buffer to the vertex array
+        gl.bind_vertex_array(Some(vao));
+        gl.bind_buffer(gl::ARRAY_BUFFER, Some(vbo));
+        gl.bind_buffer(gl::ELEMENT_ARRAY_BUFFER, Some(ebo));
+        set_vertex_attrib(gl);
+        gl.bind_vertex_array(None);
+        gl.bind_buffer(gl::ARRAY_BUFFER, None);
+
+        // The copy is bound to the vertex attributes only while drawing it
+        let unindexed = if with_unindexed {
+            Some((gl.create_buffer()?, 0))
+        } else {
+            None
+        };
+
+        let mut gpu_mesh = GpuMesh {
+            vao,
+            vbo,
+            ebo,
+            vbo_capacity: 0,
+            ebo_capacity: 0,
+            usage,
+            index_count: 0,
+            unindexed,
+        };
+        stats.upload(gpu_mesh.update(gl, mesh));
+
+        check_gl_error!(gl, "upload_mesh");
+
+        Ok(gpu_mesh)
+    }
+}
+
+/// Create an empty buffer of `Instance`s, bound to the instance attributes of a vertex array
+fn create_instance_buffer(
+    gl: &gl::Context,
+    vao: gl::VertexArray,
+) -> Result<gl::NativeBuffer, RenderError> {
+    unsafe {
+        gl.bind_vertex_array(Some(vao));
+        let instance_vbo = gl.create_buffer()?;
+        gl.bind_buffer(gl::ARRAY_BUFFER, Some(instance_vbo));
+
+        set_instance_attribs(gl, 0);
+
+        gl.bind_buffer(gl::ARRAY_BUFFER, None);
+        gl.bind_vertex_array(None);
+
+        Ok(instance_vbo)
+    }
+}
+
+/// Whether glClipControl is available, which reversed depth needs to be worth it
+fn clip_control_supported(gl: &gl::Context) -> bool {
+    let version = gl.version();
+    !version.is_embedded
+        && ((version.major, version.minor) >= (4, 5)
+            || gl.supported_extensions().contains("GL_ARB_clip_control"))
+}
+
+/// Create the depth texture shadows are rendered to, and a framebuffer with only that attached.
+/// The texture compares depths when sampled, for use with sampler2DShadow.
+unsafe fn create_shadow_map(
+    gl: &gl::Context,
+) -> Result<(gl::NativeFramebuffer, gl::NativeTexture), RenderError> {
+    let tex = gl.create_texture()?;
+    gl.bind_texture(gl::TEXTURE_2D, Some(tex));
+    gl.tex_image_2d(
+        gl::TEXTURE_2D,
+        0,
+        gl::DEPTH_COMPONENT24 as i32,
+        SHADOW_MAP_SIZE,
+        SHADOW_MAP_SIZE,
+        0,
+        gl::DEPTH_COMPONENT,
+        gl::UNSIGNED_INT,
+        None,
+    );
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+    gl.tex_parameter_i32(
+        gl::TEXTURE_2D,
+        gl::TEXTURE_COMPARE_MODE,
+        gl::COMPARE_REF_TO_TEXTURE as _,
+    );
+    gl.tex_parameter_i32(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as _);
+    gl.bind_texture(gl::TEXTURE_2D, None);
+
+    let fbo = gl.create_framebuffer()?;
+    gl.bind_framebuffer(gl::FRAMEBUFFER, Some(fbo));
+    gl.framebuffer_texture_2d(
+        gl::FRAMEBUFFER,
+        gl::DEPTH_ATTACHMENT,
+        gl::TEXTURE_2D,
+        Some(tex),
+        0,
+    );
+    gl.draw_buffers(&[gl::NONE]);
+    gl.read_buffer(gl::NONE);
+    let status = gl.check_framebuffer_status(gl::FRAMEBUFFER);
+    gl.bind_framebuffer(gl::FRAMEBUFFER, None);
+
+    if status != gl::FRAMEBUFFER_COMPLETE {
+        return Err(RenderError::FramebufferIncomplete {
+            target: "Shadow map",
+            status,
+        });
+    }
+
+    Ok((fbo, tex))
+}
+
+/// Point the instance attributes of the bound vertex array at the bound array buffer, starting
+/// from the instance at index `first`
+unsafe fn set_instance_attribs(gl: &gl::Context, first: usize) {
+    let stride = std::mem::size_of::<Instance>();
+    set_float_attribs(gl, &INSTANCE_ATTRIBS, stride, first * stride, 1);
+}
+
+/// Index ranges where `new` differs bitwise from `old`, merged into contiguous runs. Elements
+/// past the end of `old` count as changed.
+fn changed_runs<T: Pod>(old: &[T], new: &[T]) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = vec![];
+    for (i, item) in new.iter().enumerate() {
+        let same = old
+            .get(i)
+            .is_some_and(|o| bytemuck::bytes_of(o) == bytemuck::bytes_of(item));
+        if same {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if run.end == i => run.end += 1,
+            _ => runs.push(i..i + 1),
+        }
+    }
+    runs
+}
+
+/// Index ranges of `instances` to write over a buffer holding `uploaded`. Of the opaque instances
+/// before `first`, only those which changed are written, unless so many did that a single write
+/// is cheaper. Translucent instances are rewritten every frame anyway.
+fn instance_writes(
+    uploaded: &[Instance],
+    instances: &[Instance],
+    first: usize,
+) -> Vec<Range<usize>> {
+    let runs = changed_runs(uploaded, &instances[..first]);
+    let changed: usize = runs.iter().map(|run| run.len()).sum();
+    let mut runs = if changed * 2 > first {
+        vec![0..first]
+    } else {
+        runs
+    };
+    if first < instances.len() {
+        runs.push(first..instances.len());
+    }
+    runs
+}
+
+/// Capacity to grow a buffer to when `needed` bytes don't fit. Growing geometrically means a
+/// slowly rising count doesn't reallocate every time.
+fn grown_capacity(needed: usize, capacity: usize) -> usize {
+    needed.max(capacity * 2)
+}
+
+/// Allocate at least `size` bytes for a dynamic buffer. The contents are discarded if it has to
+/// grow.
+unsafe fn reserve_buffer(
+    gl: &gl::Context,
+    buffer: gl::NativeBuffer,
+    capacity: &mut usize,
+    size: usize,
+) {
+    if size <= *capacity {
+        return;
+    }
+    gl.bind_buffer(gl::ARRAY_BUFFER, Some(buffer));
+    gl.buffer_data_size(gl::ARRAY_BUFFER, size as i32, gl::DYNAMIC_DRAW);
+    gl.bind_buffer(gl::ARRAY_BUFFER, None);
+    *capacity = size;
+}
+
+/// Write data to the start of a buffer, reallocating its storage only if the data doesn't fit.
+/// The buffer keeps its name either way, so vertex arrays referring to it stay valid.
+unsafe fn write_buffer(
+    gl: &gl::Context,
+    target: u32,
+    buffer: gl::NativeBuffer,
+    capacity: &mut usize,
+    data: &[u8],
+    usage: u32,
+) {
+    gl.bind_buffer(target, Some(buffer));
+    if data.len() <= *capacity {
+        gl.buffer_sub_data_u8_slice(target, 0, data);
+    } else {
+        gl.buffer_data_u8_slice(target, data, usage);
+        *capacity = data.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector4;
+
+    #[test]
+    fn stripping_translation_keeps_the_rotation() {
+        let eye = Point3::new(3., -2., 7.);
+        let view = Matrix4::look_at_rh(&eye, &Point3::new(4., -2., 7.), &Vector3::y());
+        let rotation = strip_translation(&view);
+        for row in 0..4 {
+            for col in 0..3 {
+                assert_eq!(rotation[(row, col)], view[(row, col)]);
+            }
+        }
+        assert_eq!(rotation.column(3), Vector4::new(0., 0., 0., 1.));
+        // Moving anywhere leaves the sky where it was
+        let moved = Matrix4::look_at_rh(&Point3::origin(), &Point3::new(1., 0., 0.), &Vector3::y());
+        assert!((strip_translation(&moved) - rotation).abs().max() < 1e-6);
+        assert_eq!(
+            rotation.transform_point(&Point3::origin()),
+            Point3::origin()
+        );
+    }
+
+    #[cfg(feature = "image")]
+    fn write_cube_faces(name: &str, sizes: [u32; 6]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("cubehead-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, (face, size)) in ["px", "nx", "py", "ny", "pz", "nz"]
+            .into_iter()
+            .zip(sizes)
+            .enumerate()
+        {
+            let data = [i as u8 * 40, 0, 0, 0xFF].repeat((size * size) as usize);
+            image::save_buffer_with_format(
+                dir.join(face).with_extension("png"),
+                &data,
+                size,
+                size,
+                image::ColorType::Rgba8,
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn cube_faces_load_in_gl_order() {
+        let dir = write_cube_faces("cube-faces", [4; 6]);
+        let faces = RgbaImage::load_cube_faces(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let faces = faces.unwrap();
+        for (i, face) in faces.iter().enumerate() {
+            assert_eq!((face.width, face.height), (4, 4));
+            assert_eq!(&face.data[..4], &[i as u8 * 40, 0, 0, 0xFF]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn cube_faces_must_match() {
+        let dir = write_cube_faces("cube-mismatch", [4, 4, 8, 4, 4, 4]);
+        let mismatched = RgbaImage::load_cube_faces(&dir).err().unwrap();
+        std::fs::remove_file(dir.join("nz.png")).unwrap();
+        std::fs::remove_file(dir.join("py.png")).unwrap();
+        let missing = RgbaImage::load_cube_faces(&dir).err().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            mismatched.to_string(),
+            format!(
+                "{}: Cubemap faces must be square and all the same size; expected 4x4, found 8x8",
+                dir.join("py.png").display()
+            )
+        );
+        assert!(
+            matches!(missing, RenderError::ImageLoad { path, .. } if path == dir.join("py.png"))
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "image"))]
+    fn cube_faces_need_the_image_feature() {
+        let error = RgbaImage::load_cube_faces(Path::new("skybox"))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            RenderError::ImageLoad { path, .. } if path == Path::new("skybox/px.png")
+        ));
+    }
+
+    #[test]
+    fn attribute_sizes_count_floats() {
+        assert_eq!(attribs_size(&[]), 0);
+        assert_eq!(attribs_size(&[(0, 3)]), 12);
+        assert_eq!(attribs_size(&VERTEX_ATTRIBS), 11 * 4);
+        assert_eq!(attribs_size(&INSTANCE_ATTRIBS), 21 * 4);
+        assert_eq!(attribs_size(&BILLBOARD_ATTRIBS), 12 * 4);
+    }
+
+    #[test]
+    fn instanced_attributes_have_their_own_locations() {
+        // Vertex and instance attributes share one vertex array
+        let mut locations: Vec<u32> = VERTEX_ATTRIBS
+            .iter()
+            .chain(&INSTANCE_ATTRIBS)
+            .map(|&(location, _)| location)
+            .collect();
+        locations.sort_unstable();
+        assert_eq!(locations, (0..10).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn render_errors_name_what_failed() {
+        let compile = RenderError::ShaderCompile {
+            shader: "heads",
+            stage: gl::FRAGMENT_SHADER,
+            log: "0:12: syntax error".into(),
+        };
+        assert_eq!(
+            compile.to_string(),
+            "Failed to compile the fragment stage of shader \"heads\"; 0:12: syntax error"
+        );
+        let uniform = RenderError::MissingUniform {
+            shader: "map",
+            uniform: "u_fog",
+        };
+        assert_eq!(
+            uniform.to_string(),
+            "Shader \"map\" has no uniform \"u_fog\" (was it optimized out?)"
+        );
+        let incomplete = RenderError::FramebufferIncomplete {
+            target: "Shadow",
+            status: gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT,
+        };
+        assert_eq!(
+            incomplete.to_string(),
+            "Shadow framebuffer incomplete (status 0x8CD6)"
+        );
+        let errors = RenderError::Gl {
+            context: "uploading the map",
+            errors: vec![gl::INVALID_VALUE, gl::OUT_OF_MEMORY, 1],
+        };
+        assert_eq!(
+            errors.to_string(),
+            "GL errors uploading the map: INVALID_VALUE, OUT_OF_MEMORY, unknown error"
+        );
+        let load = RenderError::ImageLoad {
+            path: PathBuf::from("grass.png"),
+            error: "not a PNG".into(),
+        };
+        assert_eq!(load.to_string(), "grass.png: not a PNG");
+        let save = RenderError::ImageSave {
+            path: PathBuf::from("shot.png"),
+            error: "disk full".into(),
+        };
+        assert_eq!(save.to_string(), "Failed to save shot.png; disk full");
+        let size = RenderError::ImageSize {
+            width: 2,
+            height: 3,
+            len: 20,
+        };
+        assert_eq!(
+            size.to_string(),
+            "A 2x3 image has 20 bytes of pixel data instead of 24"
+        );
+    }
+
+    #[test]
+    fn render_errors_convert_and_keep_their_source() {
+        use std::error::Error;
+
+        let error = RenderError::from(MeshError::Empty);
+        assert_eq!(error.to_string(), "Invalid mesh; mesh has no triangles");
+        assert_eq!(
+            error.source().map(|e| e.to_string()),
+            Some("mesh has no triangles".to_string())
+        );
+
+        let error = RenderError::from("out of names".to_string());
+        assert!(matches!(error, RenderError::ObjectCreate(_)));
+        assert_eq!(
+            error.to_string(),
+            "Failed to create GL object; out of names"
+        );
+        assert!(error.source().is_none());
+
+        let read = RenderError::ShaderRead {
+            path: PathBuf::from("missing.glsl"),
+            error: std::io::Error::new(std::io::ErrorKind::NotFound, "gone"),
+        };
+        assert_eq!(read.to_string(), "Failed to read missing.glsl; gone");
+        assert_eq!(read.source().unwrap().to_string(), "gone");
+    }
+
+    const SHADERS: [&ShaderDesc; 11] = [
+        &UNLIT_SHADER,
+        &TEXTURED_SHADER,
+        &SKY_SHADER,
+        &GRID_SHADER,
+        &BILLBOARD_SHADER,
+        &MINIMAP_SHADER,
+        &SHADOW_SHADER,
+        &FXAA_SHADER,
+        &VIGNETTE_SHADER,
+        &TEXT_SHADER,
+        &LINES_SHADER,
+    ];
+
+    /// Look up the uniforms of a program which has only `present`, at their index in it. Also
+    /// returns every name asked for.
+    fn lookup_uniforms(
+        desc: &ShaderDesc,
+        present: &[&str],
+    ) -> (
+        Result<ShaderUniforms<usize>, RenderError>,
+        Vec<&'static str>,
+    ) {
+        let mut calls = vec![];
+        let uniforms = ShaderUniforms::lookup(desc, |uniform| {
+            calls.push(uniform);
+            present.iter().position(|p| *p == uniform)
+        });
+        (uniforms, calls)
+    }
+
+    #[test]
+    fn uniforms_are_looked_up_once_per_program() {
+        for desc in SHADERS {
+            let (uniforms, calls) = lookup_uniforms(desc, desc.required);
+            assert!(uniforms.is_ok(), "{}", desc.name);
+
+            // One GL call per uniform when the program is built, leaving none for drawing
+            let mut once = calls.clone();
+            once.sort_unstable();
+            once.dedup();
+            assert_eq!(once.len(), calls.len(), "{}", desc.name);
+            for uniform in desc.required {
+                assert!(calls.contains(uniform), "{} {}", desc.name, uniform);
+            }
+        }
+
+        let (uniforms, _) = lookup_uniforms(&UNLIT_SHADER, &["proj", "view"]);
+        assert!(uniforms.is_err());
+        let (uniforms, _) = lookup_uniforms(&LINES_SHADER, LINES_SHADER.required);
+        let uniforms = uniforms.unwrap();
+        assert_eq!(uniforms.tex, None);
+        assert_eq!(uniforms.proj, Some(1));
+    }
+
+    #[test]
+    fn missing_uniforms_name_the_shader() {
+        let present: Vec<&str> = UNLIT_SHADER
+            .required
+            .iter()
+            .copied()
+            .filter(|uniform| *uniform != "fog_density")
+            .collect();
+        let (uniforms, _) = lookup_uniforms(&UNLIT_SHADER, &present);
+        assert!(matches!(
+            uniforms,
+            Err(RenderError::MissingUniform {
+                shader: "unlit",
+                uniform: "fog_density"
+            })
+        ));
+    }
+
+    #[test]
+    fn preludes_go_after_the_version() {
+        let source = with_prelude("#version 450\nvoid main() {}\n", "float shared;\n");
+        assert_eq!(
+            source,
+            "#version 450\nfloat shared;\n\n#line 2\nvoid main() {}\n"
+        );
+    }
+
+    #[test]
+    fn discs_are_opaque_inside_and_clear_outside() {
+        let disc = RgbaImage::disc(16);
+        assert_eq!(disc.data.len(), 16 * 16 * 4);
+        let alpha = |x: usize, y: usize| disc.data[(y * 16 + x) * 4 + 3];
+        assert_eq!(alpha(8, 8), 0xFF);
+        assert_eq!(alpha(1, 8), 0xFF);
+        assert_eq!(alpha(0, 0), 0);
+        assert_eq!(alpha(15, 15), 0);
+        // Symmetric, so that it looks the same however the billboard is turned
+        for (x, y) in [(2, 3), (5, 0), (1, 13)] {
+            assert_eq!(alpha(x, y), alpha(15 - x, y));
+            assert_eq!(alpha(x, y), alpha(y, x));
+        }
+        assert!(disc.data.chunks(4).all(|p| p[..3] == [0xFF; 3]));
+    }
+
+    #[test]
+    fn shaders_dont_repeat_their_prelude() {
+        for desc in SHADERS {
+            for &(stage, file, source) in desc.stages {
+                let shared = match prelude(stage) {
+                    Some(&(_, _, shared)) => shared,
+                    None => continue,
+                };
+                // Uniforms and functions can only be declared once
+                let declarations = shared.lines().filter(|line| {
+                    line.starts_with("uniform ")
+                        || (!line.starts_with(' ') && line.ends_with(") {"))
+                });
+                for declaration in declarations {
+                    assert!(!source.contains(declaration), "{}: {}", file, declaration);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn failed_compiles_carry_the_gl_log() {
+        // What Mesa says about a vertex shader missing a semicolon
+        let log = "0:7(1): error: syntax error, unexpected '}', expecting ',' or ';'";
+        let error = compile_status("broken", gl::VERTEX_SHADER, false, || log.into())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            &error,
+            RenderError::ShaderCompile { shader: "broken", stage: gl::VERTEX_SHADER, log: l }
+                if l == log
+        ));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Failed to compile the vertex stage of shader \"broken\"; {}",
+                log
+            )
+        );
+
+        // The log is only fetched when compiling failed
+        assert!(compile_status("fine", gl::FRAGMENT_SHADER, true, || unreachable!()).is_ok());
+    }
+
+    /// One triangle in the XY plane
+    fn triangle() -> Mesh {
+        Mesh {
+            vertices: vec![
+                Vertex::new([0., 0., 0.], [1.; 3]),
+                Vertex::new([1., 0., 0.], [1.; 3]),
+                Vertex::new([0., 1., 0.], [1.; 3]),
+            ],
+            indices: vec![0, 1, 2],
+        }
+    }
+
+    #[test]
+    fn valid_mesh_passes() {
+        assert_eq!(triangle().validate(), Ok(()));
+    }
+
+    #[test]
+    fn empty_mesh_is_rejected() {
+        let mesh = Mesh {
+            vertices: triangle().vertices,
+            indices: vec![],
+        };
+        assert_eq!(mesh.validate(), Err(MeshError::Empty));
+    }
+
+    #[test]
+    fn partial_triangle_is_rejected() {
+        let mut mesh = triangle();
+        mesh.indices.push(0);
+        assert_eq!(
+            mesh.validate(),
+            Err(MeshError::PartialTriangle { indices: 4 })
+        );
+    }
+
+    #[test]
+    fn out_of_range_index_is_rejected() {
+        let mut mesh = triangle();
+        mesh.indices[1] = 3;
+        assert_eq!(
+            mesh.validate(),
+            Err(MeshError::IndexOutOfRange {
+                at: 1,
+                index: 3,
+                vertices: 3
+            })
+        );
+    }
+
+    #[test]
+    fn non_finite_vertices_are_rejected() {
+        let mut mesh = triangle();
+        mesh.vertices[2].uv[1] = f32::NAN;
+        assert_eq!(mesh.validate(), Err(MeshError::NonFinite { vertex: 2 }));
+
+        let mut mesh = triangle();
+        mesh.vertices[1].pos.x = f32::INFINITY;
+        assert_eq!(mesh.validate(), Err(MeshError::NonFinite { vertex: 1 }));
+    }
+
+    #[test]
+    fn oversized_mesh_is_rejected() {
+        let limits = MeshLimits {
+            max_vertices: 2,
+            max_indices: 3,
+        };
+        assert_eq!(
+            triangle().validate_within(&limits),
+            Err(MeshError::TooLarge {
+                vertices: 3,
+                indices: 3
+            })
+        );
+
+        let limits = MeshLimits {
+            max_vertices: 3,
+            max_indices: 2,
+        };
+        assert!(matches!(
+            triangle().validate_within(&limits),
+            Err(MeshError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn degenerate_triangles_are_allowed_and_counted() {
+        let mut mesh = triangle();
+        // Two corners in the same place, and three in a line
+        mesh.vertices.push(Vertex::new([2., 0., 0.], [1.; 3]));
+        mesh.indices.extend([0, 0, 1, 0, 1, 3]);
+        assert_eq!(mesh.degenerate_triangles(), 2);
+        assert_eq!(mesh.validate(), Ok(()));
+        assert_eq!(triangle().degenerate_triangles(), 0);
+    }
+
+    #[test]
+    fn changed_runs_merge_neighbours() {
+        let old = [1u32, 2, 3, 4, 5, 6];
+        assert!(changed_runs(&old, &old).is_empty());
+        assert_eq!(changed_runs(&old, &[1, 9, 9, 4, 9, 6]), [1..3usize, 4..5]);
+        // Growing changes the new elements, shrinking changes nothing
+        assert_eq!(changed_runs(&old, &[1, 2, 3, 4, 5, 9, 7, 8]), [5..8usize]);
+        assert!(changed_runs(&old, &old[..2]).is_empty());
+        assert_eq!(changed_runs(&[], &[1u32, 2]), [0..2usize]);
+    }
+
+    #[test]
+    fn moving_one_head_uploads_one_instance() {
+        let heads: Vec<Instance> = (0..200)
+            .map(|i| Instance::new(Matrix4::new_translation(&Vector3::new(i as f32, 0., 0.))))
+            .collect();
+        let bytes = |uploaded: &[Instance], instances: &[Instance], first| {
+            let writes = instance_writes(uploaded, instances, first);
+            writes.iter().map(|run| run.len()).sum::<usize>() * std::mem::size_of::<Instance>()
+        };
+
+        // Everything goes up the first time, and nothing while the heads stand still
+        assert_eq!(
+            bytes(&[], &heads, 200),
+            200 * std::mem::size_of::<Instance>()
+        );
+        assert_eq!(bytes(&heads, &heads, 200), 0);
+
+        let mut moved = heads.clone();
+        moved[57].transform[3][1] = 1.;
+        assert_eq!(instance_writes(&heads, &moved, 200), [57..58]);
+        assert_eq!(bytes(&heads, &moved, 200), std::mem::size_of::<Instance>());
+
+        // Once most heads move, one write of all of them is cheaper than many small ones
+        for head in moved
+            .iter_mut()
+            .filter(|head| head.transform[3][0] as usize % 3 != 2)
+        {
+            head.transform[3][1] = 2.;
+        }
+        assert_eq!(instance_writes(&heads, &moved, 200), [0..200]);
+
+        // A fading head is rewritten every frame, along with the one head that moved
+        let mut fading = heads.clone();
+        fading[57].transform[3][1] = 1.;
+        assert_eq!(instance_writes(&heads, &fading, 199), [57..58, 199..200]);
+    }
+
+    #[test]
+    fn changed_runs_compare_bits() {
+        assert_eq!(
+            changed_runs(&[0f32, f32::NAN], &[-0., f32::NAN]),
+            [0..1usize]
+        );
+    }
+
+    #[test]
+    fn buffers_grow_geometrically() {
+        assert_eq!(grown_capacity(10, 0), 10);
+        assert_eq!(grown_capacity(101, 100), 200);
+        assert_eq!(grown_capacity(500, 100), 500);
+        // Small steps reallocate only a handful of times
+        let mut capacity = 64;
+        let mut grew = 0;
+        for needed in 64..=64 * 1024 {
+            if needed > capacity {
+                capacity = grown_capacity(needed, capacity);
+                grew += 1;
+            }
+        }
+        assert_eq!(grew, 10);
+    }
+
+    #[test]
+    fn frame_stats_add_up_draws_uploads_and_culling() {
+        let mut stats = FrameStats::default();
+        stats.draw(1, 12);
+        stats.draw(40, 100);
+        stats.upload(64);
+        stats.upload(4096);
+        stats.cull(50, 40);
+        stats.cull(3, 3);
+        assert_eq!(
+            stats,
+            FrameStats {
+                draw_calls: 2,
+                instances: 41,
+                culled: 10,
+                triangles: 12 + 4000,
+                upload_bytes: 4160,
+            }
+        );
+    }
+
+    #[test]
+    fn frame_stats_count_past_32_bits() {
+        let mut stats = FrameStats::default();
+        for _ in 0..4 {
+            stats.draw(1 << 16, 1 << 16);
+        }
+        assert_eq!(stats.triangles, 1 << 34);
+        assert_eq!(stats.draw_calls, 4);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "kept 5 of 4 instances")]
+    fn keeping_more_than_submitted_is_a_bug() {
+        FrameStats::default().cull(4, 5);
+    }
+
+    /// Two triangles folded along the X axis, one facing +Z and the other +Y
+    fn fold() -> Mesh {
+        let mut mesh = triangle();
+        mesh.vertices.push(Vertex::new([0., 0., 1.], [1.; 3]));
+        mesh.indices.extend([0, 3, 1]);
+        mesh
+    }
+
+    fn normals(mesh: &Mesh) -> Vec<Vector3<f32>> {
+        mesh.vertices.iter().map(|v| v.normal).collect()
+    }
+
+    #[test]
+    fn flat_normals_split_vertices_per_triangle() {
+        let mut mesh = fold();
+        mesh.compute_normals(NormalMode::Flat, false);
+        assert_eq!(mesh.indices, [0, 1, 2, 3, 4, 5]);
+        let (z, y) = (Vector3::z(), Vector3::y());
+        assert_eq!(normals(&mesh), [z, z, z, y, y, y]);
+        let positions: Vec<_> = mesh.vertices.iter().map(|v| v.pos.coords).collect();
+        assert_eq!(positions[3], Vector3::zeros());
+        assert_eq!(positions[4], Vector3::z());
+        assert_eq!(mesh.validate(), Ok(()));
+    }
+
+    #[test]
+    fn smooth_normals_average_shared_vertices() {
+        let mut mesh = fold();
+        mesh.compute_normals(NormalMode::Smooth, false);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 3, 1]);
+        let between = Vector3::new(0., 1., 1.).normalize();
+        let normals = normals(&mesh);
+        assert!((normals[0] - between).norm() < 1e-6);
+        assert!((normals[1] - between).norm() < 1e-6);
+        assert_eq!(normals[2], Vector3::z());
+        assert_eq!(normals[3], Vector3::y());
+    }
+
+    #[test]
+    fn existing_unit_normals_can_be_kept() {
+        for mode in [NormalMode::Flat, NormalMode::Smooth] {
+            let mut mesh = fold();
+            mesh.vertices[2].normal = Vector3::x();
+            // Not unit length, so replaced either way
+            mesh.vertices[3].normal = Vector3::new(0., 2., 0.);
+            let mut kept = Mesh {
+                indices: mesh.indices.clone(),
+                vertices: mesh.vertices.clone(),
+            };
+            kept.compute_normals(mode, true);
+            mesh.compute_normals(mode, false);
+
+            // The corner off the fold on the +Y side, moved by splitting in flat mode
+            let d = match mode {
+                NormalMode::Flat => 4,
+                NormalMode::Smooth => 3,
+            };
+            assert_eq!(kept.vertices[2].normal, Vector3::x(), "{:?}", mode);
+            assert_eq!(mesh.vertices[2].normal, Vector3::z(), "{:?}", mode);
+            assert_eq!(kept.vertices[d].normal, Vector3::y(), "{:?}", mode);
+            assert_eq!(mesh.vertices[d].normal, Vector3::y(), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_leave_no_normal() {
+        // Flattened onto the X axis, sharing an edge with the triangle and a corner of its own
+        let mut mesh = triangle();
+        mesh.vertices.push(Vertex::new([2., 0., 0.], [1.; 3]));
+        mesh.indices.extend([0, 1, 3]);
+
+        let mut flat = Mesh {
+            indices: mesh.indices.clone(),
+            vertices: mesh.vertices.clone(),
+        };
+        flat.compute_normals(NormalMode::Flat, false);
+        let (z, zero) = (Vector3::z(), Vector3::zeros());
+        assert_eq!(normals(&flat), [z, z, z, zero, zero, zero]);
+
+        mesh.compute_normals(NormalMode::Smooth, false);
+        assert_eq!(normals(&mesh), [z, z, z, zero]);
+    }
+
+    #[test]
+    fn smooth_normals_of_an_octahedron_point_away_from_its_middle() {
+        // A corner at either end of each axis, shared by the four faces around it
+        let mut vertices = vec![];
+        for axis in 0..3 {
+            for sign in [1., -1.] {
+                let mut pos = [0.; 3];
+                pos[axis] = sign;
+                vertices.push(Vertex::new(pos, [1.; 3]));
+            }
+        }
+        let mut indices = vec![];
+        for signs in 0..8u32 {
+            let [x, y, z] = [0, 1, 2].map(|axis| 2 * axis + ((signs >> axis) & 1));
+            // Mirroring an odd number of axes reverses the winding
+            match signs.count_ones() % 2 {
+                0 => indices.extend([x, y, z]),
+                _ => indices.extend([x, z, y]),
+            }
+        }
+
+        let mut mesh = Mesh { indices, vertices };
+        mesh.compute_normals(NormalMode::Smooth, false);
+        assert_eq!(mesh.indices.len(), 24);
+        for vertex in &mesh.vertices {
+            assert!(
+                (vertex.normal - vertex.pos.coords).norm() < 1e-6,
+                "{:?}",
+                vertex
+            );
+        }
+    }
+
+    #[test]
+    fn appended_indices_are_offset() {
+        let mut mesh = triangle();
+        let mut other = triangle();
+        other.indices = vec![2, 1, 0];
+        mesh.append(&other).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices, [0, 1, 2, 5, 4, 3]);
+        assert_eq!(mesh.validate(), Ok(()));
+
+        assert_eq!(check_indexable(1 << 32), Ok(()));
+        assert_eq!(
+            check_indexable((1 << 32) + 1),
+            Err(MeshError::IndexOverflow {
+                vertices: (1 << 32) + 1
+            })
+        );
+    }
+
+    #[test]
+    fn transforms_keep_normals_perpendicular() {
+        // A slope facing up and back along +Z, squashed along Z
+        let mut mesh = Mesh {
+            vertices: vec![
+                Vertex::new([0., 0., 0.], [1.; 3]),
+                Vertex::new([1., 0., 0.], [1.; 3]),
+                Vertex::new([0., 1., -1.], [1.; 3]),
+            ],
+            indices: vec![0, 1, 2],
+        };
+        mesh.compute_normals(NormalMode::Smooth, false);
+        let matrix = Matrix4::new_translation(&Vector3::new(0., 2., 0.))
+            * Matrix4::new_nonuniform_scaling(&Vector3::new(1., 1., 0.5));
+        mesh.transform(&matrix);
+
+        let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[i].pos);
+        assert_eq!(a, Point3::new(0., 2., 0.));
+        assert_eq!(c, Point3::new(0., 3., -0.5));
+        let face = (b - a).cross(&(c - a)).normalize();
+        for vertex in &mesh.vertices {
+            assert!((vertex.normal - face).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mirroring_keeps_front_faces_in_front() {
+        let mut mesh = triangle();
+        mesh.compute_normals(NormalMode::Smooth, false);
+        mesh.transform(&Matrix4::new_nonuniform_scaling(&Vector3::new(-1., 1., 1.)));
+        assert_eq!(mesh.indices, [0, 2, 1]);
+        let [a, b, c] = [0, 2, 1].map(|i| mesh.vertices[i].pos);
+        let face = (b - a).cross(&(c - a));
+        assert!(face.dot(&mesh.vertices[0].normal) > 0.);
+        assert_eq!(mesh.vertices[0].normal, Vector3::z());
+    }
+
+    #[test]
+    fn identity_transforms_change_nothing() {
+        let mut mesh = triangle();
+        // Not unit length, which any other transform would fix
+        mesh.vertices[0].normal = Vector3::new(0., 0., 2.);
+        mesh.transform(&Matrix4::identity());
+        assert_eq!(mesh.vertices[0].normal, Vector3::new(0., 0., 2.));
+        assert_eq!(mesh.indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn builders_chain_mesh_edits() {
+        let mesh = MeshBuilder::new(triangle())
+            .transform(&Matrix4::new_translation(&Vector3::x()))
+            .append(&triangle())
+            .unwrap()
+            .recolor([0.5, 0., 0.])
+            .map_colors(|color| color * 2.)
+            .build();
+        assert_eq!(mesh.indices, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(mesh.vertices[0].pos, Point3::new(1., 0., 0.));
+        assert_eq!(mesh.vertices[3].pos, Point3::origin());
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.color, Vector3::x());
+        }
     }
 }
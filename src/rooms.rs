@@ -0,0 +1,325 @@
+//! Declarative layouts of rooms with doorways, read from RON or TOML and built into maps by
+//! `shapes::rooms_map`
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Rooms making up a map. Rooms which share a wall each get their own, so a doorway between
+/// them needs an opening in both.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoomsSpec {
+    pub rooms: Vec<RoomSpec>,
+}
+
+/// Axis aligned room with a floor, four walls and a ceiling
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoomSpec {
+    /// Named in errors
+    pub name: String,
+    /// Corner at -X and -Z, as (x, z) in meters
+    pub min: [f32; 2],
+    /// Corner at +X and +Z
+    pub max: [f32; 2],
+    /// From the floor at zero to the ceiling, in meters
+    pub height: f32,
+    #[serde(default)]
+    pub doors: Vec<DoorSpec>,
+}
+
+/// Opening in one wall of a room, from the floor up
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DoorSpec {
+    pub side: Side,
+    pub width: f32,
+    #[serde(default = "default_door_height")]
+    pub height: f32,
+    /// Distance from the middle of the wall to the middle of the door, towards +X or +Z
+    #[serde(default)]
+    pub offset: f32,
+}
+
+/// Wall of a room, by compass direction with north at -Z, the way players face at spawn
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    North,
+    South,
+    East,
+    West,
+}
+
+fn default_door_height() -> f32 {
+    2.1
+}
+
+impl RoomSpec {
+    /// Extent of the wall on `side` along the axis it runs, X for north and south and Z for
+    /// east and west
+    pub fn wall_span(&self, side: Side) -> (f32, f32) {
+        match side {
+            Side::North | Side::South => (self.min[0], self.max[0]),
+            Side::East | Side::West => (self.min[1], self.max[1]),
+        }
+    }
+
+    /// Where the door opens along its wall, as in `wall_span`
+    pub fn door_span(&self, door: &DoorSpec) -> (f32, f32) {
+        let (lo, hi) = self.wall_span(door.side);
+        let center = (lo + hi) / 2. + door.offset;
+        (center - door.width / 2., center + door.width / 2.)
+    }
+}
+
+impl RoomsSpec {
+    /// Read a spec from a `.ron` file, or TOML otherwise, and check it
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let spec: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ron") => ron::from_str(&text)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            _ => toml::from_str(&text)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+        };
+        spec.validate()
+            .with_context(|| format!("Invalid rooms in {}", path.display()))?;
+        Ok(spec)
+    }
+
+    /// Two rooms joined by a corridor, which players spawn in
+    pub fn two_rooms_and_corridor() -> Self {
+        let door = |side, width, height| DoorSpec {
+            side,
+            width,
+            height,
+            offset: 0.,
+        };
+        Self {
+            rooms: vec![
+                RoomSpec {
+                    name: "west".into(),
+                    min: [-9., -4.],
+                    max: [-3., 4.],
+                    height: 3.,
+                    doors: vec![door(Side::East, 1.5, 2.2)],
+                },
+                // Open at both ends, onto the doorways of the rooms
+                RoomSpec {
+                    name: "corridor".into(),
+                    min: [-3., -1.],
+                    max: [3., 1.],
+                    height: 2.6,
+                    doors: vec![door(Side::West, 2., 2.6), door(Side::East, 2., 2.6)],
+                },
+                RoomSpec {
+                    name: "east".into(),
+                    min: [3., -4.],
+                    max: [9., 4.],
+                    height: 3.,
+                    doors: vec![door(Side::West, 1.5, 2.2), door(Side::North, 1.2, 2.1)],
+                },
+            ],
+        }
+    }
+
+    /// Check that every room has a size and its doors fit in their walls without overlapping
+    pub fn validate(&self) -> Result<()> {
+        if self.rooms.is_empty() {
+            bail!("No rooms");
+        }
+
+        for (i, room) in self.rooms.iter().enumerate() {
+            if self.rooms[..i].iter().any(|other| other.name == room.name) {
+                bail!("Room \"{}\" is named twice", room.name);
+            }
+            for (axis, (lo, hi)) in ["x", "z"].iter().zip(room.min.iter().zip(&room.max)) {
+                if !(lo.is_finite() && hi.is_finite() && lo < hi) {
+                    bail!(
+                        "Room \"{}\": max.{} of {} must be greater than min.{} of {}",
+                        room.name,
+                        axis,
+                        hi,
+                        axis,
+                        lo
+                    );
+                }
+            }
+            if !(room.height > 0. && room.height.is_finite()) {
+                bail!(
+                    "Room \"{}\": height of {} must be positive",
+                    room.name,
+                    room.height
+                );
+            }
+
+            for (j, door) in room.doors.iter().enumerate() {
+                let which = format!("Room \"{}\", door {} ({:?})", room.name, j + 1, door.side);
+                if !(door.width > 0. && door.width.is_finite()) {
+                    bail!("{}: width of {} must be positive", which, door.width);
+                }
+                if !(door.height > 0. && door.height <= room.height) {
+                    bail!(
+                        "{}: height of {} must be positive and at most the room's {}",
+                        which,
+                        door.height,
+                        room.height
+                    );
+                }
+                let (lo, hi) = room.wall_span(door.side);
+                let (start, end) = room.door_span(door);
+                if !(start >= lo && end <= hi) {
+                    bail!(
+                        "{}: offset of {} puts the door past the end of its {} m wall",
+                        which,
+                        door.offset,
+                        hi - lo
+                    );
+                }
+                let overlaps = room.doors[..j].iter().position(|other| {
+                    let (other_start, other_end) = room.door_span(other);
+                    other.side == door.side && start < other_end && other_start < end
+                });
+                if let Some(k) = overlaps {
+                    bail!("{}: overlaps door {}", which, k + 1);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room() -> RoomSpec {
+        RoomSpec {
+            name: "hall".into(),
+            min: [-2., -2.],
+            max: [2., 2.],
+            height: 3.,
+            doors: vec![DoorSpec {
+                side: Side::North,
+                width: 1.,
+                height: 2.,
+                offset: 0.,
+            }],
+        }
+    }
+
+    /// Error from validating a spec of one room, changed by `change`
+    fn error(change: impl FnOnce(&mut RoomSpec)) -> String {
+        let mut room = room();
+        change(&mut room);
+        let spec = RoomsSpec { rooms: vec![room] };
+        spec.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn built_in_rooms_are_valid() {
+        RoomsSpec::two_rooms_and_corridor().validate().unwrap();
+        RoomsSpec {
+            rooms: vec![room()],
+        }
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn doors_are_placed_along_their_walls() {
+        let mut room = room();
+        assert_eq!(room.wall_span(Side::North), (-2., 2.));
+        room.max = [4., 6.];
+        assert_eq!(room.wall_span(Side::South), (-2., 4.));
+        assert_eq!(room.wall_span(Side::East), (-2., 6.));
+        let door = DoorSpec {
+            side: Side::West,
+            width: 2.,
+            height: 2.,
+            offset: 1.5,
+        };
+        assert_eq!(room.door_span(&door), (2.5, 4.5));
+    }
+
+    #[test]
+    fn doors_default_to_the_middle() {
+        let spec: RoomsSpec = toml::from_str(
+            "[[rooms]]\n\
+             name = \"hall\"\n\
+             min = [-2, -2]\n\
+             max = [2, 2]\n\
+             height = 3\n\
+             [[rooms.doors]]\n\
+             side = \"north\"\n\
+             width = 1\n",
+        )
+        .unwrap();
+        let door = &spec.rooms[0].doors[0];
+        assert_eq!(
+            (door.side, door.height, door.offset),
+            (Side::North, 2.1, 0.)
+        );
+        spec.validate().unwrap();
+    }
+
+    #[test]
+    fn bad_rooms_are_rejected() {
+        assert_eq!(
+            RoomsSpec { rooms: vec![] }
+                .validate()
+                .unwrap_err()
+                .to_string(),
+            "No rooms"
+        );
+        let twice = RoomsSpec {
+            rooms: vec![room(), room()],
+        };
+        assert_eq!(
+            twice.validate().unwrap_err().to_string(),
+            "Room \"hall\" is named twice"
+        );
+        assert_eq!(
+            error(|room| room.max[1] = -2.),
+            "Room \"hall\": max.z of -2 must be greater than min.z of -2"
+        );
+        assert_eq!(
+            error(|room| room.min[0] = f32::NAN),
+            "Room \"hall\": max.x of 2 must be greater than min.x of NaN"
+        );
+        assert_eq!(
+            error(|room| room.height = 0.),
+            "Room \"hall\": height of 0 must be positive"
+        );
+    }
+
+    #[test]
+    fn bad_doors_are_rejected() {
+        assert_eq!(
+            error(|room| room.doors[0].width = -1.),
+            "Room \"hall\", door 1 (North): width of -1 must be positive"
+        );
+        assert_eq!(
+            error(|room| room.doors[0].height = 4.),
+            "Room \"hall\", door 1 (North): height of 4 must be positive and at most the room's 3"
+        );
+        assert_eq!(
+            error(|room| room.doors[0].offset = 1.75),
+            "Room \"hall\", door 1 (North): offset of 1.75 puts the door past the end of its 4 m wall"
+        );
+        // The same door on another wall is fine, but not on the same one
+        let mut room = room();
+        let mut other = room.doors[0].clone();
+        other.side = Side::South;
+        room.doors.push(other);
+        RoomsSpec { rooms: vec![room] }.validate().unwrap();
+        assert_eq!(
+            error(|room| {
+                let mut door = room.doors[0].clone();
+                door.offset = 0.75;
+                room.doors.push(door);
+            }),
+            "Room \"hall\", door 2 (North): overlaps door 1"
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Loading of map and avatar meshes from model files
+use crate::obj;
+use crate::render::{Mesh, NormalMode};
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// What a mesh in a scene file is used for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Map,
+    Head,
+}
+
+/// Load the map mesh from an OBJ or glTF file
+pub fn load_map(path: &Path) -> Result<Mesh> {
+    load_role(path, Role::Map)
+}
+
+/// Load the avatar head mesh from an OBJ or glTF file
+pub fn load_avatar(path: &Path) -> Result<Mesh> {
+    load_role(path, Role::Head)
+}
+
+fn load_role(path: &Path, role: Role) -> Result<Mesh> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    match ext.as_str() {
+        "obj" => obj::load_obj(path),
+        "gltf" | "glb" => load_gltf(path, role),
+        _ => bail!("Unrecognized model format {}", path.display()),
+    }
+}
+
+#[cfg(not(feature = "gltf"))]
+fn load_gltf(path: &Path, _role: Role) -> Result<Mesh> {
+    bail!(
+        "Cannot load {}; cubehead was built without the `gltf` feature",
+        path.display()
+    )
+}
+
+/// Flatten a glTF scene into a single mesh for the given role.
+///
+/// Nodes (or their meshes) named `map*` belong to the map and nodes named `head*` belong to the
+/// avatar. If the file has no nodes for the role, every unnamed node is used instead.
+#[cfg(feature = "gltf")]
+fn load_gltf(path: &Path, role: Role) -> Result<Mesh> {
+    use anyhow::Context;
+    use nalgebra::Matrix4;
+
+    let (document, buffers, _images) =
+        gltf::import(path).with_context(|| format!("Failed to load {}", path.display()))?;
+
+    if document.skins().next().is_some() {
+        eprintln!("{}: Skipping skins", path.display());
+    }
+    if document.animations().next().is_some() {
+        eprintln!("{}: Skipping animations", path.display());
+    }
+
+    let scene = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(s) => s,
+        None => bail!("{} contains no scenes", path.display()),
+    };
+
+    let mut matching = empty_mesh();
+    let mut unassigned = empty_mesh();
+
+    // Walk the node hierarchy, accumulating transforms
+    let mut stack: Vec<(gltf::Node, Matrix4<f32>, Option<Role>)> = scene
+        .nodes()
+        .map(|node| (node, Matrix4::identity(), None))
+        .collect();
+
+    while let Some((node, parent_tf, parent_role)) = stack.pop() {
+        let transform = parent_tf * Matrix4::from(node.transform().matrix());
+
+        if node.skin().is_some() {
+            eprintln!(
+                "{}: Ignoring skin on node {:?}",
+                path.display(),
+                node.name()
+            );
+        }
+
+        let node_role = parent_role
+            .or_else(|| node.name().and_then(role_from_name))
+            .or_else(|| node.mesh().and_then(|m| m.name()).and_then(role_from_name));
+
+        // Meshes belonging to the other role are skipped
+        if let Some(mesh) = node.mesh() {
+            match node_role {
+                Some(r) if r == role => {
+                    append_gltf_mesh(&mut matching, &mesh, &buffers, &transform, path)
+                }
+                Some(_) => (),
+                None => append_gltf_mesh(&mut unassigned, &mesh, &buffers, &transform, path),
+            }
+        }
+
+        for child in node.children() {
+            stack.push((child, transform, node_role));
+        }
+    }
+
+    let mut mesh = if matching.indices.is_empty() {
+        unassigned
+    } else {
+        matching
+    };
+
+    if mesh.indices.is_empty() {
+        bail!("{} has no triangles for the {:?}", path.display(), role);
+    }
+
+    mesh.compute_normals(NormalMode::Smooth, true);
+    Ok(mesh)
+}
+
+#[cfg(feature = "gltf")]
+fn role_from_name(name: &str) -> Option<Role> {
+    let name = name.to_lowercase();
+    if name.starts_with("map") {
+        Some(Role::Map)
+    } else if name.starts_with("head") {
+        Some(Role::Head)
+    } else {
+        None
+    }
+}
+
+/// Append every triangle primitive of a glTF mesh, transformed into world space
+#[cfg(feature = "gltf")]
+fn append_gltf_mesh(
+    out: &mut Mesh,
+    mesh: &gltf::Mesh,
+    buffers: &[gltf::buffer::Data],
+    transform: &nalgebra::Matrix4<f32>,
+    path: &Path,
+) {
+    use crate::render::Vertex;
+    use nalgebra::{Point3, Vector3};
+
+    // Mirroring transforms flip the winding order
+    let linear = transform.fixed_slice::<3, 3>(0, 0).into_owned();
+    let flip = linear.determinant() < 0.;
+
+    // Normals transform by the inverse transpose, to stay perpendicular under non-uniform scale
+    let normal_matrix = linear.try_inverse().unwrap_or(linear).transpose();
+
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            eprintln!(
+                "{}: Skipping non-triangle primitive in mesh {:?}",
+                path.display(),
+                mesh.name()
+            );
+            continue;
+        }
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let positions: Vec<[f32; 3]> = match reader.read_positions() {
+            Some(p) => p.collect(),
+            None => continue,
+        };
+
+        let colors: Vec<[f32; 3]> = match reader.read_colors(0) {
+            Some(c) => c.into_rgb_f32().collect(),
+            None => {
+                let [r, g, b, _] = primitive
+                    .material()
+                    .pbr_metallic_roughness()
+                    .base_color_factor();
+                vec![[r, g, b]; positions.len()]
+            }
+        };
+
+        let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+            Some(t) => t.into_f32().collect(),
+            None => vec![[0.; 2]; positions.len()],
+        };
+
+        // Missing normals are filled in once the whole mesh is loaded
+        let normals: Vec<[f32; 3]> = match reader.read_normals() {
+            Some(n) => n.collect(),
+            None => vec![[0.; 3]; positions.len()],
+        };
+
+        let base = out.vertices.len() as u32;
+        for (((pos, color), uv), normal) in positions.iter().zip(&colors).zip(&uvs).zip(&normals) {
+            let pos = transform.transform_point(&Point3::from(*pos));
+            let normal = (normal_matrix * Vector3::from(*normal))
+                .try_normalize(0.)
+                .unwrap_or_else(Vector3::zeros);
+            out.vertices.push(
+                Vertex::new([pos.x, pos.y, pos.z], *color)
+                    .with_normal_uv([normal.x, normal.y, normal.z], *uv),
+            );
+        }
+
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(i) => i.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        for tri in indices.chunks_exact(3) {
+            let (a, b, c) = if flip {
+                (tri[0], tri[2], tri[1])
+            } else {
+                (tri[0], tri[1], tri[2])
+            };
+            out.indices.extend([a + base, b + base, c + base]);
+        }
+    }
+}
+
+#[cfg(feature = "gltf")]
+fn empty_mesh() -> Mesh {
+    Mesh {
+        indices: vec![],
+        vertices: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Write `contents` to `name` in a folder of its own, returning the path
+    fn write_temp(name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("cubehead-scene-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn remove_temp(path: &Path) {
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn formats_are_picked_by_extension() {
+        let path = write_temp("triangle.OBJ", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n");
+        let (map, avatar) = (load_map(&path), load_avatar(&path));
+        remove_temp(&path);
+        assert_eq!(map.unwrap().indices, [0, 1, 2]);
+        assert_eq!(avatar.unwrap().indices, [0, 1, 2]);
+
+        let error = load_map(Path::new("map.fbx")).unwrap_err();
+        assert_eq!(error.to_string(), "Unrecognized model format map.fbx");
+    }
+
+    #[cfg(not(feature = "gltf"))]
+    #[test]
+    fn gltf_needs_its_feature() {
+        let error = load_map(Path::new("map.glb")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot load map.glb; cubehead was built without the `gltf` feature"
+        );
+    }
+
+    /// A triangle at (0, 0, 0), (1, 0, 0) and (0, 1, 0) facing +Z, as positions then u16 indices
+    #[cfg(feature = "gltf")]
+    const TRIANGLE_BUFFER: &str =
+        "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA=";
+
+    /// glTF scene with the triangle as its only mesh, used by each of `nodes`
+    #[cfg(feature = "gltf")]
+    fn gltf_scene(nodes: &str, roots: &str) -> String {
+        format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": 44, "uri": "{}" }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+                ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [0, 0, 0], "max": [1, 1, 0] }},
+                    {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+                ],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}],
+                "nodes": [{}],
+                "scenes": [{{ "nodes": [{}] }}],
+                "scene": 0
+            }}"#,
+            TRIANGLE_BUFFER, nodes, roots
+        )
+    }
+
+    #[cfg(feature = "gltf")]
+    fn positions(mesh: &Mesh) -> Vec<[f32; 3]> {
+        let points = mesh.indices.iter().map(|&i| mesh.vertices[i as usize].pos);
+        points.map(|p| [p.x, p.y, p.z]).collect()
+    }
+
+    /// Binary glTF with a `Map floor` node, a mirrored `head` node, and an unnamed node with a
+    /// `detail` child. The head's triangle has normals, red, green and blue vertex colors,
+    /// texture coordinates and u16 indices; the map's has only positions and u32 indices.
+    #[cfg(feature = "gltf")]
+    const ROLES_GLB: &[u8] = include_bytes!("fixtures/roles.glb");
+
+    #[cfg(feature = "gltf")]
+    #[test]
+    fn gltf_nodes_are_split_by_name() {
+        let path = write_temp("roles.glb", ROLES_GLB);
+        let (map, avatar) = (load_map(&path).unwrap(), load_avatar(&path).unwrap());
+        remove_temp(&path);
+
+        assert_eq!(positions(&map), [[0., 0., 5.], [1., 0., 5.], [0., 1., 5.]]);
+        for vertex in &map.vertices {
+            // The default material is white
+            assert_eq!(vertex.color, nalgebra::Vector3::repeat(1.));
+            // The triangle faces +Z, so the normals filled in for it do too
+            assert_eq!(vertex.normal, nalgebra::Vector3::z());
+        }
+
+        // Mirrored, with the winding flipped to keep facing the same way
+        assert_eq!(
+            positions(&avatar),
+            [[0., 0., 0.], [0., 1., 0.], [-1., 0., 0.]]
+        );
+        let colors: Vec<[f32; 3]> = avatar.vertices.iter().map(|v| v.color.into()).collect();
+        assert_eq!(colors, [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]]);
+        let uvs: Vec<[f32; 2]> = avatar.vertices.iter().map(|v| v.uv).collect();
+        assert_eq!(uvs, [[0., 0.], [1., 0.], [0., 1.]]);
+        for vertex in &avatar.vertices {
+            // The file's normals are kept, and mirrored along with the positions
+            let expected = nalgebra::Vector3::new(-0.6, 0., 0.8);
+            assert!((vertex.normal - expected).norm() < 1e-6);
+        }
+    }
+
+    #[cfg(feature = "gltf")]
+    #[test]
+    fn unnamed_gltf_nodes_fill_in_for_both() {
+        let path = write_temp(
+            "unnamed.gltf",
+            &gltf_scene(
+                r#"
+                { "mesh": 0, "translation": [10, 0, 0], "children": [1] },
+                { "name": "detail", "mesh": 0, "translation": [0, 1, 0] }
+                "#,
+                "0",
+            ),
+        );
+        let (map, avatar) = (load_map(&path).unwrap(), load_avatar(&path).unwrap());
+        remove_temp(&path);
+
+        // Children are moved along with their parents
+        let expected = [
+            [10., 0., 0.],
+            [11., 0., 0.],
+            [10., 1., 0.],
+            [10., 1., 0.],
+            [11., 1., 0.],
+            [10., 2., 0.],
+        ];
+        assert_eq!(positions(&map), expected);
+        assert_eq!(positions(&avatar), expected);
+    }
+
+    #[cfg(feature = "gltf")]
+    #[test]
+    fn gltf_without_the_role_is_an_error() {
+        let path = write_temp(
+            "map_only.gltf",
+            &gltf_scene(r#"{ "name": "map", "mesh": 0 }"#, "0"),
+        );
+        let (map, avatar) = (load_map(&path), load_avatar(&path));
+        remove_temp(&path);
+
+        assert_eq!(map.unwrap().indices, [0, 1, 2]);
+        let error = avatar.unwrap_err().to_string();
+        assert!(
+            error.ends_with("has no triangles for the Head"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Moving a client to another server while it keeps running, from the settings panel or commands
+//! typed on stdin with `--console`
+use anyhow::{Context, Result};
+use cubehead::Connection;
+use std::io::BufRead;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+/// Which server a client's connections go to; one, or two with `--split`. Switching dials the
+/// new server on a thread, so that rendering carries on with an empty scene meanwhile, and only
+/// replaces the connections once every one of them is through.
+pub struct ServerSwitch {
+    /// Address of the server connected to, or None offline
+    current: Option<String>,
+    /// Address being dialed, and where the new connections come back from the thread dialing it
+    pending: Option<(String, Receiver<Result<Vec<Connection>>>)>,
+    /// The connections from before switching, put back if it fails
+    parked: Vec<Connection>,
+    /// Lines typed on stdin
+    console: Option<Receiver<String>>,
+    /// Whether the status changed since the last update
+    changed: bool,
+}
+
+impl ServerSwitch {
+    /// Start from the server at `current`, reading commands from stdin if `console` is set
+    pub fn new(current: Option<String>, console: bool) -> Result<Self> {
+        let console = match console {
+            true => Some(spawn_console()?),
+            false => None,
+        };
+        Ok(Self {
+            current,
+            pending: None,
+            parked: vec![],
+            console,
+            changed: false,
+        })
+    }
+
+    /// Start switching `connections` to the server at `addr`. They are offline until the new
+    /// server accepts all of them, or they are put back as they were if it doesn't.
+    pub fn connect(&mut self, connections: &mut [&mut Connection], addr: &str) -> Result<()> {
+        if let Some((pending, _)) = &self.pending {
+            anyhow::bail!("Still connecting to {}", pending);
+        }
+        let addr = addr.trim().to_string();
+        cubehead::split_server_addr(&addr)?;
+
+        let (sender, receiver) = channel();
+        let count = connections.len();
+        let thread_addr = addr.clone();
+        std::thread::Builder::new()
+            .name("connect".into())
+            .spawn(move || {
+                let dialed = cubehead::resolve_server_addr(&thread_addr).and_then(|addrs| {
+                    (0..count)
+                        .map(|_| Connection::online(&addrs))
+                        .collect::<Result<Vec<_>>>()
+                });
+                // Nobody is listening if the client has quit meanwhile
+                let _ = sender.send(dialed);
+            })
+            .context("Failed to start the connecting thread")?;
+
+        self.parked = connections
+            .iter_mut()
+            .map(|connection| std::mem::replace(&mut **connection, Connection::offline()))
+            .collect();
+        println!("Connecting to {}", addr);
+        self.pending = Some((addr, receiver));
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Drop the connections to the server, staying offline until told to connect
+    pub fn disconnect(&mut self, connections: &mut [&mut Connection]) {
+        match &self.pending {
+            Some((pending, _)) => eprintln!("Still connecting to {}", pending),
+            None => {
+                for connection in connections {
+                    **connection = Connection::offline();
+                }
+                self.current = None;
+                self.changed = true;
+            }
+        }
+    }
+
+    /// Go offline after one of the connections failed
+    pub fn connection_lost(&mut self, connections: &mut [&mut Connection], error: anyhow::Error) {
+        eprintln!("Lost the connection to the server; {:#}", error);
+        self.disconnect(connections);
+    }
+
+    /// Carry out commands typed on stdin and put the connections in place once switching is
+    /// done. Returns whether the status changed since the last update.
+    pub fn update(&mut self, connections: &mut [&mut Connection]) -> bool {
+        let lines: Vec<String> = match &self.console {
+            Some(console) => console.try_iter().collect(),
+            None => vec![],
+        };
+        for line in lines {
+            self.command(connections, &line);
+        }
+
+        if let Some((addr, receiver)) = &self.pending {
+            let dialed = match receiver.try_recv() {
+                Ok(dialed) => dialed,
+                Err(TryRecvError::Empty) => return std::mem::take(&mut self.changed),
+                Err(TryRecvError::Disconnected) => Err(anyhow::format_err!("Dialing panicked")),
+            };
+            match dialed {
+                Ok(dialed) => {
+                    println!("Connected to {}", addr);
+                    for (connection, new) in connections.iter_mut().zip(dialed) {
+                        **connection = new;
+                    }
+                    self.parked.clear();
+                    self.current = Some(addr.clone());
+                }
+                Err(e) => {
+                    eprintln!("Failed to switch to {}; {:#}", addr, e);
+                    for (connection, old) in connections.iter_mut().zip(self.parked.drain(..)) {
+                        **connection = old;
+                    }
+                }
+            }
+            self.pending = None;
+            self.changed = true;
+        }
+
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Where the client is connected, for the window title
+    pub fn status(&self) -> String {
+        match (&self.pending, &self.current) {
+            (Some((addr, _)), _) => format!("connecting to {}\u{2026}", addr),
+            (None, Some(addr)) => format!("@ {}", addr),
+            (None, None) => "(offline)".into(),
+        }
+    }
+
+    fn command(&mut self, connections: &mut [&mut Connection], line: &str) {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (None, ..) => (),
+            (Some("connect"), Some(addr), None) => {
+                if let Err(e) = self.connect(connections, addr) {
+                    eprintln!("{:#}", e);
+                }
+            }
+            (Some("disconnect"), None, _) => self.disconnect(connections),
+            (Some("status"), None, _) => println!("{}", self.status()),
+            _ => eprintln!("Commands: connect <address>, disconnect, status"),
+        }
+    }
+}
+
+/// Start the thread which reads lines from stdin, until it closes
+fn spawn_console() -> Result<Receiver<String>> {
+    let (sender, receiver) = channel();
+    std::thread::Builder::new()
+        .name("console".into())
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) if sender.send(line).is_ok() => (),
+                    _ => break,
+                }
+            }
+        })
+        .context("Failed to start the console thread")?;
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubehead::{write_hello, ClientState};
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    /// Accept one client on a thread, greeting it as the server does
+    fn serve_one(listener: TcpListener) -> std::thread::JoinHandle<TcpStream> {
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            write_hello(&mut stream).unwrap();
+            stream
+        })
+    }
+
+    /// Read one message, blocking
+    fn read_msg<T: serde::de::DeserializeOwned>(stream: &mut TcpStream) -> T {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).unwrap();
+        let mut body = vec![0; u32::from_le_bytes(header) as usize];
+        stream.read_exact(&mut body).unwrap();
+        bincode::deserialize(&body).unwrap()
+    }
+
+    /// Update the switch until it is done dialing, giving up after ten seconds
+    fn finish(switch: &mut ServerSwitch, connection: &mut Connection) {
+        for _ in 0..1000 {
+            switch.update(&mut [&mut *connection]);
+            if switch.pending.is_none() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Still {}", switch.status());
+    }
+
+    /// Send a state named `name` over `connection`
+    fn send_named(connection: &mut Connection, name: &str) {
+        let mut state = ClientState {
+            name: name.into(),
+            ..ClientState::default()
+        };
+        connection.send_state(&mut state).unwrap();
+    }
+
+    /// A client connected to a server listening on loopback, and that server's end
+    fn connected() -> (ServerSwitch, Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = serve_one(listener);
+        let connection = Connection::online(&[addr]).unwrap();
+        let switch = ServerSwitch::new(Some(addr.to_string()), false).unwrap();
+        (switch, connection, peer.join().unwrap())
+    }
+
+    #[test]
+    fn switching_moves_the_connection_to_the_new_server() {
+        let (mut switch, mut connection, mut first_peer) = connected();
+        let second = TcpListener::bind("127.0.0.1:0").unwrap();
+        let second_addr = second.local_addr().unwrap().to_string();
+        let second_peer = serve_one(second);
+
+        switch
+            .connect(&mut [&mut connection], &second_addr)
+            .unwrap();
+        assert_eq!(
+            switch.status(),
+            format!("connecting to {}\u{2026}", second_addr)
+        );
+        assert!(matches!(connection, Connection::Offline(_)));
+        let error = switch
+            .connect(&mut [&mut connection], "example.com")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Still connecting to {}", second_addr)
+        );
+
+        finish(&mut switch, &mut connection);
+        assert_eq!(switch.status(), format!("@ {}", second_addr));
+        let mut second_peer = second_peer.join().unwrap();
+
+        // The old server sees us hang up, and the new one hears from us
+        let mut buf = [0; 1];
+        assert_eq!(first_peer.read(&mut buf).unwrap(), 0);
+        send_named(&mut connection, "switcher");
+        let received: ClientState = read_msg(&mut second_peer);
+        assert_eq!(received.name, "switcher");
+    }
+
+    #[test]
+    fn failed_switches_put_the_old_connection_back() {
+        let (mut switch, mut connection, mut peer) = connected();
+        let old_status = switch.status();
+
+        // Nothing listens on a port just given up
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        switch
+            .connect(&mut [&mut connection], &closed.to_string())
+            .unwrap();
+        finish(&mut switch, &mut connection);
+        assert_eq!(switch.status(), old_status);
+
+        send_named(&mut connection, "stayer");
+        let received: ClientState = read_msg(&mut peer);
+        assert_eq!(received.name, "stayer");
+    }
+
+    #[test]
+    fn disconnecting_goes_offline() {
+        let (mut switch, mut connection, mut peer) = connected();
+        switch.disconnect(&mut [&mut connection]);
+        assert_eq!(switch.status(), "(offline)");
+        assert!(matches!(connection, Connection::Offline(_)));
+        assert!(switch.update(&mut [&mut connection]));
+
+        let mut buf = [0; 1];
+        assert_eq!(peer.read(&mut buf).unwrap(), 0);
+    }
+}
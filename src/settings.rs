@@ -0,0 +1,92 @@
+//! Runtime settings shared between the settings panel and the systems which read them
+use crate::camera::{CameraSettings, FlySmoothing};
+use crate::render::{DebugMode, DEFAULT_HEAD_DRAW_DISTANCE, DEFAULT_HEAD_LOD_DISTANCE};
+
+/// Settings which can be changed while the client is running. Systems read these every frame,
+/// so changes take effect immediately.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub camera: CameraSettings,
+    /// Time constant of the fly camera speeding up, in seconds; zero starts instantly
+    pub fly_accelerate: f32,
+    /// Time constant of the fly camera slowing down, in seconds; zero stops instantly
+    pub fly_decelerate: f32,
+    pub debug_mode: DebugMode,
+    pub labels_through_walls: bool,
+    pub show_stats: bool,
+    pub debug_lines: bool,
+    /// Draw debug lines on top of all other geometry
+    pub debug_lines_xray: bool,
+    /// Convert linear shader output to sRGB; off shows the uncorrected colors for comparison
+    pub gamma_correct: bool,
+    /// Smooth out jitter in the heads and hands of other players
+    pub smooth_heads: bool,
+    /// Time constant of head smoothing, in seconds
+    pub head_smoothing: f32,
+    /// Blend distant geometry towards the background color
+    pub fog: bool,
+    /// Fog density per meter
+    pub fog_density: f32,
+    pub shadows: bool,
+    /// Constant shadow map depth bias
+    pub shadow_bias: f32,
+    /// Extra shadow map depth bias on surfaces at a grazing angle to the sun
+    pub shadow_slope_bias: f32,
+    /// Draw a reference grid on the ground
+    pub grid: bool,
+    /// Show a top-down map of the surroundings in the corner of the window
+    pub minimap: bool,
+    /// Width of the minimap in pixels
+    pub minimap_size: f32,
+    /// Width of the area shown on the minimap, in meters
+    pub minimap_extent: f32,
+    /// Draw a fading trail behind each head
+    pub trails: bool,
+    /// Let heads gently bob and breathe
+    pub idle_animation: bool,
+    /// Heads further away than this many meters are drawn as boxes
+    pub head_lod_distance: f32,
+    /// Heads further away than this many meters are not drawn
+    pub head_draw_distance: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            camera: CameraSettings::default(),
+            fly_accelerate: 0.1,
+            fly_decelerate: 0.15,
+            debug_mode: DebugMode::Solid,
+            labels_through_walls: false,
+            show_stats: false,
+            debug_lines: false,
+            debug_lines_xray: false,
+            gamma_correct: true,
+            smooth_heads: true,
+            head_smoothing: 0.08,
+            fog: false,
+            fog_density: 0.02,
+            shadows: false,
+            shadow_bias: 0.002,
+            shadow_slope_bias: 0.005,
+            grid: false,
+            minimap: false,
+            minimap_size: 200.,
+            minimap_extent: 40.,
+            trails: false,
+            idle_animation: true,
+            head_lod_distance: DEFAULT_HEAD_LOD_DISTANCE,
+            head_draw_distance: DEFAULT_HEAD_DRAW_DISTANCE,
+        }
+    }
+}
+
+impl Settings {
+    /// How the fly camera eases in and out of movement
+    pub fn fly_smoothing(&self) -> FlySmoothing {
+        FlySmoothing {
+            accelerate: self.fly_accelerate,
+            decelerate: self.fly_decelerate,
+        }
+    }
+}
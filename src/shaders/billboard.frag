@@ -0,0 +1,22 @@
+#version 450
+precision mediump float;
+
+uniform bool use_texture;
+uniform sampler2D tex;
+
+in vec2 f_uv;
+in vec4 f_color;
+in float f_view_dist;
+
+out vec4 out_color;
+
+void main() {
+    vec4 color = f_color;
+    if (use_texture) {
+        color *= texture(tex, f_uv);
+    }
+    if (color.a <= 0.) {
+        discard;
+    }
+    out_color = to_output(apply_fog(color, f_view_dist));
+}
@@ -0,0 +1,32 @@
+#version 450
+
+uniform mat4 view;
+uniform mat4 proj;
+// Rotation of the camera the quads face, from world space to view space
+uniform mat3 facing;
+
+layout (location = 0) in vec3 pos;
+layout (location = 1) in float size;
+layout (location = 2) in vec4 color;
+layout (location = 3) in vec4 uv_rect;
+
+out vec2 f_uv;
+out vec4 f_color;
+out float f_view_dist;
+
+void main() {
+    vec2 corner = quad_corner();
+
+    // The rows of the rotation are the camera's axes in world space
+    vec3 right = vec3(facing[0][0], facing[1][0], facing[2][0]);
+    vec3 up = vec3(facing[0][1], facing[1][1], facing[2][1]);
+    vec2 offset = (corner - 0.5) * size;
+    vec3 world = pos + right * offset.x + up * offset.y;
+
+    vec4 view_pos = view * vec4(world, 1.);
+    gl_Position = proj * view_pos;
+    f_view_dist = length(view_pos.xyz);
+
+    f_uv = mix(uv_rect.xy, uv_rect.zw, vec2(corner.x, 1. - corner.y));
+    f_color = color;
+}
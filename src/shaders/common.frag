@@ -0,0 +1,98 @@
+// Shared by every fragment shader, and inserted after its #version line
+
+uniform bool encode_srgb;
+
+uniform vec3 fog_color;
+uniform float fog_density;
+
+uniform bool shadows;
+uniform sampler2DShadow shadow_map;
+uniform vec3 sun_dir;
+uniform float shadow_bias;
+uniform float shadow_slope_bias;
+
+// Distances from the eye over which geometry dithers out; equal values disable fading
+uniform vec2 fade_range;
+
+// Encode linear output as sRGB, for targets which don't do it in hardware
+vec4 to_output(vec4 linear) {
+    if (!encode_srgb) {
+        return linear;
+    }
+    vec3 lo = linear.rgb * 12.92;
+    vec3 hi = 1.055 * pow(linear.rgb, vec3(1. / 2.4)) - 0.055;
+    return vec4(mix(hi, lo, lessThanEqual(linear.rgb, vec3(0.0031308))), linear.a);
+}
+
+// Blend towards the fog color with distance from the eye (exponential squared)
+vec4 apply_fog(vec4 color, float view_dist) {
+    if (fog_density <= 0.) {
+        return color;
+    }
+    float d = view_dist * fog_density;
+    float visibility = exp(-d * d);
+    return vec4(mix(fog_color, color.rgb, visibility), color.a);
+}
+
+// Brightness of surfaces in shadow or facing away from the sun
+const float AMBIENT = 0.45;
+
+// Fraction of sunlight reaching a fragment with the given vertex normal and position in the
+// shadow map's clip space, filtered over 3x3 shadow map texels
+float sunlight(vec3 normal, vec4 light_pos) {
+    // Vertices without a normal face the sun. The back of double-sided meshes faces the
+    // other way.
+    float facing = 1.;
+    if (length(normal) > 1e-4) {
+        normal = gl_FrontFacing ? normal : -normal;
+        facing = dot(normalize(normal), normalize(sun_dir));
+    }
+    if (facing <= 0.) {
+        return 0.;
+    }
+
+    // Anything outside of the shadow map is lit
+    vec3 coords = light_pos.xyz / light_pos.w * 0.5 + 0.5;
+    if (any(lessThan(coords, vec3(0.))) || any(greaterThan(coords, vec3(1.)))) {
+        return facing;
+    }
+
+    // Surfaces at a grazing angle to the light need more bias
+    float bias = shadow_bias + shadow_slope_bias * (1. - facing);
+    vec2 texel = 1. / vec2(textureSize(shadow_map, 0));
+
+    float lit = 0.;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(shadow_map, vec3(coords.xy + vec2(x, y) * texel, coords.z - bias));
+        }
+    }
+    return facing * lit / 9.;
+}
+
+// Darken surfaces facing away from the sun or in shadow, if shadows are on
+vec4 apply_lighting(vec4 color, vec3 normal, vec4 light_pos) {
+    if (!shadows) {
+        return color;
+    }
+    return vec4(color.rgb * mix(AMBIENT, 1., sunlight(normal, light_pos)), color.a);
+}
+
+// Discard a growing share of fragments in a fixed 4x4 pattern, fading out geometry between the
+// ends of `fade_range` from the eye without blending
+void dither_fade(float view_dist) {
+    if (fade_range.y <= fade_range.x) {
+        return;
+    }
+    float fade = clamp((view_dist - fade_range.x) / (fade_range.y - fade_range.x), 0., 1.);
+    const float BAYER[16] = float[](
+        0., 8., 2., 10.,
+        12., 4., 14., 6.,
+        3., 11., 1., 9.,
+        15., 7., 13., 5.
+    );
+    ivec2 p = ivec2(gl_FragCoord.xy) % 4;
+    if (fade > (BAYER[p.y * 4 + p.x] + 0.5) / 16.) {
+        discard;
+    }
+}
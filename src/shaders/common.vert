@@ -0,0 +1,31 @@
+// Shared by every vertex shader, and inserted after its #version line
+
+// Corner of a unit quad drawn as a triangle strip of four vertices, from (0, 0) to (1, 1)
+vec2 quad_corner() {
+    return vec2(gl_VertexID & 1, (gl_VertexID >> 1) & 1);
+}
+
+// Seconds on a monotonic clock, and whether instances bob and breathe with it
+uniform float time;
+uniform bool idle;
+
+const float TAU = 6.2831853;
+
+// Height of the idle bob in meters, and its period in seconds
+const float BOB_HEIGHT = 0.004;
+const float BOB_PERIOD = 2.7;
+
+// Relative change in size while breathing, and its period in seconds
+const float BREATH_SCALE = 0.015;
+const float BREATH_PERIOD = 4.3;
+
+// World position of a vertex of an instance, bobbing and breathing `phase` cycles into the idle
+// animation
+vec4 idle_world_pos(mat4 transform, vec3 pos, float phase) {
+    vec3 offset = vec3(0.);
+    if (idle) {
+        pos *= 1. + BREATH_SCALE * sin(TAU * (time / BREATH_PERIOD + phase));
+        offset.y = BOB_HEIGHT * sin(TAU * (time / BOB_PERIOD + phase));
+    }
+    return transform * vec4(pos, 1.0) + vec4(offset, 0.);
+}
@@ -0,0 +1,70 @@
+#version 450
+precision mediump float;
+
+// Fast approximate anti-aliasing, after Timothy Lottes' public domain FXAA
+
+uniform sampler2D source;
+uniform sampler2D depth;
+// Size of one texel of the source
+uniform vec2 texel_size;
+// Whether the source holds linear colors, rather than sRGB encoded ones
+uniform bool linear_source;
+// Fraction of the source drawn into
+uniform vec2 uv_scale;
+
+in vec2 f_source_uv;
+
+out vec4 out_color;
+
+const float REDUCE_MIN = 1. / 128.;
+const float REDUCE_MUL = 1. / 8.;
+const float SPAN_MAX = 8.;
+
+// The source at `uv`, kept within the part drawn into
+vec4 source_at(vec2 uv) {
+    return texture(source, min(uv, uv_scale - 0.5 * texel_size));
+}
+
+// Perceptual brightness; edges are found where this changes sharply
+float luma(vec3 color) {
+    float l = dot(color, vec3(0.299, 0.587, 0.114));
+    return linear_source ? sqrt(l) : l;
+}
+
+void main() {
+    vec4 center = texture(source, f_source_uv);
+    float luma_nw = luma(source_at(f_source_uv + vec2(-1., -1.) * texel_size).rgb);
+    float luma_ne = luma(source_at(f_source_uv + vec2(1., -1.) * texel_size).rgb);
+    float luma_sw = luma(source_at(f_source_uv + vec2(-1., 1.) * texel_size).rgb);
+    float luma_se = luma(source_at(f_source_uv + vec2(1., 1.) * texel_size).rgb);
+    float luma_m = luma(center.rgb);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, perpendicular to the luma gradient
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float rcp_dir_min = 1. / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel_size;
+
+    vec3 rgb_a = 0.5 * (
+        source_at(f_source_uv + dir * (1. / 3. - 0.5)).rgb +
+        source_at(f_source_uv + dir * (2. / 3. - 0.5)).rgb
+    );
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        source_at(f_source_uv + dir * -0.5).rgb +
+        source_at(f_source_uv + dir * 0.5).rgb
+    );
+
+    // Fall back to the narrower blur if the wider one crossed into another edge
+    float luma_b = luma(rgb_b);
+    vec3 rgb = (luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b;
+    out_color = vec4(rgb, center.a);
+
+    // Pass the scene depth through, so later draws are occluded by it
+    gl_FragDepth = texture(depth, f_source_uv).r;
+}
@@ -0,0 +1,79 @@
+#version 450
+precision highp float;
+
+uniform mat4 view_proj;
+uniform mat4 inv_view_proj;
+uniform vec3 camera_pos;
+// Whether window depth equals NDC depth, as with glClipControl, rather than being remapped
+// from -1..1
+uniform bool depth_zero_to_one;
+
+in vec2 f_ndc;
+
+out vec4 out_color;
+
+const vec3 MINOR_COLOR = vec3(0.5);
+const vec3 MAJOR_COLOR = vec3(0.8);
+const vec3 X_AXIS_COLOR = vec3(0.9, 0.2, 0.2);
+const vec3 Z_AXIS_COLOR = vec3(0.2, 0.3, 0.9);
+const float MINOR_ALPHA = 0.35;
+const float MAJOR_ALPHA = 0.6;
+// Distance in meters over which the grid fades out
+const float FADE_START = 20.;
+const float FADE_END = 150.;
+// Fraction of the distance to the grid it is pulled towards the eye, so that it draws over a
+// floor at the same height
+const float DEPTH_PULL = 0.0005;
+
+// Coverage of lines every `spacing` meters, about a pixel wide. Lines fade out before they are
+// closer together than a couple of pixels, rather than shimmering.
+float grid_lines(vec2 coord, float spacing) {
+    vec2 cell = coord / spacing;
+    vec2 width = fwidth(cell);
+    vec2 dist = abs(fract(cell - 0.5) - 0.5) / width;
+    float line = 1. - min(min(dist.x, dist.y), 1.);
+    return line * (1. - smoothstep(0.15, 0.5, max(width.x, width.y)));
+}
+
+// Coverage of the line along one axis, where `offset` is the distance from it in meters
+float axis_line(float offset) {
+    return 1. - min(abs(offset) / fwidth(offset), 1.);
+}
+
+void main() {
+    // Cast a ray from the eye through this pixel onto the ground plane. NDC depth 1 is finite
+    // with both standard and reversed depth.
+    vec4 point = inv_view_proj * vec4(f_ndc, 1., 1.);
+    vec3 dir = point.xyz / point.w - camera_pos;
+    bool reaches_ground = dir.y * camera_pos.y < 0.;
+    vec3 hit = camera_pos - dir * (camera_pos.y / dir.y);
+    vec2 coord = hit.xz;
+
+    float minor = grid_lines(coord, 1.) * MINOR_ALPHA;
+    float major = grid_lines(coord, 10.) * MAJOR_ALPHA;
+    float x_axis = axis_line(coord.y);
+    float z_axis = axis_line(coord.x);
+
+    vec3 color = MINOR_COLOR;
+    float alpha = minor;
+    color = mix(color, MAJOR_COLOR, step(alpha, major));
+    alpha = max(alpha, major);
+    color = mix(color, X_AXIS_COLOR, x_axis);
+    color = mix(color, Z_AXIS_COLOR, z_axis);
+    alpha = max(alpha, max(x_axis, z_axis));
+
+    float dist = length(hit - camera_pos);
+    alpha *= 1. - smoothstep(FADE_START, FADE_END, dist);
+
+    // Derivatives are undefined after a discard, so all of them are taken first
+    if (!reaches_ground || alpha <= 0.) {
+        discard;
+    }
+
+    // Depth of the grid, so that it is hidden behind other geometry
+    vec4 clip = view_proj * vec4(mix(hit, camera_pos, DEPTH_PULL), 1.);
+    float depth = clip.z / clip.w;
+    gl_FragDepth = depth_zero_to_one ? depth : depth * 0.5 + 0.5;
+
+    out_color = to_output(vec4(color, alpha));
+}
@@ -0,0 +1,10 @@
+#version 450
+
+out vec2 f_ndc;
+
+void main() {
+    // Fullscreen triangle
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2. - 1.;
+    gl_Position = vec4(ndc, 0., 1.);
+    f_ndc = ndc;
+}
@@ -0,0 +1,10 @@
+#version 450
+precision mediump float;
+
+in vec3 f_color;
+
+out vec4 out_color;
+
+void main() {
+    out_color = to_output(vec4(f_color, 1.));
+}
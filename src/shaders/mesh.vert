@@ -0,0 +1,37 @@
+#version 450
+
+uniform mat4 view;
+uniform mat4 proj;
+uniform mat4 light_view_proj;
+
+layout (location = 0) in vec3 pos;
+layout (location = 1) in vec3 color;
+layout (location = 2) in vec2 uv;
+layout (location = 3) in vec3 normal;
+layout (location = 4) in mat4 transform;
+layout (location = 8) in vec4 tint;
+layout (location = 9) in float phase;
+
+out vec4 f_color;
+out vec4 f_tint;
+out vec3 f_pos;
+out vec2 f_uv;
+out vec3 f_normal;
+out vec4 f_light_pos;
+out float f_view_dist;
+// Barycentric coordinates of this corner, only meaningful for draws without indices
+out vec3 f_barycentric;
+
+void main() {
+    vec4 world_pos = idle_world_pos(transform, pos, phase);
+    vec4 view_pos = view * world_pos;
+    gl_Position = proj * view_pos;
+    f_view_dist = length(view_pos.xyz);
+    f_pos = world_pos.xyz;
+    f_color = vec4(color, 1.) * tint;
+    f_tint = tint;
+    f_uv = uv;
+    f_normal = mat3(transform) * normal;
+    f_light_pos = light_view_proj * world_pos;
+    f_barycentric = vec3(equal(ivec3(gl_VertexID % 3), ivec3(0, 1, 2)));
+}
@@ -0,0 +1,44 @@
+#version 450
+precision mediump float;
+
+uniform sampler2D tex;
+// Border width as a fraction of the minimap's size
+uniform float border;
+// Direction the viewer faces on the minimap, for the marker in the middle
+uniform vec2 heading;
+
+in vec2 f_uv;
+
+out vec4 out_color;
+
+const vec3 BORDER_COLOR = vec3(0.8);
+const vec3 MARKER_COLOR = vec3(1.);
+const float OPACITY = 0.85;
+// Length and half width of the viewer marker, as fractions of the minimap's size
+const float MARKER_LENGTH = 0.06;
+const float MARKER_WIDTH = 0.035;
+
+// Whether a point relative to the middle is inside the arrowhead pointing along `heading`
+bool in_marker(vec2 p) {
+    if (heading == vec2(0.)) {
+        return false;
+    }
+    vec2 side = vec2(heading.y, -heading.x);
+    float along = dot(p, heading) + MARKER_LENGTH / 2.;
+    float across = abs(dot(p, side));
+    return along >= 0. && along <= MARKER_LENGTH
+        && across <= MARKER_WIDTH * (1. - along / MARKER_LENGTH);
+}
+
+void main() {
+    vec3 color = texture(tex, f_uv).rgb;
+
+    vec2 edge = min(f_uv, 1. - f_uv);
+    if (min(edge.x, edge.y) < border) {
+        color = BORDER_COLOR;
+    } else if (in_marker(f_uv - 0.5)) {
+        color = MARKER_COLOR;
+    }
+
+    out_color = to_output(vec4(color, OPACITY));
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// Corners of the minimap on screen, in normalized device coordinates
+uniform vec4 rect;
+
+out vec2 f_uv;
+
+void main() {
+    vec2 corner = quad_corner();
+    gl_Position = vec4(mix(rect.xy, rect.zw, corner), 0., 1.);
+    f_uv = corner;
+}
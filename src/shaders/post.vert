@@ -0,0 +1,17 @@
+#version 450
+
+// Fraction of the source textures drawn into, which can be smaller than they are
+uniform vec2 uv_scale;
+
+// Position within the view, from (0, 0) to (1, 1)
+out vec2 f_uv;
+// Where that is in the source textures
+out vec2 f_source_uv;
+
+void main() {
+    // Fullscreen triangle
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2. - 1.;
+    gl_Position = vec4(ndc, 0., 1.);
+    f_uv = ndc * 0.5 + 0.5;
+    f_source_uv = f_uv * uv_scale;
+}
@@ -0,0 +1,6 @@
+#version 450
+precision mediump float;
+
+// Only depth is written
+void main() {
+}
@@ -0,0 +1,12 @@
+#version 450
+
+uniform mat4 light_view_proj;
+
+layout (location = 0) in vec3 pos;
+layout (location = 4) in mat4 transform;
+layout (location = 9) in float phase;
+
+void main() {
+    // Shadows follow the heads casting them
+    gl_Position = light_view_proj * idle_world_pos(transform, pos, phase);
+}
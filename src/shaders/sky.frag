@@ -0,0 +1,36 @@
+#version 450
+precision mediump float;
+
+uniform bool use_cubemap;
+uniform samplerCube cubemap;
+
+in vec3 f_dir;
+
+out vec4 out_color;
+
+const vec3 ZENITH = vec3(0.05, 0.15, 0.4);
+// Keep in sync with HORIZON_COLOR in render.rs
+const vec3 HORIZON = vec3(0.45, 0.55, 0.65);
+const vec3 GROUND = vec3(0.1, 0.1, 0.1);
+const vec3 SUN = vec3(1., 0.95, 0.8);
+
+void main() {
+    vec3 dir = normalize(f_dir);
+
+    if (use_cubemap) {
+        out_color = to_output(texture(cubemap, dir));
+        return;
+    }
+
+    // Procedural vertical gradient
+    vec3 color;
+    if (dir.y > 0.) {
+        color = mix(HORIZON, ZENITH, sqrt(dir.y));
+    } else {
+        color = mix(HORIZON, GROUND, pow(-dir.y, 0.3));
+    }
+
+    // Sun disc with a soft edge
+    float sun = smoothstep(0.9990, 0.9995, dot(dir, normalize(sun_dir)));
+    out_color = to_output(vec4(mix(color, SUN, sun), 1.));
+}
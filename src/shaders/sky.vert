@@ -0,0 +1,16 @@
+#version 450
+
+uniform mat4 inv_view_proj;
+
+out vec3 f_dir;
+
+void main() {
+    // Fullscreen triangle
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2. - 1.;
+    gl_Position = vec4(ndc, 0., 1.);
+
+    // World-space direction through this vertex; the view has no translation. Depth 1 is finite
+    // with both standard and reversed depth.
+    vec4 world = inv_view_proj * vec4(ndc, 1., 1.);
+    f_dir = world.xyz / world.w;
+}
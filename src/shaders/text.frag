@@ -0,0 +1,17 @@
+#version 450
+precision mediump float;
+
+uniform sampler2D tex;
+
+in vec2 f_uv;
+in vec4 f_color;
+
+out vec4 out_color;
+
+void main() {
+    vec4 color = texture(tex, f_uv);
+    if (color.a < 0.1) {
+        discard;
+    }
+    out_color = to_output(color * f_color);
+}
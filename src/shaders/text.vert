@@ -0,0 +1,17 @@
+#version 450
+
+uniform mat4 view;
+uniform mat4 proj;
+
+layout (location = 0) in vec3 pos;
+layout (location = 1) in vec2 uv;
+layout (location = 2) in vec4 color;
+
+out vec2 f_uv;
+out vec4 f_color;
+
+void main() {
+    gl_Position = proj * view * vec4(pos, 1.);
+    f_uv = uv;
+    f_color = color;
+}
@@ -1,10 +1,35 @@
 #version 450
 precision mediump float;
 
+uniform int debug_mode;
+
+// Opacity of the whole mesh
+uniform float alpha;
+
 in vec4 f_color;
+in vec3 f_pos;
+in float f_view_dist;
+in vec3 f_normal;
+in vec4 f_light_pos;
+in vec3 f_barycentric;
 
 out vec4 out_color;
 
 void main() {
-    out_color = f_color;
+    dither_fade(f_view_dist);
+
+    // Without glPolygonMode, wireframes keep the fragments within a pixel of an edge
+    if (debug_mode == 3 && all(greaterThan(f_barycentric, fwidth(f_barycentric)))) {
+        discard;
+    }
+
+    if (debug_mode == 2) {
+        // Flat face normal from screen-space derivatives
+        vec3 normal = normalize(cross(dFdx(f_pos), dFdy(f_pos)));
+        out_color = to_output(vec4(normal * 0.5 + 0.5, 1.));
+    } else {
+        vec4 color = f_color;
+        color.a *= alpha;
+        out_color = to_output(apply_fog(apply_lighting(color, f_normal, f_light_pos), f_view_dist));
+    }
 }
@@ -0,0 +1,28 @@
+#version 450
+precision mediump float;
+
+// Comfort vignette: darkens the view outside a circle around the direction of view
+
+uniform sampler2D source;
+uniform sampler2D depth;
+// Where the direction of view lands, in normalized device coordinates
+uniform vec2 center;
+// Radius of the clear part of the view, in normalized device coordinates
+uniform float radius;
+
+in vec2 f_uv;
+in vec2 f_source_uv;
+
+out vec4 out_color;
+
+// Width of the soft edge, beyond which the view is fully dark
+const float FEATHER = 0.35;
+
+void main() {
+    vec4 color = texture(source, f_source_uv);
+    float dark = smoothstep(radius, radius + FEATHER, distance(f_uv * 2. - 1., center));
+    out_color = vec4(color.rgb * (1. - dark), color.a);
+
+    // Pass the scene depth through, so later draws are occluded by it
+    gl_FragDepth = texture(depth, f_source_uv).r;
+}
@@ -1,17 +1,187 @@
-use crate::render::{Mesh, Vertex};
+use crate::camera::HeightQuery;
+use crate::debug_lines::LineMesh;
+use crate::frustum::{frustum_corners, FRUSTUM_EDGES};
+use crate::render::{Mesh, MeshBuilder, MeshError, NormalMode, Vertex};
+use crate::rooms::{RoomsSpec, Side};
+use cubehead::{Heightfield, TERRAIN_AMPLITUDE};
+use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3};
+use std::f32::consts::{FRAC_PI_2, TAU};
 
+const UP: [f32; 3] = [0., 1., 0.];
+const DOWN: [f32; 3] = [0., -1., 0.];
+
+/// Most tiles along each side of a checkerboard, keeping it within the engine's mesh limits
+const MAX_CHECKER_TILES: u32 = 512;
+
+/// Most lines along each axis of a grid of lines
+const MAX_GRID_LINES: u32 = 1000;
+
+/// Linear colors of the lines of a grid, every `spacing` and every `major_every` lines
+const GRID_MINOR_COLOR: [f32; 3] = [0.3, 0.3, 0.3];
+const GRID_MAJOR_COLOR: [f32; 3] = [0.8, 0.8, 0.8];
+
+/// Fewest segments around a round shape, making a triangular prism or pyramid
+const MIN_SEGMENTS: u32 = 3;
+
+/// Parameters a shape can't be made from
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShapeError {
+    /// Fewer than `MIN_SEGMENTS` segments around
+    TooFewSegments(u32),
+    /// No rings around a capsule's ends
+    TooFewRings(u32),
+    /// Fewer than two stacks of a sphere, one for each hemisphere
+    TooFewStacks(u32),
+    /// A maze with no cells, or more than `MAX_MAZE_CELLS` along a side
+    BadMazeSize { cells_x: u32, cells_z: u32 },
+    /// A grid resolution too small for the shape (zero, or below 2 for terrain), or with more
+    /// indices than fit in u32
+    BadResolution(u32),
+    /// A grid of lines with more than `MAX_GRID_LINES` along an axis
+    TooManyLines(u32),
+    /// A dimension which is zero, negative or not finite
+    BadDimension { name: &'static str, value: f32 },
+    /// Putting the parts of a shape together failed
+    Mesh(MeshError),
+}
+
+impl std::fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::TooFewSegments(segments) => write!(
+                f,
+                "{} segments is too few; need at least {}",
+                segments, MIN_SEGMENTS
+            ),
+            Self::TooFewRings(rings) => write!(f, "{} rings is too few; need at least 1", rings),
+            Self::TooFewStacks(stacks) => {
+                write!(f, "{} stacks is too few; need at least 2", stacks)
+            }
+            Self::BadMazeSize { cells_x, cells_z } => write!(
+                f,
+                "a maze of {} by {} cells must have from 1 to {} along each side",
+                cells_x, cells_z, MAX_MAZE_CELLS
+            ),
+            Self::BadResolution(resolution) => write!(
+                f,
+                "resolution of {} is too small, or has more indices than fit in 32 bits",
+                resolution
+            ),
+            Self::TooManyLines(lines) => write!(
+                f,
+                "{} lines along each axis is too many; at most {}",
+                lines, MAX_GRID_LINES
+            ),
+            Self::BadDimension { name, value } => {
+                write!(f, "{} of {} must be positive and finite", name, value)
+            }
+            Self::Mesh(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+impl From<MeshError> for ShapeError {
+    fn from(e: MeshError) -> Self {
+        Self::Mesh(e)
+    }
+}
+
+/// Colors of the ground quad's corners, at (-X, -Z), (-X, +Z), (+X, +Z) and (+X, -Z)
+const QUAD_CORNER_COLORS: [[f32; 3]; 4] = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.], [1., 1., 1.]];
+
+/// Ground quad spanning [-size, size] on X and Z; UVs tile once per meter
+// The built in map is subdivided, this is kept as the simplest ground
+#[allow(dead_code)]
 pub fn big_quad_map(size: f32) -> Mesh {
-    Mesh {
-        indices: vec![0, 1, 2, 0, 2, 3],
-        vertices: vec![
-            Vertex::new([-size, 0., -size], [1., 0., 0.]),
-            Vertex::new([-size, 0., size], [0., 1., 0.]),
-            Vertex::new([size, 0., size], [0., 0., 1.]),
-            Vertex::new([size, 0., -size], [1., 1., 1.]),
-        ],
+    big_quad_map_subdivided(size, 1).expect("A single quad is a valid grid")
+}
+
+/// Ground quad spanning [-size, size] on X and Z, split into `divisions` by `divisions` quads
+/// which share their vertices, so that per-vertex effects don't stretch across the whole map.
+/// Colors blend between the corners with a gentle ripple in brightness, normals face up and UVs
+/// tile once per meter.
+pub fn big_quad_map_subdivided(size: f32, divisions: u32) -> Result<Mesh, ShapeError> {
+    check_resolution(divisions)?;
+
+    let n = divisions + 1;
+    let mut vertices = Vec::with_capacity((n * n) as usize);
+    for i in 0..n {
+        for j in 0..n {
+            let (u, v) = (i as f32 / divisions as f32, j as f32 / divisions as f32);
+            let (x, z) = (size * (2. * u - 1.), size * (2. * v - 1.));
+
+            let [a, b, c, d] = QUAD_CORNER_COLORS.map(Vector3::from);
+            let blend = (a * (1. - v) + b * v) * (1. - u) + (d * (1. - v) + c * v) * u;
+            let ripple = 1. + 0.08 * (x * 0.9 + z * 0.4).sin() * (z * 0.7 - x * 0.3).sin();
+            let color = (blend * ripple).map(|c| c.min(1.));
+
+            vertices.push(Vertex::new([x, 0., z], color.into()).with_normal_uv(UP, [x, z]));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((divisions * divisions * 6) as usize);
+    for i in 0..divisions {
+        for j in 0..divisions {
+            let a = i * n + j;
+            let (b, c, d) = (a + 1, a + n + 1, a + n);
+            indices.extend([a, b, c, a, c, d]);
+        }
+    }
+
+    Ok(Mesh { indices, vertices })
+}
+
+/// Checkerboard floor covering [-half_extent, half_extent] on X and Z with square tiles of
+/// `tile_size` meters, alternating between `color_a` and `color_b` with `color_a` in the corner.
+/// Tiles don't share vertices, so that each is a flat color. Too many tiles are made larger
+/// instead, with a warning. UVs tile once per meter.
+pub fn checkerboard(
+    half_extent: f32,
+    tile_size: f32,
+    color_a: [f32; 3],
+    color_b: [f32; 3],
+) -> Mesh {
+    let requested = (2. * half_extent / tile_size).ceil();
+    let tiles = if requested.is_finite() {
+        (requested as u32).clamp(1, MAX_CHECKER_TILES)
+    } else {
+        MAX_CHECKER_TILES
+    };
+    let tile_size = if requested > tiles as f32 || !requested.is_finite() {
+        eprintln!(
+            "A checkerboard of {} m tiles is too fine; using {} tiles along each side",
+            tile_size, tiles
+        );
+        2. * half_extent / tiles as f32
+    } else {
+        tile_size
+    };
+    // Centered, overhanging the extent if it isn't a whole number of tiles
+    let start = -tile_size * tiles as f32 / 2.;
+
+    let mut indices = vec![];
+    let mut vertices = vec![];
+    for i in 0..tiles {
+        for j in 0..tiles {
+            let color = if (i + j) % 2 == 0 { color_a } else { color_b };
+            let (x0, z0) = (start + i as f32 * tile_size, start + j as f32 * tile_size);
+            let (x1, z1) = (x0 + tile_size, z0 + tile_size);
+
+            let base = vertices.len() as u32;
+            for [x, z] in [[x0, z0], [x0, z1], [x1, z1], [x1, z0]] {
+                vertices.push(Vertex::new([x, 0., z], color).with_normal_uv(UP, [x, z]));
+            }
+            indices.extend([0, 1, 2, 0, 2, 3].map(|d| d + base));
+        }
     }
+
+    Mesh { indices, vertices }
 }
 
+/// Cube spanning [-size, size], with each pair of opposite faces colored red, green or blue.
+/// Each face has its own vertices, with UVs spanning 0 to 1.
 pub fn rgb_cube(size: f32) -> Mesh {
     // We do a little geometry
 
@@ -32,11 +202,15 @@ pub fn rgb_cube(size: f32) -> Mesh {
                 [sgn, size, -size],
             ];
 
+            let mut normal = [0.; 3];
+            normal[i] = sgn.signum();
+
             let base = vertices.len() as u32;
 
             for mut pos in square {
+                let uv = [(pos[1] / size + 1.) / 2., (pos[2] / size + 1.) / 2.];
                 pos.rotate_right(i);
-                vertices.push(Vertex::new(pos, color));
+                vertices.push(Vertex::new(pos, color).with_normal_uv(normal, uv));
             }
 
             let offsets = if j == 0 {
@@ -51,3 +225,1878 @@ pub fn rgb_cube(size: f32) -> Mesh {
 
     Mesh { indices, vertices }
 }
+
+/// Light grey of the head avatar, which leaves the per-player tint recognizable
+const HEAD_COLOR: [f32; 3] = [0.85, 0.85, 0.85];
+const EYE_COLOR: [f32; 3] = [0.05, 0.05, 0.05];
+const NOSE_COLOR: [f32; 3] = [0.7, 0.7, 0.7];
+
+/// Placement of the face of `head_avatar`, as fractions of the head's half width
+#[derive(Copy, Clone, Debug)]
+pub struct FaceLayout {
+    /// Distance of each eye from the middle of the face
+    pub eye_spacing: f32,
+    /// Height of the eyes above the middle of the face
+    pub eye_height: f32,
+    /// Half width of each eye
+    pub eye_size: f32,
+    /// How far the tip of the nose sticks out of the face
+    pub nose_length: f32,
+    /// Half width of the nose
+    pub nose_width: f32,
+    /// Vertical extent of the nose on the face, from the tip up to the bridge
+    pub nose_height: f32,
+}
+
+impl Default for FaceLayout {
+    fn default() -> Self {
+        Self {
+            eye_spacing: 0.4,
+            eye_height: 0.25,
+            eye_size: 0.12,
+            nose_length: 0.35,
+            nose_width: 0.12,
+            nose_height: 0.35,
+        }
+    }
+}
+
+/// Cube spanning [-size, size] with the default face on -Z, where players look
+pub fn head_avatar(size: f32) -> Mesh {
+    head_avatar_with(size, &FaceLayout::default())
+}
+
+/// Cube spanning [-size, size] with eyes and a nose on -Z, laid out by `face`. The head is light
+/// grey so the tint of each player shows, and the eyes are dark.
+pub fn head_avatar_with(size: f32, face: &FaceLayout) -> Mesh {
+    let face_z = -size;
+    let box_at = |center: Vector3<f32>, half: f32| {
+        Matrix4::new_translation(&center) * Matrix4::new_scaling(half)
+    };
+
+    let mut builder = MeshBuilder::new(rgb_cube(size)).recolor(HEAD_COLOR);
+    // Eyes are sunk halfway into the face
+    for side in [-1., 1.] {
+        let center = Vector3::new(
+            side * face.eye_spacing * size,
+            face.eye_height * size,
+            face_z,
+        );
+        let eye = MeshBuilder::new(rgb_cube(1.))
+            .transform(&box_at(center, face.eye_size * size))
+            .recolor(EYE_COLOR)
+            .build();
+        builder = builder.append(&eye).expect("Head avatar has few vertices");
+    }
+    builder
+        .append(&nose_wedge(
+            face.nose_width * size,
+            face.nose_height * size,
+            face.nose_length * size,
+            face_z,
+        ))
+        .expect("Head avatar has few vertices")
+        .build()
+}
+
+/// Wedge `2 * half_width` across and `height` tall on the plane z = `face_z`, sloping from its
+/// bridge down and out along -Z to a tip `length` away at the bottom. The back, which lies on the
+/// face, is left open.
+fn nose_wedge(half_width: f32, height: f32, length: f32, face_z: f32) -> Mesh {
+    let (top, bottom) = (height / 2., -height / 2.);
+    let tip_z = face_z - length;
+    let vertices = [
+        [-half_width, top, face_z],
+        [half_width, top, face_z],
+        [-half_width, bottom, face_z],
+        [half_width, bottom, face_z],
+        [-half_width, bottom, tip_z],
+        [half_width, bottom, tip_z],
+    ]
+    .map(|pos| Vertex::new(pos, NOSE_COLOR))
+    .to_vec();
+    // Slope, underside, then the two sides
+    let indices = vec![0, 1, 5, 0, 5, 4, 2, 4, 5, 2, 5, 3, 0, 4, 2, 1, 3, 5];
+
+    let mut mesh = Mesh { indices, vertices };
+    mesh.compute_normals(NormalMode::Flat, false);
+    mesh
+}
+
+/// Handheld controller; a grey box 16 cm long on Z, centered on the grip
+pub fn controller() -> Mesh {
+    let scale = Matrix4::new_nonuniform_scaling(&Vector3::new(0.025, 0.03, 0.08));
+    MeshBuilder::new(rgb_cube(1.))
+        .transform(&scale)
+        .recolor([0.6, 0.6, 0.6])
+        .build()
+}
+
+/// Laser beam; a white rod 4 mm thick reaching from the origin to 1 m along -Z, to be stretched
+/// to length
+pub fn beam() -> Mesh {
+    let mut mesh = cylinder(0.002, 1., 8, [1., 1., 1.]).expect("Beam dimensions are valid");
+    // Turn +Y to -Z, then reach forward from the origin
+    let turn = Matrix4::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2);
+    mesh.transform(&(Matrix4::new_translation(&Vector3::new(0., 0., -0.5)) * turn));
+    mesh
+}
+
+/// Axes from the origin, each `length` meters long with an arrowhead: +X red, +Y green and +Z
+/// blue. The arms are boxes `thickness` meters across.
+pub fn axis_gizmo(length: f32, thickness: f32) -> Result<Mesh, ShapeError> {
+    check_dimension("length", length)?;
+    check_dimension("thickness", thickness)?;
+
+    // Arrowheads are twice as wide as the arms, and take up no more than half the length
+    let head_length = (thickness * 4.).min(length / 2.);
+    let arm_length = length - head_length;
+    let mut mesh = Mesh {
+        indices: vec![],
+        vertices: vec![],
+    };
+    for (axis, color) in [
+        (Vector3::x(), [1., 0., 0.]),
+        (Vector3::y(), [0., 1., 0.]),
+        (Vector3::z(), [0., 0., 1.]),
+    ] {
+        // Point +Y, along which the pieces are built, down the axis
+        let turn = UnitQuaternion::rotation_between(&Vector3::y(), &axis)
+            .unwrap_or_else(UnitQuaternion::identity)
+            .to_homogeneous();
+        let along = |distance: f32| Matrix4::new_translation(&(axis * distance)) * turn;
+
+        let size = Vector3::new(thickness / 2., arm_length / 2., thickness / 2.);
+        let arm = MeshBuilder::new(rgb_cube(1.))
+            .transform(&(along(arm_length / 2.) * Matrix4::new_nonuniform_scaling(&size)))
+            .recolor(color)
+            .build();
+        let mut head = cone(thickness, head_length, 12, color)?;
+        head.transform(&along(arm_length + head_length / 2.));
+
+        mesh.append(&arm)?;
+        mesh.append(&head)?;
+    }
+
+    Ok(mesh)
+}
+
+/// Flat white cross on the ground at the origin, with arms reaching `size` meters out along X
+/// and Z, each `width` meters wide. Raised a millimeter to stay above a floor at zero.
+pub fn origin_grid_marker(size: f32, width: f32) -> Result<Mesh, ShapeError> {
+    check_dimension("size", size)?;
+    check_dimension("width", width)?;
+
+    let (y, half) = (0.001, width / 2.);
+    let mut mesh = Mesh {
+        indices: vec![],
+        vertices: vec![],
+    };
+    for [x0, z0, x1, z1] in [[-size, -half, size, half], [-half, -size, half, size]] {
+        let base = mesh.vertices.len() as u32;
+        for [x, z] in [[x0, z0], [x0, z1], [x1, z1], [x1, z0]] {
+            mesh.vertices
+                .push(Vertex::new([x, y, z], [1., 1., 1.]).with_normal_uv(UP, [x, z]));
+        }
+        mesh.indices.extend([0, 1, 2, 0, 2, 3].map(|i| i + base));
+    }
+    Ok(mesh)
+}
+
+/// Ground grid of lines on X and Z covering [-extent, extent], every `spacing` meters from the
+/// origin. Every `major_every`th line counting from the origin is brighter, or none if zero.
+pub fn grid_lines(extent: f32, spacing: f32, major_every: u32) -> Result<LineMesh, ShapeError> {
+    check_dimension("extent", extent)?;
+    check_dimension("spacing", spacing)?;
+    let steps = (extent / spacing).floor();
+    let lines = steps * 2. + 1.;
+    if lines > MAX_GRID_LINES as f32 {
+        return Err(ShapeError::TooManyLines(lines.min(u32::MAX as f32) as u32));
+    }
+
+    let mut mesh = LineMesh::default();
+    let steps = steps as i32;
+    for i in -steps..=steps {
+        let major = major_every > 0 && i.unsigned_abs() % major_every == 0;
+        let color = if major {
+            GRID_MAJOR_COLOR
+        } else {
+            GRID_MINOR_COLOR
+        };
+        let at = i as f32 * spacing;
+        mesh.line(
+            Point3::new(at, 0., -extent),
+            Point3::new(at, 0., extent),
+            color,
+        );
+        mesh.line(
+            Point3::new(-extent, 0., at),
+            Point3::new(extent, 0., at),
+            color,
+        );
+    }
+    Ok(mesh)
+}
+
+/// The 12 edges of a frustum, given the inverse of its projection times view, between normalized
+/// device depths `near` and `far`
+pub fn frustum_lines(
+    inv_view_proj: &Matrix4<f32>,
+    near: f32,
+    far: f32,
+    color: [f32; 3],
+) -> LineMesh {
+    let corners = frustum_corners(inv_view_proj, near, far);
+    let mut mesh = LineMesh::default();
+    for (a, b) in FRUSTUM_EDGES {
+        mesh.line(corners[a], corners[b], color);
+    }
+    mesh
+}
+
+/// The 12 edges of an axis aligned box
+pub fn aabb_lines(min: Point3<f32>, max: Point3<f32>, color: [f32; 3]) -> LineMesh {
+    let corner = |i: usize| {
+        Point3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        )
+    };
+
+    // Connect corners which differ in exactly one axis
+    let mut mesh = LineMesh::default();
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                mesh.line(corner(i), corner(i | bit), color);
+            }
+        }
+    }
+    mesh
+}
+
+/// Cylinder of `radius` along Y, spanning `height` centered on the origin, with `segments` sides.
+/// The side is smooth and the caps are flat, each with its own vertices. UVs wrap once around the
+/// side from +X, and the caps are mapped from above.
+pub fn cylinder(
+    radius: f32,
+    height: f32,
+    segments: u32,
+    color: [f32; 3],
+) -> Result<Mesh, ShapeError> {
+    check_dimension("radius", radius)?;
+    check_dimension("height", height)?;
+    check_segments(segments)?;
+
+    let half = height / 2.;
+    let mut mesh = Mesh {
+        indices: vec![],
+        vertices: vec![],
+    };
+
+    // Side, with the ring at the seam duplicated for the UVs
+    let base = mesh.vertices.len() as u32;
+    for y in [-half, half] {
+        for i in 0..=segments {
+            let u = i as f32 / segments as f32;
+            let [x, z] = around(u);
+            let pos = [x * radius, y, z * radius];
+            let v = (y + half) / height;
+            mesh.vertices
+                .push(Vertex::new(pos, color).with_normal_uv([x, 0., z], [u, v]));
+        }
+    }
+    band(&mut mesh.indices, base, base + segments + 1, segments);
+
+    cap(&mut mesh, radius, -half, segments, color);
+    cap(&mut mesh, radius, half, segments, color);
+    Ok(mesh)
+}
+
+/// Cone of `radius` along Y, spanning `height` centered on the origin with the point at the top,
+/// with `segments` sides. The side is smooth and the base is flat.
+pub fn cone(radius: f32, height: f32, segments: u32, color: [f32; 3]) -> Result<Mesh, ShapeError> {
+    check_dimension("radius", radius)?;
+    check_dimension("height", height)?;
+    check_segments(segments)?;
+
+    let half = height / 2.;
+    let slant = radius.hypot(height);
+    // Leaning out from the side by the slope, which is steeper the taller the cone
+    let normal = |[x, z]: [f32; 2]| [x * height / slant, radius / slant, z * height / slant];
+    let mut mesh = Mesh {
+        indices: vec![],
+        vertices: vec![],
+    };
+
+    // Rim, with the seam duplicated for the UVs
+    for i in 0..=segments {
+        let u = i as f32 / segments as f32;
+        let [x, z] = around(u);
+        let pos = [x * radius, -half, z * radius];
+        mesh.vertices
+            .push(Vertex::new(pos, color).with_normal_uv(normal([x, z]), [u, 0.]));
+    }
+
+    // The point, once per side so that each faces the middle of its side
+    let tip = mesh.vertices.len() as u32;
+    for i in 0..segments {
+        let u = (i as f32 + 0.5) / segments as f32;
+        let pos = [0., half, 0.];
+        mesh.vertices
+            .push(Vertex::new(pos, color).with_normal_uv(normal(around(u)), [u, 1.]));
+    }
+    for i in 0..segments {
+        mesh.indices.extend([i, i + 1, tip + i]);
+    }
+
+    cap(&mut mesh, radius, -half, segments, color);
+    Ok(mesh)
+}
+
+/// Capsule of `radius` along Y: a cylinder `height` long between the centers of its rounded
+/// ends, centered on the origin. `segments` sides go around it and `rings` bands go from each
+/// end's pole to its equator. Smooth all over.
+// Not drawn by the client yet
+#[allow(dead_code)]
+pub fn capsule(
+    radius: f32,
+    height: f32,
+    segments: u32,
+    rings: u32,
+    color: [f32; 3],
+) -> Result<Mesh, ShapeError> {
+    check_dimension("radius", radius)?;
+    check_dimension("height", height)?;
+    check_segments(segments)?;
+    if rings < 1 {
+        return Err(ShapeError::TooFewRings(rings));
+    }
+
+    let half = height / 2.;
+    let length = height + 2. * radius;
+    let mut mesh = Mesh {
+        indices: vec![],
+        vertices: vec![],
+    };
+
+    // Rows of vertices from the bottom pole to the top one. The equator is doubled, one row
+    // for each end, with the cylinder between them.
+    let bottom = (0..=rings).map(|j| (-half, -FRAC_PI_2 + FRAC_PI_2 * j as f32 / rings as f32));
+    let top = (0..=rings).map(|j| (half, FRAC_PI_2 * j as f32 / rings as f32));
+    for (center, latitude) in bottom.chain(top) {
+        let (sin, cos) = latitude.sin_cos();
+        for i in 0..=segments {
+            let u = i as f32 / segments as f32;
+            let [x, z] = around(u);
+            let normal = [x * cos, sin, z * cos];
+            let pos = normal.map(|n| n * radius);
+            let pos = [pos[0], pos[1] + center, pos[2]];
+            let v = (pos[1] + length / 2.) / length;
+            mesh.vertices
+                .push(Vertex::new(pos, color).with_normal_uv(normal, [u, v]));
+        }
+    }
+
+    // Bands between rows, leaving out the triangles which would collapse onto a pole
+    let row = segments + 1;
+    let rows = 2 * (rings + 1);
+    for j in 0..rows - 1 {
+        let (lower, upper) = (j * row, (j + 1) * row);
+        for i in 0..segments {
+            let (a, b) = (lower + i, upper + i);
+            if j != 0 {
+                mesh.indices.extend([a, a + 1, b + 1]);
+            }
+            if j != rows - 2 {
+                mesh.indices.extend([a, b + 1, b]);
+            }
+        }
+    }
+
+    Ok(mesh)
+}
+
+/// Point on the unit circle `u` of the way around, counter-clockwise seen from above, as X and Z
+fn around(u: f32) -> [f32; 2] {
+    let (sin, cos) = (u * TAU).sin_cos();
+    [cos, -sin]
+}
+
+/// Quads facing outward between two rings of `segments + 1` vertices going around, the upper
+/// one starting at `upper`
+fn band(indices: &mut Vec<u32>, lower: u32, upper: u32, segments: u32) {
+    for i in 0..segments {
+        let (a, b) = (lower + i, upper + i);
+        indices.extend([a, a + 1, b + 1, a, b + 1, b]);
+    }
+}
+
+/// Flat disc closing a round shape at height `y`, facing up if above the origin and down if
+/// below it
+fn cap(mesh: &mut Mesh, radius: f32, y: f32, segments: u32, color: [f32; 3]) {
+    let up = y > 0.;
+    let normal = if up { UP } else { DOWN };
+    let center = mesh.vertices.len() as u32;
+    mesh.vertices
+        .push(Vertex::new([0., y, 0.], color).with_normal_uv(normal, [0.5, 0.5]));
+    for i in 0..segments {
+        let [x, z] = around(i as f32 / segments as f32);
+        let uv = [0.5 + x / 2., 0.5 + z / 2.];
+        mesh.vertices
+            .push(Vertex::new([x * radius, y, z * radius], color).with_normal_uv(normal, uv));
+    }
+
+    for i in 0..segments {
+        let a = center + 1 + i;
+        let b = center + 1 + (i + 1) % segments;
+        match up {
+            true => mesh.indices.extend([center, a, b]),
+            false => mesh.indices.extend([center, b, a]),
+        }
+    }
+}
+
+/// Sphere of `radius` around the origin facing inwards, to be seen from inside as a sky, with
+/// `stacks` bands from pole to pole split into `slices` around. Colors blend from
+/// `horizon_color` to `zenith_color` with the sine of the elevation, and everything below the
+/// horizon is `ground_color`, with a sharp edge between them. The vertices have no normals.
+pub fn sky_dome(
+    radius: f32,
+    stacks: u32,
+    slices: u32,
+    horizon_color: [f32; 3],
+    zenith_color: [f32; 3],
+    ground_color: [f32; 3],
+) -> Result<Mesh, ShapeError> {
+    check_dimension("radius", radius)?;
+    check_segments(slices)?;
+    if stacks < 2 {
+        return Err(ShapeError::TooFewStacks(stacks));
+    }
+
+    // Each hemisphere has its own rings, so that the horizon ring can take both colors
+    let upper = (stacks + 1) / 2;
+    let (horizon, zenith) = (Vector3::from(horizon_color), Vector3::from(zenith_color));
+    let mut mesh = hemisphere(radius, upper, slices, 0., FRAC_PI_2, |elevation| {
+        horizon.lerp(&zenith, elevation.sin()).into()
+    });
+    let lower = hemisphere(radius, stacks - upper, slices, -FRAC_PI_2, 0., |_| {
+        ground_color
+    });
+    mesh.append(&lower)?;
+    Ok(mesh)
+}
+
+/// Inward facing band of a sphere between two elevations, split into `stacks` bands and `slices`
+/// around, colored by elevation. Rings at a pole collapse to a point, skipping the triangles which
+/// would have no area.
+fn hemisphere(
+    radius: f32,
+    stacks: u32,
+    slices: u32,
+    from: f32,
+    to: f32,
+    color: impl Fn(f32) -> [f32; 3],
+) -> Mesh {
+    let mut vertices = vec![];
+    for i in 0..=stacks {
+        let elevation = from + (to - from) * i as f32 / stacks as f32;
+        let (y, across) = (elevation.sin() * radius, elevation.cos() * radius);
+        for j in 0..=slices {
+            let azimuth = TAU * j as f32 / slices as f32;
+            let pos = [across * azimuth.cos(), y, across * azimuth.sin()];
+            vertices.push(Vertex::new(pos, color(elevation)));
+        }
+    }
+
+    let is_pole = |elevation: f32| (elevation.abs() - FRAC_PI_2).abs() < 1e-6;
+    let n = slices + 1;
+    let mut indices = vec![];
+    for i in 0..stacks {
+        for j in 0..slices {
+            let a = i * n + j;
+            let (b, c, d) = (a + 1, a + n + 1, a + n);
+            // Counterclockwise from inside
+            if !(i == 0 && is_pole(from)) {
+                indices.extend([a, b, c]);
+            }
+            if !(i + 1 == stacks && is_pole(to)) {
+                indices.extend([a, c, d]);
+            }
+        }
+    }
+
+    Mesh { indices, vertices }
+}
+
+fn check_dimension(name: &'static str, value: f32) -> Result<(), ShapeError> {
+    match value > 0. && value.is_finite() {
+        true => Ok(()),
+        false => Err(ShapeError::BadDimension { name, value }),
+    }
+}
+
+/// Grids need at least one quad along each side, and indices must fit in u32 and be counted in
+/// u32 too
+fn check_resolution(resolution: u32) -> Result<(), ShapeError> {
+    let index_count = resolution
+        .checked_mul(resolution)
+        .and_then(|quads| quads.checked_mul(6));
+    match resolution > 0 && index_count.is_some() {
+        true => Ok(()),
+        false => Err(ShapeError::BadResolution(resolution)),
+    }
+}
+
+fn check_segments(segments: u32) -> Result<(), ShapeError> {
+    match segments >= MIN_SEGMENTS {
+        true => Ok(()),
+        false => Err(ShapeError::TooFewSegments(segments)),
+    }
+}
+
+impl HeightQuery for Heightfield {
+    fn ground_height(&self, x: f32, z: f32) -> Option<f32> {
+        self.height_at(x, z)
+    }
+}
+
+/// Hills spanning [-extent, extent] on X and Z with `resolution` quads along each side, shaped by
+/// layers of value noise from `seed`. Colored green in the valleys, brown on the slopes and
+/// white on the peaks. Returns the heights too, for standing on.
+pub fn terrain(extent: f32, resolution: u32, seed: u32) -> Result<(Mesh, Heightfield), ShapeError> {
+    check_dimension("extent", extent)?;
+    check_resolution(resolution)?;
+
+    // The extent is already checked, so only the resolution can be refused
+    let field = Heightfield::generate(extent, resolution, seed)
+        .map_err(|_| ShapeError::BadResolution(resolution))?;
+    let n = resolution + 1;
+    let step = 2. * extent / resolution as f32;
+
+    let mut vertices = vec![];
+    for j in 0..n {
+        for i in 0..n {
+            let pos = field.point(i, j);
+
+            // Normal from the slope between the neighbors, or to the edge where there are none
+            let (left, right) = (i.saturating_sub(1), (i + 1).min(resolution));
+            let (back, front) = (j.saturating_sub(1), (j + 1).min(resolution));
+            let dx =
+                (field.height(right, j) - field.height(left, j)) / ((right - left) as f32 * step);
+            let dz =
+                (field.height(i, front) - field.height(i, back)) / ((front - back) as f32 * step);
+            let normal = Vector3::new(-dx, 1., -dz).normalize();
+
+            let color = terrain_color(pos[1] / TERRAIN_AMPLITUDE);
+            vertices.push(Vertex::new(pos, color).with_normal_uv(normal.into(), [pos[0], pos[2]]));
+        }
+    }
+
+    let mut indices = vec![];
+    for j in 0..resolution {
+        for i in 0..resolution {
+            let a = j * n + i;
+            let b = a + 1;
+            let c = a + n;
+            let d = c + 1;
+            indices.extend([a, c, d, a, d, b]);
+        }
+    }
+
+    Ok((Mesh { indices, vertices }, field))
+}
+
+/// Color of terrain at `altitude`, from -1 at the lowest to 1 at the highest
+fn terrain_color(altitude: f32) -> [f32; 3] {
+    const RAMP: [(f32, [f32; 3]); 4] = [
+        (-0.6, [0.15, 0.35, 0.1]),
+        (0., [0.25, 0.45, 0.12]),
+        (0.35, [0.4, 0.3, 0.18]),
+        (0.6, [0.9, 0.9, 0.92]),
+    ];
+    let altitude = altitude.clamp(RAMP[0].0, RAMP[RAMP.len() - 1].0);
+    let upper = RAMP
+        .iter()
+        .position(|&(at, _)| at >= altitude)
+        .unwrap_or(0)
+        .max(1);
+    let ((a, from), (b, to)) = (RAMP[upper - 1], RAMP[upper]);
+    let t = (altitude - a) / (b - a);
+    [0, 1, 2].map(|k| from[k] + (to[k] - from[k]) * t)
+}
+
+/// Most cells along each side of a maze, bounding the size of its mesh
+const MAX_MAZE_CELLS: u32 = 100;
+
+/// Thickness of maze walls, as a fraction of the cell size
+const MAZE_WALL_THICKNESS: f32 = 0.1;
+
+/// Linear colors of maze walls and floor
+const MAZE_WALL_COLOR: [f32; 3] = [0.55, 0.5, 0.45];
+const MAZE_FLOOR_COLOR: [f32; 3] = [0.2, 0.25, 0.2];
+
+/// Layout of a perfect maze, in which exactly one path leads between any two cells. Cells are
+/// numbered from the corner at -X and -Z.
+#[derive(Clone, Debug)]
+pub struct Maze {
+    cells_x: u32,
+    cells_z: u32,
+    /// Whether each cell opens onto its neighbor at +X, row by row along Z
+    open_x: Vec<bool>,
+    /// Whether each cell opens onto its neighbor at +Z
+    open_z: Vec<bool>,
+}
+
+impl Maze {
+    /// Carve a maze of `cells_x` by `cells_z` cells by randomized depth first search from the
+    /// corner, the same every time for the same seed
+    pub fn generate(cells_x: u32, cells_z: u32, seed: u32) -> Result<Self, ShapeError> {
+        let sizes = [cells_x, cells_z];
+        if sizes
+            .iter()
+            .any(|&cells| cells == 0 || cells > MAX_MAZE_CELLS)
+        {
+            return Err(ShapeError::BadMazeSize { cells_x, cells_z });
+        }
+
+        let count = (cells_x * cells_z) as usize;
+        let mut maze = Self {
+            cells_x,
+            cells_z,
+            open_x: vec![false; count],
+            open_z: vec![false; count],
+        };
+        let mut visited = vec![false; count];
+        let mut rng = seed;
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+
+        while let Some(&(x, z)) = stack.last() {
+            let neighbors: Vec<(u32, u32)> = [
+                (x.wrapping_sub(1), z),
+                (x + 1, z),
+                (x, z.wrapping_sub(1)),
+                (x, z + 1),
+            ]
+            .into_iter()
+            .filter(|&(nx, nz)| nx < cells_x && nz < cells_z && !visited[maze.index(nx, nz)])
+            .collect();
+
+            // Back up once every neighbor has been reached
+            if neighbors.is_empty() {
+                stack.pop();
+                continue;
+            }
+
+            rng = next_random(rng);
+            let (nx, nz) = neighbors[rng as usize % neighbors.len()];
+            let (lower_x, lower_z) = (x.min(nx), z.min(nz));
+            let lower = maze.index(lower_x, lower_z);
+            match nx != x {
+                true => maze.open_x[lower] = true,
+                false => maze.open_z[lower] = true,
+            }
+            visited[maze.index(nx, nz)] = true;
+            stack.push((nx, nz));
+        }
+
+        Ok(maze)
+    }
+
+    /// Number of cells along X and Z
+    pub fn size(&self) -> (u32, u32) {
+        (self.cells_x, self.cells_z)
+    }
+
+    /// Whether cell (x, z) opens onto its neighbor at +X. Never at the edge.
+    pub fn open_x(&self, x: u32, z: u32) -> bool {
+        x + 1 < self.cells_x && self.open_x[self.index(x, z)]
+    }
+
+    /// Whether cell (x, z) opens onto its neighbor at +Z. Never at the edge.
+    pub fn open_z(&self, x: u32, z: u32) -> bool {
+        z + 1 < self.cells_z && self.open_z[self.index(x, z)]
+    }
+
+    /// The cell players start in, in the middle of the maze
+    pub fn spawn_cell(&self) -> (u32, u32) {
+        (self.cells_x / 2, self.cells_z / 2)
+    }
+
+    /// Middle of the floor of cell (x, z), laid out as `maze_mesh` does with `cell_size` meter
+    /// cells
+    pub fn cell_center(&self, x: u32, z: u32, cell_size: f32) -> Point3<f32> {
+        let center = |cell: u32, cells: u32| (cell as f32 + 0.5 - cells as f32 / 2.) * cell_size;
+        Point3::new(center(x, self.cells_x), 0., center(z, self.cells_z))
+    }
+
+    fn index(&self, x: u32, z: u32) -> usize {
+        (z * self.cells_x + x) as usize
+    }
+}
+
+/// Walls of `maze` `wall_height` meters tall on a floor, with square cells of `cell_size`
+/// meters, centered on the origin
+pub fn maze_mesh(maze: &Maze, cell_size: f32, wall_height: f32) -> Result<Mesh, ShapeError> {
+    check_dimension("cell size", cell_size)?;
+    check_dimension("wall height", wall_height)?;
+
+    let (cells_x, cells_z) = maze.size();
+    let (x0, z0) = (
+        -(cells_x as f32) * cell_size / 2.,
+        -(cells_z as f32) * cell_size / 2.,
+    );
+    let (x1, z1) = (-x0, -z0);
+    let floor = Mesh {
+        indices: vec![0, 1, 2, 0, 2, 3],
+        vertices: [[x0, z0], [x0, z1], [x1, z1], [x1, z0]]
+            .into_iter()
+            .map(|[x, z]| Vertex::new([x, 0., z], MAZE_FLOOR_COLOR).with_normal_uv(UP, [x, z]))
+            .collect(),
+    };
+
+    // Each wall runs along one side of a cell, reaching half its thickness past the corners so
+    // that walls meet
+    let half = MAZE_WALL_THICKNESS * cell_size / 2.;
+    let edge = |cells: u32, start: f32| start + cells as f32 * cell_size;
+    let mut walls = vec![];
+    for z in 0..=cells_z {
+        for x in 0..cells_x {
+            if z == 0 || z == cells_z || !maze.open_z(x, z - 1) {
+                let (xa, xb, zc) = (edge(x, x0), edge(x + 1, x0), edge(z, z0));
+                let min = [xa - half, 0., zc - half];
+                walls.push(block(
+                    min,
+                    [xb + half, wall_height, zc + half],
+                    MAZE_WALL_COLOR,
+                ));
+            }
+        }
+    }
+    for x in 0..=cells_x {
+        for z in 0..cells_z {
+            if x == 0 || x == cells_x || !maze.open_x(x - 1, z) {
+                let (za, zb, xc) = (edge(z, z0), edge(z + 1, z0), edge(x, x0));
+                let min = [xc - half, 0., za - half];
+                walls.push(block(
+                    min,
+                    [xc + half, wall_height, zb + half],
+                    MAZE_WALL_COLOR,
+                ));
+            }
+        }
+    }
+
+    let mesh = walls
+        .iter()
+        .try_fold(MeshBuilder::new(floor), MeshBuilder::append)?;
+    Ok(mesh.build())
+}
+
+/// A maze generated from `seed` and its mesh, as `Maze::generate` and `maze_mesh`
+pub fn maze(
+    cells_x: u32,
+    cells_z: u32,
+    cell_size: f32,
+    wall_height: f32,
+    seed: u32,
+) -> Result<(Mesh, Maze), ShapeError> {
+    let maze = Maze::generate(cells_x, cells_z, seed)?;
+    let mesh = maze_mesh(&maze, cell_size, wall_height)?;
+    Ok((mesh, maze))
+}
+
+/// Axis aligned box from `min` to `max` in one color
+fn block(min: [f32; 3], max: [f32; 3], color: [f32; 3]) -> Mesh {
+    let (min, max) = (Point3::from(min), Point3::from(max));
+    let center = nalgebra::center(&min, &max);
+    let scale = Matrix4::new_nonuniform_scaling(&((max - min) / 2.));
+    let mut mesh = rgb_cube(1.);
+    mesh.transform(&(Matrix4::new_translation(&center.coords) * scale));
+    mesh.recolor(color);
+    mesh
+}
+
+/// Corners of an axis aligned box, min then max
+pub type Bounds = (Point3<f32>, Point3<f32>);
+
+/// Side of the square cells props are scattered on, each holding at most one prop
+const PROP_CELL_SIZE: f32 = 4.;
+
+/// Gap kept between a prop and the edges of its cell, so that props in neighboring cells never
+/// touch
+const PROP_MARGIN: f32 = 0.5;
+
+/// Cells within this distance of the origin are left empty, for players to spawn in
+const PROP_CLEARANCE: f32 = 4.;
+
+/// Linear colors of the props
+const PROP_FLOOR_COLOR: [f32; 3] = [0.3, 0.32, 0.3];
+const COLUMN_COLOR: [f32; 3] = [0.7, 0.68, 0.62];
+const BOX_COLOR: [f32; 3] = [0.55, 0.4, 0.25];
+
+/// Landmark colors at +X, -X, +Z and -Z, telling the edges of the map apart
+const LANDMARK_COLORS: [[f32; 3]; 4] = [
+    [0.8, 0.15, 0.1],
+    [0.1, 0.6, 0.7],
+    [0.15, 0.25, 0.8],
+    [0.8, 0.7, 0.1],
+];
+const LANDMARK_RADIUS: f32 = 0.5;
+const LANDMARK_HEIGHT: f32 = 10.;
+
+/// Ground spanning [-extent, extent] on X and Z scattered with columns and boxes to judge
+/// distances by, and a tall colored landmark near the middle of each edge. The layout is the
+/// same every time for the same seed. Props are placed at most one per cell of a grid by
+/// rejection sampling, so they never overlap, and the middle is kept clear. Also returns the
+/// bounds of every prop.
+pub fn props_map(extent: f32, seed: u32) -> Result<(Mesh, Vec<Bounds>), ShapeError> {
+    check_dimension("extent", extent)?;
+    let mut ground = big_quad_map_subdivided(extent, (extent * 2.).ceil() as u32)?;
+    ground.recolor(PROP_FLOOR_COLOR);
+
+    let cells = (extent * 2. / PROP_CELL_SIZE).floor() as u32;
+    let start = -(cells as f32) * PROP_CELL_SIZE / 2.;
+    let center = |cell: u32| start + (cell as f32 + 0.5) * PROP_CELL_SIZE;
+    let mut occupied = vec![false; (cells * cells) as usize];
+    let mut props = vec![];
+    let mut bounds = vec![];
+
+    // Landmarks go in first, so that they are always there
+    let mid = cells / 2;
+    let edges = [
+        (cells.wrapping_sub(1), mid),
+        (0, mid),
+        (mid, cells.wrapping_sub(1)),
+        (mid, 0),
+    ];
+    for ((x, z), color) in edges.into_iter().zip(LANDMARK_COLORS) {
+        if x >= cells || z >= cells || occupied[(z * cells + x) as usize] {
+            continue;
+        }
+        occupied[(z * cells + x) as usize] = true;
+        let (mesh, prop) = column(
+            center(x),
+            center(z),
+            LANDMARK_RADIUS,
+            LANDMARK_HEIGHT,
+            color,
+        )?;
+        props.push(mesh);
+        bounds.push(prop);
+    }
+
+    let mut rng = seed;
+    let mut random = || {
+        rng = next_random(rng);
+        (rng >> 8) as f32 / (1 << 24) as f32
+    };
+    let half_cell = PROP_CELL_SIZE / 2. - PROP_MARGIN;
+    for _ in 0..cells * cells / 2 {
+        let x = ((random() * cells as f32) as u32).min(cells - 1);
+        let z = ((random() * cells as f32) as u32).min(cells - 1);
+        let (cx, cz) = (center(x), center(z));
+        let index = (z * cells + x) as usize;
+        if occupied[index] || cx.hypot(cz) < PROP_CLEARANCE {
+            continue;
+        }
+        occupied[index] = true;
+
+        // Sizes are picked first, then the prop is moved about within what's left of its cell
+        let (mesh, prop) = if random() < 0.4 {
+            let radius = 0.15 + random() * 0.25;
+            let height = 1.5 + random() * 2.5;
+            let room = half_cell - radius;
+            let (px, pz) = (room * (random() * 2. - 1.), room * (random() * 2. - 1.));
+            column(cx + px, cz + pz, radius, height, COLUMN_COLOR)?
+        } else {
+            let half = [0.3 + random() * 0.9, 0.3 + random() * 0.9];
+            let height = 0.5 + random() * 2.;
+            let offset = half.map(|h| (half_cell - h) * (random() * 2. - 1.));
+            let min = [cx + offset[0] - half[0], 0., cz + offset[1] - half[1]];
+            let max = [cx + offset[0] + half[0], height, cz + offset[1] + half[1]];
+            let shade = 0.8 + random() * 0.4;
+            let color = BOX_COLOR.map(|c| c * shade);
+            (block(min, max, color), (min.into(), max.into()))
+        };
+        props.push(mesh);
+        bounds.push(prop);
+    }
+
+    let mesh = props
+        .iter()
+        .try_fold(MeshBuilder::new(ground), MeshBuilder::append)?;
+    Ok((mesh.build(), bounds))
+}
+
+/// Rings of points searched around the start for a place to spawn
+const SPAWN_RINGS: u32 = 16;
+
+/// Point nearest `start` on the XZ plane where a circle of `radius` is clear of every one of
+/// `bounds`, searching outwards in rings `radius` apart. Falls back to `start` when nowhere
+/// nearby is clear.
+pub fn clear_spawn(bounds: &[Bounds], start: Point3<f32>, radius: f32) -> Point3<f32> {
+    let clear = |p: &Point3<f32>| {
+        bounds.iter().all(|(min, max)| {
+            p.x <= min.x - radius
+                || p.x >= max.x + radius
+                || p.z <= min.z - radius
+                || p.z >= max.z + radius
+        })
+    };
+    if clear(&start) {
+        return start;
+    }
+    for ring in 1..=SPAWN_RINGS {
+        let distance = ring as f32 * radius;
+        let points = ring * 8;
+        let found = (0..points)
+            .map(|i| {
+                let angle = i as f32 / points as f32 * TAU;
+                start + Vector3::new(angle.cos(), 0., angle.sin()) * distance
+            })
+            .find(clear);
+        if let Some(point) = found {
+            return point;
+        }
+    }
+    start
+}
+
+/// Thickness of the walls of rooms in meters, centered on the edges of each room
+const ROOM_WALL_THICKNESS: f32 = 0.1;
+
+/// Linear colors of the floors, walls and ceilings of rooms
+const ROOM_FLOOR_COLOR: [f32; 3] = [0.35, 0.3, 0.25];
+const ROOM_WALL_COLOR: [f32; 3] = [0.75, 0.72, 0.65];
+const ROOM_CEILING_COLOR: [f32; 3] = [0.9, 0.9, 0.9];
+
+/// Floors, walls and ceilings of the rooms in `spec`, with openings for the doors, and the bounds
+/// of each piece of wall. The spec should have passed `RoomsSpec::validate`; doors which don't
+/// fit are cut off at the ends of their walls.
+pub fn rooms_map(spec: &RoomsSpec) -> Result<(Mesh, Vec<Bounds>), ShapeError> {
+    let half = ROOM_WALL_THICKNESS / 2.;
+    let mut pieces = vec![];
+    let mut walls = vec![];
+    for room in &spec.rooms {
+        check_dimension("room height", room.height)?;
+        let ([x0, z0], [x1, z1]) = (room.min, room.max);
+        pieces.push(room_quad(room.min, room.max, 0., UP, ROOM_FLOOR_COLOR));
+        pieces.push(room_quad(
+            room.min,
+            room.max,
+            room.height,
+            DOWN,
+            ROOM_CEILING_COLOR,
+        ));
+
+        for (side, edge) in [
+            (Side::North, z0),
+            (Side::South, z1),
+            (Side::East, x1),
+            (Side::West, x0),
+        ] {
+            // Walls reach half their thickness past the corners, so that they meet
+            let (lo, hi) = room.wall_span(side);
+            let (lo, hi) = (lo - half, hi + half);
+
+            // Solid from the floor up between the doors, and above each door
+            let mut doors: Vec<_> = room
+                .doors
+                .iter()
+                .filter(|door| door.side == side)
+                .map(|door| (room.door_span(door), door.height))
+                .collect();
+            doors.sort_by(|a, b| a.0 .0.total_cmp(&b.0 .0));
+            let mut spans = vec![];
+            let mut start = lo;
+            for &((door_start, door_end), door_height) in &doors {
+                let (door_start, door_end) = (door_start.max(lo), door_end.min(hi));
+                spans.push((start, door_start, 0.));
+                spans.push((door_start, door_end, door_height));
+                start = start.max(door_end);
+            }
+            spans.push((start, hi, 0.));
+
+            for (from, to, bottom) in spans {
+                if to <= from || bottom >= room.height {
+                    continue;
+                }
+                let (min, max) = match side {
+                    Side::North | Side::South => {
+                        ([from, bottom, edge - half], [to, room.height, edge + half])
+                    }
+                    Side::East | Side::West => {
+                        ([edge - half, bottom, from], [edge + half, room.height, to])
+                    }
+                };
+                pieces.push(block(min, max, ROOM_WALL_COLOR));
+                walls.push((min.into(), max.into()));
+            }
+        }
+    }
+
+    let empty = Mesh {
+        indices: vec![],
+        vertices: vec![],
+    };
+    let mesh = pieces
+        .iter()
+        .try_fold(MeshBuilder::new(empty), MeshBuilder::append)?;
+    Ok((mesh.build(), walls))
+}
+
+/// Horizontal quad between the (x, z) corners `min` and `max` at height `y`, facing along
+/// `normal`
+fn room_quad(min: [f32; 2], max: [f32; 2], y: f32, normal: [f32; 3], color: [f32; 3]) -> Mesh {
+    let ([x0, z0], [x1, z1]) = (min, max);
+    let indices = match normal[1] > 0. {
+        true => vec![0, 1, 2, 0, 2, 3],
+        false => vec![0, 2, 1, 0, 3, 2],
+    };
+    let vertices = [[x0, z0], [x0, z1], [x1, z1], [x1, z0]]
+        .into_iter()
+        .map(|[x, z]| Vertex::new([x, y, z], color).with_normal_uv(normal, [x, z]))
+        .collect();
+    Mesh { indices, vertices }
+}
+
+/// Column of `radius` standing on the ground at (`x`, `z`), and its bounds
+fn column(
+    x: f32,
+    z: f32,
+    radius: f32,
+    height: f32,
+    color: [f32; 3],
+) -> Result<(Mesh, Bounds), ShapeError> {
+    let mut mesh = cylinder(radius, height, 16, color)?;
+    mesh.transform(&Matrix4::new_translation(&Vector3::new(x, height / 2., z)));
+    let bounds = (
+        Point3::new(x - radius, 0., z - radius),
+        Point3::new(x + radius, height, z + radius),
+    );
+    Ok((mesh, bounds))
+}
+
+/// Next of a sequence of pseudorandom numbers, by xorshift
+fn next_random(state: u32) -> u32 {
+    // Zero would stay zero
+    let mut x = state.max(1);
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::{DoorSpec, RoomSpec};
+
+    /// Check that every triangle of a convex mesh around the origin faces out, the same way as
+    /// the normals of its vertices, and that those normals are unit length
+    fn check_outward(mesh: &Mesh) {
+        assert_eq!(mesh.validate(), Ok(()));
+        for vertex in &mesh.vertices {
+            assert!((vertex.normal.norm() - 1.).abs() < 1e-5, "{:?}", vertex);
+        }
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize]);
+            let face = (b.pos - a.pos).cross(&(c.pos - a.pos));
+            assert!(face.norm() > 1e-6, "degenerate {:?}", triangle);
+            let centroid = (a.pos.coords + b.pos.coords + c.pos.coords) / 3.;
+            assert!(face.dot(&centroid) > 0., "inwards {:?}", triangle);
+            for vertex in [a, b, c] {
+                assert!(
+                    face.dot(&vertex.normal) > 0.,
+                    "against normals {:?}",
+                    triangle
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cylinders_are_closed_and_face_out() {
+        let mesh = cylinder(0.5, 2., 12, [1.; 3]).unwrap();
+        check_outward(&mesh);
+        assert_eq!(mesh.vertices.len(), 4 * 12 + 4);
+        assert_eq!(mesh.indices.len(), 12 * 12);
+        for vertex in &mesh.vertices {
+            let radius = vertex.pos.x.hypot(vertex.pos.z);
+            assert!(radius < 0.5 + 1e-5 && vertex.pos.y.abs() < 1. + 1e-5);
+            // On the side, or on a cap
+            assert!((radius - 0.5).abs() < 1e-5 || (vertex.pos.y.abs() - 1.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn cones_are_closed_and_face_out() {
+        let mesh = cone(0.5, 2., 12, [1.; 3]).unwrap();
+        check_outward(&mesh);
+        assert_eq!(mesh.vertices.len(), 3 * 12 + 2);
+        assert_eq!(mesh.indices.len(), 6 * 12);
+        let tip = mesh
+            .vertices
+            .iter()
+            .filter(|v| v.pos == Point3::new(0., 1., 0.));
+        assert_eq!(tip.count(), 12);
+    }
+
+    #[test]
+    fn capsules_are_round_around_their_axis() {
+        let mesh = capsule(0.5, 2., 12, 4, [1.; 3]).unwrap();
+        check_outward(&mesh);
+        assert_eq!(mesh.vertices.len(), 2 * 5 * 13);
+        assert_eq!(mesh.indices.len(), 12 * 4 * 12);
+        for vertex in &mesh.vertices {
+            // Every point is a radius from the nearest point on the axis, along its normal
+            let axis = Point3::new(0., vertex.pos.y.clamp(-1., 1.), 0.);
+            let out = vertex.pos - axis;
+            assert!((out.norm() - 0.5).abs() < 1e-5);
+            assert!((out / 0.5 - vertex.normal).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn round_shapes_check_their_sizes() {
+        let color = [1.; 3];
+        assert_eq!(
+            cylinder(0., 1., 8, color).err(),
+            Some(ShapeError::BadDimension {
+                name: "radius",
+                value: 0.
+            })
+        );
+        assert_eq!(
+            cone(1., -1., 8, color).err(),
+            Some(ShapeError::BadDimension {
+                name: "height",
+                value: -1.
+            })
+        );
+        assert!(matches!(
+            capsule(f32::INFINITY, 1., 8, 2, color),
+            Err(ShapeError::BadDimension { name: "radius", .. })
+        ));
+        assert_eq!(
+            cylinder(1., 1., 2, color).err(),
+            Some(ShapeError::TooFewSegments(2))
+        );
+        assert_eq!(
+            cone(1., 1., 0, color).err(),
+            Some(ShapeError::TooFewSegments(0))
+        );
+        assert_eq!(
+            capsule(1., 1., 8, 0, color).err(),
+            Some(ShapeError::TooFewRings(0))
+        );
+        // The fewest segments and rings still make closed shapes
+        check_outward(&cylinder(1., 1., MIN_SEGMENTS, color).unwrap());
+        check_outward(&cone(1., 1., MIN_SEGMENTS, color).unwrap());
+        check_outward(&capsule(1., 1., MIN_SEGMENTS, 1, color).unwrap());
+    }
+
+    const LIGHT: [f32; 3] = [1., 1., 1.];
+    const DARK: [f32; 3] = [0., 0., 0.];
+
+    /// Corner of each tile of a checkerboard nearest -X and -Z, with its color
+    fn tiles(mesh: &Mesh) -> Vec<(Point3<f32>, Vector3<f32>)> {
+        mesh.vertices
+            .chunks(4)
+            .map(|tile| (tile[0].pos, tile[0].color))
+            .collect()
+    }
+
+    #[test]
+    fn checkerboards_alternate_colors() {
+        let mesh = checkerboard(2., 1., LIGHT, DARK);
+        assert_eq!(mesh.validate(), Ok(()));
+        assert_eq!(mesh.vertices.len(), 16 * 4);
+        assert_eq!(mesh.indices.len(), 16 * 6);
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize].pos);
+            assert!((b - a).cross(&(c - a)).y > 0.);
+        }
+
+        let tiles = tiles(&mesh);
+        assert_eq!(tiles[0], (Point3::new(-2., 0., -2.), Vector3::from(LIGHT)));
+        assert_eq!(tiles[1], (Point3::new(-2., 0., -1.), Vector3::from(DARK)));
+        assert_eq!(tiles[4], (Point3::new(-1., 0., -2.), Vector3::from(DARK)));
+        assert_eq!(tiles[5], (Point3::new(-1., 0., -1.), Vector3::from(LIGHT)));
+        for (corner, _) in tiles {
+            assert!(corner.x >= -2. && corner.x < 2. && corner.z >= -2. && corner.z < 2.);
+        }
+    }
+
+    #[test]
+    fn checkerboards_overhang_to_whole_tiles() {
+        // Five meters needs three two meter tiles, hanging half a meter over each side
+        let mesh = checkerboard(2.5, 2., LIGHT, DARK);
+        assert_eq!(mesh.vertices.len(), 9 * 4);
+        assert_eq!(tiles(&mesh)[0].0, Point3::new(-3., 0., -3.));
+        assert_eq!(mesh.vertices.last().unwrap().pos, Point3::new(3., 0., 1.));
+
+        // Too small for one tile still gets one
+        let mesh = checkerboard(0.1, 1., LIGHT, DARK);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.vertices[0].pos, Point3::new(-0.5, 0., -0.5));
+    }
+
+    #[test]
+    fn checkerboards_are_limited_in_tiles() {
+        for tile_size in [0.001, 0., f32::NAN] {
+            let mesh = checkerboard(10., tile_size, LIGHT, DARK);
+            let count = (MAX_CHECKER_TILES * MAX_CHECKER_TILES) as usize;
+            assert_eq!(mesh.vertices.len(), count * 4);
+            assert_eq!(mesh.validate(), Ok(()));
+            // Made larger to cover the same ground
+            let tiles = tiles(&mesh);
+            assert_eq!(tiles[0].0, Point3::new(-10., 0., -10.));
+            let last = mesh.vertices.last().unwrap().pos;
+            assert!((last - Point3::new(10., 0., 10. - 20. / 512.)).norm() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn terrain_stands_on_its_heightfield() {
+        let (mesh, field) = terrain(10., 8, 7).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        assert_eq!(mesh.vertices.len(), 81);
+        assert_eq!(mesh.indices.len(), 8 * 8 * 6);
+        for vertex in &mesh.vertices {
+            let height = field.height_at(vertex.pos.x, vertex.pos.z).unwrap();
+            assert!((vertex.pos.y - height).abs() < 1e-4);
+            assert!((vertex.normal.norm() - 1.).abs() < 1e-5);
+            assert!(vertex.normal.y > 0.);
+        }
+
+        let (again, _) = terrain(10., 8, 7).unwrap();
+        assert!(mesh
+            .vertices
+            .iter()
+            .zip(&again.vertices)
+            .all(|(a, b)| a.pos == b.pos));
+    }
+
+    #[test]
+    fn terrain_rejects_bad_sizes() {
+        assert_eq!(terrain(10., 0, 7).err(), Some(ShapeError::BadResolution(0)));
+        assert_eq!(terrain(10., 1, 7).err(), Some(ShapeError::BadResolution(1)));
+        assert_eq!(
+            terrain(10., 1 << 16, 7).err(),
+            Some(ShapeError::BadResolution(1 << 16))
+        );
+        assert!(matches!(
+            terrain(-1., 8, 7),
+            Err(ShapeError::BadDimension { .. })
+        ));
+    }
+
+    /// Cells reachable from the corner through the openings of `maze`
+    fn reachable(maze: &Maze) -> usize {
+        let (cells_x, cells_z) = maze.size();
+        let mut seen = vec![false; (cells_x * cells_z) as usize];
+        let mut stack = vec![(0, 0)];
+        seen[0] = true;
+        while let Some((x, z)) = stack.pop() {
+            let mut neighbors = vec![];
+            if maze.open_x(x, z) {
+                neighbors.push((x + 1, z));
+            }
+            if maze.open_z(x, z) {
+                neighbors.push((x, z + 1));
+            }
+            if x > 0 && maze.open_x(x - 1, z) {
+                neighbors.push((x - 1, z));
+            }
+            if z > 0 && maze.open_z(x, z - 1) {
+                neighbors.push((x, z - 1));
+            }
+            for (nx, nz) in neighbors {
+                if !std::mem::replace(&mut seen[(nz * cells_x + nx) as usize], true) {
+                    stack.push((nx, nz));
+                }
+            }
+        }
+        seen.iter().filter(|&&seen| seen).count()
+    }
+
+    /// Openings between cells of `maze`
+    fn openings(maze: &Maze) -> usize {
+        let (cells_x, cells_z) = maze.size();
+        (0..cells_z)
+            .flat_map(|z| (0..cells_x).map(move |x| (x, z)))
+            .map(|(x, z)| maze.open_x(x, z) as usize + maze.open_z(x, z) as usize)
+            .sum()
+    }
+
+    #[test]
+    fn mazes_reach_every_cell_by_one_path() {
+        for (cells_x, cells_z, seed) in [(1, 1, 0), (11, 11, 1), (7, 3, 2), (1, 20, 3)] {
+            let maze = Maze::generate(cells_x, cells_z, seed).unwrap();
+            let cells = (cells_x * cells_z) as usize;
+            assert_eq!(reachable(&maze), cells);
+            // A tree has one opening fewer than cells, so there are no loops
+            assert_eq!(openings(&maze), cells - 1);
+        }
+    }
+
+    #[test]
+    fn mazes_are_the_same_for_the_same_seed() {
+        let layout = |maze: Maze| (maze.open_x, maze.open_z);
+        let maze = layout(Maze::generate(9, 9, 5).unwrap());
+        assert_eq!(maze, layout(Maze::generate(9, 9, 5).unwrap()));
+        assert_ne!(maze, layout(Maze::generate(9, 9, 6).unwrap()));
+    }
+
+    #[test]
+    fn players_spawn_in_the_middle_cell() {
+        let maze = Maze::generate(3, 5, 0).unwrap();
+        assert_eq!(maze.spawn_cell(), (1, 2));
+        assert_eq!(maze.cell_center(1, 2, 2.), Point3::origin());
+        assert_eq!(maze.cell_center(0, 0, 2.), Point3::new(-2., 0., -4.));
+        assert_eq!(maze.cell_center(2, 4, 2.), Point3::new(2., 0., 4.));
+
+        // Off the middle of a cell for an even number of them, rather than on a wall
+        let maze = Maze::generate(4, 4, 0).unwrap();
+        let (x, z) = maze.spawn_cell();
+        assert_eq!(maze.cell_center(x, z, 2.), Point3::new(1., 0., 1.));
+    }
+
+    #[test]
+    fn maze_sizes_are_checked() {
+        for (cells_x, cells_z) in [(0, 5), (5, 0), (MAX_MAZE_CELLS + 1, 5)] {
+            assert_eq!(
+                Maze::generate(cells_x, cells_z, 0).err(),
+                Some(ShapeError::BadMazeSize { cells_x, cells_z })
+            );
+        }
+        let maze = Maze::generate(MAX_MAZE_CELLS, MAX_MAZE_CELLS, 0).unwrap();
+        assert_eq!(maze_mesh(&maze, 2., 3.).unwrap().validate(), Ok(()));
+    }
+
+    #[test]
+    fn recomputed_flat_normals_of_a_cube_face_each_side() {
+        let cube = rgb_cube(1.);
+        let mut mesh = Mesh {
+            indices: cube.indices.clone(),
+            vertices: cube
+                .vertices
+                .iter()
+                .map(|v| Vertex {
+                    normal: Vector3::zeros(),
+                    ..*v
+                })
+                .collect(),
+        };
+        mesh.compute_normals(NormalMode::Flat, false);
+        assert_eq!(mesh.vertices.len(), cube.indices.len());
+        assert_eq!(mesh.validate(), Ok(()));
+        for (vertex, &i) in mesh.vertices.iter().zip(&cube.indices) {
+            let original = cube.vertices[i as usize];
+            assert_eq!(vertex.pos, original.pos);
+            assert!(
+                (vertex.normal - original.normal).norm() < 1e-6,
+                "{:?}",
+                vertex
+            );
+        }
+    }
+
+    /// Positions of the vertices of `mesh` in `color`
+    fn colored(mesh: &Mesh, color: [f32; 3]) -> Vec<Point3<f32>> {
+        let color = Vector3::from(color);
+        let vertices = mesh.vertices.iter().filter(|v| v.color == color);
+        vertices.map(|v| v.pos).collect()
+    }
+
+    #[test]
+    fn axis_gizmos_point_an_arrow_of_each_color_along_each_axis() {
+        let mesh = axis_gizmo(1., 0.02).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize]);
+            let face = (b.pos - a.pos).cross(&(c.pos - a.pos));
+            for vertex in [a, b, c] {
+                assert!((vertex.normal.norm() - 1.).abs() < 1e-5, "{:?}", vertex);
+                assert!(
+                    face.dot(&vertex.normal) > 0.,
+                    "against normals {:?}",
+                    triangle
+                );
+            }
+        }
+
+        let arms = [(0, [1., 0., 0.]), (1, [0., 1., 0.]), (2, [0., 0., 1.])];
+        let count = colored(&mesh, arms[0].1).len();
+        assert_eq!(count * 3, mesh.vertices.len());
+        for (axis, color) in arms {
+            let arm = colored(&mesh, color);
+            assert_eq!(arm.len(), count);
+            for p in &arm {
+                assert!((-1e-5..=1. + 1e-5).contains(&p[axis]), "{:?}", p);
+                let off = (0..3).filter(|&i| i != axis).map(|i| p[i].abs());
+                // The arrowhead is twice as wide as the arm
+                assert!(off.fold(0., f32::max) <= 0.02 + 1e-5, "{:?}", p);
+            }
+
+            // The tip of the arrowhead is at the end of the axis
+            let tip = arm
+                .iter()
+                .max_by(|a, b| a[axis].total_cmp(&b[axis]))
+                .unwrap();
+            let mut expected = Point3::origin();
+            expected[axis] = 1.;
+            assert!((tip - expected).norm() < 1e-5, "{:?}", tip);
+        }
+    }
+
+    #[test]
+    fn origin_markers_lie_flat_facing_up() {
+        let mesh = origin_grid_marker(0.5, 0.02).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        assert_eq!(mesh.indices.len(), 2 * 6);
+        for vertex in &mesh.vertices {
+            assert!((vertex.pos.y - 0.001).abs() < 1e-6);
+            assert_eq!(vertex.normal, Vector3::y());
+            let [x, z] = [vertex.pos.x.abs(), vertex.pos.z.abs()];
+            // At the end of an arm, along one side of it
+            assert!(
+                (x == 0.5 && z == 0.01) || (x == 0.01 && z == 0.5),
+                "{:?}",
+                vertex
+            );
+        }
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize].pos);
+            assert!((b - a).cross(&(c - a)).y > 0.);
+        }
+    }
+
+    #[test]
+    fn gizmo_sizes_are_checked() {
+        assert!(matches!(
+            axis_gizmo(0., 0.02),
+            Err(ShapeError::BadDimension { name: "length", .. })
+        ));
+        assert!(matches!(
+            axis_gizmo(1., f32::NAN),
+            Err(ShapeError::BadDimension {
+                name: "thickness",
+                ..
+            })
+        ));
+        assert!(matches!(
+            origin_grid_marker(-0.5, 0.02),
+            Err(ShapeError::BadDimension { name: "size", .. })
+        ));
+        assert!(matches!(
+            origin_grid_marker(0.5, f32::INFINITY),
+            Err(ShapeError::BadDimension { name: "width", .. })
+        ));
+    }
+
+    #[test]
+    fn head_avatars_face_forward() {
+        let mesh = head_avatar(0.25);
+        assert_eq!(mesh.validate(), Ok(()));
+        let (eyes, nose) = (colored(&mesh, EYE_COLOR), colored(&mesh, NOSE_COLOR));
+        assert_eq!(eyes.len(), 2 * 24);
+        assert_eq!(nose.len(), 6 * 3);
+        assert_eq!(colored(&mesh, HEAD_COLOR).len(), 24);
+
+        // On the front at -Z, with the tip of the nose furthest forward
+        let front = |p: &Point3<f32>| p.z;
+        let tip = nose.iter().map(front).fold(f32::INFINITY, f32::min);
+        assert!((tip + 0.25 * 1.35).abs() < 1e-6);
+        assert!(eyes.iter().all(|p| p.z < 0. && p.z > tip));
+
+        // Eyes either side of the middle, above the nose
+        let (left, right): (Vec<_>, Vec<_>) = eyes.iter().partition(|p| p.x < 0.);
+        assert_eq!(left.len(), right.len());
+        let height = |points: &[Point3<f32>]| points.iter().map(|p| p.y).sum::<f32>();
+        assert!(height(&nose) / 18. < height(&eyes) / 48.);
+        assert!(nose.iter().all(|p| p.x.abs() < 0.25 * 0.12 + 1e-6));
+
+        // The nose is open at the back and faces away from the head
+        let nose_color = Vector3::from(NOSE_COLOR);
+        for vertex in mesh.vertices.iter().filter(|v| v.color == nose_color) {
+            assert!((vertex.normal.norm() - 1.).abs() < 1e-5);
+            assert!(vertex.normal.z < 1e-6);
+        }
+    }
+
+    #[test]
+    fn face_layouts_scale_with_the_head() {
+        let face = FaceLayout {
+            eye_spacing: 0.6,
+            nose_length: 0.5,
+            ..FaceLayout::default()
+        };
+        let mesh = head_avatar_with(2., &face);
+        assert_eq!(mesh.validate(), Ok(()));
+        let eyes = colored(&mesh, EYE_COLOR);
+        let widest = eyes.iter().map(|p| p.x.abs()).fold(0., f32::max);
+        assert!((widest - 2. * (0.6 + 0.12)).abs() < 1e-5);
+        let tip = colored(&mesh, NOSE_COLOR)
+            .iter()
+            .map(|p| p.z)
+            .fold(0., f32::min);
+        assert!((tip + 2. * 1.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn quad_maps_share_vertices_between_quads() {
+        let mesh = big_quad_map_subdivided(2., 4).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        assert_eq!(mesh.vertices.len(), 5 * 5);
+        assert_eq!(mesh.indices.len(), 4 * 4 * 6);
+
+        // One vertex per grid point, each used, one meter apart and tiling UVs by the meter
+        let mut used = vec![false; mesh.vertices.len()];
+        mesh.indices.iter().for_each(|&i| used[i as usize] = true);
+        assert!(used.iter().all(|used| *used));
+        for (i, vertex) in mesh.vertices.iter().enumerate() {
+            let expected = Point3::new((i / 5) as f32 - 2., 0., (i % 5) as f32 - 2.);
+            assert_eq!(vertex.pos, expected);
+            assert_eq!(vertex.uv, [expected.x, expected.z]);
+            assert_eq!(vertex.normal, Vector3::y());
+            assert!(vertex.color.iter().all(|c| (0. ..=1.).contains(c)));
+        }
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize].pos);
+            assert!((b - a).cross(&(c - a)).y > 0.);
+        }
+    }
+
+    #[test]
+    fn quad_map_divisions_are_checked() {
+        assert_eq!(big_quad_map_subdivided(1., 1).unwrap().vertices.len(), 4);
+        for divisions in [0, 26755, u32::MAX] {
+            assert_eq!(
+                big_quad_map_subdivided(1., divisions).err(),
+                Some(ShapeError::BadResolution(divisions))
+            );
+        }
+        assert_eq!(check_resolution(26754), Ok(()));
+    }
+
+    /// Gap between the footprints of two boxes on the XZ plane, negative where they overlap
+    fn gap((a_min, a_max): &Bounds, (b_min, b_max): &Bounds) -> f32 {
+        let x = (b_min.x - a_max.x).max(a_min.x - b_max.x);
+        let z = (b_min.z - a_max.z).max(a_min.z - b_max.z);
+        x.max(z)
+    }
+
+    #[test]
+    fn props_keep_apart_and_off_the_spawn() {
+        let (mesh, bounds) = props_map(20., 3).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        let landmarks = bounds.iter().filter(|(_, max)| max.y == LANDMARK_HEIGHT);
+        assert_eq!(landmarks.count(), 4);
+        assert!(bounds.len() > 4);
+
+        for (i, a) in bounds.iter().enumerate() {
+            assert!(a.0.x >= -20. && a.0.z >= -20. && a.1.x <= 20. && a.1.z <= 20.);
+            for b in &bounds[i + 1..] {
+                assert!(gap(a, b) >= PROP_MARGIN * 2. - 1e-4, "{:?} and {:?}", a, b);
+            }
+        }
+
+        // Props stay within their cells, whose centers are kept away from the origin
+        let half_cell = PROP_CELL_SIZE / 2. - PROP_MARGIN;
+        let clearance = PROP_CLEARANCE - half_cell * 2f32.sqrt();
+        for (min, max) in &bounds {
+            let nearest_x = min.x.max(-max.x).max(0.);
+            let nearest_z = min.z.max(-max.z).max(0.);
+            assert!(nearest_x.hypot(nearest_z) >= clearance);
+        }
+        assert_eq!(clear_spawn(&bounds, Point3::origin(), 1.), Point3::origin());
+    }
+
+    #[test]
+    fn props_are_the_same_for_the_same_seed() {
+        let layout = |seed| {
+            let (mesh, bounds) = props_map(20., seed).unwrap();
+            let positions: Vec<_> = mesh.vertices.iter().map(|v| v.pos).collect();
+            (positions, mesh.indices, bounds)
+        };
+        assert_eq!(layout(11), layout(11));
+        assert_ne!(layout(11).2, layout(12).2);
+    }
+
+    #[test]
+    fn props_map_checks_its_extent() {
+        for extent in [0., -1., f32::NAN] {
+            assert!(matches!(
+                props_map(extent, 0),
+                Err(ShapeError::BadDimension { name: "extent", .. })
+            ));
+        }
+        // Too small for any cells, leaving bare ground
+        let (mesh, bounds) = props_map(1., 0).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        assert!(bounds.is_empty());
+    }
+
+    #[test]
+    fn spawns_move_clear_of_bounds() {
+        let start = Point3::new(0., 1., 0.);
+        assert_eq!(clear_spawn(&[], start, 0.5), start);
+
+        let pillar = (Point3::new(-1., 0., -1.), Point3::new(1., 3., 1.));
+        let spawn = clear_spawn(&[pillar], start, 0.5);
+        assert_eq!(spawn.y, 1.);
+        assert!(gap(&pillar, &(spawn, spawn)) >= 0.5 - 1e-4);
+        // The nearest ring that clears the pillar, on a side rather than a corner
+        assert!((spawn.coords.xz().norm() - 1.5).abs() < 1e-4);
+
+        // Boxed in with nowhere to go
+        let everything = (Point3::new(-100., 0., -100.), Point3::new(100., 3., 100.));
+        assert_eq!(clear_spawn(&[everything], start, 0.5), start);
+    }
+
+    const HORIZON: [f32; 3] = [0.5, 0.6, 0.7];
+    const ZENITH: [f32; 3] = [0.1, 0.2, 0.6];
+    const GROUND: [f32; 3] = [0.3, 0.25, 0.2];
+
+    #[test]
+    fn sky_domes_face_inwards() {
+        for stacks in [2, 4, 5] {
+            let mesh = sky_dome(10., stacks, 8, HORIZON, ZENITH, GROUND).unwrap();
+            assert_eq!(mesh.validate(), Ok(()));
+            // Each hemisphere's rings, and all its bands but those meeting at the pole
+            assert_eq!(mesh.vertices.len(), ((stacks + 2) * 9) as usize);
+            assert_eq!(mesh.indices.len(), ((stacks - 1) * 8 * 6) as usize);
+            for vertex in &mesh.vertices {
+                assert!((vertex.pos.coords.norm() - 10.).abs() < 1e-4);
+                assert_eq!(vertex.normal, Vector3::zeros());
+            }
+            for triangle in mesh.indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize].pos);
+                let face = (b - a).cross(&(c - a));
+                assert!(face.norm() > 1e-4);
+                assert!(face.dot(&(a.coords + b.coords + c.coords)) < 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn sky_domes_blend_down_to_a_sharp_horizon() {
+        let mesh = sky_dome(10., 6, 8, HORIZON, ZENITH, GROUND).unwrap();
+        let color = |y: f32| {
+            let matching = mesh.vertices.iter().filter(|v| (v.pos.y - y).abs() < 1e-4);
+            matching.map(|v| v.color).collect::<Vec<_>>()
+        };
+        let close = |a: &Vector3<f32>, b: [f32; 3]| (a - Vector3::from(b)).norm() < 1e-5;
+
+        assert!(color(10.).iter().all(|c| close(c, ZENITH)));
+        assert!(color(-10.).iter().all(|c| close(c, GROUND)));
+        // The horizon ring is doubled, once in each color
+        let horizon = color(0.);
+        assert_eq!(horizon.len(), 18);
+        assert_eq!(horizon.iter().filter(|c| close(c, HORIZON)).count(), 9);
+        assert_eq!(horizon.iter().filter(|c| close(c, GROUND)).count(), 9);
+        // Halfway up the sky is halfway between its colors by the sine of the elevation
+        let thirty = color(5.);
+        let expected = Vector3::from(HORIZON).lerp(&Vector3::from(ZENITH), 0.5);
+        assert!(!thirty.is_empty());
+        assert!(thirty.iter().all(|c| (c - expected).norm() < 1e-5));
+    }
+
+    #[test]
+    fn sky_dome_sizes_are_checked() {
+        let dome =
+            |radius, stacks, slices| sky_dome(radius, stacks, slices, HORIZON, ZENITH, GROUND);
+        assert!(matches!(
+            dome(0., 4, 8),
+            Err(ShapeError::BadDimension { name: "radius", .. })
+        ));
+        assert_eq!(dome(1., 1, 8).err(), Some(ShapeError::TooFewStacks(1)));
+        assert_eq!(dome(1., 4, 2).err(), Some(ShapeError::TooFewSegments(2)));
+    }
+
+    fn inside((min, max): &Bounds, point: Point3<f32>) -> bool {
+        (0..3).all(|i| min[i] < point[i] && point[i] < max[i])
+    }
+
+    #[test]
+    fn rooms_have_openings_for_their_doors() {
+        let (mesh, walls) = rooms_map(&RoomsSpec::two_rooms_and_corridor()).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        let blocked = |x: f32, z: f32| walls.iter().any(|wall| inside(wall, Point3::new(x, 1., z)));
+
+        // From the west room through the corridor into the east room, then out its north door
+        assert!((-85..=85).all(|x| !blocked(x as f32 / 10., 0.)));
+        assert!((-60..=0).all(|z| !blocked(6., z as f32 / 10.)));
+        // Beside the doors are walls
+        assert!(blocked(-3., 2.));
+        assert!(blocked(0., -1.));
+        assert!(blocked(3., -2.));
+        assert!(blocked(8., -4.));
+    }
+
+    /// A room 4 m square and 3 m tall, with a door 1 m wide and 2 m tall in the middle of its
+    /// north wall
+    fn hall() -> RoomsSpec {
+        RoomsSpec {
+            rooms: vec![RoomSpec {
+                name: "hall".into(),
+                min: [-2., -2.],
+                max: [2., 2.],
+                height: 3.,
+                doors: vec![DoorSpec {
+                    side: Side::North,
+                    width: 1.,
+                    height: 2.,
+                    offset: 0.,
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn doorways_have_no_triangles_in_them() {
+        // Triangles may touch the sides, top and bottom of the opening, but any reaching across
+        // it would be a wall left in the way, even one flush with the face of the wall
+        let (min, max) = (Point3::new(-0.5, 0., -2.05), Point3::new(0.5, 2., -1.95));
+        let across = |lo: f32, hi: f32, min: f32, max: f32| lo < max - 1e-4 && hi > min + 1e-4;
+        let in_doorway = |mesh: &Mesh| {
+            mesh.indices.chunks(3).any(|triangle| {
+                let points = triangle.iter().map(|&i| mesh.vertices[i as usize].pos);
+                let start = (
+                    Point3::from([f32::INFINITY; 3]),
+                    Point3::from([f32::NEG_INFINITY; 3]),
+                );
+                let (lo, hi) = points.fold(start, |(lo, hi), p| (lo.inf(&p), hi.sup(&p)));
+                across(lo.x, hi.x, min.x, max.x)
+                    && across(lo.y, hi.y, min.y, max.y)
+                    && lo.z <= max.z
+                    && hi.z >= min.z
+            })
+        };
+
+        let (mesh, _) = rooms_map(&hall()).unwrap();
+        assert_eq!(mesh.validate(), Ok(()));
+        assert!(!in_doorway(&mesh));
+
+        // A wall without the door is caught
+        let mut closed = hall();
+        closed.rooms[0].doors.clear();
+        assert!(in_doorway(&rooms_map(&closed).unwrap().0));
+    }
+
+    #[test]
+    fn doors_leave_walls_above_them() {
+        let spec = hall();
+        let (_, walls) = rooms_map(&spec).unwrap();
+        // Three solid walls, and either side of and above the door
+        assert_eq!(walls.len(), 6);
+        let above = walls.iter().find(|(min, _)| min.y == 2.).unwrap();
+        assert!((above.0 - Point3::new(-0.5, 2., -2.05)).norm() < 1e-6);
+        assert!((above.1 - Point3::new(0.5, 3., -1.95)).norm() < 1e-6);
+
+        // Doors as tall as the room leave no wall above
+        let mut spec = spec;
+        spec.rooms[0].doors[0].height = 3.;
+        assert_eq!(rooms_map(&spec).unwrap().1.len(), 5);
+        spec.rooms[0].height = 0.;
+        assert!(matches!(
+            rooms_map(&spec),
+            Err(ShapeError::BadDimension {
+                name: "room height",
+                ..
+            })
+        ));
+    }
+
+    /// Segments of a line mesh, each with its color
+    fn segments(mesh: &LineMesh) -> Vec<(Point3<f32>, Point3<f32>, [f32; 3])> {
+        assert_eq!(mesh.points.len() % 2, 0);
+        let pairs = mesh.points.chunks(2);
+        pairs
+            .map(|pair| (pair[0].0, pair[1].0, pair[0].1))
+            .collect()
+    }
+
+    #[test]
+    fn grids_brighten_every_few_lines() {
+        let lines = segments(&grid_lines(2., 0.5, 2).unwrap());
+        // Nine lines along each axis, at -2, -1.5, ..., 2
+        assert_eq!(lines.len(), 18);
+        let major = lines.iter().filter(|line| line.2 == GRID_MAJOR_COLOR);
+        assert_eq!(major.count(), 10);
+        for (a, b, color) in &lines {
+            assert_eq!((a.y, b.y), (0., 0.));
+            let (along_x, along_z) = (a.z == b.z, a.x == b.x);
+            assert!(along_x != along_z);
+            let at = if along_x { a.z } else { a.x };
+            assert_eq!(*color == GRID_MAJOR_COLOR, at % 1. == 0.);
+            assert_eq!((b - a).norm(), 4.);
+        }
+
+        // Lines stop short of the extent when the spacing doesn't divide it, and none are major
+        let lines = segments(&grid_lines(1.2, 0.5, 0).unwrap());
+        assert_eq!(lines.len(), 10);
+        assert!(lines.iter().all(|line| line.2 == GRID_MINOR_COLOR));
+    }
+
+    #[test]
+    fn grids_are_limited_in_lines() {
+        assert_eq!(
+            grid_lines(499.5, 1., 10).unwrap().points.len(),
+            (MAX_GRID_LINES - 1) as usize * 4
+        );
+        assert_eq!(
+            grid_lines(500., 1., 10).err(),
+            Some(ShapeError::TooManyLines(MAX_GRID_LINES + 1))
+        );
+        assert_eq!(
+            grid_lines(1., 1e-30, 10).err(),
+            Some(ShapeError::TooManyLines(u32::MAX))
+        );
+        assert!(matches!(
+            grid_lines(1., 0., 10),
+            Err(ShapeError::BadDimension {
+                name: "spacing",
+                ..
+            })
+        ));
+        assert!(matches!(
+            grid_lines(-1., 1., 10),
+            Err(ShapeError::BadDimension { name: "extent", .. })
+        ));
+    }
+
+    #[test]
+    fn boxes_and_frusta_outline_their_edges() {
+        let (min, max) = (Point3::new(-2., -3., 0.), Point3::new(2., 3., 4.));
+        let edges = segments(&aabb_lines(min, max, [1.; 3]));
+        assert_eq!(edges.len(), 12);
+        for (a, b, _) in &edges {
+            // Along exactly one axis, for the whole size of the box on it
+            let along: Vec<_> = (0..3).filter(|&i| a[i] != b[i]).collect();
+            assert_eq!(along.len(), 1);
+            assert_eq!(
+                (b[along[0]] - a[along[0]]).abs(),
+                max[along[0]] - min[along[0]]
+            );
+        }
+
+        // A frustum which is a box has the same edges
+        let inv_view_proj = Matrix4::new_translation(&Vector3::new(0., 0., 2.))
+            * Matrix4::new_nonuniform_scaling(&Vector3::new(2., 3., 2.));
+        let frustum = segments(&frustum_lines(&inv_view_proj, -1., 1., [1.; 3]));
+        assert_eq!(frustum.len(), 12);
+        for (a, b, _) in &frustum {
+            let same = |(c, d, _): &&(Point3<f32>, Point3<f32>, [f32; 3])| {
+                (a, b) == (c, d) || (a, b) == (d, c)
+            };
+            assert_eq!(edges.iter().filter(same).count(), 1, "{} to {}", a, b);
+        }
+    }
+}
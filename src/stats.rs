@@ -0,0 +1,560 @@
+//! Frame time and network statistics for the debug overlay
+use crate::gpu_timer::{GpuPass, GpuTimings};
+use crate::render::{FrameStats, HeadCounts};
+use crate::vr_input::VrInput;
+use cubehead::NetStats;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of frames the overlay keeps timings for
+const FRAME_HISTORY: usize = 120;
+
+/// How often bandwidth figures are recomputed
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Period VR frame statistics are counted over and logged at
+const VR_WINDOW: Duration = Duration::from_secs(10);
+
+/// Number of VR frames timings are kept for, about `VR_WINDOW` at 90 Hz
+const VR_FRAME_HISTORY: usize = 900;
+
+/// Fraction of frames over budget in a full window that counts as consistently over budget
+const VR_OVER_BUDGET_WARNING: f32 = 0.5;
+
+/// Fixed-size ring buffer of frame times in milliseconds
+pub struct FrameTimes {
+    samples: Vec<f32>,
+    /// Index the next sample is written to, once the buffer is full
+    next: usize,
+    capacity: usize,
+}
+
+/// Accumulates the statistics shown by `Engine::draw_stats`
+pub struct StatsOverlay {
+    pub visible: bool,
+    frame_times: FrameTimes,
+    last_frame: Option<Instant>,
+    heads: HeadCounts,
+    net: NetStats,
+    gpu: Option<GpuTimings>,
+    render: FrameStats,
+    pixels: Option<u64>,
+    render_scale: Option<f32>,
+    vr_input: Option<VrInput>,
+    vr_frames: Option<VrFrameStats>,
+
+    /// Start of the current bandwidth window and the counters at that time
+    rate_window: (Instant, NetStats),
+    /// Bytes per second sent and received over the last complete window
+    rates: (f32, f32),
+}
+
+impl FrameTimes {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            samples: Vec::with_capacity(capacity),
+            next: 0,
+            capacity,
+        }
+    }
+
+    /// Add a sample, replacing the oldest one if the buffer is full
+    pub fn push(&mut self, ms: f32) {
+        if self.samples.len() < self.capacity {
+            self.samples.push(ms);
+        } else {
+            self.samples[self.next] = ms;
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Samples from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = f32> + '_ {
+        let (newer, older) = self.samples.split_at(self.next);
+        older.iter().chain(newer).copied()
+    }
+
+    /// Mean of all samples, if any
+    pub fn average(&self) -> Option<f32> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some(self.samples.iter().sum::<f32>() / self.samples.len() as f32)
+    }
+
+    /// The sample at the given fraction (0 to 1) of the sorted samples, if any
+    pub fn percentile(&self, fraction: f32) -> Option<f32> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let idx = ((sorted.len() - 1) as f32 * fraction.clamp(0., 1.)).round() as usize;
+        Some(sorted[idx])
+    }
+}
+
+impl StatsOverlay {
+    pub fn new(visible: bool) -> Self {
+        Self {
+            visible,
+            frame_times: FrameTimes::new(FRAME_HISTORY),
+            last_frame: None,
+            heads: HeadCounts::default(),
+            net: NetStats::default(),
+            gpu: None,
+            render: FrameStats::default(),
+            pixels: None,
+            render_scale: None,
+            vr_input: None,
+            vr_frames: None,
+            rate_window: (Instant::now(), NetStats::default()),
+            rates: (0., 0.),
+        }
+    }
+
+    /// Call once per frame to record the time since the previous call
+    pub fn frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_frame.replace(now) {
+            self.frame_times.push((now - last).as_secs_f32() * 1e3);
+        }
+    }
+
+    /// Set the number of heads rendered and culled this frame
+    pub fn set_heads(&mut self, heads: HeadCounts) {
+        self.heads = heads;
+    }
+
+    /// Set the latest GPU pass timings, if available
+    pub fn set_gpu(&mut self, gpu: Option<GpuTimings>) {
+        self.gpu = gpu;
+    }
+
+    /// Set the engine's counters for the last complete frame
+    pub fn set_render(&mut self, render: FrameStats) {
+        self.render = render;
+    }
+
+    /// Set the number of pixels rendered per frame across all views, if known
+    pub fn set_pixels(&mut self, pixels: Option<u64>) {
+        self.pixels = pixels;
+    }
+
+    /// Set the fraction of each side of the VR views rendered, if it is dynamic
+    pub fn set_render_scale(&mut self, scale: Option<f32>) {
+        self.render_scale = scale;
+    }
+
+    /// Set this frame's controller input, in VR
+    pub fn set_vr_input(&mut self, vr_input: Option<VrInput>) {
+        self.vr_input = vr_input;
+    }
+
+    /// VR frame timing, counted from the first call
+    pub fn vr_frames(&mut self) -> &mut VrFrameStats {
+        self.vr_frames.get_or_insert_with(VrFrameStats::new)
+    }
+
+    /// Update the network counters, recomputing bandwidth once per `RATE_INTERVAL`
+    pub fn set_net(&mut self, net: NetStats) {
+        self.net = net;
+
+        let (start, at_start) = self.rate_window;
+        let elapsed = start.elapsed();
+        if elapsed >= RATE_INTERVAL {
+            let secs = elapsed.as_secs_f32();
+            self.rates = (
+                // A new connection starts its counters again from zero
+                net.bytes_sent.saturating_sub(at_start.bytes_sent) as f32 / secs,
+                net.bytes_received.saturating_sub(at_start.bytes_received) as f32 / secs,
+            );
+            self.rate_window = (Instant::now(), net);
+        }
+    }
+
+    pub fn frame_times(&self) -> &FrameTimes {
+        &self.frame_times
+    }
+
+    /// Lines of text to display
+    pub fn lines(&self) -> Vec<String> {
+        let avg = self.frame_times.average().unwrap_or(0.);
+        let p95 = self.frame_times.percentile(0.95).unwrap_or(0.);
+        let fps = if avg > 0. { 1e3 / avg } else { 0. };
+
+        let rtt = match self.net.rtt {
+            Some(rtt) => format!("{} ms", rtt.as_millis()),
+            None => "-".into(),
+        };
+
+        let gpu = match &self.gpu {
+            Some(gpu) => {
+                let passes: Vec<String> = GpuPass::ALL
+                    .iter()
+                    .map(|pass| format!("{} {:.2}", pass.name(), gpu.get(*pass)))
+                    .collect();
+                format!("gpu {:.2} ms ({})", gpu.total(), passes.join(", "))
+            }
+            None => "gpu -".into(),
+        };
+
+        let mut lines = vec![
+            format!("{:.0} FPS", fps),
+            format!("frame {:.2} ms avg, {:.2} ms 95th", avg, p95),
+            gpu,
+            format!(
+                "heads {} full, {} boxes, {} culled",
+                self.heads.full, self.heads.lod, self.heads.culled
+            ),
+            format!(
+                "draws {}, instances {} ({} culled), tris {:.1} K",
+                self.render.draw_calls,
+                self.render.instances,
+                self.render.culled,
+                self.render.triangles as f32 / 1e3
+            ),
+            format!("upload {:.1} KiB", self.render.upload_bytes as f32 / 1024.),
+            format!("rtt {}", rtt),
+            format!(
+                "up {:.1} KiB/s, down {:.1} KiB/s",
+                self.rates.0 / 1024.,
+                self.rates.1 / 1024.
+            ),
+        ];
+        if let Some(pixels) = self.pixels {
+            lines.push(format!("pixels {:.2} M", pixels as f64 / 1e6));
+        }
+        if let Some(scale) = self.render_scale {
+            lines.push(format!("render scale {:.2} (dynamic)", scale));
+        }
+        if let Some(vr_frames) = &self.vr_frames {
+            lines.extend(vr_frames.lines());
+        }
+        if let Some(vr_input) = &self.vr_input {
+            for (side, hand) in ["left", "right"].iter().zip(&vr_input.hands) {
+                if !hand.active && hand.tracked.is_none() {
+                    lines.push(format!("{} inactive", side));
+                    continue;
+                }
+                let pos = match hand.pose() {
+                    Some(grip) => {
+                        format!("({:.2}, {:.2}, {:.2})", grip.pos.x, grip.pos.y, grip.pos.z)
+                    }
+                    None => "untracked".into(),
+                };
+                lines.push(format!(
+                    "{} {} stick ({:.2}, {:.2}){}{}{}",
+                    side,
+                    pos,
+                    hand.thumbstick[0],
+                    hand.thumbstick[1],
+                    if hand.primary { " primary" } else { "" },
+                    if hand.menu { " menu" } else { "" },
+                    if hand.tracked.is_some() { " hand" } else { "" },
+                ));
+            }
+        }
+        lines
+    }
+}
+
+/// Time spent on each part of a VR frame, in milliseconds
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VrFrameCosts {
+    /// From the frame wait returning to the frame being submitted
+    pub total: f32,
+    /// Receiving and smoothing heads from the server
+    pub network: f32,
+    /// Culling and uploading head instances
+    pub heads: f32,
+    /// Rendering the shadow map both eyes share
+    pub shadows: f32,
+    /// Rendering the left and right eye
+    pub eyes: [f32; 2],
+}
+
+/// Rolling statistics of VR frames: CPU cost against the display period, periods the runtime
+/// skipped and frames it told us not to render. Logs a summary every `VR_WINDOW`.
+pub struct VrFrameStats {
+    total: FrameTimes,
+    network: FrameTimes,
+    heads: FrameTimes,
+    shadows: FrameTimes,
+    eyes: [FrameTimes; 2],
+    /// When frames over budget, skipped periods and unrendered frames happened within the window
+    over_budget: VecDeque<Instant>,
+    skipped: VecDeque<Instant>,
+    not_rendered: VecDeque<Instant>,
+    frames: VecDeque<Instant>,
+    last_display_time: Option<i64>,
+    last_log: Instant,
+    warned: bool,
+}
+
+impl VrFrameStats {
+    pub fn new() -> Self {
+        Self {
+            total: FrameTimes::new(VR_FRAME_HISTORY),
+            network: FrameTimes::new(VR_FRAME_HISTORY),
+            heads: FrameTimes::new(VR_FRAME_HISTORY),
+            shadows: FrameTimes::new(VR_FRAME_HISTORY),
+            eyes: [
+                FrameTimes::new(VR_FRAME_HISTORY),
+                FrameTimes::new(VR_FRAME_HISTORY),
+            ],
+            over_budget: VecDeque::new(),
+            skipped: VecDeque::new(),
+            not_rendered: VecDeque::new(),
+            frames: VecDeque::new(),
+            last_display_time: None,
+            last_log: Instant::now(),
+            warned: false,
+        }
+    }
+
+    /// Record the predicted display time of a frame, in nanoseconds, and count the display
+    /// periods skipped since the last one
+    pub fn display_time(&mut self, now: Instant, time: i64, period: i64) {
+        if let Some(last) = self.last_display_time.replace(time) {
+            if period > 0 {
+                let periods = ((time - last) as f64 / period as f64).round() as i64;
+                for _ in 1..periods {
+                    self.skipped.push_back(now);
+                }
+            }
+        }
+    }
+
+    /// Record a frame the runtime told us not to render
+    pub fn not_rendered(&mut self, now: Instant) {
+        self.not_rendered.push_back(now);
+        self.expire(now);
+    }
+
+    /// Record the costs of a rendered frame against the display period in milliseconds
+    pub fn frame(&mut self, now: Instant, costs: VrFrameCosts, budget: f32) {
+        self.total.push(costs.total);
+        self.network.push(costs.network);
+        self.heads.push(costs.heads);
+        self.shadows.push(costs.shadows);
+        for (times, ms) in self.eyes.iter_mut().zip(costs.eyes) {
+            times.push(ms);
+        }
+        self.frames.push_back(now);
+        if costs.total > budget {
+            self.over_budget.push_back(now);
+        }
+        self.expire(now);
+
+        // Only judge full windows, so that startup hitches don't count
+        let full = now.duration_since(self.last_log) >= VR_WINDOW;
+        let over = self.over_budget.len() as f32 / self.frames.len().max(1) as f32;
+        if full && over >= VR_OVER_BUDGET_WARNING && !self.warned {
+            eprintln!(
+                "VR frames are consistently over the {:.1} ms budget: {}",
+                budget,
+                self.breakdown()
+            );
+            self.warned = true;
+        }
+        if full {
+            println!("{}", self.lines().join("; "));
+            self.last_log = now;
+        }
+    }
+
+    /// Forget events older than the window
+    fn expire(&mut self, now: Instant) {
+        for events in [
+            &mut self.over_budget,
+            &mut self.skipped,
+            &mut self.not_rendered,
+            &mut self.frames,
+        ] {
+            while events
+                .front()
+                .is_some_and(|&t| now.duration_since(t) > VR_WINDOW)
+            {
+                events.pop_front();
+            }
+        }
+    }
+
+    /// Average cost of each part of a frame
+    fn breakdown(&self) -> String {
+        let avg = |times: &FrameTimes| times.average().unwrap_or(0.);
+        format!(
+            "{:.2} ms total, network {:.2}, heads {:.2}, shadows {:.2}, left eye {:.2}, right eye {:.2}",
+            avg(&self.total),
+            avg(&self.network),
+            avg(&self.heads),
+            avg(&self.shadows),
+            avg(&self.eyes[0]),
+            avg(&self.eyes[1])
+        )
+    }
+
+    /// Lines of text to display
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!(
+                "vr cpu {:.2} ms avg, {:.2} ms 99th",
+                self.total.average().unwrap_or(0.),
+                self.total.percentile(0.99).unwrap_or(0.)
+            ),
+            format!(
+                "vr last {}s: {} over budget, {} skipped, {} not rendered",
+                VR_WINDOW.as_secs(),
+                self.over_budget.len(),
+                self.skipped.len(),
+                self.not_rendered.len()
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_times_keep_the_newest_samples() {
+        let mut times = FrameTimes::new(3);
+        assert_eq!(times.average(), None);
+        times.push(1.);
+        times.push(2.);
+        assert_eq!(times.iter().collect::<Vec<_>>(), [1., 2.]);
+
+        for ms in [3., 4., 5.] {
+            times.push(ms);
+        }
+        assert_eq!(times.iter().collect::<Vec<_>>(), [3., 4., 5.]);
+        assert_eq!(times.average(), Some(4.));
+        assert_eq!(times.capacity(), 3);
+    }
+
+    #[test]
+    fn percentiles_pick_from_the_sorted_samples() {
+        let mut times = FrameTimes::new(100);
+        assert_eq!(times.percentile(0.5), None);
+        // Out of order, and wrapping around
+        for ms in (1..=100).rev().chain([1000]) {
+            times.push(ms as f32);
+        }
+        assert_eq!(times.percentile(0.), Some(1.));
+        assert_eq!(times.percentile(0.5), Some(51.));
+        assert_eq!(times.percentile(0.99), Some(99.));
+        assert_eq!(times.percentile(1.), Some(1000.));
+        // The 100 was pushed out
+        assert_eq!(times.iter().filter(|&ms| ms == 100.).count(), 0);
+
+        assert_eq!(times.percentile(-1.), Some(1.));
+        assert_eq!(times.percentile(2.), Some(1000.));
+    }
+
+    #[test]
+    #[should_panic]
+    fn frame_times_need_room() {
+        FrameTimes::new(0);
+    }
+
+    #[test]
+    fn vr_stats_roll_over_the_window() {
+        let mut vr = VrFrameStats::new();
+        let start = Instant::now();
+        // 15 s at 100 Hz, with every tenth frame over an 11 ms budget
+        let mut now = start;
+        for i in 0..1500 {
+            now = start + Duration::from_millis(10 * i);
+            let total = if i % 10 == 0 { 15. } else { 5. };
+            let costs = VrFrameCosts {
+                total,
+                ..VrFrameCosts::default()
+            };
+            vr.frame(now, costs, 11.);
+        }
+
+        // Frames from the last 10 s are counted, and the last 900 are timed
+        assert_eq!(vr.frames.len(), 1001);
+        assert_eq!(
+            vr.lines(),
+            [
+                "vr cpu 6.00 ms avg, 15.00 ms 99th",
+                "vr last 10s: 100 over budget, 0 skipped, 0 not rendered",
+            ]
+        );
+
+        // Unrendered frames expire too
+        vr.not_rendered(now);
+        assert_eq!(vr.not_rendered.len(), 1);
+        vr.not_rendered(now + VR_WINDOW + Duration::from_millis(1));
+        assert_eq!(vr.not_rendered.len(), 1);
+        assert!(vr.over_budget.is_empty());
+    }
+
+    #[test]
+    fn breakdowns_time_each_eye() {
+        let mut vr = VrFrameStats::new();
+        let now = Instant::now();
+        for eyes in [[3., 4.], [5., 4.]] {
+            let costs = VrFrameCosts {
+                total: 10.,
+                network: 1.,
+                heads: 0.5,
+                shadows: 0.25,
+                eyes,
+            };
+            vr.frame(now, costs, 11.);
+        }
+        assert_eq!(
+            vr.breakdown(),
+            "10.00 ms total, network 1.00, heads 0.50, shadows 0.25, left eye 4.00, right eye 4.00"
+        );
+    }
+
+    #[test]
+    fn skipped_display_periods_are_counted() {
+        let now = Instant::now();
+        let mut vr = VrFrameStats::new();
+        let period = 11_111_111;
+        vr.display_time(now, 0, period);
+        vr.display_time(now, period, period);
+        assert!(vr.skipped.is_empty());
+        // Three periods later, so two were skipped
+        vr.display_time(now, 4 * period, period);
+        assert_eq!(vr.skipped.len(), 2);
+        // An unknown period counts nothing
+        vr.display_time(now, 10 * period, 0);
+        assert_eq!(vr.skipped.len(), 2);
+    }
+
+    #[test]
+    fn rates_restart_with_a_new_connection() {
+        let net = |bytes| NetStats {
+            rtt: None,
+            bytes_sent: bytes,
+            bytes_received: bytes,
+        };
+        let ago = |duration| Instant::now().checked_sub(duration).unwrap();
+        let mut overlay = StatsOverlay::new(true);
+        overlay.rate_window = (ago(RATE_INTERVAL * 2), net(10_000));
+        overlay.set_net(net(30_000));
+        let (sent, received) = overlay.rates;
+        assert!(sent > 0. && sent <= 10_000., "{}", sent);
+        assert_eq!(received, sent);
+
+        // The replacement connection has sent less than the last window started at
+        overlay.rate_window = (ago(RATE_INTERVAL * 2), net(30_000));
+        overlay.set_net(net(500));
+        assert_eq!(overlay.rates, (0., 0.));
+        assert_eq!(overlay.rate_window.1.bytes_sent, 500);
+    }
+}
@@ -0,0 +1,331 @@
+//! Layout of camera-facing text labels, drawn with the embedded bitmap font
+use crate::font::{FIRST_CHAR, GLYPHS, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::render::RgbaImage;
+use bytemuck::{Pod, Zeroable};
+use nalgebra::{Matrix4, Point3, Vector3};
+
+/// Glyphs per row of the font atlas
+const ATLAS_COLUMNS: u32 = 16;
+
+/// Atlas cell after the last glyph, filled solid for drawing untextured quads
+const SOLID_CELL: u32 = GLYPHS.len() as u32;
+
+/// Glyph rows of the font atlas
+const ATLAS_ROWS: u32 = (SOLID_CELL + ATLAS_COLUMNS) / ATLAS_COLUMNS;
+
+/// Labels further than this from the camera (in meters) are not drawn
+pub const MAX_LABEL_DISTANCE: f32 = 30.;
+
+/// Character height of labels close to the camera, in meters
+const NEAR_HEIGHT: f32 = 0.08;
+
+/// Labels keep their world-space size up to this distance, and grow beyond it to stay legible
+const GROW_DISTANCE: f32 = 4.;
+
+/// Labels never grow larger than this multiple of `NEAR_HEIGHT`
+const MAX_GROWTH: f32 = 3.;
+
+/// Vertex of a text quad
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TextVertex {
+    pub pos: [f32; 3],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+// Allow TextVertex to be cast to bytes using bytemuck
+unsafe impl Zeroable for TextVertex {}
+unsafe impl Pod for TextVertex {}
+
+/// Bake the embedded font into a white atlas, with glyph coverage in the alpha channel
+pub fn font_atlas() -> RgbaImage {
+    let width = ATLAS_COLUMNS * GLYPH_WIDTH;
+    let height = ATLAS_ROWS * GLYPH_HEIGHT;
+    let mut data = vec![0; (width * height * 4) as usize];
+
+    for (i, glyph) in GLYPHS.iter().enumerate() {
+        let x0 = (i as u32 % ATLAS_COLUMNS) * GLYPH_WIDTH;
+        let y0 = (i as u32 / ATLAS_COLUMNS) * GLYPH_HEIGHT;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                let covered = bits & (0x80 >> col) != 0;
+                let idx = (((y0 + row as u32) * width + x0 + col) * 4) as usize;
+                data[idx..idx + 3].copy_from_slice(&[0xff; 3]);
+                data[idx + 3] = if covered { 0xff } else { 0 };
+            }
+        }
+    }
+
+    let x0 = (SOLID_CELL % ATLAS_COLUMNS) * GLYPH_WIDTH;
+    let y0 = (SOLID_CELL / ATLAS_COLUMNS) * GLYPH_HEIGHT;
+    for y in y0..y0 + GLYPH_HEIGHT {
+        let start = ((y * width + x0) * 4) as usize;
+        data[start..start + (GLYPH_WIDTH * 4) as usize].fill(0xff);
+    }
+
+    RgbaImage {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Texture coordinates of the top-left and bottom-right corners of a character in the atlas.
+/// Characters the font doesn't cover are drawn as '?'.
+fn glyph_uv(c: char) -> ([f32; 2], [f32; 2]) {
+    let idx = match c {
+        ' '..='~' => c as u32 - FIRST_CHAR as u32,
+        _ => (b'?' - FIRST_CHAR) as u32,
+    };
+    cell_uv(idx)
+}
+
+/// Texture coordinates of the top-left and bottom-right corners of an atlas cell
+fn cell_uv(idx: u32) -> ([f32; 2], [f32; 2]) {
+    let (col, row) = (idx % ATLAS_COLUMNS, idx / ATLAS_COLUMNS);
+    let (w, h) = (1. / ATLAS_COLUMNS as f32, 1. / ATLAS_ROWS as f32);
+    let min = [col as f32 * w, row as f32 * h];
+    ([min[0], min[1]], [min[0] + w, min[1] + h])
+}
+
+/// Camera position, right and up vectors in world space, from a view matrix
+pub fn camera_basis(view: &Matrix4<f32>) -> (Point3<f32>, Vector3<f32>, Vector3<f32>) {
+    // The rows of the rotation part are the camera axes in world space
+    let rotation = view.fixed_slice::<3, 3>(0, 0);
+    let translation = view.fixed_slice::<3, 1>(0, 3);
+
+    let right = rotation.row(0).transpose();
+    let up = rotation.row(1).transpose();
+    let position = Point3::from(-(rotation.transpose() * translation));
+
+    (position, right, up)
+}
+
+/// Character height of a label the given distance from the camera, or None if it is too far
+/// away to be drawn
+pub fn label_height(distance: f32) -> Option<f32> {
+    if distance > MAX_LABEL_DISTANCE {
+        return None;
+    }
+
+    let growth = (distance / GROW_DISTANCE).clamp(1., MAX_GROWTH);
+    Some(NEAR_HEIGHT * growth)
+}
+
+/// Append two triangles per character of `text`, facing the camera along the given `right` and
+/// `up` vectors. The label is centered horizontally on `anchor` and sits on top of it.
+pub fn layout_label(
+    out: &mut Vec<TextVertex>,
+    text: &str,
+    anchor: Point3<f32>,
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    height: f32,
+) {
+    let width = text.chars().count() as f32 * glyph_width(height);
+    let start = anchor - right * (width / 2.);
+    layout_text(out, text, start, right, up, height, [1.; 4]);
+}
+
+/// Append two triangles per character of `text`, starting at the bottom-left corner `origin` and
+/// advancing along `right`
+pub fn layout_text(
+    out: &mut Vec<TextVertex>,
+    text: &str,
+    origin: Point3<f32>,
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    height: f32,
+    color: [f32; 4],
+) {
+    let width = glyph_width(height);
+    for (i, c) in text.chars().enumerate() {
+        let bottom_left = origin + right * (width * i as f32);
+        push_quad(
+            out,
+            bottom_left,
+            right * width,
+            up * height,
+            glyph_uv(c),
+            color,
+        );
+    }
+}
+
+/// Append a solid, untextured rectangle spanning `right` and `up` from `origin`
+pub fn layout_rect(
+    out: &mut Vec<TextVertex>,
+    origin: Point3<f32>,
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    color: [f32; 4],
+) {
+    // Sample the middle of the solid cell, away from neighbouring glyphs
+    let ([u0, v0], [u1, v1]) = cell_uv(SOLID_CELL);
+    let center = [(u0 + u1) / 2., (v0 + v1) / 2.];
+    push_quad(out, origin, right, up, (center, center), color);
+}
+
+/// Width of a glyph with the given height
+pub fn glyph_width(height: f32) -> f32 {
+    height * GLYPH_WIDTH as f32 / GLYPH_HEIGHT as f32
+}
+
+/// Append two counter-clockwise triangles spanning `right` and `up` from `bottom_left`
+fn push_quad(
+    out: &mut Vec<TextVertex>,
+    bottom_left: Point3<f32>,
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    (uv_min, uv_max): ([f32; 2], [f32; 2]),
+    color: [f32; 4],
+) {
+    let vertex = |p: Point3<f32>, u: f32, v: f32| TextVertex {
+        pos: [p.x, p.y, p.z],
+        uv: [u, v],
+        color,
+    };
+
+    let [u0, v0] = uv_min;
+    let [u1, v1] = uv_max;
+    let bottom_right = bottom_left + right;
+    let top_left = bottom_left + up;
+    let top_right = bottom_right + up;
+
+    // Atlas rows start from the top, so the bottom of the glyph has the larger v
+    out.extend([
+        vertex(bottom_left, u0, v1),
+        vertex(bottom_right, u1, v1),
+        vertex(top_right, u1, v0),
+        vertex(bottom_left, u0, v1),
+        vertex(top_right, u1, v0),
+        vertex(top_left, u0, v0),
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Isometry3;
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    fn labels_grow_with_distance_up_to_a_limit() {
+        assert_eq!(label_height(MAX_LABEL_DISTANCE + 1.), None);
+        assert_eq!(label_height(0.5), Some(NEAR_HEIGHT));
+        assert_eq!(label_height(GROW_DISTANCE), Some(NEAR_HEIGHT));
+
+        let grown = label_height(GROW_DISTANCE * 2.).unwrap();
+        assert!((grown - NEAR_HEIGHT * 2.).abs() < 1e-6);
+
+        let capped = label_height(MAX_LABEL_DISTANCE).unwrap();
+        assert!((capped - NEAR_HEIGHT * MAX_GROWTH).abs() < 1e-6);
+    }
+
+    #[test]
+    fn labels_are_centered_on_their_anchor() {
+        let mut out = vec![];
+        layout_label(
+            &mut out,
+            "ab",
+            Point3::origin(),
+            Vector3::x(),
+            Vector3::y(),
+            0.16,
+        );
+        assert_eq!(out.len(), 12);
+
+        // Glyphs are half as wide as they are tall, and advance by their width
+        assert_eq!(out[0].pos, [-0.08, 0., 0.]);
+        assert_eq!(out[1].pos, [0., 0., 0.]);
+        assert_eq!(out[5].pos, [-0.08, 0.16, 0.]);
+        assert_eq!(out[6].pos, [0., 0., 0.]);
+        assert_eq!(out[7].pos, [0.08, 0., 0.]);
+
+        // The bottom of each glyph samples the bottom of its atlas cell
+        let ([u0, _], [_, v1]) = glyph_uv('a');
+        assert_eq!(out[0].uv, [u0, v1]);
+    }
+
+    #[test]
+    fn text_advances_along_its_right_vector() {
+        let mut out = vec![];
+        let color = [1., 0.5, 0., 1.];
+        layout_text(
+            &mut out,
+            "abc",
+            Point3::new(1., 2., 3.),
+            -Vector3::z(),
+            Vector3::y(),
+            0.16,
+            color,
+        );
+        assert_eq!(out.len(), 18);
+        assert_eq!(out[0].pos, [1., 2., 3.]);
+        assert_eq!(out[12].pos, [1., 2., 3. - 0.16]);
+        assert!(out.iter().all(|v| v.color == color));
+    }
+
+    #[test]
+    fn rects_sample_the_solid_cell() {
+        let mut out = vec![];
+        layout_rect(
+            &mut out,
+            Point3::origin(),
+            Vector3::x() * 2.,
+            Vector3::y(),
+            [0.; 4],
+        );
+        assert_eq!(out.len(), 6);
+        assert_eq!(out[2].pos, [2., 1., 0.]);
+
+        // Every corner samples the same texel, in the middle of the solid cell
+        let ([u0, v0], [u1, v1]) = cell_uv(SOLID_CELL);
+        let center = [(u0 + u1) / 2., (v0 + v1) / 2.];
+        assert!(out.iter().all(|v| v.uv == center));
+    }
+
+    #[test]
+    fn unknown_characters_are_drawn_as_question_marks() {
+        assert_eq!(glyph_uv('é'), glyph_uv('?'));
+        assert_eq!(glyph_uv('\n'), glyph_uv('?'));
+        assert_eq!(
+            glyph_uv(' '),
+            (
+                [0., 0.],
+                [1. / ATLAS_COLUMNS as f32, 1. / ATLAS_ROWS as f32]
+            )
+        );
+    }
+
+    #[test]
+    fn atlas_cells_hold_their_glyphs() {
+        let atlas = font_atlas();
+        assert_eq!(atlas.width, ATLAS_COLUMNS * GLYPH_WIDTH);
+        assert_eq!(atlas.height, ATLAS_ROWS * GLYPH_HEIGHT);
+        assert_eq!(atlas.data.len(), (atlas.width * atlas.height * 4) as usize);
+
+        // '!' is the second glyph, with a vertical bar two pixels wide in its middle
+        let alpha = |x: u32, y: u32| atlas.data[((y * atlas.width + x) * 4 + 3) as usize];
+        assert_eq!(alpha(GLYPH_WIDTH + 3, 2), 0xff);
+        assert_eq!(alpha(GLYPH_WIDTH, 2), 0);
+
+        // The solid cell is opaque everywhere
+        let x0 = (SOLID_CELL % ATLAS_COLUMNS) * GLYPH_WIDTH;
+        let y0 = (SOLID_CELL / ATLAS_COLUMNS) * GLYPH_HEIGHT;
+        assert_eq!(alpha(x0, y0), 0xff);
+        assert_eq!(alpha(x0 + GLYPH_WIDTH - 1, y0 + GLYPH_HEIGHT - 1), 0xff);
+    }
+
+    #[test]
+    fn camera_basis_follows_a_rotated_view() {
+        // Standing at (1, 2, 3), turned a quarter to the left
+        let camera = Isometry3::new(Vector3::new(1., 2., 3.), Vector3::y() * FRAC_PI_2);
+        let (position, right, up) = camera_basis(&camera.inverse().to_homogeneous());
+
+        assert!((position - Point3::new(1., 2., 3.)).norm() < 1e-5);
+        assert!((right + Vector3::z()).norm() < 1e-5);
+        assert!((up - Vector3::y()).norm() < 1e-5);
+    }
+}
@@ -0,0 +1,210 @@
+//! Debug and settings panel for the desktop client, drawn with egui
+use crate::camera::{FLY_SPEED_RANGE, LOOK_SENSITIVITY_RANGE};
+use crate::render::DebugMode;
+use crate::settings::Settings;
+use cubehead::{Player, PlayerId};
+use glutin::event::{ElementState, WindowEvent};
+use glutin::event_loop::EventLoopWindowTarget;
+use glutin::window::Window;
+use std::sync::Arc;
+
+/// egui state and renderer, drawn on top of the 3D scene
+pub struct UiLayer {
+    egui: egui_glow::EguiGlow,
+    pub visible: bool,
+    /// Server address being typed into the panel
+    server_addr: String,
+}
+
+impl UiLayer {
+    pub fn new<E>(event_loop: &EventLoopWindowTarget<E>, gl: Arc<gl::Context>) -> Self {
+        Self {
+            egui: egui_glow::EguiGlow::new(event_loop, gl),
+            visible: false,
+            server_addr: String::new(),
+        }
+    }
+
+    /// Give a window event to egui. Returns whether the event should also be passed on to the
+    /// rest of the application.
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return true;
+        }
+
+        let consumed = self.egui.on_event(event);
+
+        // Releases and cursor movement are always passed on, so that a button or key pressed
+        // outside the panel doesn't get stuck down when it's released over it
+        let release = match event {
+            WindowEvent::MouseInput { state, .. } => *state == ElementState::Released,
+            WindowEvent::KeyboardInput { input, .. } => input.state == ElementState::Released,
+            WindowEvent::CursorMoved { .. } | WindowEvent::CursorLeft { .. } => true,
+            _ => false,
+        };
+
+        !consumed || release
+    }
+
+    /// Run the panel, applying any changes to `settings` and to the `selected` player, and draw
+    /// it. Returns the address of a server to switch to, if one was entered.
+    pub fn draw(
+        &mut self,
+        window: &Window,
+        settings: &mut Settings,
+        players: &[Player],
+        selected: &mut Option<PlayerId>,
+        server_status: &str,
+    ) -> Option<String> {
+        if !self.visible {
+            return None;
+        }
+
+        let server_addr = &mut self.server_addr;
+        let mut connect = false;
+        self.egui.run(window, |ctx| {
+            egui::Window::new("Settings").show(ctx, |ui| settings_ui(ui, settings));
+            egui::Window::new("Players").show(ctx, |ui| players_ui(ui, players, selected));
+            egui::Window::new("Server").show(ctx, |ui| {
+                connect = server_ui(ui, server_addr, server_status);
+            });
+        });
+
+        self.egui.paint(window);
+        (connect && !self.server_addr.trim().is_empty()).then(|| self.server_addr.clone())
+    }
+
+    /// Free the GL resources of the renderer
+    pub fn destroy(&mut self) {
+        self.egui.destroy();
+    }
+}
+
+fn settings_ui(ui: &mut egui::Ui, settings: &mut Settings) {
+    let (min_speed, max_speed) = FLY_SPEED_RANGE;
+    ui.add(
+        egui::Slider::new(&mut settings.camera.fly_speed, min_speed..=max_speed)
+            .logarithmic(true)
+            .text("Fly speed (m/s)"),
+    );
+    ui.add(egui::Slider::new(&mut settings.fly_accelerate, 0.0..=0.5).text("Fly acceleration (s)"));
+    ui.add(egui::Slider::new(&mut settings.fly_decelerate, 0.0..=0.5).text("Fly deceleration (s)"));
+    let (min_sensitivity, max_sensitivity) = LOOK_SENSITIVITY_RANGE;
+    ui.add(
+        egui::Slider::new(
+            &mut settings.camera.look_sensitivity,
+            min_sensitivity..=max_sensitivity,
+        )
+        .logarithmic(true)
+        .text("Look sensitivity"),
+    );
+    ui.checkbox(&mut settings.camera.invert_y, "Invert mouse Y");
+
+    egui::ComboBox::from_label("Render mode")
+        .selected_text(format!("{:?}", settings.debug_mode))
+        .show_ui(ui, |ui| {
+            for mode in [DebugMode::Solid, DebugMode::Wireframe, DebugMode::Normals] {
+                ui.selectable_value(&mut settings.debug_mode, mode, format!("{:?}", mode));
+            }
+        });
+
+    ui.checkbox(&mut settings.labels_through_walls, "Nametags through walls");
+    ui.checkbox(&mut settings.show_stats, "Statistics overlay");
+    ui.checkbox(&mut settings.gamma_correct, "sRGB output");
+    ui.checkbox(&mut settings.fog, "Fog");
+    ui.add_enabled(
+        settings.fog,
+        egui::Slider::new(&mut settings.fog_density, 0.001..=0.5)
+            .logarithmic(true)
+            .text("Fog density"),
+    );
+    ui.checkbox(&mut settings.shadows, "Shadows");
+    ui.add_enabled(
+        settings.shadows,
+        egui::Slider::new(&mut settings.shadow_bias, 0.0..=0.02).text("Shadow bias"),
+    );
+    ui.add_enabled(
+        settings.shadows,
+        egui::Slider::new(&mut settings.shadow_slope_bias, 0.0..=0.05).text("Shadow slope bias"),
+    );
+    ui.add(
+        egui::Slider::new(&mut settings.head_lod_distance, 1.0..=500.)
+            .logarithmic(true)
+            .text("Head detail distance (m)"),
+    );
+    ui.add(
+        egui::Slider::new(&mut settings.head_draw_distance, 1.0..=1000.)
+            .logarithmic(true)
+            .text("Head draw distance (m)"),
+    );
+    ui.checkbox(&mut settings.smooth_heads, "Smooth heads");
+    ui.add_enabled(
+        settings.smooth_heads,
+        egui::Slider::new(&mut settings.head_smoothing, 0.01..=0.5)
+            .logarithmic(true)
+            .text("Head smoothing (s)"),
+    );
+    ui.checkbox(&mut settings.trails, "Head trails");
+    ui.checkbox(&mut settings.idle_animation, "Idle animation");
+    ui.checkbox(&mut settings.grid, "Ground grid");
+    ui.checkbox(&mut settings.minimap, "Minimap");
+    ui.add_enabled(
+        settings.minimap,
+        egui::Slider::new(&mut settings.minimap_size, 64.0..=512.).text("Minimap size (px)"),
+    );
+    ui.add_enabled(
+        settings.minimap,
+        egui::Slider::new(&mut settings.minimap_extent, 5.0..=500.)
+            .logarithmic(true)
+            .text("Minimap area (m)"),
+    );
+    ui.checkbox(&mut settings.debug_lines, "Debug lines");
+    ui.add_enabled(
+        settings.debug_lines,
+        egui::Checkbox::new(&mut settings.debug_lines_xray, "Debug lines through walls"),
+    );
+}
+
+/// Address field for switching servers. Returns whether to connect to the address.
+fn server_ui(ui: &mut egui::Ui, addr: &mut String, status: &str) -> bool {
+    ui.label(status);
+    ui.horizontal(|ui| {
+        let field = ui.text_edit_singleline(addr);
+        let entered = field.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+        ui.button("Connect").clicked() || entered
+    })
+    .inner
+}
+
+fn players_ui(ui: &mut egui::Ui, players: &[Player], selected: &mut Option<PlayerId>) {
+    if players.is_empty() {
+        ui.label("Nobody else is connected");
+        return;
+    }
+
+    if let Some(player) = players.iter().find(|p| Some(p.id) == *selected) {
+        ui.horizontal(|ui| {
+            ui.label(format!("Selected: {} (id {})", player.name, player.id));
+            if ui.button("Clear").clicked() {
+                *selected = None;
+            }
+        });
+        ui.separator();
+    }
+
+    egui::Grid::new("players").striped(true).show(ui, |ui| {
+        for player in players {
+            let pos = player.head.pos;
+            let is_selected = Some(player.id) == *selected;
+            if ui
+                .selectable_label(is_selected, player.id.to_string())
+                .clicked()
+            {
+                *selected = Some(player.id);
+            }
+            ui.label(&player.name);
+            ui.label(format!("{:.1}, {:.1}, {:.1}", pos.x, pos.y, pos.z));
+            ui.end_row();
+        }
+    });
+}
@@ -0,0 +1,374 @@
+//! Controller input through OpenXR actions: hand poses, buttons and thumbsticks. Bare hands
+//! are tracked too where the runtime supports `XR_EXT_hand_tracking`.
+use crate::xr_math::head_from_xr_pose;
+use anyhow::Result;
+use cubehead::{Hands, Head};
+use openxr as xr;
+use std::time::{Duration, Instant};
+
+/// Interaction profiles bindings are suggested for
+const PROFILES: [(&str, ProfileInputs); 3] = [
+    (
+        "/interaction_profiles/khr/simple_controller",
+        ProfileInputs {
+            primary: "input/select/click",
+            menu: ["input/menu/click", "input/menu/click"],
+            thumbstick: None,
+        },
+    ),
+    (
+        "/interaction_profiles/oculus/touch_controller",
+        ProfileInputs {
+            primary: "input/trigger/value",
+            // The right menu button is reserved for the runtime
+            menu: ["input/menu/click", "input/b/click"],
+            thumbstick: Some("input/thumbstick"),
+        },
+    ),
+    (
+        "/interaction_profiles/valve/index_controller",
+        ProfileInputs {
+            primary: "input/trigger/click",
+            menu: ["input/b/click", "input/b/click"],
+            thumbstick: Some("input/thumbstick"),
+        },
+    ),
+];
+
+/// Paths of the inputs of an interaction profile, relative to `/user/hand/<side>/`
+struct ProfileInputs {
+    primary: &'static str,
+    /// For the left and right hand
+    menu: [&'static str; 2],
+    thumbstick: Option<&'static str>,
+}
+
+/// State of one controller this frame
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HandInput {
+    /// Whether a controller is connected and bound for this hand
+    pub active: bool,
+    /// Where the controller is held, or None if it isn't tracked
+    pub grip: Option<Head>,
+    /// Where the controller points, or None if it isn't tracked
+    pub aim: Option<Head>,
+    /// Trigger (or select) held down
+    pub primary: bool,
+    /// Trigger pressed since the last frame
+    pub primary_pressed: bool,
+    pub menu: bool,
+    pub menu_pressed: bool,
+    /// Thumbstick deflection, from -1 to 1 on each axis, with +y forward
+    pub thumbstick: [f32; 2],
+    /// Thumbstick pressed in
+    pub thumbstick_click: bool,
+    /// The bare hand, while hand tracking sees it
+    pub tracked: Option<TrackedHand>,
+}
+
+impl HandInput {
+    /// Where the hand is: the palm when it is tracked, else the controller grip
+    pub fn pose(&self) -> Option<Head> {
+        match &self.tracked {
+            Some(tracked) => Some(tracked.palm),
+            None => self.grip,
+        }
+    }
+}
+
+/// Joints of a bare hand, indexed by `xr::HandJoint`
+#[derive(Copy, Clone, Debug)]
+pub struct TrackedHand {
+    pub palm: Head,
+    /// Each joint's pose, or None where the runtime could only estimate part of it
+    pub joints: [Option<Head>; xr::HAND_JOINT_COUNT],
+    /// Each joint's radius in meters
+    pub radii: [f32; xr::HAND_JOINT_COUNT],
+}
+
+/// Input from both controllers, left then right
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VrInput {
+    pub hands: [HandInput; 2],
+}
+
+impl VrInput {
+    /// Poses of both hands, as sent to the server
+    pub fn hand_poses(&self) -> Hands {
+        self.hands.map(|hand| hand.pose())
+    }
+
+    /// The same input with every pose moved from the stage into the world
+    pub fn transformed(&self, world_from_stage: &Head) -> Self {
+        let mut input = *self;
+        for hand in &mut input.hands {
+            let transform = |pose: &mut Option<Head>| {
+                *pose = pose.map(|pose| world_from_stage.transform(&pose));
+            };
+            transform(&mut hand.grip);
+            transform(&mut hand.aim);
+            if let Some(tracked) = &mut hand.tracked {
+                tracked.palm = world_from_stage.transform(&tracked.palm);
+                tracked.joints.iter_mut().for_each(transform);
+            }
+        }
+        input
+    }
+}
+
+/// Fires once when a button has been held for a while, then not again until it is released
+#[derive(Default)]
+pub struct HoldButton {
+    held_since: Option<Instant>,
+    fired: bool,
+}
+
+impl HoldButton {
+    /// Returns true on the first call after `held` has stayed true for `duration`
+    pub fn update(&mut self, now: Instant, held: bool, duration: Duration) -> bool {
+        if !held {
+            *self = Self::default();
+            return false;
+        }
+        let since = *self.held_since.get_or_insert(now);
+        if !self.fired && now - since >= duration {
+            self.fired = true;
+            return true;
+        }
+        false
+    }
+}
+
+/// The action set and the spaces of its pose actions
+pub struct VrActions {
+    action_set: xr::ActionSet,
+    /// `/user/hand/left` and `/user/hand/right`
+    hand_paths: [xr::Path; 2],
+    grip: xr::Action<xr::Posef>,
+    primary: xr::Action<bool>,
+    menu: xr::Action<bool>,
+    thumbstick: xr::Action<xr::Vector2f>,
+    thumbstick_click: xr::Action<bool>,
+    haptic: xr::Action<xr::Haptic>,
+    grip_spaces: Vec<xr::Space>,
+    aim_spaces: Vec<xr::Space>,
+    /// Left and right hand trackers, if the runtime supports hand tracking
+    hand_trackers: Option<[xr::HandTracker; 2]>,
+}
+
+impl VrActions {
+    /// Create the actions, suggest bindings for the supported controllers and attach them to the
+    /// session. Profiles the runtime rejects are skipped with a warning. Pass `hand_tracking` if
+    /// `XR_EXT_hand_tracking` is enabled and the system supports it.
+    pub fn new(
+        instance: &xr::Instance,
+        session: &xr::Session<xr::OpenGL>,
+        hand_tracking: bool,
+    ) -> Result<Self> {
+        let hand_paths = [
+            instance.string_to_path("/user/hand/left")?,
+            instance.string_to_path("/user/hand/right")?,
+        ];
+
+        let action_set = instance.create_action_set("input", "Input", 0)?;
+        let grip = action_set.create_action::<xr::Posef>("grip_pose", "Grip pose", &hand_paths)?;
+        let aim = action_set.create_action::<xr::Posef>("aim_pose", "Aim pose", &hand_paths)?;
+        let primary = action_set.create_action::<bool>("primary", "Primary button", &hand_paths)?;
+        let menu = action_set.create_action::<bool>("menu", "Menu", &hand_paths)?;
+        let thumbstick =
+            action_set.create_action::<xr::Vector2f>("thumbstick", "Thumbstick", &hand_paths)?;
+        let thumbstick_click = action_set.create_action::<bool>(
+            "thumbstick_click",
+            "Thumbstick click",
+            &hand_paths,
+        )?;
+        let haptic = action_set.create_action::<xr::Haptic>("haptic", "Vibration", &hand_paths)?;
+
+        for (profile, inputs) in &PROFILES {
+            let mut bindings = vec![];
+            for (side, menu_input) in ["left", "right"].into_iter().zip(inputs.menu) {
+                let path = |input: &str| {
+                    instance.string_to_path(&format!("/user/hand/{}/{}", side, input))
+                };
+                bindings.push(xr::Binding::new(&grip, path("input/grip/pose")?));
+                bindings.push(xr::Binding::new(&aim, path("input/aim/pose")?));
+                bindings.push(xr::Binding::new(&primary, path(inputs.primary)?));
+                bindings.push(xr::Binding::new(&menu, path(menu_input)?));
+                bindings.push(xr::Binding::new(&haptic, path("output/haptic")?));
+                if let Some(input) = inputs.thumbstick {
+                    bindings.push(xr::Binding::new(&thumbstick, path(input)?));
+                    let click = format!("{}/click", input);
+                    bindings.push(xr::Binding::new(&thumbstick_click, path(&click)?));
+                }
+            }
+            if let Err(e) = instance
+                .suggest_interaction_profile_bindings(instance.string_to_path(profile)?, &bindings)
+            {
+                eprintln!("Failed to suggest bindings for {}; {}", profile, e);
+            }
+        }
+
+        session.attach_action_sets(&[&action_set])?;
+
+        let mut grip_spaces = vec![];
+        let mut aim_spaces = vec![];
+        for path in hand_paths {
+            grip_spaces.push(grip.create_space(session.clone(), path, xr::Posef::IDENTITY)?);
+            aim_spaces.push(aim.create_space(session.clone(), path, xr::Posef::IDENTITY)?);
+        }
+
+        let hand_trackers = if hand_tracking {
+            Some([
+                session.create_hand_tracker(xr::Hand::LEFT)?,
+                session.create_hand_tracker(xr::Hand::RIGHT)?,
+            ])
+        } else {
+            None
+        };
+
+        Ok(Self {
+            action_set,
+            hand_paths,
+            grip,
+            primary,
+            menu,
+            thumbstick,
+            thumbstick_click,
+            haptic,
+            grip_spaces,
+            aim_spaces,
+            hand_trackers,
+        })
+    }
+
+    /// Sync the actions and read both controllers, with poses in `base` at `time`. Controllers
+    /// which are off or unbound read as inactive, untracked and released. Hands are checked every
+    /// frame, so putting the controllers down switches to bare hands and back.
+    pub fn update(
+        &self,
+        session: &xr::Session<xr::OpenGL>,
+        base: &xr::Space,
+        time: xr::Time,
+    ) -> Result<VrInput> {
+        session.sync_actions(&[(&self.action_set).into()])?;
+
+        let mut input = VrInput::default();
+        for (i, hand) in input.hands.iter_mut().enumerate() {
+            let path = self.hand_paths[i];
+            hand.active = self.grip.is_active(session, path)?;
+            hand.grip = locate(&self.grip_spaces[i], base, time)?;
+            hand.aim = locate(&self.aim_spaces[i], base, time)?;
+
+            let primary = self.primary.state(session, path)?;
+            hand.primary = primary.is_active && primary.current_state;
+            hand.primary_pressed = hand.primary && primary.changed_since_last_sync;
+
+            let menu = self.menu.state(session, path)?;
+            hand.menu = menu.is_active && menu.current_state;
+            hand.menu_pressed = hand.menu && menu.changed_since_last_sync;
+
+            let thumbstick = self.thumbstick.state(session, path)?;
+            if thumbstick.is_active {
+                hand.thumbstick = [thumbstick.current_state.x, thumbstick.current_state.y];
+            }
+            let click = self.thumbstick_click.state(session, path)?;
+            hand.thumbstick_click = click.is_active && click.current_state;
+
+            if let Some(trackers) = &self.hand_trackers {
+                hand.tracked = locate_joints(&trackers[i], base, time)?;
+            }
+        }
+        Ok(input)
+    }
+
+    /// Vibrate the controller in `hand` (0 left, 1 right) with an amplitude from 0 to 1. Does
+    /// nothing if the controller has no motor or isn't connected.
+    pub fn vibrate(
+        &self,
+        session: &xr::Session<xr::OpenGL>,
+        hand: usize,
+        amplitude: f32,
+        duration: Duration,
+    ) -> Result<()> {
+        let vibration = xr::HapticVibration::new()
+            .amplitude(amplitude.clamp(0., 1.))
+            .duration(xr::Duration::from_nanos(duration.as_nanos() as i64))
+            .frequency(xr::FREQUENCY_UNSPECIFIED);
+        self.haptic
+            .apply_feedback(session, self.hand_paths[hand], &vibration)?;
+        Ok(())
+    }
+}
+
+/// The pose of a space within `base`, or None if it is not tracked
+pub fn locate(space: &xr::Space, base: &xr::Space, time: xr::Time) -> Result<Option<Head>> {
+    let location = space.locate(base, time)?;
+    let valid = xr::SpaceLocationFlags::POSITION_VALID | xr::SpaceLocationFlags::ORIENTATION_VALID;
+    Ok(location
+        .location_flags
+        .contains(valid)
+        .then(|| head_from_xr_pose(&location.pose)))
+}
+
+/// The joints of a hand within `base`, or None if the hand isn't tracked or its palm is lost
+fn locate_joints(
+    tracker: &xr::HandTracker,
+    base: &xr::Space,
+    time: xr::Time,
+) -> Result<Option<TrackedHand>> {
+    let locations = match base.locate_hand_joints(tracker, time)? {
+        Some(locations) => locations,
+        None => return Ok(None),
+    };
+
+    let valid = xr::SpaceLocationFlags::POSITION_VALID | xr::SpaceLocationFlags::ORIENTATION_VALID;
+    let joints = locations.map(|joint| {
+        joint
+            .location_flags
+            .contains(valid)
+            .then(|| head_from_xr_pose(&joint.pose))
+    });
+    let radii = locations.map(|joint| joint.radius);
+
+    Ok(
+        joints[xr::HandJoint::PALM.into_raw() as usize].map(|palm| TrackedHand {
+            palm,
+            joints,
+            radii,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLD: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn holds_fire_once_after_the_duration() {
+        let start = Instant::now();
+        let mut button = HoldButton::default();
+        assert!(!button.update(start, true, HOLD));
+        assert!(!button.update(start + HOLD / 2, true, HOLD));
+        assert!(button.update(start + HOLD, true, HOLD));
+        // Keeping it held doesn't fire again
+        assert!(!button.update(start + HOLD * 3, true, HOLD));
+    }
+
+    #[test]
+    fn letting_go_starts_the_hold_over() {
+        let start = Instant::now();
+        let mut button = HoldButton::default();
+        button.update(start, true, HOLD);
+        assert!(!button.update(start + HOLD / 2, false, HOLD));
+        assert!(!button.update(start + HOLD, true, HOLD));
+        assert!(!button.update(start + HOLD * 3 / 2, true, HOLD));
+        assert!(button.update(start + HOLD * 2, true, HOLD));
+
+        // And lets it fire again
+        button.update(start + HOLD * 3, false, HOLD);
+        button.update(start + HOLD * 4, true, HOLD);
+        assert!(button.update(start + HOLD * 5, true, HOLD));
+    }
+}
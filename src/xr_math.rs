@@ -0,0 +1,97 @@
+//! Conversions between OpenXR and our own poses, and combining them
+use cubehead::Head;
+use nalgebra::{Point3, Quaternion, Unit};
+use openxr as xr;
+
+/// Converts an OpenXR pose to a head pose
+pub fn head_from_xr_pose(pose: &xr::Posef) -> Head {
+    // Convert the rotation quaternion from OpenXR to nalgebra
+    let orient = pose.orientation;
+    let orient = Quaternion::new(orient.w, orient.x, orient.y, orient.z);
+    let orient = Unit::try_new(orient, 0.0).expect("Not a unit orienternion");
+
+    // Convert the position vector from OpenXR to nalgebra
+    let pos = pose.position;
+    let pos = Point3::new(pos.x, pos.y, pos.z);
+
+    Head { pos, orient }
+}
+
+/// The pose between the eyes of any number of views (left to right), facing halfway between
+/// the outermost ones. Prefer locating the VIEW reference space, which is defined this way; this
+/// is for when it isn't tracked.
+pub fn mid_eye(eyes: &[Head]) -> Head {
+    let (first, last) = match (eyes.first(), eyes.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Head::default(),
+    };
+    let sum = eyes
+        .iter()
+        .fold(Point3::origin(), |sum, eye| sum + eye.pos.coords);
+    Head {
+        pos: sum / eyes.len() as f32,
+        orient: first.orient.slerp(&last.orient, 0.5),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{UnitQuaternion, Vector3};
+
+    fn eye(x: f32, yaw: f32) -> Head {
+        Head {
+            pos: Point3::new(x, 1.5, -0.25),
+            orient: UnitQuaternion::from_euler_angles(0., yaw, 0.),
+        }
+    }
+
+    #[test]
+    fn no_eyes_is_the_origin() {
+        let head = mid_eye(&[]);
+        assert_eq!(head.pos, Point3::origin());
+        assert_eq!(head.orient, UnitQuaternion::identity());
+    }
+
+    #[test]
+    fn one_eye_is_itself() {
+        let only = eye(0.5, 0.25);
+        let head = mid_eye(&[only]);
+        assert_eq!(head.pos, only.pos);
+        assert!(head.orient.angle_to(&only.orient) < 1e-3);
+    }
+
+    #[test]
+    fn mid_eye_is_between_the_eyes() {
+        let head = mid_eye(&[eye(-0.03125, -0.1), eye(0.03125, 0.1)]);
+        assert_eq!(head.pos, Point3::new(0., 1.5, -0.25));
+        assert!(head.orient.angle() < 1e-3);
+
+        // Only the outermost views set the facing, but all of them the position
+        let head = mid_eye(&[eye(-1., 0.), eye(0.5, 1.), eye(2., 0.5)]);
+        assert_eq!(head.pos, Point3::new(0.5, 1.5, -0.25));
+        let halfway = UnitQuaternion::from_euler_angles(0., 0.25, 0.);
+        assert!(head.orient.angle_to(&halfway) < 1e-3);
+    }
+
+    #[test]
+    fn xr_poses_convert_component_by_component() {
+        let turn = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.5);
+        let pose = xr::Posef {
+            orientation: xr::Quaternionf {
+                x: turn.i,
+                y: turn.j,
+                z: turn.k,
+                w: turn.w,
+            },
+            position: xr::Vector3f {
+                x: 1.,
+                y: 2.,
+                z: 3.,
+            },
+        };
+        let head = head_from_xr_pose(&pose);
+        assert_eq!(head.pos, Point3::new(1., 2., 3.));
+        assert!(head.orient.angle_to(&turn) < 1e-3);
+    }
+}